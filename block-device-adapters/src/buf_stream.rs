@@ -38,6 +38,81 @@ impl<T: core::fmt::Debug> embedded_io_async::Error for BufStreamError<T> {
 ///
 /// [`BufStream<T, const SIZE: usize, const ALIGN: usize`](BufStream) implements the [`embedded_io_async`] traits, and implicitly
 /// handles the RMW (Read, Modify, Write) cycle for you.
+///
+/// # Block size
+///
+/// `SIZE` does not have to match the sector size a filesystem on top of the stream uses. Accesses smaller than a
+/// block, or not aligned to one, go through the internal buffer: the block is read, modified and written back. A
+/// 512 byte sector FAT volume therefore works on a device with 4096 byte blocks, but every sector write costs a block
+/// read and a block write. Formatting the volume with a sector size equal to `SIZE` avoids that overhead.
+///
+/// Writes are buffered until another block is accessed or [`Write::flush`] is called, so the stream must be flushed
+/// before the device is powered off or handed back with [`BufStream::into_inner`].
+///
+/// # Example
+///
+/// Wrapping a sector-addressed card driver, in the style of `embedded-sdmmc`:
+///
+/// ```rust
+/// use aligned::{Aligned, A4};
+/// use block_device_adapters::BufStream;
+/// use block_device_driver::BlockDevice;
+///
+/// struct SdCard {
+///     // SPI bus, chip select, ...
+/// #   data: Vec<[u8; 512]>,
+/// }
+///
+/// impl SdCard {
+///     async fn read_sector(&mut self, sector: u32, buf: &mut [u8; 512]) -> Result<(), ()> {
+/// #       *buf = self.data[sector as usize];
+///         // issue CMD17 and read the data token
+///         Ok(())
+///     }
+///
+///     async fn write_sector(&mut self, sector: u32, buf: &[u8; 512]) -> Result<(), ()> {
+/// #       self.data[sector as usize] = *buf;
+///         // issue CMD24 and wait for the card to finish programming
+///         Ok(())
+///     }
+///
+///     fn num_sectors(&self) -> u32 {
+/// #       return self.data.len() as u32;
+///         // read from the CSD register
+///     }
+/// }
+///
+/// impl BlockDevice<512> for SdCard {
+///     type Error = ();
+///     type Align = A4;
+///
+///     async fn read(&mut self, block_address: u32, data: &mut [Aligned<A4, [u8; 512]>]) -> Result<(), ()> {
+///         for (i, block) in data.iter_mut().enumerate() {
+///             self.read_sector(block_address + i as u32, block).await?;
+///         }
+///         Ok(())
+///     }
+///
+///     async fn write(&mut self, block_address: u32, data: &[Aligned<A4, [u8; 512]>]) -> Result<(), ()> {
+///         for (i, block) in data.iter().enumerate() {
+///             self.write_sector(block_address + i as u32, block).await?;
+///         }
+///         Ok(())
+///     }
+///
+///     async fn size(&mut self) -> Result<u64, ()> {
+///         Ok(u64::from(self.num_sectors()) * 512)
+///     }
+/// }
+///
+/// # async fn example() {
+/// # let card = SdCard { data: vec![[0; 512]; 8] };
+/// // `stream` implements `embedded_io_async::{Read, Write, Seek}` and can be passed to
+/// // `embedded_fatfs::FileSystem::new`.
+/// let stream = BufStream::<_, 512>::new(card);
+/// # let _ = stream;
+/// # }
+/// ```
 pub struct BufStream<T: BlockDevice<SIZE>, const SIZE: usize> {
     inner: T,
     buffer: Aligned<T::Align, [u8; SIZE]>,
@@ -265,7 +340,7 @@ mod tests {
         }
     }
 
-    impl<T: Read + Write + Seek> BlockDevice<512> for TestBlockDevice<T> {
+    impl<T: Read + Write + Seek, const SIZE: usize> BlockDevice<SIZE> for TestBlockDevice<T> {
         type Error = T::Error;
         type Align = aligned::A4;
 
@@ -273,10 +348,10 @@ mod tests {
         async fn read(
             &mut self,
             block_address: u32,
-            data: &mut [Aligned<Self::Align, [u8; 512]>],
+            data: &mut [Aligned<Self::Align, [u8; SIZE]>],
         ) -> Result<(), Self::Error> {
             self.0
-                .seek(SeekFrom::Start((block_address * 512).into()))
+                .seek(SeekFrom::Start(u64::from(block_address) * SIZE as u64))
                 .await?;
             for b in data {
                 self.0.read(&mut b[..]).await?;
@@ -288,10 +363,10 @@ mod tests {
         async fn write(
             &mut self,
             block_address: u32,
-            data: &[Aligned<Self::Align, [u8; 512]>],
+            data: &[Aligned<Self::Align, [u8; SIZE]>],
         ) -> Result<(), Self::Error> {
             self.0
-                .seek(SeekFrom::Start((block_address * 512).into()))
+                .seek(SeekFrom::Start(u64::from(block_address) * SIZE as u64))
                 .await?;
             for b in data {
                 self.0.write(&b[..]).await?;
@@ -537,4 +612,43 @@ mod tests {
             ("A".repeat(524) + &"B".repeat(512) + &"C".repeat(512) + &"A".repeat(500)).into_bytes()
         )
     }
+
+    #[tokio::test]
+    async fn block_4096_write_512_sectors() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let buf = "A".repeat(8192).into_bytes();
+        let cur = std::io::Cursor::new(buf);
+        let mut block: BufStream<_, 4096> = BufStream::new(TestBlockDevice(
+            embedded_io_adapters::tokio_1::FromTokio::new(cur),
+        ));
+
+        // Write 512 byte sectors in the middle of and across 4096 byte blocks
+        block.seek(SeekFrom::Start(1024)).await.unwrap();
+        block
+            .write_all(&"B".repeat(512).into_bytes())
+            .await
+            .unwrap();
+        block.seek(SeekFrom::Start(4096 - 512)).await.unwrap();
+        block
+            .write_all(&"C".repeat(1024).into_bytes())
+            .await
+            .unwrap();
+        block.flush().await.unwrap();
+
+        let mut tmp = [0u8; 512];
+        block.seek(SeekFrom::Start(1024)).await.unwrap();
+        block.read_exact(&mut tmp[..]).await.unwrap();
+        assert_eq!(&tmp[..], "B".repeat(512).into_bytes().as_slice());
+
+        let buf = block.into_inner().0.into_inner().into_inner();
+        assert_eq!(
+            buf,
+            ("A".repeat(1024)
+                + &"B".repeat(512)
+                + &"A".repeat(2048)
+                + &"C".repeat(1024)
+                + &"A".repeat(3584))
+                .into_bytes()
+        )
+    }
 }
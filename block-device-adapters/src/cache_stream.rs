@@ -0,0 +1,482 @@
+use core::cmp;
+use embedded_io_async::{ErrorKind, Read, ReadExactError, Seek, SeekFrom, Write};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum CacheStreamError<T> {
+    Io(T),
+    UnexpectedEof,
+}
+
+impl<T> From<T> for CacheStreamError<T> {
+    fn from(t: T) -> Self {
+        CacheStreamError::Io(t)
+    }
+}
+
+impl<T> From<ReadExactError<T>> for CacheStreamError<T> {
+    fn from(e: ReadExactError<T>) -> Self {
+        match e {
+            ReadExactError::UnexpectedEof => CacheStreamError::UnexpectedEof,
+            ReadExactError::Other(t) => CacheStreamError::Io(t),
+        }
+    }
+}
+
+impl<T: core::fmt::Debug> embedded_io_async::Error for CacheStreamError<T> {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+struct CacheEntry<const SIZE: usize> {
+    sector: Option<u64>,
+    data: [u8; SIZE],
+    dirty: bool,
+    last_used: u64,
+}
+
+/// A write-back sector cache wrapping a stream.
+///
+/// [`CacheStream<T, const SIZE: usize, const ENTRIES: usize>`](CacheStream) can be initialized with the following
+/// parameters.
+///
+/// - `T`: The inner stream.
+/// - `SIZE`: The size of the cached sector.
+/// - `ENTRIES`: The number of sectors kept in the cache.
+///
+/// Partial sector reads and writes go through the cache, the least recently used sector is evicted when the cache is
+/// full. Modified sectors are only written to the inner stream when they are evicted or when [`Write::flush`] is
+/// called. Runs of adjacent dirty sectors are written back with a single seek. Whole sector accesses of sectors that
+/// are not cached bypass the cache so that large file transfers do not evict metadata sectors.
+///
+/// The size of the inner stream must be a multiple of `SIZE`. Data that is not flushed before the [`CacheStream`] is
+/// dropped is lost.
+pub struct CacheStream<T: Read + Write + Seek, const SIZE: usize, const ENTRIES: usize> {
+    inner: T,
+    entries: [CacheEntry<SIZE>; ENTRIES],
+    current_offset: u64,
+    tick: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl<T: Read + Write + Seek, const SIZE: usize, const ENTRIES: usize>
+    CacheStream<T, SIZE, ENTRIES>
+{
+    /// Create a new [`CacheStream`] around a stream.
+    pub fn new(inner: T) -> Self {
+        assert!(SIZE > 0 && ENTRIES > 0);
+        Self {
+            inner,
+            entries: core::array::from_fn(|_| CacheEntry {
+                sector: None,
+                data: [0; SIZE],
+                dirty: false,
+                last_used: 0,
+            }),
+            current_offset: 0,
+            tick: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Returns inner object.
+    ///
+    /// Dirty sectors are not written back, call [`Write::flush`] before.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Returns number of sector accesses served from the cache.
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// Returns number of sector accesses that required an access to the inner stream.
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    /// Resets hit and miss counters.
+    pub fn reset_stats(&mut self) {
+        self.hits = 0;
+        self.misses = 0;
+    }
+
+    fn find(&self, sector: u64) -> Option<usize> {
+        self.entries.iter().position(|e| e.sector == Some(sector))
+    }
+
+    fn find_dirty(&self, sector: u64) -> Option<usize> {
+        self.find(sector).filter(|&idx| self.entries[idx].dirty)
+    }
+
+    fn touch(&mut self, idx: usize) {
+        self.tick += 1;
+        self.entries[idx].last_used = self.tick;
+    }
+
+    /// Writes back the run of adjacent dirty sectors containing the sector cached in entry `idx`.
+    async fn write_back_run(&mut self, idx: usize) -> Result<(), CacheStreamError<T::Error>> {
+        let Some(mut sector) = self.entries[idx].sector else {
+            return Ok(());
+        };
+        // find the start of the run
+        while sector > 0 && self.find_dirty(sector - 1).is_some() {
+            sector -= 1;
+        }
+        trace!("writing back dirty sectors starting at {}", sector);
+        self.inner
+            .seek(SeekFrom::Start(sector * SIZE as u64))
+            .await?;
+        while let Some(idx) = self.find_dirty(sector) {
+            let entry = &mut self.entries[idx];
+            self.inner.write_all(&entry.data).await?;
+            entry.dirty = false;
+            sector += 1;
+        }
+        Ok(())
+    }
+
+    /// Loads `sector` into the cache evicting the least recently used entry and returns the entry index.
+    async fn load(&mut self, sector: u64) -> Result<usize, CacheStreamError<T::Error>> {
+        let (idx, _) = self
+            .entries
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, e)| if e.sector.is_some() { e.last_used } else { 0 })
+            .expect("cache has no entries");
+        if self.entries[idx].dirty {
+            // dirty sectors must reach the inner stream before they are evicted
+            self.write_back_run(idx).await?;
+        }
+        let entry = &mut self.entries[idx];
+        entry.sector = None;
+        self.inner
+            .seek(SeekFrom::Start(sector * SIZE as u64))
+            .await?;
+        self.inner.read_exact(&mut entry.data).await?;
+        entry.sector = Some(sector);
+        Ok(idx)
+    }
+
+    /// Returns number of sectors starting at `sector` that are not cached (at most `max`).
+    fn uncached_run(&self, sector: u64, max: usize) -> usize {
+        (0..max)
+            .take_while(|&i| self.find(sector + i as u64).is_none())
+            .count()
+    }
+
+    async fn flush(&mut self) -> Result<(), CacheStreamError<T::Error>> {
+        // write back dirty sectors in ascending order so adjacent sectors are coalesced
+        while let Some(idx) = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| e.dirty)
+            .min_by_key(|(_, e)| e.sector)
+            .map(|(idx, _)| idx)
+        {
+            self.write_back_run(idx).await?;
+        }
+        self.inner.flush().await?;
+        Ok(())
+    }
+}
+
+impl<T: Read + Write + Seek, const SIZE: usize, const ENTRIES: usize> embedded_io_async::ErrorType
+    for CacheStream<T, SIZE, ENTRIES>
+{
+    type Error = CacheStreamError<T::Error>;
+}
+
+impl<T: Read + Write + Seek, const SIZE: usize, const ENTRIES: usize> Read
+    for CacheStream<T, SIZE, ENTRIES>
+{
+    async fn read(&mut self, mut buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let mut total = 0;
+        while !buf.is_empty() {
+            let sector = self.current_offset / SIZE as u64;
+            let offset_in_sector = (self.current_offset % SIZE as u64) as usize;
+            let uncached = if offset_in_sector == 0 {
+                self.uncached_run(sector, buf.len() / SIZE)
+            } else {
+                0
+            };
+            let bytes_read = if uncached > 0 {
+                // whole sectors not present in the cache are read directly into the buffer
+                self.misses += uncached as u64;
+                let len = uncached * SIZE;
+                self.inner
+                    .seek(SeekFrom::Start(self.current_offset))
+                    .await?;
+                self.inner.read_exact(&mut buf[..len]).await?;
+                len
+            } else {
+                let idx = if let Some(idx) = self.find(sector) {
+                    self.hits += 1;
+                    idx
+                } else {
+                    self.misses += 1;
+                    self.load(sector).await?
+                };
+                self.touch(idx);
+                let len = cmp::min(SIZE - offset_in_sector, buf.len());
+                buf[..len].copy_from_slice(
+                    &self.entries[idx].data[offset_in_sector..offset_in_sector + len],
+                );
+                len
+            };
+            buf = &mut buf[bytes_read..];
+            self.current_offset += bytes_read as u64;
+            total += bytes_read;
+        }
+        Ok(total)
+    }
+}
+
+impl<T: Read + Write + Seek, const SIZE: usize, const ENTRIES: usize> Write
+    for CacheStream<T, SIZE, ENTRIES>
+{
+    async fn write(&mut self, mut buf: &[u8]) -> Result<usize, Self::Error> {
+        let mut total = 0;
+        while !buf.is_empty() {
+            let sector = self.current_offset / SIZE as u64;
+            let offset_in_sector = (self.current_offset % SIZE as u64) as usize;
+            let uncached = if offset_in_sector == 0 {
+                self.uncached_run(sector, buf.len() / SIZE)
+            } else {
+                0
+            };
+            let bytes_written = if uncached > 0 {
+                // whole sectors not present in the cache are written directly
+                self.misses += uncached as u64;
+                let len = uncached * SIZE;
+                self.inner
+                    .seek(SeekFrom::Start(self.current_offset))
+                    .await?;
+                self.inner.write_all(&buf[..len]).await?;
+                len
+            } else {
+                let idx = if let Some(idx) = self.find(sector) {
+                    self.hits += 1;
+                    idx
+                } else {
+                    self.misses += 1;
+                    self.load(sector).await?
+                };
+                self.touch(idx);
+                let len = cmp::min(SIZE - offset_in_sector, buf.len());
+                let entry = &mut self.entries[idx];
+                entry.data[offset_in_sector..offset_in_sector + len].copy_from_slice(&buf[..len]);
+                entry.dirty = true;
+                len
+            };
+            buf = &buf[bytes_written..];
+            self.current_offset += bytes_written as u64;
+            total += bytes_written;
+        }
+        Ok(total)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.flush().await
+    }
+}
+
+impl<T: Read + Write + Seek, const SIZE: usize, const ENTRIES: usize> Seek
+    for CacheStream<T, SIZE, ENTRIES>
+{
+    async fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+        self.current_offset = match pos {
+            SeekFrom::Start(x) => x,
+            SeekFrom::End(x) => self.inner.seek(SeekFrom::End(x)).await?,
+            SeekFrom::Current(x) => (self.current_offset as i64 + x) as u64,
+        };
+        Ok(self.current_offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_io_adapters::tokio_1::FromTokio;
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// Stream counting seeks and writes reaching the underlying buffer.
+    struct CountingStream {
+        inner: FromTokio<Cursor<Vec<u8>>>,
+        seeks: usize,
+        writes: usize,
+    }
+
+    impl CountingStream {
+        fn new(buf: Vec<u8>) -> Self {
+            Self {
+                inner: FromTokio::new(Cursor::new(buf)),
+                seeks: 0,
+                writes: 0,
+            }
+        }
+
+        fn data(&self) -> &[u8] {
+            self.inner.inner().get_ref()
+        }
+    }
+
+    impl embedded_io_async::ErrorType for CountingStream {
+        type Error = std::io::Error;
+    }
+
+    impl Read for CountingStream {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            self.inner.read(buf).await
+        }
+    }
+
+    impl Write for CountingStream {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.writes += 1;
+            self.inner.write(buf).await
+        }
+
+        async fn flush(&mut self) -> Result<(), Self::Error> {
+            self.inner.flush().await
+        }
+    }
+
+    impl Seek for CountingStream {
+        async fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+            self.seeks += 1;
+            self.inner.seek(pos).await
+        }
+    }
+
+    #[tokio::test]
+    async fn read_hits_and_misses() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let buf = ("A".repeat(512) + "B".repeat(512).as_str()).into_bytes();
+        let mut cache: CacheStream<_, 512, 2> = CacheStream::new(CountingStream::new(buf));
+
+        let mut buf = [0; 16];
+        cache.seek(SeekFrom::Start(8)).await.unwrap();
+        cache.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, "A".repeat(16).as_bytes());
+        assert_eq!((cache.hits(), cache.misses()), (0, 1));
+
+        cache.read_exact(&mut buf).await.unwrap();
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+
+        // read across sectors
+        cache.seek(SeekFrom::Start(512 - 8)).await.unwrap();
+        cache.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, ("A".repeat(8) + "B".repeat(8).as_str()).as_bytes());
+        assert_eq!((cache.hits(), cache.misses()), (2, 2));
+
+        cache.reset_stats();
+        assert_eq!((cache.hits(), cache.misses()), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn write_back_on_flush() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let mut cache: CacheStream<_, 512, 4> =
+            CacheStream::new(CountingStream::new(vec![0; 2048]));
+
+        cache.seek(SeekFrom::Start(100)).await.unwrap();
+        cache.write_all(&[1; 4]).await.unwrap();
+        // nothing reaches the inner stream before flush
+        assert_eq!(cache.inner.writes, 0);
+        assert_eq!(&cache.inner.data()[100..104], [0; 4]);
+
+        // data is read back from the cache
+        let mut buf = [0; 4];
+        cache.seek(SeekFrom::Start(100)).await.unwrap();
+        cache.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, [1; 4]);
+
+        cache.flush().await.unwrap();
+        assert_eq!(&cache.inner.data()[100..104], [1; 4]);
+        // flushing again writes nothing
+        let writes = cache.inner.writes;
+        cache.flush().await.unwrap();
+        assert_eq!(cache.inner.writes, writes);
+    }
+
+    #[tokio::test]
+    async fn adjacent_dirty_sectors_are_coalesced() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let mut cache: CacheStream<_, 512, 4> =
+            CacheStream::new(CountingStream::new(vec![0; 4096]));
+
+        // dirty sectors 1, 3 and 2 - written in non-sequential order
+        for sector in [1, 3, 2] {
+            cache
+                .seek(SeekFrom::Start(sector * 512 + 10))
+                .await
+                .unwrap();
+            cache.write_all(&[sector as u8; 10]).await.unwrap();
+        }
+        let seeks = cache.inner.seeks;
+        cache.flush().await.unwrap();
+        // a single seek for the whole run of sectors
+        assert_eq!(cache.inner.seeks, seeks + 1);
+        for sector in 1..4 {
+            let start = sector * 512 + 10;
+            assert_eq!(&cache.inner.data()[start..start + 10], [sector as u8; 10]);
+        }
+    }
+
+    #[tokio::test]
+    async fn dirty_sector_written_before_eviction() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let mut cache: CacheStream<_, 512, 2> =
+            CacheStream::new(CountingStream::new(vec![0; 4096]));
+
+        cache.seek(SeekFrom::Start(0)).await.unwrap();
+        cache.write_all(&[1; 8]).await.unwrap();
+        let mut buf = [0; 8];
+        // fill the cache with other sectors, sector 0 is the least recently used one
+        for sector in [4, 6] {
+            cache.seek(SeekFrom::Start(sector * 512)).await.unwrap();
+            cache.read_exact(&mut buf).await.unwrap();
+        }
+        assert_eq!(&cache.inner.data()[..8], [1; 8]);
+        assert_eq!(cache.find(0), None);
+
+        // the evicted sector is read back with the modification
+        cache.seek(SeekFrom::Start(0)).await.unwrap();
+        cache.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, [1; 8]);
+    }
+
+    #[tokio::test]
+    async fn whole_sectors_bypass_cache() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let mut cache: CacheStream<_, 512, 2> =
+            CacheStream::new(CountingStream::new(vec![0; 4096]));
+
+        // cached sector in the middle of a large write is updated in the cache
+        cache.seek(SeekFrom::Start(1024)).await.unwrap();
+        cache.write_all(&[1; 8]).await.unwrap();
+        cache.seek(SeekFrom::Start(0)).await.unwrap();
+        cache.write_all(&[2; 2048]).await.unwrap();
+        assert_eq!(cache.find(0), None);
+        assert_eq!(&cache.inner.data()[..1024], [2; 1024]);
+        // the cached sector is only updated in the cache
+        assert_eq!(&cache.inner.data()[1024..1032], [0; 8]);
+        assert_eq!(&cache.inner.data()[1536..2048], [2; 512]);
+
+        let mut buf = vec![0; 2048];
+        cache.seek(SeekFrom::Start(0)).await.unwrap();
+        cache.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, [2; 2048]);
+
+        cache.flush().await.unwrap();
+        assert_eq!(&cache.into_inner().data()[..2048], [2; 2048]);
+    }
+}
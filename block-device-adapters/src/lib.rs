@@ -6,7 +6,9 @@
 mod fmt;
 
 mod buf_stream;
+mod cache_stream;
 mod stream_slice;
 
 pub use buf_stream::{BufStream, BufStreamError};
+pub use cache_stream::{CacheStream, CacheStreamError};
 pub use stream_slice::{StreamSlice, StreamSliceError};
@@ -278,7 +278,7 @@ impl BiosParameterBlock {
         Ok(())
     }
 
-    fn validate_total_clusters<E: IoError>(&self) -> Result<(), Error<E>> {
+    fn validate_total_clusters<E: IoError>(&self, allow_small_fat: bool) -> Result<(), Error<E>> {
         let is_fat32 = self.is_fat32();
         let total_clusters = self.total_clusters();
         let fat_type = FatType::from_clusters(total_clusters);
@@ -286,15 +286,24 @@ impl BiosParameterBlock {
             error!("Invalid BPB: result of FAT32 determination from total number of clusters and sectors_per_fat_16 field differs");
             return Err(Error::CorruptedFileSystem);
         }
-        if fat_type == FatType::Fat32 && total_clusters > 0x0FFF_FFFF {
+        if total_clusters > fat_type.max_clusters() {
             error!("Invalid BPB: too many clusters {}", total_clusters);
             return Err(Error::CorruptedFileSystem);
         }
 
         let bits_per_fat_entry = fat_type.bits_per_fat_entry();
-        let total_fat_entries = self.sectors_per_fat() * u32::from(self.bytes_per_sector) * 8 / bits_per_fat_entry;
-        let usable_fat_entries = total_fat_entries - RESERVED_FAT_ENTRIES;
-        if usable_fat_entries < total_clusters {
+        // 64 bit arithmetic: sectors_per_fat_32 comes straight from the BPB and can be arbitrarily large
+        let total_fat_entries =
+            u64::from(self.sectors_per_fat()) * u64::from(self.bytes_per_sector) * 8 / u64::from(bits_per_fat_entry);
+        let usable_fat_entries = total_fat_entries.saturating_sub(u64::from(RESERVED_FAT_ENTRIES));
+        if usable_fat_entries < u64::from(total_clusters) {
+            if !allow_small_fat {
+                error!(
+                    "Invalid BPB: FAT is too small (allows allocation of {} clusters) compared to the total number of clusters ({})",
+                    usable_fat_entries, total_clusters
+                );
+                return Err(Error::CorruptedFileSystem);
+            }
             warn!(
                 "FAT is too small (allows allocation of {} clusters) compared to the total number of clusters ({})",
                 usable_fat_entries, total_clusters
@@ -303,7 +312,7 @@ impl BiosParameterBlock {
         Ok(())
     }
 
-    fn validate<E: IoError>(&self) -> Result<(), Error<E>> {
+    fn validate<E: IoError>(&self, allow_small_fat: bool) -> Result<(), Error<E>> {
         if self.fs_version != 0 {
             error!("Unsupported filesystem version: expected 0 but got {}", self.fs_version);
             return Err(Error::CorruptedFileSystem);
@@ -315,7 +324,7 @@ impl BiosParameterBlock {
         self.validate_root_entries()?;
         self.validate_total_sectors()?;
         self.validate_sectors_per_fat()?;
-        self.validate_total_clusters()?;
+        self.validate_total_clusters(allow_small_fat)?;
         Ok(())
     }
 
@@ -369,19 +378,23 @@ impl BiosParameterBlock {
     }
 
     pub(crate) fn sectors_per_all_fats(&self) -> u32 {
-        u32::from(self.fats) * self.sectors_per_fat()
+        // Saturates on a corrupted BPB; validation then rejects it because the data region starts after the volume end
+        u32::from(self.fats).saturating_mul(self.sectors_per_fat())
     }
 
     pub(crate) fn first_data_sector(&self) -> u32 {
         let root_dir_sectors = self.root_dir_sectors();
         let fat_sectors = self.sectors_per_all_fats();
-        self.reserved_sectors() + fat_sectors + root_dir_sectors
+        self.reserved_sectors()
+            .saturating_add(fat_sectors)
+            .saturating_add(root_dir_sectors)
     }
 
     pub(crate) fn total_clusters(&self) -> u32 {
         let total_sectors = self.total_sectors();
         let first_data_sector = self.first_data_sector();
-        let data_sectors = total_sectors - first_data_sector;
+        // Zero if the BPB places the data region after the end of the volume (rejected by validation)
+        let data_sectors = total_sectors.saturating_sub(first_data_sector);
         data_sectors / u32::from(self.sectors_per_cluster)
     }
 
@@ -415,7 +428,7 @@ impl BiosParameterBlock {
 
 pub(crate) struct BootSector {
     bootjmp: [u8; 3],
-    oem_name: [u8; 8],
+    pub(crate) oem_name: [u8; 8],
     pub(crate) bpb: BiosParameterBlock,
     boot_code: [u8; 448],
     boot_sig: [u8; 2],
@@ -452,7 +465,7 @@ impl BootSector {
         Ok(())
     }
 
-    pub(crate) fn validate<E: IoError>(&self) -> Result<(), Error<E>> {
+    pub(crate) fn validate<E: IoError>(&self, allow_small_fat: bool) -> Result<(), Error<E>> {
         if self.boot_sig != [0x55, 0xAA] {
             error!(
                 "Invalid boot sector signature: expected [0x55, 0xAA] but got {:?}",
@@ -463,7 +476,7 @@ impl BootSector {
         if self.bootjmp[0] != 0xEB && self.bootjmp[0] != 0xE9 {
             warn!("Unknown opcode {:x} in bootjmp boot sector field", self.bootjmp[0]);
         }
-        self.bpb.validate()?;
+        self.bpb.validate(allow_small_fat)?;
         Ok(())
     }
 }
@@ -491,9 +504,10 @@ pub(crate) fn estimate_fat_type(total_bytes: u64) -> FatType {
     }
 }
 
-fn determine_bytes_per_cluster(total_bytes: u64, bytes_per_sector: u16, fat_type: Option<FatType>) -> u32 {
-    const MAX_CLUSTER_SIZE: u32 = 32 * KB_32;
+// maximal cluster size selected automatically or suggested when formatting
+const MAX_CLUSTER_SIZE: u32 = 32 * KB_32;
 
+fn determine_bytes_per_cluster(total_bytes: u64, bytes_per_sector: u16, fat_type: Option<FatType>) -> u32 {
     let fat_type = fat_type.unwrap_or_else(|| estimate_fat_type(total_bytes));
     let bytes_per_cluster = match fat_type {
         FatType::Fat12 => (total_bytes.next_power_of_two() / MB_64 * 512) as u32,
@@ -580,6 +594,10 @@ fn determine_sectors_per_fat(
     sectors_per_fat as u32
 }
 
+// FSInfo sector and backup boot sector locations used when formatting FAT32 volumes
+const FAT32_FS_INFO_SECTOR: u16 = 1;
+const FAT32_BACKUP_BOOT_SECTOR: u16 = 6;
+
 fn try_fs_geometry(
     total_sectors: u32,
     bytes_per_sector: u16,
@@ -587,11 +605,16 @@ fn try_fs_geometry(
     fat_type: FatType,
     root_dir_sectors: u32,
     fats: u8,
+    reserved_sectors: Option<u16>,
 ) -> Result<(u16, u32), Error<()>> {
     // Note: most of implementations use 32 reserved sectors for FAT32 but it's wasting of space
-    // This implementation uses only 8. This is enough to fit in two boot sectors (main and backup) with additional
-    // bootstrap code and one FSInfo sector. It also makes FAT alligned to 4096 which is a nice number.
-    let reserved_sectors: u16 = if fat_type == FatType::Fat32 { 8 } else { 1 };
+    // This implementation uses only 8 by default. This is enough to fit in two boot sectors (main and backup) with
+    // additional bootstrap code and one FSInfo sector. It also makes FAT alligned to 4096 which is a nice number.
+    let reserved_sectors: u16 = reserved_sectors.unwrap_or(if fat_type == FatType::Fat32 { 8 } else { 1 });
+    if fat_type == FatType::Fat32 && reserved_sectors <= FAT32_BACKUP_BOOT_SECTOR {
+        error!("Too few reserved sectors for FAT32");
+        return Err(Error::InvalidInput);
+    }
 
     // Check if volume has enough space to accomodate reserved sectors, FAT, root directory and some data space
     // Having less than 8 sectors for FAT and data would make a little sense
@@ -614,15 +637,13 @@ fn try_fs_geometry(
     let data_sectors =
         total_sectors - u32::from(reserved_sectors) - root_dir_sectors - sectors_per_fat * u32::from(fats);
     let total_clusters = data_sectors / u32::from(sectors_per_cluster);
-    if fat_type != FatType::from_clusters(total_clusters) {
-        error!("Invalid FAT type");
-        return Err(Error::InvalidInput);
-    }
-    debug_assert!(total_clusters >= fat_type.min_clusters());
-    if total_clusters > fat_type.max_clusters() {
-        // Note: it can happen for FAT32
-        error!("Too many clusters");
-        return Err(Error::InvalidInput);
+    // Note: too many clusters can happen for FAT32
+    if fat_type != FatType::from_clusters(total_clusters) || total_clusters > fat_type.max_clusters() {
+        return Err(Error::FatTypeMismatch {
+            fat_type,
+            clusters: total_clusters,
+            suggested_cluster_size: None,
+        });
     }
 
     Ok((reserved_sectors, sectors_per_fat))
@@ -643,6 +664,7 @@ fn determine_fs_geometry<E: IoError>(
     sectors_per_cluster: u8,
     root_dir_entries: u16,
     fats: u8,
+    reserved_sectors: Option<u16>,
 ) -> Result<(FatType, u16, u32), Error<E>> {
     for &fat_type in &[FatType::Fat32, FatType::Fat16, FatType::Fat12] {
         let root_dir_sectors = determine_root_dir_sectors(root_dir_entries, bytes_per_sector, fat_type);
@@ -653,6 +675,7 @@ fn determine_fs_geometry<E: IoError>(
             fat_type,
             root_dir_sectors,
             fats,
+            reserved_sectors,
         );
         if let Ok((reserved_sectors, sectors_per_fat)) = result {
             return Ok((fat_type, reserved_sectors, sectors_per_fat));
@@ -663,29 +686,234 @@ fn determine_fs_geometry<E: IoError>(
     Err(Error::InvalidInput)
 }
 
-fn format_bpb<E: IoError>(
+// Finds the smallest cluster size (as a number of sectors) allowing a volume to be formatted with the given FAT type
+fn suggest_sectors_per_cluster(
+    total_sectors: u32,
+    bytes_per_sector: u16,
+    root_dir_entries: u16,
+    fats: u8,
+    reserved_sectors: Option<u16>,
+    fat_type: FatType,
+) -> Option<u8> {
+    let root_dir_sectors = determine_root_dir_sectors(root_dir_entries, bytes_per_sector, fat_type);
+    (0..8).map(|shift| 1_u8 << shift).find(|&sectors_per_cluster| {
+        u32::from(sectors_per_cluster) * u32::from(bytes_per_sector) <= MAX_CLUSTER_SIZE
+            && try_fs_geometry(
+                total_sectors,
+                bytes_per_sector,
+                sectors_per_cluster,
+                fat_type,
+                root_dir_sectors,
+                fats,
+                reserved_sectors,
+            )
+            .is_ok()
+    })
+}
+
+// Checks if the number of clusters fits the requested FAT type before the whole BPB is built and suggests a cluster
+// size if it does not
+fn check_requested_fat_type<E: IoError>(
+    total_sectors: u32,
+    bytes_per_sector: u16,
+    sectors_per_cluster: u8,
+    root_dir_entries: u16,
+    fats: u8,
+    reserved_sectors: Option<u16>,
+    fat_type: FatType,
+) -> Result<(), Error<E>> {
+    let root_dir_sectors = determine_root_dir_sectors(root_dir_entries, bytes_per_sector, fat_type);
+    let geometry = try_fs_geometry(
+        total_sectors,
+        bytes_per_sector,
+        sectors_per_cluster,
+        fat_type,
+        root_dir_sectors,
+        fats,
+        reserved_sectors,
+    );
+    // other problems than the number of clusters are reported when the BPB is built
+    let Err(Error::FatTypeMismatch { clusters, .. }) = geometry else {
+        return Ok(());
+    };
+    error!(
+        "{:?} requires between {} and {} clusters but the volume has {} clusters of {} bytes",
+        fat_type,
+        fat_type.min_clusters(),
+        fat_type.max_clusters(),
+        clusters,
+        u32::from(sectors_per_cluster) * u32::from(bytes_per_sector)
+    );
+    let suggested_cluster_size = suggest_sectors_per_cluster(
+        total_sectors,
+        bytes_per_sector,
+        root_dir_entries,
+        fats,
+        reserved_sectors,
+        fat_type,
+    )
+    .map(|suggested| u32::from(suggested) * u32::from(bytes_per_sector));
+    Err(Error::FatTypeMismatch {
+        fat_type,
+        clusters,
+        suggested_cluster_size,
+    })
+}
+
+// Geometry of a standard floppy disk format with 512 byte sectors, two heads and two FATs
+struct FloppyFormat {
+    total_sectors: u32,
+    sectors_per_cluster: u8,
+    root_dir_entries: u16,
+    media: u8,
+    sectors_per_track: u16,
+}
+
+// The same values are used by DOS and `mkfs.fat`
+const FLOPPY_FORMATS: [FloppyFormat; 5] = [
+    // 360 KB 5.25"
+    FloppyFormat {
+        total_sectors: 720,
+        sectors_per_cluster: 2,
+        root_dir_entries: 112,
+        media: 0xFD,
+        sectors_per_track: 9,
+    },
+    // 720 KB 3.5"
+    FloppyFormat {
+        total_sectors: 1440,
+        sectors_per_cluster: 2,
+        root_dir_entries: 112,
+        media: 0xF9,
+        sectors_per_track: 9,
+    },
+    // 1.2 MB 5.25"
+    FloppyFormat {
+        total_sectors: 2400,
+        sectors_per_cluster: 1,
+        root_dir_entries: 224,
+        media: 0xF9,
+        sectors_per_track: 15,
+    },
+    // 1.44 MB 3.5"
+    FloppyFormat {
+        total_sectors: 2880,
+        sectors_per_cluster: 1,
+        root_dir_entries: 224,
+        media: 0xF0,
+        sectors_per_track: 18,
+    },
+    // 2.88 MB 3.5"
+    FloppyFormat {
+        total_sectors: 5760,
+        sectors_per_cluster: 2,
+        root_dir_entries: 240,
+        media: 0xF0,
+        sectors_per_track: 36,
+    },
+];
+
+// Returns the floppy disk format of the volume unless the options select another FAT type or FAT count
+fn floppy_format(
     options: &FormatVolumeOptions,
     total_sectors: u32,
     bytes_per_sector: u16,
-) -> Result<(BiosParameterBlock, FatType), Error<E>> {
-    let bytes_per_cluster = options.bytes_per_cluster.unwrap_or_else(|| {
-        let total_bytes = u64::from(total_sectors) * u64::from(bytes_per_sector);
-        determine_bytes_per_cluster(total_bytes, bytes_per_sector, options.fat_type)
-    });
+) -> Option<&'static FloppyFormat> {
+    if bytes_per_sector != 512
+        || options.fat_type.is_some_and(|t| t != FatType::Fat12)
+        || options.fats.is_some_and(|n| n != 2)
+    {
+        return None;
+    }
+    FLOPPY_FORMATS.iter().find(|f| f.total_sectors == total_sectors)
+}
 
-    let sectors_per_cluster = bytes_per_cluster / u32::from(bytes_per_sector);
-    assert!(sectors_per_cluster <= u32::from(u8::MAX));
-    let sectors_per_cluster = sectors_per_cluster as u8;
+// BPB values taken from the options or, if not set there, from the floppy disk format or the volume size
+struct FormatDefaults {
+    sectors_per_cluster: u8,
+    root_dir_entries: u16,
+    media: u8,
+    sectors_per_track: u16,
+    heads: u16,
+}
+
+fn resolve_format_defaults<E: IoError>(
+    options: &FormatVolumeOptions,
+    total_sectors: u32,
+    bytes_per_sector: u16,
+) -> Result<FormatDefaults, Error<E>> {
+    let floppy = floppy_format(options, total_sectors, bytes_per_sector);
+    let sectors_per_cluster = if let Some(sectors_per_cluster) = options.sectors_per_cluster {
+        let bytes_per_cluster = u32::from(sectors_per_cluster) * u32::from(bytes_per_sector);
+        if options.bytes_per_cluster.is_some_and(|n| n != bytes_per_cluster) {
+            error!(
+                "bytes_per_cluster {:?} does not match sectors_per_cluster {} with {} bytes per sector",
+                options.bytes_per_cluster, sectors_per_cluster, bytes_per_sector
+            );
+            return Err(Error::InvalidInput);
+        }
+        sectors_per_cluster
+    } else {
+        let bytes_per_cluster = options.bytes_per_cluster.unwrap_or_else(|| {
+            if let Some(floppy) = floppy {
+                return u32::from(floppy.sectors_per_cluster) * u32::from(bytes_per_sector);
+            }
+            let total_bytes = u64::from(total_sectors) * u64::from(bytes_per_sector);
+            determine_bytes_per_cluster(total_bytes, bytes_per_sector, options.fat_type)
+        });
+        let sectors_per_cluster = bytes_per_cluster / u32::from(bytes_per_sector);
+        assert!(sectors_per_cluster <= u32::from(u8::MAX));
+        sectors_per_cluster as u8
+    };
+    Ok(FormatDefaults {
+        sectors_per_cluster,
+        root_dir_entries: options
+            .max_root_dir_entries
+            .unwrap_or(floppy.map_or(512, |f| f.root_dir_entries)),
+        media: options.media.unwrap_or(floppy.map_or(0xF8, |f| f.media)),
+        sectors_per_track: options
+            .sectors_per_track
+            .unwrap_or(floppy.map_or(0x20, |f| f.sectors_per_track)),
+        heads: options.heads.unwrap_or(if floppy.is_some() { 2 } else { 0x40 }),
+    })
+}
 
+fn format_bpb<E: IoError>(
+    options: &FormatVolumeOptions,
+    total_sectors: u32,
+    bytes_per_sector: u16,
+) -> Result<(BiosParameterBlock, FatType), Error<E>> {
+    let FormatDefaults {
+        sectors_per_cluster,
+        root_dir_entries,
+        media,
+        sectors_per_track,
+        heads,
+    } = resolve_format_defaults(options, total_sectors, bytes_per_sector)?;
     let fats = options.fats.unwrap_or(2_u8);
-    let root_dir_entries = options.max_root_dir_entries.unwrap_or(512);
+    if let Some(fat_type) = options.fat_type {
+        check_requested_fat_type(
+            total_sectors,
+            bytes_per_sector,
+            sectors_per_cluster,
+            root_dir_entries,
+            fats,
+            options.reserved_sectors,
+            fat_type,
+        )?;
+    }
     let (fat_type, reserved_sectors, sectors_per_fat) = determine_fs_geometry(
         total_sectors,
         bytes_per_sector,
         sectors_per_cluster,
         root_dir_entries,
         fats,
+        options.reserved_sectors,
     )?;
+    if options.fat_type.is_some_and(|t| t != fat_type) {
+        error!("Volume size does not fit requested FAT type");
+        return Err(Error::InvalidInput);
+    }
 
     // drive_num should be 0 for floppy disks and 0x80 for hard disks - determine it using FAT type
     let drive_num = options
@@ -704,13 +932,7 @@ fn format_bpb<E: IoError>(
     }
 
     // setup fs_type_label field
-    let mut fs_type_label = [0_u8; 8];
-    let fs_type_label_str = match fat_type {
-        FatType::Fat12 => b"FAT12   ",
-        FatType::Fat16 => b"FAT16   ",
-        FatType::Fat32 => b"FAT32   ",
-    };
-    fs_type_label.copy_from_slice(fs_type_label_str);
+    let fs_type_label = fs_type_label(fat_type);
 
     // create Bios Parameter Block struct
     let is_fat32 = fat_type == FatType::Fat32;
@@ -731,19 +953,19 @@ fn format_bpb<E: IoError>(
         } else {
             0
         },
-        media: options.media.unwrap_or(0xF8),
+        media,
         sectors_per_fat_16,
-        sectors_per_track: options.sectors_per_track.unwrap_or(0x20),
-        heads: options.heads.unwrap_or(0x40),
-        hidden_sectors: 0,
+        sectors_per_track,
+        heads,
+        hidden_sectors: options.hidden_sectors.unwrap_or(0),
         total_sectors_32: if total_sectors >= 0x10000 { total_sectors } else { 0 },
         // FAT32 fields start
         sectors_per_fat_32: if is_fat32 { sectors_per_fat } else { 0 },
         extended_flags: 0, // mirroring enabled
         fs_version: 0,
         root_dir_first_cluster: if is_fat32 { 2 } else { 0 },
-        fs_info_sector: if is_fat32 { 1 } else { 0 },
-        backup_boot_sector: if is_fat32 { 6 } else { 0 },
+        fs_info_sector: if is_fat32 { FAT32_FS_INFO_SECTOR } else { 0 },
+        backup_boot_sector: if is_fat32 { FAT32_BACKUP_BOOT_SECTOR } else { 0 },
         reserved_0,
         // FAT32 fields end
         drive_num,
@@ -763,6 +985,15 @@ fn format_bpb<E: IoError>(
     Ok((bpb, fat_type))
 }
 
+/// Returns the informational `fs_type_label` BPB field matching the FAT type.
+pub(crate) fn fs_type_label(fat_type: FatType) -> [u8; 8] {
+    match fat_type {
+        FatType::Fat12 => *b"FAT12   ",
+        FatType::Fat16 => *b"FAT16   ",
+        FatType::Fat32 => *b"FAT32   ",
+    }
+}
+
 pub(crate) fn format_boot_sector<E: IoError>(
     options: &FormatVolumeOptions,
     total_sectors: u32,
@@ -771,32 +1002,38 @@ pub(crate) fn format_boot_sector<E: IoError>(
     let mut boot = BootSector::default();
     let (bpb, fat_type) = format_bpb(options, total_sectors, bytes_per_sector)?;
     boot.bpb = bpb;
-    boot.oem_name.copy_from_slice(b"MSWIN4.1");
-    // Boot code copied from FAT32 boot sector initialized by mkfs.fat
-    boot.bootjmp = [0xEB, 0x58, 0x90];
-    let boot_code: [u8; 129] = [
-        0x0E, 0x1F, 0xBE, 0x77, 0x7C, 0xAC, 0x22, 0xC0, 0x74, 0x0B, 0x56, 0xB4, 0x0E, 0xBB, 0x07, 0x00, 0xCD, 0x10,
-        0x5E, 0xEB, 0xF0, 0x32, 0xE4, 0xCD, 0x16, 0xCD, 0x19, 0xEB, 0xFE, 0x54, 0x68, 0x69, 0x73, 0x20, 0x69, 0x73,
-        0x20, 0x6E, 0x6F, 0x74, 0x20, 0x61, 0x20, 0x62, 0x6F, 0x6F, 0x74, 0x61, 0x62, 0x6C, 0x65, 0x20, 0x64, 0x69,
-        0x73, 0x6B, 0x2E, 0x20, 0x20, 0x50, 0x6C, 0x65, 0x61, 0x73, 0x65, 0x20, 0x69, 0x6E, 0x73, 0x65, 0x72, 0x74,
-        0x20, 0x61, 0x20, 0x62, 0x6F, 0x6F, 0x74, 0x61, 0x62, 0x6C, 0x65, 0x20, 0x66, 0x6C, 0x6F, 0x70, 0x70, 0x79,
-        0x20, 0x61, 0x6E, 0x64, 0x0D, 0x0A, 0x70, 0x72, 0x65, 0x73, 0x73, 0x20, 0x61, 0x6E, 0x79, 0x20, 0x6B, 0x65,
-        0x79, 0x20, 0x74, 0x6F, 0x20, 0x74, 0x72, 0x79, 0x20, 0x61, 0x67, 0x61, 0x69, 0x6E, 0x20, 0x2E, 0x2E, 0x2E,
-        0x20, 0x0D, 0x0A,
-    ];
-    boot.boot_code[..boot_code.len()].copy_from_slice(&boot_code);
+    boot.oem_name = options.oem_name.unwrap_or(*b"MSWIN4.1");
+    // offset of boot code - FAT32 BPB is longer so the boot code starts later
+    let boot_code_offset: u8 = if fat_type == FatType::Fat32 { 0x5A } else { 0x36 + 8 };
+    boot.bootjmp = options.bootjmp.unwrap_or([0xEB, boot_code_offset - 2, 0x90]);
     boot.boot_sig = [0x55, 0xAA];
-
-    // fix offsets in bootjmp and boot code for non-FAT32 filesystems (bootcode is on a different offset)
-    if fat_type != FatType::Fat32 {
-        // offset of boot code
-        const BOOT_CODE_OFFSET: u8 = 0x36 + 8;
-        // offset of message
-        const MESSAGE_OFFSET: u16 = 29;
-        boot.bootjmp[1] = BOOT_CODE_OFFSET - 2;
-        let message_offset_in_sector = u16::from(BOOT_CODE_OFFSET) + MESSAGE_OFFSET + 0x7c00;
-        boot.boot_code[3] = (message_offset_in_sector & 0xff) as u8;
-        boot.boot_code[4] = (message_offset_in_sector >> 8) as u8;
+    if let Some((boot_code, len)) = options.boot_code {
+        let max_len = if fat_type == FatType::Fat32 { 420 } else { 448 };
+        if len > max_len {
+            error!("boot code of {} bytes does not fit in {} bytes", len, max_len);
+            return Err(Error::InvalidInput);
+        }
+        boot.boot_code = boot_code;
+    } else {
+        // Boot code copied from FAT32 boot sector initialized by mkfs.fat
+        let boot_code: [u8; 129] = [
+            0x0E, 0x1F, 0xBE, 0x77, 0x7C, 0xAC, 0x22, 0xC0, 0x74, 0x0B, 0x56, 0xB4, 0x0E, 0xBB, 0x07, 0x00, 0xCD, 0x10,
+            0x5E, 0xEB, 0xF0, 0x32, 0xE4, 0xCD, 0x16, 0xCD, 0x19, 0xEB, 0xFE, 0x54, 0x68, 0x69, 0x73, 0x20, 0x69, 0x73,
+            0x20, 0x6E, 0x6F, 0x74, 0x20, 0x61, 0x20, 0x62, 0x6F, 0x6F, 0x74, 0x61, 0x62, 0x6C, 0x65, 0x20, 0x64, 0x69,
+            0x73, 0x6B, 0x2E, 0x20, 0x20, 0x50, 0x6C, 0x65, 0x61, 0x73, 0x65, 0x20, 0x69, 0x6E, 0x73, 0x65, 0x72, 0x74,
+            0x20, 0x61, 0x20, 0x62, 0x6F, 0x6F, 0x74, 0x61, 0x62, 0x6C, 0x65, 0x20, 0x66, 0x6C, 0x6F, 0x70, 0x70, 0x79,
+            0x20, 0x61, 0x6E, 0x64, 0x0D, 0x0A, 0x70, 0x72, 0x65, 0x73, 0x73, 0x20, 0x61, 0x6E, 0x79, 0x20, 0x6B, 0x65,
+            0x79, 0x20, 0x74, 0x6F, 0x20, 0x74, 0x72, 0x79, 0x20, 0x61, 0x67, 0x61, 0x69, 0x6E, 0x20, 0x2E, 0x2E, 0x2E,
+            0x20, 0x0D, 0x0A,
+        ];
+        boot.boot_code[..boot_code.len()].copy_from_slice(&boot_code);
+        // fix offset of the message for non-FAT32 filesystems (bootcode is on a different offset)
+        if fat_type != FatType::Fat32 {
+            const MESSAGE_OFFSET: u16 = 29;
+            let message_offset_in_sector = u16::from(boot_code_offset) + MESSAGE_OFFSET + 0x7c00;
+            boot.boot_code[3] = (message_offset_in_sector & 0xff) as u8;
+            boot.boot_code[4] = (message_offset_in_sector >> 8) as u8;
+        }
     }
 
     Ok((boot, fat_type))
@@ -990,7 +1227,170 @@ mod tests {
         for total_sectors in total_sectors_vec {
             let (boot, _) = format_boot_sector::<Dummy>(&FormatVolumeOptions::new(), total_sectors, bytes_per_sector)
                 .expect("format_boot_sector");
-            boot.validate::<Dummy>().expect("validate");
+            boot.validate::<Dummy>(false).expect("validate");
+        }
+    }
+
+    #[test]
+    fn test_suggest_sectors_per_cluster() {
+        init();
+        let total_sectors = (64 * MB_64 / 512) as u32;
+        // FAT12 allows at most 4084 clusters so 64 MB volume needs 32 KB clusters
+        assert_eq!(
+            suggest_sectors_per_cluster(total_sectors, 512, 512, 2, None, FatType::Fat12),
+            Some(64)
+        );
+        assert_eq!(
+            suggest_sectors_per_cluster(total_sectors, 512, 512, 2, None, FatType::Fat16),
+            Some(2)
+        );
+        assert_eq!(
+            suggest_sectors_per_cluster(total_sectors, 512, 512, 2, None, FatType::Fat32),
+            Some(1)
+        );
+        // 16 MB volume is too small for FAT32
+        let total_sectors = (16 * MB_64 / 512) as u32;
+        assert_eq!(
+            suggest_sectors_per_cluster(total_sectors, 512, 512, 2, None, FatType::Fat32),
+            None
+        );
+        // FAT12 cannot describe 2 GB volume even with the maximal cluster size
+        let total_sectors = (2048 * MB_64 / 512) as u32;
+        assert_eq!(
+            suggest_sectors_per_cluster(total_sectors, 512, 512, 2, None, FatType::Fat12),
+            None
+        );
+        assert!(matches!(
+            check_requested_fat_type::<core::convert::Infallible>(total_sectors, 512, 1, 512, 2, None, FatType::Fat12),
+            Err(Error::FatTypeMismatch {
+                fat_type: FatType::Fat12,
+                suggested_cluster_size: None,
+                ..
+            })
+        ));
+        assert!(check_requested_fat_type::<core::convert::Infallible>(
+            total_sectors,
+            512,
+            1,
+            512,
+            2,
+            None,
+            FatType::Fat32
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_fat_type_cluster_bounds() {
+        #[derive(Debug)]
+        struct Dummy;
+
+        impl embedded_io_async::ErrorType for Dummy {
+            type Error = Self;
+        }
+
+        impl embedded_io_async::Error for Dummy {
+            fn kind(&self) -> embedded_io_async::ErrorKind {
+                embedded_io_async::ErrorKind::Other
+            }
+        }
+
+        let fat_types = [FatType::Fat12, FatType::Fat16, FatType::Fat32];
+        for fat_type in fat_types {
+            assert_eq!(FatType::from_clusters(fat_type.min_clusters()), fat_type);
+            assert_eq!(FatType::from_clusters(fat_type.max_clusters()), fat_type);
+        }
+        for pair in fat_types.windows(2) {
+            assert_eq!(pair[0].max_clusters() + 1, pair[1].min_clusters());
+        }
+        assert_eq!(FatType::Fat12.bits_per_fat_entry(), 12);
+        assert_eq!(FatType::Fat16.bits_per_fat_entry(), 16);
+        assert_eq!(FatType::Fat32.bits_per_fat_entry(), 32);
+
+        // validation accepts exactly the FAT32 maximum
+        let (boot, _) = format_boot_sector::<Dummy>(&FormatVolumeOptions::new(), 1024 * 1024 * 2, 512).unwrap();
+        let mut bpb = boot.bpb;
+        bpb.sectors_per_cluster = 1;
+        bpb.sectors_per_fat_32 = (FatType::Fat32.max_clusters() + 2).div_ceil(512 / 4) + 1;
+        let max_total_sectors = bpb.first_data_sector() + FatType::Fat32.max_clusters();
+        bpb.total_sectors_32 = max_total_sectors;
+        assert_eq!(bpb.total_clusters(), FatType::Fat32.max_clusters());
+        bpb.validate::<Dummy>(false).unwrap();
+        bpb.total_sectors_32 = max_total_sectors + 1;
+        assert!(matches!(bpb.validate::<Dummy>(false), Err(Error::CorruptedFileSystem)));
+    }
+
+    #[test]
+    fn test_validate_rejects_overflowing_bpb() {
+        #[derive(Debug)]
+        struct Dummy;
+
+        impl embedded_io_async::ErrorType for Dummy {
+            type Error = Self;
+        }
+
+        impl embedded_io_async::Error for Dummy {
+            fn kind(&self) -> embedded_io_async::ErrorKind {
+                embedded_io_async::ErrorKind::Other
+            }
+        }
+
+        init();
+
+        let bytes_per_sector = 512_u16;
+        let (fat16, _) =
+            format_boot_sector::<Dummy>(&FormatVolumeOptions::new(), 64 * 1024 * 2, bytes_per_sector).unwrap();
+        let (fat32, _) =
+            format_boot_sector::<Dummy>(&FormatVolumeOptions::new(), 1024 * 1024 * 2, bytes_per_sector).unwrap();
+        assert!(!fat16.bpb.is_fat32());
+        assert!(fat32.bpb.is_fat32());
+
+        // reserved region larger than the whole volume
+        let mut bpb = fat16.bpb.clone();
+        bpb.reserved_sectors = u16::MAX;
+        bpb.total_sectors_16 = 100;
+        bpb.total_sectors_32 = 0;
+        assert_eq!(bpb.total_clusters(), 0);
+        assert!(matches!(bpb.validate::<Dummy>(false), Err(Error::CorruptedFileSystem)));
+
+        // FAT size that overflows u32 when multiplied by the number of FATs and the sector size
+        let mut bpb = fat32.bpb.clone();
+        bpb.fats = u8::MAX;
+        bpb.sectors_per_fat_32 = u32::MAX;
+        assert_eq!(bpb.first_data_sector(), u32::MAX);
+        assert_eq!(bpb.total_clusters(), 0);
+        assert!(matches!(bpb.validate::<Dummy>(false), Err(Error::CorruptedFileSystem)));
+
+        // randomized fields must never panic (xorshift, fixed seed)
+        let mut state = 0x2545_F491_u32;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        };
+        for base in [&fat16.bpb, &fat32.bpb] {
+            for _ in 0..10_000 {
+                let mut bpb = base.clone();
+                bpb.bytes_per_sector = 1 << (next() % 16);
+                bpb.sectors_per_cluster = next() as u8;
+                bpb.reserved_sectors = next() as u16;
+                bpb.fats = next() as u8;
+                bpb.root_entries = next() as u16;
+                if next() % 2 == 0 {
+                    bpb.total_sectors_16 = next() as u16;
+                    bpb.total_sectors_32 = 0;
+                } else {
+                    bpb.total_sectors_16 = 0;
+                    bpb.total_sectors_32 = next();
+                }
+                if bpb.is_fat32() {
+                    bpb.sectors_per_fat_32 = next();
+                } else {
+                    bpb.sectors_per_fat_16 = (next() as u16).max(1);
+                }
+                let _ = bpb.validate::<Dummy>(false);
+            }
         }
     }
 }
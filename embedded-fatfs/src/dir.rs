@@ -1,19 +1,27 @@
+#[cfg(all(not(feature = "std"), feature = "stream"))]
+use alloc::boxed::Box;
 #[cfg(all(not(feature = "std"), feature = "alloc", feature = "lfn"))]
 use alloc::vec::Vec;
 
 use core::char;
 use core::cmp;
+#[cfg(feature = "stream")]
+use core::future::Future;
 use core::num;
+#[cfg(feature = "stream")]
+use core::pin::Pin;
 use core::str;
+#[cfg(feature = "stream")]
+use core::task::{Context, Poll};
 #[cfg(feature = "lfn")]
 use core::{iter, slice};
 
 use crate::dir_entry::{
-    DirEntry, DirEntryData, DirFileEntryData, DirLfnEntryData, FileAttributes, ShortName, DIR_ENTRY_SIZE,
+    DirEntry, DirEntryData, DirFileEntryData, DirLfnEntryData, FileAttributes, Metadata, ShortName, DIR_ENTRY_SIZE,
 };
+use crate::dir_entry::{DIR_ENTRY_DELETED_FLAG, DIR_ENTRY_REALLY_E5_FLAG, SFN_PADDING, SFN_SIZE};
 #[cfg(feature = "lfn")]
 use crate::dir_entry::{LFN_ENTRY_LAST_FLAG, LFN_PART_LEN};
-use crate::dir_entry::{SFN_PADDING, SFN_SIZE};
 use crate::error::{Error, IoError};
 use crate::file::File;
 use crate::fs::{DiskSlice, FileSystem, FsIoAdapter, OemCpConverter, ReadWriteSeek};
@@ -41,6 +49,29 @@ impl<IO: ReadWriteSeek, TP, OCC> DirRawStream<'_, IO, TP, OCC> {
             DirRawStream::Root(_) => None,
         }
     }
+
+    fn is_root_dir(&self) -> bool {
+        match self {
+            DirRawStream::File(file) => file.is_root_dir(),
+            DirRawStream::Root(_) => true,
+        }
+    }
+
+    async fn set_position(&mut self, position: DirPosition) -> Result<(), Error<IO::Error>> {
+        match self {
+            DirRawStream::File(file) => {
+                if position.cluster.is_none() {
+                    error!("position does not belong to a directory stored in clusters");
+                    return Err(Error::InvalidInput);
+                }
+                file.set_position(position.offset, position.cluster);
+            }
+            DirRawStream::Root(raw) => {
+                raw.seek(SeekFrom::Start(u64::from(position.offset))).await?;
+            }
+        }
+        Ok(())
+    }
 }
 
 // Note: derive cannot be used because of invalid bounds. See: https://github.com/rust-lang/rust/issues/26925
@@ -69,7 +100,7 @@ impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> Read for DirRawStream<'_, IO, TP,
 impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> Write for DirRawStream<'_, IO, TP, OCC> {
     async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
         match self {
-            DirRawStream::File(file) => file.write(buf).await,
+            DirRawStream::File(file) => file.write_dir_data(buf).await,
             DirRawStream::Root(raw) => raw.write(buf).await,
         }
     }
@@ -97,6 +128,11 @@ fn split_path(path: &str) -> (&str, Option<&str>) {
     })
 }
 
+// `.` and `..` name directories which exist implicitly and cannot be created, removed or renamed
+fn is_dot_name(name: &str) -> bool {
+    name == "." || name == ".."
+}
+
 enum DirEntryOrShortName<'a, IO: ReadWriteSeek, TP, OCC> {
     DirEntry(DirEntry<'a, IO, TP, OCC>),
     ShortName([u8; SFN_SIZE]),
@@ -106,6 +142,10 @@ enum DirEntryOrShortName<'a, IO: ReadWriteSeek, TP, OCC> {
 ///
 /// This struct is created by the `open_dir` or `create_dir` methods on `Dir`.
 /// The root directory is returned by the `root_dir` method on `FileSystem`.
+///
+/// Paths passed to the methods are '/' separated. A `.` component refers to the directory itself and `..` to its
+/// parent. `..` in the root directory refers to the root directory, so a path cannot leave the filesystem. Entries
+/// named `.` or `..` cannot be created, removed or renamed.
 pub struct Dir<'a, IO: ReadWriteSeek, TP, OCC> {
     stream: DirRawStream<'a, IO, TP, OCC>,
     fs: &'a FileSystem<IO, TP, OCC>,
@@ -116,11 +156,70 @@ impl<'a, IO: ReadWriteSeek, TP, OCC> Dir<'a, IO, TP, OCC> {
         Dir { stream, fs }
     }
 
+    /// Returns the maximal number of entries the directory can hold.
+    ///
+    /// Only the root directory of a FAT12/FAT16 volume has a fixed size, given by the number of root directory entries
+    /// in the BPB. Other directories grow by allocating new clusters so `None` is returned for them. Long names take
+    /// more than one entry, so fewer files fit in the directory than its capacity.
+    #[must_use]
+    pub fn capacity(&self) -> Option<u32> {
+        match self.stream {
+            DirRawStream::Root(_) => Some(self.fs.root_dir_entries()),
+            DirRawStream::File(_) => None,
+        }
+    }
+
+    /// Returns metadata of the directory.
+    ///
+    /// The metadata comes from the directory entry of this directory. The root directory has no entry, see `Metadata`
+    /// for the values returned for it.
+    #[must_use]
+    pub fn metadata(&self) -> Metadata {
+        match self.stream {
+            DirRawStream::File(ref file) => file.metadata(),
+            DirRawStream::Root(_) => Metadata::root_dir(None),
+        }
+    }
+
     /// Creates directory entries iterator.
     #[must_use]
     #[allow(clippy::iter_not_returning_iterator)]
     pub fn iter(&self) -> DirIter<'a, IO, TP, OCC> {
-        DirIter::new(self.stream.clone(), self.fs, true)
+        DirIter::new(self.stream.clone(), self.fs, true, None)
+    }
+
+    /// Creates an iterator over the files of this directory.
+    ///
+    /// Works like `iter` but directories are skipped before a `DirEntry` is built for them.
+    #[must_use]
+    pub fn files(&self) -> DirIter<'a, IO, TP, OCC> {
+        DirIter::new(self.stream.clone(), self.fs, true, Some(false))
+    }
+
+    /// Creates an iterator over the subdirectories of this directory.
+    ///
+    /// Works like `iter` but files and the `.` and `..` entries are skipped before a `DirEntry` is built for them.
+    #[must_use]
+    pub fn dirs(&self) -> DirIter<'a, IO, TP, OCC> {
+        DirIter::new(self.stream.clone(), self.fs, true, Some(true))
+    }
+
+    /// Creates directory entries iterator starting after the entry a position was taken from.
+    ///
+    /// This allows listing a big directory in parts without keeping an iterator alive. The position is obtained by
+    /// calling `position` on a `DirEntry` returned by an iterator of this directory.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if the position was taken from the FAT12/FAT16 root directory and
+    ///   this is a different directory.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub async fn iter_from(&self, position: DirPosition) -> Result<DirIter<'a, IO, TP, OCC>, Error<IO::Error>> {
+        let mut stream = self.stream.clone();
+        stream.set_position(position).await?;
+        Ok(DirIter::new(stream, self.fs, true, None))
     }
 }
 
@@ -157,7 +256,7 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
 
     #[allow(clippy::type_complexity)]
     pub(crate) async fn find_volume_entry(&self) -> Result<Option<DirEntry<'a, IO, TP, OCC>>, Error<IO::Error>> {
-        let mut iter = DirIter::new(self.stream.clone(), self.fs, false);
+        let mut iter = DirIter::new(self.stream.clone(), self.fs, false, None);
         while let Some(r) = iter.next().await {
             let e = r?;
             if e.data.is_volume() {
@@ -167,12 +266,78 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
         Ok(None)
     }
 
+    // Resolves a path component naming a directory. `.` is this directory and `..` its parent, both stay in the root
+    // directory when used there, so a path cannot leave the filesystem.
+    async fn open_dir_component(&self, name: &str) -> Result<Self, Error<IO::Error>> {
+        if name == "." || (name == ".." && self.stream.is_root_dir()) {
+            return Ok(self.clone());
+        }
+        let dir = self.find_entry(name, Some(true), None).await?.to_dir();
+        // some formatters store the root directory cluster in `..` entries instead of 0 on FAT32
+        let root_dir = self.fs.root_dir();
+        if name == ".." && dir.stream.first_cluster() == root_dir.stream.first_cluster() {
+            return Ok(root_dir);
+        }
+        Ok(dir)
+    }
+
+    // Prepares the name of a new entry before looking for an existing one. Trailing dots and spaces are stripped like
+    // Windows does. Characters not allowed in long names are rejected or replaced by `_` if enabled in `FsOptions`,
+    // the replaced name is stored in `buf`.
+    fn new_entry_name<'n>(
+        &self,
+        name: &'n str,
+        buf: &'n mut [u8; MAX_LONG_NAME_BYTES],
+    ) -> Result<&'n str, Error<IO::Error>> {
+        let name = name.trim_end_matches(['.', ' ']);
+        if !self.fs.options.replace_invalid_name_chars || name.chars().all(is_valid_long_name_char) {
+            validate_long_name(name)?;
+            return Ok(name);
+        }
+        let len = name.len();
+        if len > buf.len() {
+            return Err(Error::InvalidFileNameLength);
+        }
+        // invalid characters are ASCII so replacing them keeps the name valid UTF-8
+        for (dst, &b) in buf.iter_mut().zip(name.as_bytes()) {
+            *dst = if b.is_ascii() && !is_valid_long_name_char(char::from(b)) {
+                b'_'
+            } else {
+                b
+            };
+        }
+        let name = str::from_utf8(&buf[..len]).map_err(|_| Error::InvalidInput)?;
+        validate_long_name(name)?;
+        Ok(name)
+    }
+
+    // Rejects paths exceeding the limits set in `FsOptions` before any directory is read
+    fn validate_path(&self, path: &str) -> Result<(), Error<IO::Error>> {
+        let options = &self.fs.options;
+        let mut depth = 0;
+        for component in path.split('/').filter(|component| !component.is_empty()) {
+            depth += 1;
+            if depth > options.max_path_depth {
+                error!("Path has more than {} components", options.max_path_depth);
+                return Err(Error::InvalidInput);
+            }
+            if component.chars().count() > options.max_path_component_len as usize {
+                error!(
+                    "Path component is longer than {} characters",
+                    options.max_path_component_len
+                );
+                return Err(Error::InvalidInput);
+            }
+        }
+        Ok(())
+    }
+
     async fn check_for_existence(
         &self,
         name: &str,
         is_dir: Option<bool>,
     ) -> Result<DirEntryOrShortName<'a, IO, TP, OCC>, Error<IO::Error>> {
-        let mut short_name_gen = ShortNameGenerator::new(name);
+        let mut short_name_gen = ShortNameGenerator::new(name, &self.fs.options.oem_cp_converter);
         loop {
             // find matching entry
             let r = self.find_entry(name, is_dir, Some(&mut short_name_gen)).await;
@@ -184,6 +349,16 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
                 // directory already exists - return it
                 Ok(e) => return Ok(DirEntryOrShortName::DirEntry(e)),
             };
+            // in 8.3 mode the name must be used as is
+            if self.fs.options.short_names_only {
+                return short_name_gen
+                    .exact_short_name()
+                    .map(DirEntryOrShortName::ShortName)
+                    .ok_or_else(|| {
+                        error!("Not a valid 8.3 name: {}", name);
+                        Error::InvalidInput
+                    });
+            }
             // try to generate short name
             if let Ok(name) = short_name_gen.generate() {
                 return Ok(DirEntryOrShortName::ShortName(name));
@@ -203,10 +378,13 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
     /// Errors that can be returned:
     ///
     /// * `Error::NotFound` will be returned if `path` does not point to any existing directory entry.
-    /// * `Error::InvalidInput` will be returned if `path` points to a file that is not a directory.
+    /// * `Error::InvalidInput` will be returned if `path` points to a file that is not a directory or if the path is
+    ///   deeper or has a longer component than allowed by `FsOptions::max_path_depth` and
+    ///   `FsOptions::max_path_component_len`.
     /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
     pub async fn open_dir(&self, path: &str) -> Result<Self, Error<IO::Error>> {
         trace!("Dir::open_dir {}", path);
+        self.validate_path(path)?;
         let mut split = split_path(path);
         let mut e = self.clone();
         loop {
@@ -214,10 +392,10 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
             match rest_opt {
                 Some(rest) => {
                     split = split_path(rest);
-                    e = e.find_entry(name, Some(true), None).await?.to_dir();
+                    e = e.open_dir_component(name).await?;
                 }
                 None => {
-                    e = e.find_entry(name, Some(true), None).await?.to_dir();
+                    e = e.open_dir_component(name).await?;
                     break;
                 }
             }
@@ -226,6 +404,27 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
         Ok(e)
     }
 
+    /// Opens existing subdirectory returning `None` if it does not exist.
+    ///
+    /// Works like `open_dir` but a missing entry (also a missing parent directory) is reported as `Ok(None)` instead
+    /// of `Error::NotFound`, so it cannot be mixed up with a failure of the storage.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if `path` points to a file that is not a directory or if the path is
+    ///   deeper or has a longer component than allowed by `FsOptions::max_path_depth` and
+    ///   `FsOptions::max_path_component_len`.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub async fn try_open_dir(&self, path: &str) -> Result<Option<Self>, Error<IO::Error>> {
+        match self.open_dir(path).await {
+            Ok(dir) => Ok(Some(dir)),
+            Err(Error::NotFound) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
     /// Opens existing meta.
     ///
     /// `path` is a '/' separated file path relative to self directory.
@@ -235,10 +434,12 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
     /// Errors that can be returned:
     ///
     /// * `Error::NotFound` will be returned if `path` points to a non-existing directory entry.
-    /// * `Error::InvalidInput` will be returned if `path` points to a file that is a directory.
+    /// * `Error::InvalidInput` will be returned if `path` points to a file that is a directory or if the path is deeper
+    ///   or has a longer component than allowed by `FsOptions::max_path_depth` and `FsOptions::max_path_component_len`.
     /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
     pub async fn open_meta(&self, path: &str) -> Result<DirEntry<'a, IO, TP, OCC>, Error<IO::Error>> {
         trace!("Dir::open_meta {}", path);
+        self.validate_path(path)?;
         let mut split = split_path(path);
         let mut e = self.clone();
         loop {
@@ -246,7 +447,7 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
             match rest_opt {
                 Some(rest) => {
                     split = split_path(rest);
-                    e = e.find_entry(name, Some(true), None).await?.to_dir();
+                    e = e.open_dir_component(name).await?;
                 }
                 None => {
                     return Ok(e.find_entry(name, None, None).await?);
@@ -264,10 +465,24 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
     /// Errors that can be returned:
     ///
     /// * `Error::NotFound` will be returned if `path` points to a non-existing directory entry.
-    /// * `Error::InvalidInput` will be returned if `path` points to a file that is a directory.
+    /// * `Error::InvalidInput` will be returned if `path` points to a file that is a directory or if the path is deeper
+    ///   or has a longer component than allowed by `FsOptions::max_path_depth` and `FsOptions::max_path_component_len`.
+    /// * `Error::InvalidData` will be returned if the size of the file exceeds the capacity of its cluster chain and
+    ///   `SizeMismatchPolicy::Error` is selected in `FsOptions::size_mismatch_policy`.
+    /// * `Error::ReadOnly` will be returned if the size has to be fixed because of `SizeMismatchPolicy::Fix` and the
+    ///   filesystem is mounted in read-only mode.
+    /// * `Error::CorruptedFileSystem` will be returned if the cluster chain of the file is circular.
     /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
     pub async fn open_file(&self, path: &str) -> Result<File<'a, IO, TP, OCC>, Error<IO::Error>> {
         trace!("Dir::open_file {}", path);
+        let mut file = self.open_file_unchecked(path).await?;
+        file.check_size().await?;
+        Ok(file)
+    }
+
+    // Opens existing file without applying `FsOptions::size_mismatch_policy`
+    pub(crate) async fn open_file_unchecked(&self, path: &str) -> Result<File<'a, IO, TP, OCC>, Error<IO::Error>> {
+        self.validate_path(path)?;
         let mut split = split_path(path);
         let mut e = self.clone();
         loop {
@@ -275,7 +490,7 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
             match rest_opt {
                 Some(rest) => {
                     split = split_path(rest);
-                    e = e.find_entry(name, Some(true), None).await?.to_dir();
+                    e = e.open_dir_component(name).await?;
                 }
                 None => {
                     return Ok(e.find_entry(name, Some(false), None).await?.to_file());
@@ -284,22 +499,99 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
         }
     }
 
+    /// Opens existing file returning `None` if it does not exist.
+    ///
+    /// Works like `open_file` but a missing entry (also a missing parent directory) is reported as `Ok(None)` instead
+    /// of `Error::NotFound`, so it cannot be mixed up with a failure of the storage.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if `path` points to a file that is a directory or if the path is deeper
+    ///   or has a longer component than allowed by `FsOptions::max_path_depth` and `FsOptions::max_path_component_len`.
+    /// * `Error::InvalidData`, `Error::ReadOnly` or `Error::CorruptedFileSystem` will be returned if the size of the
+    ///   file does not pass the check selected by `FsOptions::size_mismatch_policy` (see `open_file`).
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub async fn try_open_file(&self, path: &str) -> Result<Option<File<'a, IO, TP, OCC>>, Error<IO::Error>> {
+        match self.open_file(path).await {
+            Ok(file) => Ok(Some(file)),
+            Err(Error::NotFound) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
     /// Creates new or opens existing file=.
     ///
     /// `path` is a '/' separated file path relative to `self` directory.
     /// File is never truncated when opening. It can be achieved by calling `File::truncate` method after opening.
+    /// Trailing dots and spaces are stripped from the name of a new file.
     ///
     /// # Errors
     ///
     /// Errors that can be returned:
     ///
-    /// * `Error::InvalidInput` will be returned if `path` points to an existing file that is a directory.
+    /// * `Error::InvalidInput` will be returned if `path` points to an existing file that is a directory, if the last
+    ///   component of `path` is `.` or `..` or if the path is deeper or has a longer component than allowed by
+    ///   `FsOptions::max_path_depth` and `FsOptions::max_path_component_len`.
     /// * `Error::InvalidFileNameLength` will be returned if the file name is empty or if it is too long.
     /// * `Error::UnsupportedFileNameCharacter` will be returned if the file name contains an invalid character.
     /// * `Error::NotEnoughSpace` will be returned if there is not enough free space to create a new file.
+    /// * `Error::RootDirectoryFull` will be returned if a new entry does not fit in the FAT12/FAT16 root directory.
+    /// * `Error::InvalidData` will be returned if the size of an existing file does not pass the check selected by
+    ///   `FsOptions::size_mismatch_policy` (see `open_file`).
+    /// * `Error::ReadOnly` will be returned if the filesystem is mounted in read-only mode.
     /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
     pub async fn create_file(&self, path: &str) -> Result<File<'a, IO, TP, OCC>, Error<IO::Error>> {
         trace!("Dir::create_file {}", path);
+        self.create_file_with_attributes(path, FileAttributes::empty()).await
+    }
+
+    /// Creates new file with the given attributes or opens existing file.
+    ///
+    /// Works like `create_file` but `attrs` (e.g. `FileAttributes::HIDDEN | FileAttributes::SYSTEM`) are stored in
+    /// the directory entry of a newly created file, so the file never exists without them. Attributes of an existing
+    /// file are not changed.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if `attrs` contains `FileAttributes::DIRECTORY` or
+    ///   `FileAttributes::VOLUME_ID`, if `path` points to an existing file that is a directory, if the last component
+    ///   of `path` is `.` or `..` or if the path is deeper or has a longer component than allowed by
+    ///   `FsOptions::max_path_depth` and `FsOptions::max_path_component_len`.
+    /// * `Error::InvalidFileNameLength` will be returned if the file name is empty or if it is too long.
+    /// * `Error::UnsupportedFileNameCharacter` will be returned if the file name contains an invalid character.
+    /// * `Error::NotEnoughSpace` will be returned if there is not enough free space to create a new file.
+    /// * `Error::RootDirectoryFull` will be returned if a new entry does not fit in the FAT12/FAT16 root directory.
+    /// * `Error::InvalidData` will be returned if the size of an existing file does not pass the check selected by
+    ///   `FsOptions::size_mismatch_policy` (see `open_file`).
+    /// * `Error::ReadOnly` will be returned if the filesystem is mounted in read-only mode.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub async fn create_file_with_attributes(
+        &self,
+        path: &str,
+        attrs: FileAttributes,
+    ) -> Result<File<'a, IO, TP, OCC>, Error<IO::Error>> {
+        trace!("Dir::create_file_with_attributes {} {:?}", path, attrs);
+        let mut file = self.create_file_inner(path, attrs, false).await?;
+        file.check_size().await?;
+        Ok(file)
+    }
+
+    async fn create_file_inner(
+        &self,
+        path: &str,
+        attrs: FileAttributes,
+        create_new: bool,
+    ) -> Result<File<'a, IO, TP, OCC>, Error<IO::Error>> {
+        // volume label and long name entries are not files and directories need the "." and ".." entries
+        if attrs.intersects(FileAttributes::DIRECTORY | FileAttributes::VOLUME_ID) {
+            error!("Invalid attributes for a file: {:?}", attrs);
+            return Err(Error::InvalidInput);
+        }
+        self.validate_path(path)?;
         let mut split = split_path(path);
         let mut e = self.clone();
         loop {
@@ -307,7 +599,7 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
             match rest_opt {
                 Some(rest) => {
                     split = split_path(rest);
-                    e = e.find_entry(name, Some(true), None).await?.to_dir();
+                    e = e.open_dir_component(name).await?;
                 }
                 None => {
                     break;
@@ -318,33 +610,177 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
         // this is final filename in the path
         let parent = e;
         let (name, _) = split;
-        let r = parent.check_for_existence(name, Some(false)).await?;
+        if is_dot_name(name) {
+            error!("Cannot create a file named {}", name);
+            return Err(Error::InvalidInput);
+        }
+        // nothing else may create the entry between the check and the creation
+        let mut guard = self.fs.lock_dir_updates().await;
+        let name = self.new_entry_name(name, guard.name_buf())?;
+        // any entry with the name makes `create_new` fail, not only a file
+        let is_dir = if create_new { None } else { Some(false) };
+        let r = parent.check_for_existence(name, is_dir).await?;
         match r {
             // file does not exist - create it
             DirEntryOrShortName::ShortName(short_name) => {
-                let sfn_entry = parent.create_sfn_entry(short_name, FileAttributes::from_bits_truncate(0), None);
+                let sfn_entry = parent.create_sfn_entry(short_name, attrs, None);
                 Ok(parent.write_entry(name, sfn_entry).await?.to_file())
             }
+            DirEntryOrShortName::DirEntry(_) if create_new => Err(Error::AlreadyExists),
             // file already exists - return it
             DirEntryOrShortName::DirEntry(e) => Ok(e.to_file()),
         }
     }
 
+    /// Opens a file with the access mode and the creation behaviour selected by `options`.
+    ///
+    /// `path` is a '/' separated file path relative to `self` directory. Works like `std::fs::OpenOptions::open`:
+    /// the file is created if `create` or `create_new` is set, emptied if `truncate` is set and every write goes to
+    /// the end of the file if `append` is set. Reading from a file opened without `read` access and writing to a file
+    /// opened without `write` or `append` access fails.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if `options` do not select any access mode, if `create`, `create_new`
+    ///   or `truncate` is set without `write` or `append`, if `truncate` is combined with `append`, if `path` points
+    ///   to a file that is a directory or if the path is deeper or has a longer component than allowed by
+    ///   `FsOptions::max_path_depth` and `FsOptions::max_path_component_len`.
+    /// * `Error::NotFound` will be returned if `path` points to a non-existing directory entry and neither `create`
+    ///   nor `create_new` is set.
+    /// * `Error::AlreadyExists` will be returned if `create_new` is set and `path` points to an existing entry.
+    /// * `Error::InvalidData` will be returned if `truncate` is not set and the size of an existing file does not pass
+    ///   the check selected by `FsOptions::size_mismatch_policy` (see `open_file`).
+    /// * `Error::InvalidFileNameLength` will be returned if the file name is empty or if it is too long.
+    /// * `Error::UnsupportedFileNameCharacter` will be returned if the file name contains an invalid character.
+    /// * `Error::NotEnoughSpace` will be returned if there is not enough free space to create a new file.
+    /// * `Error::RootDirectoryFull` will be returned if a new entry does not fit in the FAT12/FAT16 root directory.
+    /// * `Error::ReadOnly` will be returned if write access is requested and the filesystem is mounted in read-only
+    ///   mode.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub async fn open_file_with(
+        &self,
+        path: &str,
+        options: OpenOptions,
+    ) -> Result<File<'a, IO, TP, OCC>, Error<IO::Error>> {
+        trace!("Dir::open_file_with {} {:?}", path, options);
+        let writable = options.write || options.append;
+        if !options.read && !writable {
+            error!("No access mode selected in open options");
+            return Err(Error::InvalidInput);
+        }
+        if (options.create || options.create_new || options.truncate) && !writable {
+            error!("Creating or truncating a file requires write access");
+            return Err(Error::InvalidInput);
+        }
+        if options.truncate && options.append {
+            error!("Truncate cannot be combined with append");
+            return Err(Error::InvalidInput);
+        }
+        if writable {
+            self.fs.ensure_writable()?;
+        }
+        let mut file = if options.create_new || options.create {
+            self.create_file_inner(path, FileAttributes::empty(), options.create_new)
+                .await?
+        } else {
+            self.open_file_unchecked(path).await?
+        };
+        // the size of a truncated file does not matter
+        if options.truncate {
+            file.truncate().await?;
+            file.flush().await?;
+        } else {
+            file.check_size().await?;
+        }
+        file.set_access_mode(options.read, writable, options.append);
+        if options.append {
+            file.seek(SeekFrom::End(0)).await?;
+        }
+        Ok(file)
+    }
+
+    /// Creates new or opens existing file creating any missing parent directories first.
+    ///
+    /// `path` is a '/' separated file path relative to `self` directory. Works like `create_file` but every missing
+    /// directory on the path is created as if by `create_dir`. Directories that already exist are reused, so calling
+    /// this method again with the same path opens the file.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if a parent component of `path` is an existing file that is not a
+    ///   directory, if `path` points to an existing directory or if the path is deeper or has a longer component than
+    ///   allowed by `FsOptions::max_path_depth` and `FsOptions::max_path_component_len`.
+    /// * `Error::InvalidFileNameLength` will be returned if a file name is empty or if it is too long.
+    /// * `Error::UnsupportedFileNameCharacter` will be returned if a file name contains an invalid character.
+    /// * `Error::NotEnoughSpace` will be returned if there is not enough free space to create a directory or the file.
+    /// * `Error::RootDirectoryFull` will be returned if a new entry does not fit in the FAT12/FAT16 root directory.
+    /// * `Error::ReadOnly` will be returned if the filesystem is mounted in read-only mode.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub async fn create_file_all(&self, path: &str) -> Result<File<'a, IO, TP, OCC>, Error<IO::Error>> {
+        trace!("Dir::create_file_all {}", path);
+        self.validate_path(path)?;
+        let mut split = split_path(path);
+        let mut e = self.clone();
+        while let (name, Some(rest)) = split {
+            e = e.create_dir(name).await?;
+            split = split_path(rest);
+        }
+        let (name, _) = split;
+        e.create_file(name).await
+    }
+
     /// Creates new directory or opens existing.
     ///
-    /// `path` is a '/' separated path relative to self directory.
+    /// `path` is a '/' separated path relative to self directory. Trailing dots and spaces are stripped from the name
+    /// of a new directory.
     ///
     /// # Errors
     ///
     /// Errors that can be returned:
     ///
-    /// * `Error::InvalidInput` will be returned if `path` points to an existing file that is not a directory.
+    /// * `Error::InvalidInput` will be returned if `path` points to an existing file that is not a directory or if the
+    ///   path is deeper or has a longer component than allowed by `FsOptions::max_path_depth` and
+    ///   `FsOptions::max_path_component_len`.
     /// * `Error::InvalidFileNameLength` will be returned if the file name is empty or if it is too long.
     /// * `Error::UnsupportedFileNameCharacter` will be returned if the file name contains an invalid character.
     /// * `Error::NotEnoughSpace` will be returned if there is not enough free space to create a new directory.
+    /// * `Error::RootDirectoryFull` will be returned if a new entry does not fit in the FAT12/FAT16 root directory.
+    /// * `Error::ReadOnly` will be returned if the filesystem is mounted in read-only mode.
     /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
     pub async fn create_dir(&self, path: &str) -> Result<Self, Error<IO::Error>> {
         trace!("Dir::create_dir {}", path);
+        self.create_dir_inner(path, false).await
+    }
+
+    /// Creates new directory failing if an entry with the given name exists already.
+    ///
+    /// `path` is a '/' separated path relative to self directory. Unlike `create_dir` an existing directory is not
+    /// opened, so only one of many calls with the same path succeeds, even if their futures are polled concurrently.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::AlreadyExists` will be returned if `path` points to an existing file or directory.
+    /// * `Error::InvalidFileNameLength` will be returned if the file name is empty or if it is too long.
+    /// * `Error::UnsupportedFileNameCharacter` will be returned if the file name contains an invalid character.
+    /// * `Error::NotEnoughSpace` will be returned if there is not enough free space to create a new directory.
+    /// * `Error::RootDirectoryFull` will be returned if a new entry does not fit in the FAT12/FAT16 root directory.
+    /// * `Error::ReadOnly` will be returned if the filesystem is mounted in read-only mode.
+    /// * `Error::InvalidInput` will be returned if the path is deeper or has a longer component than allowed by
+    ///   `FsOptions::max_path_depth` and `FsOptions::max_path_component_len`.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub async fn create_new_dir(&self, path: &str) -> Result<Self, Error<IO::Error>> {
+        trace!("Dir::create_new_dir {}", path);
+        self.create_dir_inner(path, true).await
+    }
+
+    async fn create_dir_inner(&self, path: &str, create_new: bool) -> Result<Self, Error<IO::Error>> {
+        self.validate_path(path)?;
         let mut split = split_path(path);
         let mut e = self.clone();
         loop {
@@ -352,7 +788,7 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
             match rest_opt {
                 Some(rest) => {
                     split = split_path(rest);
-                    e = e.find_entry(name, Some(true), None).await?.to_dir();
+                    e = e.open_dir_component(name).await?;
                 }
                 None => {
                     break;
@@ -362,7 +798,18 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
 
         // this is final filename in the path
         let (name, _) = split;
-        let r = e.check_for_existence(name, Some(true)).await?;
+        if is_dot_name(name) {
+            return if create_new {
+                Err(Error::AlreadyExists)
+            } else {
+                e.open_dir_component(name).await
+            };
+        }
+        // nothing else may create the entry between the check and the creation
+        let mut guard = self.fs.lock_dir_updates().await;
+        let name = self.new_entry_name(name, guard.name_buf())?;
+        let is_dir = if create_new { None } else { Some(true) };
+        let r = e.check_for_existence(name, is_dir).await?;
         match r {
             // directory does not exist - create it
             DirEntryOrShortName::ShortName(short_name) => {
@@ -370,22 +817,76 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
                 let cluster = self.fs.alloc_cluster(None, true).await?;
                 // create entry in parent directory
                 let sfn_entry = e.create_sfn_entry(short_name, FileAttributes::DIRECTORY, Some(cluster));
-                let entry = e.write_entry(name, sfn_entry).await?;
+                let entry = match e.write_entry(name, sfn_entry).await {
+                    Ok(entry) => entry,
+                    Err(err) => {
+                        // the cluster is not referenced by any entry
+                        self.fs.free_cluster_chain(cluster).await?;
+                        return Err(err);
+                    }
+                };
                 let dir = entry.to_dir();
                 // create special entries "." and ".."
                 let dot_sfn = ShortNameGenerator::generate_dot();
                 let sfn_entry = e.create_sfn_entry(dot_sfn, FileAttributes::DIRECTORY, entry.first_cluster());
                 dir.write_entry(".", sfn_entry).await?;
                 let dotdot_sfn = ShortNameGenerator::generate_dotdot();
-                let sfn_entry = e.create_sfn_entry(dotdot_sfn, FileAttributes::DIRECTORY, e.stream.first_cluster());
+                // Note: ".." entry of a directory in the root directory points to cluster 0 even on FAT32
+                let parent_cluster = if e.stream.is_root_dir() {
+                    None
+                } else {
+                    e.stream.first_cluster()
+                };
+                let sfn_entry = e.create_sfn_entry(dotdot_sfn, FileAttributes::DIRECTORY, parent_cluster);
                 dir.write_entry("..", sfn_entry).await?;
                 Ok(dir)
             }
+            DirEntryOrShortName::DirEntry(_) if create_new => Err(Error::AlreadyExists),
             // directory already exists - return it
             DirEntryOrShortName::DirEntry(e) => Ok(e.to_dir()),
         }
     }
 
+    /// Creates a directory and all its missing parent directories or opens the existing one.
+    ///
+    /// `path` is a '/' separated path relative to self directory. Each component is created with `create_dir` so
+    /// every new directory gets `.` and `..` entries pointing to itself and its parent. Components that already exist
+    /// as directories are reused.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if a component of `path` is an existing file that is not a directory or
+    ///   if the path is deeper or has a longer component than allowed by `FsOptions::max_path_depth` and
+    ///   `FsOptions::max_path_component_len`.
+    /// * `Error::InvalidFileNameLength` will be returned if a file name is empty or if it is too long.
+    /// * `Error::UnsupportedFileNameCharacter` will be returned if a file name contains an invalid character.
+    /// * `Error::NotEnoughSpace` will be returned if there is not enough free space to create a new directory.
+    /// * `Error::RootDirectoryFull` will be returned if a new entry does not fit in the FAT12/FAT16 root directory.
+    /// * `Error::ReadOnly` will be returned if the filesystem is mounted in read-only mode.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub async fn create_dir_all(&self, path: &str) -> Result<Self, Error<IO::Error>> {
+        trace!("Dir::create_dir_all {}", path);
+        self.validate_path(path)?;
+        let mut split = split_path(path);
+        let mut e = self.clone();
+        while let (name, Some(rest)) = split {
+            e = e.create_dir(name).await?;
+            split = split_path(rest);
+        }
+        let (name, _) = split;
+        e.create_dir(name).await
+    }
+
+    /// Checks if this directory has no children.
+    ///
+    /// The special entries `.` and `..`, deleted entries and the volume label entry of the root directory are not
+    /// counted. Iteration stops at the first child, so it is cheaper than collecting all entries.
+    ///
+    /// # Errors
+    ///
+    /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
     pub async fn is_empty(&self) -> Result<bool, Error<IO::Error>> {
         trace!("Dir::is_empty");
         // check if directory contains no files
@@ -412,13 +913,18 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
     /// Errors that can be returned:
     ///
     /// * `Error::NotFound` will be returned if `path` points to a non-existing directory entry.
-    /// * `Error::InvalidInput` will be returned if `path` points to a file that is not a directory.
+    /// * `Error::InvalidInput` will be returned if `path` points to a file that is not a directory, if the last
+    ///   component of `path` is `.` or `..` or if the path is deeper or has a longer component than allowed by
+    ///   `FsOptions::max_path_depth` and `FsOptions::max_path_component_len`.
     /// * `Error::DirectoryIsNotEmpty` will be returned if the specified directory is not empty.
+    /// * `Error::ReadOnly` will be returned if the filesystem is mounted in read-only mode.
     /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
     pub async fn remove(&self, path: &str) -> Result<(), Error<IO::Error>> {
         trace!("Dir::remove {}", path);
+        self.fs.ensure_writable()?;
 
         // traverse path
+        self.validate_path(path)?;
         let mut split = split_path(path);
         let mut e = self.clone();
         loop {
@@ -426,7 +932,7 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
             match rest_opt {
                 Some(rest) => {
                     split = split_path(rest);
-                    e = e.find_entry(name, Some(true), None).await?.to_dir();
+                    e = e.open_dir_component(name).await?;
                 }
                 None => {
                     break;
@@ -437,17 +943,17 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
         // this is final filename in the path
         let parent = e;
         let (name, _) = split;
+        if is_dot_name(name) {
+            error!("Cannot remove {}", name);
+            return Err(Error::InvalidInput);
+        }
 
         // in case of directory check if it is empty
         let e = parent.find_entry(name, None, None).await?;
         if e.is_dir() && !e.to_dir().is_empty().await? {
             return Err(Error::DirectoryIsNotEmpty);
         }
-        // free data
-        if let Some(n) = e.first_cluster() {
-            self.fs.free_cluster_chain(n).await?;
-        }
-        // free long and short name entries
+        // free long and short name entries before the data so the entry never points to free clusters
         let mut stream = parent.stream.clone();
         stream.seek(SeekFrom::Start(e.offset_range.0)).await?;
         let num = ((e.offset_range.1 - e.offset_range.0) / u64::from(DIR_ENTRY_SIZE)) as usize;
@@ -460,6 +966,10 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
         }
         // remove requires stream flush
         stream.flush().await?;
+        // free data
+        if let Some(n) = e.first_cluster() {
+            self.fs.free_cluster_chain(n).await?;
+        }
         Ok(())
     }
 
@@ -467,7 +977,9 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
     ///
     /// `src_path` is a '/' separated source file path relative to self directory.
     /// `dst_path` is a '/' separated destination file path relative to `dst_dir`.
-    /// `dst_dir` can be set to self directory if rename operation without moving is needed.
+    /// `dst_dir` can be set to self directory if rename operation without moving is needed. Such a rename only
+    /// rewrites the name entries, reusing the slots of the old name when the new one fits in them.
+    /// Trailing dots and spaces are stripped from the destination name.
     /// Make sure there is no reference to this file (no File instance) or filesystem corruption
     /// can happen.
     ///
@@ -478,6 +990,14 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
     /// * `Error::NotFound` will be returned if `src_path` points to a non-existing directory entry or if `dst_path`
     ///   stripped from the last component does not point to an existing directory.
     /// * `Error::AlreadyExists` will be returned if `dst_path` points to an existing directory entry.
+    /// * `Error::InvalidFileNameLength` will be returned if the destination file name is empty or if it is too long.
+    /// * `Error::UnsupportedFileNameCharacter` will be returned if the destination file name contains an invalid
+    ///   character and `FsOptions::replace_invalid_name_chars` is not enabled.
+    /// * `Error::RootDirectoryFull` will be returned if a new entry does not fit in the FAT12/FAT16 root directory.
+    /// * `Error::ReadOnly` will be returned if the filesystem is mounted in read-only mode.
+    /// * `Error::InvalidInput` will be returned if the last component of `src_path` or `dst_path` is `.` or `..` or if
+    ///   a path is deeper or has a longer component than allowed by `FsOptions::max_path_depth` and
+    ///   `FsOptions::max_path_component_len`.
     /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
     pub async fn rename(
         &self,
@@ -486,6 +1006,8 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
         dst_path: &str,
     ) -> Result<(), Error<IO::Error>> {
         trace!("Dir::rename {} {}", src_path, dst_path);
+        self.validate_path(src_path)?;
+        self.validate_path(dst_path)?;
         // traverse source path
         let mut split_src = split_path(src_path);
         let mut e_src = self.clone();
@@ -494,7 +1016,7 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
             match rest_opt {
                 Some(rest) => {
                     split_src = split_path(rest);
-                    e_src = e_src.find_entry(name, Some(true), None).await?.to_dir();
+                    e_src = e_src.open_dir_component(name).await?;
                 }
                 None => {
                     break;
@@ -510,7 +1032,7 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
             match rest_opt {
                 Some(rest) => {
                     split_dst = split_path(rest);
-                    e_dst = e_dst.find_entry(name, Some(true), None).await?.to_dir();
+                    e_dst = e_dst.open_dir_component(name).await?;
                 }
                 None => {
                     break;
@@ -528,6 +1050,14 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
         dst_name: &str,
     ) -> Result<(), Error<IO::Error>> {
         trace!("Dir::rename_internal {} {}", src_name, dst_name);
+        self.fs.ensure_writable()?;
+        if is_dot_name(src_name) || is_dot_name(dst_name) {
+            error!("Cannot rename {} to {}", src_name, dst_name);
+            return Err(Error::InvalidInput);
+        }
+        // the destination name must stay unused until the entry is written
+        let mut guard = self.fs.lock_dir_updates().await;
+        let dst_name = self.new_entry_name(dst_name, guard.name_buf())?;
         // find existing file
         let e = self.find_entry(src_name, None, None).await?;
         // check if destionation filename is unused
@@ -546,27 +1076,195 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
             // destionation file does not exist, short name has been generated
             DirEntryOrShortName::ShortName(short_name) => short_name,
         };
+        let sfn_entry = e.data.renamed(short_name);
+        if self.stream.first_cluster() == dst_dir.stream.first_cluster() {
+            return self.rename_in_place(&e, dst_name, sfn_entry).await;
+        }
+        // when moving to another directory write the new entry first so the file is not lost if there is no room for
+        // it, e.g. in the FAT12/FAT16 root directory
+        dst_dir.write_entry(dst_name, sfn_entry).await?;
         // free long and short name entries
+        let mut stream = self.delete_entries(e.offset_range).await?;
+        // rename requires stream flush (no async drop :()
+        stream.flush().await?;
+        Ok(())
+    }
+
+    // Rewrites the name of an entry without moving it to another directory. The short name entry stays in its slot
+    // when the new name fits in the slots of the old one and the deleted entries right before them, otherwise the
+    // new entries are written to a free run first and the old ones are freed afterwards. The cluster chain of the
+    // entry is never touched.
+    async fn rename_in_place(
+        &self,
+        e: &DirEntry<'a, IO, TP, OCC>,
+        name: &str,
+        raw_entry: DirFileEntryData,
+    ) -> Result<(), Error<IO::Error>> {
+        trace!("Dir::rename_in_place {}", name);
+        let (raw_entry, lfn_utf16) = self.prepare_entry(name, raw_entry)?;
+        let lfn_iter = LfnEntriesGenerator::new(lfn_utf16.as_ucs2_units(), lfn_checksum(raw_entry.name()));
+        let entries_len = (lfn_iter.len() as u64 + 1) * u64::from(DIR_ENTRY_SIZE);
+        let (old_start, old_end) = e.offset_range;
+        // extend the old slots backwards over deleted entries until the new entries fit
+        let mut start = old_start;
+        {
+            let mut stream = self.stream.clone();
+            while old_end - start < entries_len && start > 0 {
+                stream.seek(SeekFrom::Start(start - u64::from(DIR_ENTRY_SIZE))).await?;
+                if !DirEntryData::deserialize(&mut stream).await?.is_deleted() {
+                    break;
+                }
+                start -= u64::from(DIR_ENTRY_SIZE);
+            }
+            // reading may have updated the access date of the directory
+            stream.flush().await?;
+        }
+        let mut stream = if old_end - start >= entries_len {
+            let new_start = old_end - entries_len;
+            // free the slots not needed by a shorter name
+            let mut stream = self.delete_entries((old_start, cmp::max(old_start, new_start))).await?;
+            stream.seek(SeekFrom::Start(new_start)).await?;
+            for lfn_entry in lfn_iter {
+                lfn_entry.serialize(&mut stream).await?;
+            }
+            raw_entry.serialize(&mut stream).await?;
+            stream
+        } else {
+            let (mut stream, _) = self.alloc_and_write_lfn_entries(&lfn_utf16, raw_entry.name()).await?;
+            raw_entry.serialize(&mut stream).await?;
+            stream.flush().await?;
+            self.delete_entries(e.offset_range).await?
+        };
+        // rename requires stream flush (no async drop :()
+        stream.flush().await?;
+        Ok(())
+    }
+
+    // Marks the entries in the given range of the directory stream as deleted
+    async fn delete_entries(&self, range: (u64, u64)) -> Result<DirRawStream<'a, IO, TP, OCC>, Error<IO::Error>> {
         let mut stream = self.stream.clone();
-        stream.seek(SeekFrom::Start(e.offset_range.0)).await?;
-        let num = ((e.offset_range.1 - e.offset_range.0) / u64::from(DIR_ENTRY_SIZE)) as usize;
+        stream.seek(SeekFrom::Start(range.0)).await?;
+        let num = ((range.1 - range.0) / u64::from(DIR_ENTRY_SIZE)) as usize;
         for _ in 0..num {
             let mut data = DirEntryData::deserialize(&mut stream).await?;
-            trace!("removing LFN entry {:?}", data);
+            trace!("removing dir entry {:?}", data);
             data.set_deleted();
             stream.seek(SeekFrom::Current(-i64::from(DIR_ENTRY_SIZE))).await?;
             data.serialize(&mut stream).await?;
         }
-        // save new directory entry
-        let sfn_entry = e.data.renamed(short_name);
-        dst_dir.write_entry(dst_name, sfn_entry).await?;
+        Ok(stream)
+    }
 
-        // rename requires stream flush (no async drop :()
-        stream.flush().await?;
+    /// Copies an existing file.
+    ///
+    /// `src_path` is a '/' separated source file path relative to self directory.
+    /// `dst_path` is a '/' separated destination file path relative to `dst_dir`.
+    /// File data is copied through a small internal buffer into a newly allocated cluster chain, so no user buffer is
+    /// needed. The copy gets the attributes of the source file and fresh timestamps. If `overwrite` is set an existing
+    /// destination file is removed first. If copying fails (e.g. because the volume is full) the partially written
+    /// destination file is removed. Trailing dots and spaces are stripped from the destination name.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::NotFound` will be returned if `src_path` points to a non-existing directory entry or if `dst_path`
+    ///   stripped from the last component does not point to an existing directory.
+    /// * `Error::InvalidInput` will be returned if `src_path` or `dst_path` points to a directory, if both paths point
+    ///   to the same file, if the last component of `dst_path` is `.` or `..` or if a path is deeper or has a longer
+    ///   component than allowed by `FsOptions::max_path_depth` and `FsOptions::max_path_component_len`.
+    /// * `Error::AlreadyExists` will be returned if `dst_path` points to an existing file and `overwrite` is not set.
+    /// * `Error::InvalidFileNameLength` will be returned if the destination file name is empty or if it is too long.
+    /// * `Error::UnsupportedFileNameCharacter` will be returned if the destination file name contains an invalid
+    ///   character and `FsOptions::replace_invalid_name_chars` is not enabled.
+    /// * `Error::NotEnoughSpace` will be returned if there is not enough free space to copy the file.
+    /// * `Error::RootDirectoryFull` will be returned if a new entry does not fit in the FAT12/FAT16 root directory.
+    /// * `Error::ReadOnly` will be returned if the filesystem is mounted in read-only mode.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub async fn copy_file(
+        &self,
+        src_path: &str,
+        dst_dir: &Dir<'_, IO, TP, OCC>,
+        dst_path: &str,
+        overwrite: bool,
+    ) -> Result<(), Error<IO::Error>> {
+        trace!("Dir::copy_file {} {}", src_path, dst_path);
+        self.validate_path(dst_path)?;
+        self.fs.ensure_writable()?;
+        let src = self.open_meta(src_path).await?;
+        if src.is_dir() {
+            error!("Is a directory");
+            return Err(Error::InvalidInput);
+        }
+
+        // traverse destination path
+        let mut split_dst = split_path(dst_path);
+        let mut dst_parent = dst_dir.clone();
+        while let (name, Some(rest)) = split_dst {
+            dst_parent = dst_parent.open_dir_component(name).await?;
+            split_dst = split_path(rest);
+        }
+        let (dst_name, _) = split_dst;
+        if is_dot_name(dst_name) {
+            error!("Cannot create a file named {}", dst_name);
+            return Err(Error::InvalidInput);
+        }
+        let mut dst = {
+            // the destination name must stay unused until the entry is written
+            let mut guard = self.fs.lock_dir_updates().await;
+            let dst_name = self.new_entry_name(dst_name, guard.name_buf())?;
+            let short_name = loop {
+                match dst_parent.check_for_existence(dst_name, Some(false)).await? {
+                    DirEntryOrShortName::ShortName(short_name) => break short_name,
+                    DirEntryOrShortName::DirEntry(ref dst_e) if src.is_same_entry(dst_e) => {
+                        error!("Source and destination is the same file");
+                        return Err(Error::InvalidInput);
+                    }
+                    DirEntryOrShortName::DirEntry(_) if overwrite => dst_parent.remove(dst_name).await?,
+                    DirEntryOrShortName::DirEntry(_) => return Err(Error::AlreadyExists),
+                }
+            };
+            let sfn_entry = dst_parent.create_sfn_entry(short_name, src.attributes(), None);
+            dst_parent.write_entry(dst_name, sfn_entry).await?.to_file()
+        };
+        // the lock is not held while copying the data so other entries can be created meanwhile
+        let mut src = src.to_file();
+        let result = Self::copy_data(&mut src, &mut dst).await;
+        // flush both files also if copying failed, the source file access date may have been updated
+        let result = result.and(src.flush().await).and(dst.flush().await);
+        drop(dst);
+        if result.is_err() {
+            // do not leave a truncated copy behind
+            let mut guard = self.fs.lock_dir_updates().await;
+            if let Ok(dst_name) = self.new_entry_name(dst_name, guard.name_buf()) {
+                let _ = dst_parent.remove(dst_name).await;
+            }
+        }
+        result
+    }
+
+    async fn copy_data(
+        src: &mut File<'_, IO, TP, OCC>,
+        dst: &mut File<'_, IO, TP, OCC>,
+    ) -> Result<(), Error<IO::Error>> {
+        // one sector sized buffer bypasses the shared file buffer and keeps the future small
+        let mut buf = [0_u8; 512];
+        loop {
+            let n = src.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            dst.write_all(&buf[..n]).await?;
+        }
         Ok(())
     }
 
+    /// Finds `num_entries` contiguous free entries, reusing the slots of deleted entries when possible.
+    ///
+    /// The run must be contiguous because LFN entries have to immediately precede their short entry. If no such run
+    /// exists, the stream is positioned at the end of the directory and writing through it allocates new clusters.
     async fn find_free_entries(&self, num_entries: u32) -> Result<DirRawStream<'a, IO, TP, OCC>, Error<IO::Error>> {
+        let capacity = self.capacity();
         let mut stream = self.stream.clone();
         let mut first_free: u32 = 0;
         let mut num_free: u32 = 0;
@@ -578,6 +1276,11 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
                 if num_free == 0 {
                     first_free = i;
                 }
+                // a fixed size root directory cannot grow
+                if capacity.is_some_and(|n| first_free + num_entries > n) {
+                    error!("No space for {} entries in root directory", num_entries);
+                    return Err(Error::RootDirectoryFull);
+                }
                 let pos = u64::from(first_free * DIR_ENTRY_SIZE);
                 stream.seek(io::SeekFrom::Start(pos)).await?;
                 return Ok(stream);
@@ -646,16 +1349,38 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
         Ok((stream, start_pos))
     }
 
+    // Validates the name and returns the short entry with its case flags set and the long name to store with it
+    fn prepare_entry(
+        &self,
+        name: &str,
+        raw_entry: DirFileEntryData,
+    ) -> Result<(DirFileEntryData, LfnBuffer), Error<IO::Error>> {
+        // check if name doesn't contain unsupported characters
+        validate_long_name(name)?;
+        // a name restored from the short name and its lowercase flags does not need a long name
+        let lowercase_parts = lowercase_name_parts(name, raw_entry.name());
+        // convert long name to UTF-16 - entries in 8.3 mode never have a long name
+        let has_lfn = !self.fs.options.short_names_only && lowercase_parts.is_none();
+        let mut raw_entry = raw_entry;
+        // renamed entries must not keep the case of the old name
+        let (lowercase_basename, lowercase_ext) = if self.fs.options.short_names_only {
+            (false, false)
+        } else {
+            lowercase_parts.unwrap_or_default()
+        };
+        raw_entry.set_lowercase_parts(lowercase_basename, lowercase_ext);
+        let lfn_utf16 = Self::encode_lfn_utf16(if has_lfn { name } else { "" });
+        Ok((raw_entry, lfn_utf16))
+    }
+
     async fn write_entry(
         &self,
         name: &str,
         raw_entry: DirFileEntryData,
     ) -> Result<DirEntry<'a, IO, TP, OCC>, Error<IO::Error>> {
         trace!("Dir::write_entry {}", name);
-        // check if name doesn't contain unsupported characters
-        validate_long_name(name)?;
-        // convert long name to UTF-16
-        let lfn_utf16 = Self::encode_lfn_utf16(name);
+        self.fs.ensure_writable()?;
+        let (raw_entry, lfn_utf16) = self.prepare_entry(name, raw_entry)?;
         // write LFN entries
         let (mut stream, start_pos) = self.alloc_and_write_lfn_entries(&lfn_utf16, raw_entry.name()).await?;
         // write short name entry
@@ -697,22 +1422,127 @@ impl<IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Clone for Dir<'_,
     }
 }
 
+/// Options selecting how a file is opened by `Dir::open_file_with`.
+///
+/// The options follow `std::fs::OpenOptions`. All of them are disabled by default.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Debug, Default)]
+// independent flags mirroring the setters of `std::fs::OpenOptions`
+#[allow(clippy::struct_excessive_bools)]
+pub struct OpenOptions {
+    read: bool,
+    write: bool,
+    append: bool,
+    create: bool,
+    create_new: bool,
+    truncate: bool,
+}
+
+impl OpenOptions {
+    /// Creates an `OpenOptions` struct with all options disabled.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the option for read access.
+    #[must_use]
+    pub fn read(mut self, enabled: bool) -> Self {
+        self.read = enabled;
+        self
+    }
+
+    /// Sets the option for write access.
+    #[must_use]
+    pub fn write(mut self, enabled: bool) -> Self {
+        self.write = enabled;
+        self
+    }
+
+    /// Sets the option for the append mode.
+    ///
+    /// Implies write access. Before every write the position is moved to the end of the file, so data is never
+    /// overwritten even if the file was extended by another handle in the meantime. Seeking is still possible for
+    /// reading.
+    #[must_use]
+    pub fn append(mut self, enabled: bool) -> Self {
+        self.append = enabled;
+        self
+    }
+
+    /// Sets the option to create the file if it does not exist.
+    #[must_use]
+    pub fn create(mut self, enabled: bool) -> Self {
+        self.create = enabled;
+        self
+    }
+
+    /// Sets the option to always create a new file.
+    ///
+    /// Opening fails with `Error::AlreadyExists` if an entry with the given name exists already. No other entry can be
+    /// created between the check and the creation, so only one of many futures creating the same file succeeds even
+    /// if they are polled concurrently, e.g. when using the file as a lock. If set, `create` and `truncate` are
+    /// ignored.
+    #[must_use]
+    pub fn create_new(mut self, enabled: bool) -> Self {
+        self.create_new = enabled;
+        self
+    }
+
+    /// Sets the option to truncate an existing file to zero length when it is opened.
+    ///
+    /// Like `O_TRUNC` all clusters of the file are freed and its directory entry is updated before the file is
+    /// returned. Truncating an empty file changes nothing. The size of the file is not checked against its cluster
+    /// chain (see `FsOptions::size_mismatch_policy`) because its data is discarded. Requires `write` access.
+    #[must_use]
+    pub fn truncate(mut self, enabled: bool) -> Self {
+        self.truncate = enabled;
+        self
+    }
+}
+
+/// A position in a directory.
+///
+/// This is obtained by calling [`DirEntry::position`] and can be used to continue reading the directory after the
+/// entry with the [`Dir::iter_from`] method. The position is only valid as long as entries before it are not removed
+/// and the directory is not moved or removed. Using a position of another directory returns wrong entries or an
+/// error.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct DirPosition {
+    // Note: cluster containing the byte before offset - None for the FAT12/FAT16 root directory
+    pub(crate) cluster: Option<u32>,
+    // offset after the entry in the directory
+    pub(crate) offset: u32,
+}
+
 /// An iterator over the directory entries.
 ///
-/// This struct is created by the `iter` method on `Dir`.
+/// This struct is created by the `iter` method on `Dir`. Entries are read from the storage one by one by the async
+/// `next` method, so the iterator is consumed like a stream without collecting the entries first:
+/// `while let Some(r) = iter.next().await { let entry = r?; ... }`. With the `stream` feature `into_stream` converts
+/// it into a `futures_core::Stream`.
 pub struct DirIter<'a, IO: ReadWriteSeek, TP, OCC> {
     stream: DirRawStream<'a, IO, TP, OCC>,
     fs: &'a FileSystem<IO, TP, OCC>,
     skip_volume: bool,
+    // only files or only directories (without `.` and `..`) are returned if set
+    is_dir: Option<bool>,
     err: bool,
 }
 
 impl<'a, IO: ReadWriteSeek, TP, OCC> DirIter<'a, IO, TP, OCC> {
-    fn new(stream: DirRawStream<'a, IO, TP, OCC>, fs: &'a FileSystem<IO, TP, OCC>, skip_volume: bool) -> Self {
+    fn new(
+        stream: DirRawStream<'a, IO, TP, OCC>,
+        fs: &'a FileSystem<IO, TP, OCC>,
+        skip_volume: bool,
+        is_dir: Option<bool>,
+    ) -> Self {
         DirIter {
             stream,
             fs,
             skip_volume,
+            is_dir,
             err: false,
         }
     }
@@ -724,7 +1554,11 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC> DirIter<'a, IO, TP, OCC> {
             return true;
         }
         match raw_entry {
-            DirEntryData::File(sfn_entry) => self.skip_volume && sfn_entry.is_volume(),
+            DirEntryData::File(sfn_entry) => {
+                let is_dot = sfn_entry.name()[0] == b'.';
+                let wrong_kind = self.is_dir.is_some_and(|is_dir| sfn_entry.is_dir() != is_dir || is_dot);
+                (self.skip_volume && sfn_entry.is_volume()) || wrong_kind
+            }
             DirEntryData::Lfn(_) => false,
         }
     }
@@ -762,7 +1596,10 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC> DirIter<'a, IO, TP, OCC> {
                     // Calculate SFN entry start position on the storage
                     let abs_pos = end_abs_pos - u64::from(DIR_ENTRY_SIZE);
                     // Check if LFN checksum is valid
-                    lfn_builder.validate_chksum(data.name());
+                    if !lfn_builder.validate_chksum(data.name()) && self.fs.options.strict_lfn_checksum {
+                        error!("LFN checksum mismatch for {:?}", data.name());
+                        return Err(Error::CorruptedFileSystem);
+                    }
                     // Return directory entry
                     let short_name = ShortName::new(data.name());
                     trace!("file entry {:?}", data.name());
@@ -777,14 +1614,21 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC> DirIter<'a, IO, TP, OCC> {
                     }));
                 }
                 DirEntryData::Lfn(data) => {
-                    // Append to LFN buffer
+                    // Append to LFN buffer - long names are ignored in 8.3 mode
                     trace!("lfn entry");
-                    lfn_builder.process(&data);
+                    if !self.fs.options.short_names_only {
+                        lfn_builder.process(&data);
+                    }
                 }
             }
         }
     }
 
+    /// Reads the next entry of the directory.
+    ///
+    /// `None` is returned at the end of the directory. An error (e.g. an I/O error returned by the storage) is
+    /// returned as an `Err` item and ends the iteration, so following calls return `None`. A new iterator can be
+    /// created to read the directory again.
     pub async fn next(&mut self) -> Option<Result<DirEntry<'a, IO, TP, OCC>, Error<IO::Error>>> {
         if self.err {
             return None;
@@ -800,6 +1644,27 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC> DirIter<'a, IO, TP, OCC> {
         }
     }
 
+    /// Converts the iterator into a `futures_core::Stream` yielding the same items as `next`.
+    ///
+    /// The stream ends after the first error. It allocates the future reading the next entry on the heap.
+    #[cfg(feature = "stream")]
+    #[must_use]
+    pub fn into_stream(self) -> DirStream<'a, IO, TP, OCC> {
+        DirStream {
+            next: Some(Box::pin(self.into_next())),
+        }
+    }
+
+    // Reads the next entry and gives the iterator back, so the future does not borrow it
+    #[cfg(feature = "stream")]
+    async fn into_next(mut self) -> (Self, Option<DirIterItem<'a, IO, TP, OCC>>) {
+        let item = self.next().await;
+        (self, item)
+    }
+
+    /// Reads all remaining entries into a vector.
+    ///
+    /// The vector ends with the first error if reading an entry failed.
     #[cfg(feature = "alloc")]
     pub async fn collect(&mut self) -> Vec<Result<DirEntry<'a, IO, TP, OCC>, Error<IO::Error>>> {
         let mut v = Vec::new();
@@ -810,6 +1675,39 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC> DirIter<'a, IO, TP, OCC> {
     }
 }
 
+#[cfg(feature = "stream")]
+type DirIterItem<'a, IO, TP, OCC> = Result<DirEntry<'a, IO, TP, OCC>, Error<<IO as IoBase>::Error>>;
+
+/// A stream over the directory entries.
+///
+/// This struct is created by the `into_stream` method on `DirIter` and implements `futures_core::Stream`. Items are
+/// the same as returned by `DirIter::next`, the stream ends after the first error.
+#[cfg(feature = "stream")]
+#[allow(clippy::type_complexity)]
+pub struct DirStream<'a, IO: ReadWriteSeek, TP, OCC> {
+    // the pending read owns the iterator and returns it with the entry - None after the end or an error
+    next: Option<Pin<Box<dyn Future<Output = (DirIter<'a, IO, TP, OCC>, Option<DirIterItem<'a, IO, TP, OCC>>)> + 'a>>>,
+}
+
+#[cfg(feature = "stream")]
+impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC> futures_core::Stream for DirStream<'a, IO, TP, OCC> {
+    type Item = DirIterItem<'a, IO, TP, OCC>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let Some(next) = self.next.as_mut() else {
+            return Poll::Ready(None);
+        };
+        let Poll::Ready((iter, item)) = next.as_mut().poll(cx) else {
+            return Poll::Pending;
+        };
+        self.next = match item {
+            Some(Ok(_)) => Some(Box::pin(iter.into_next())),
+            _ => None,
+        };
+        Poll::Ready(item)
+    }
+}
+
 // Note: derive cannot be used because of invalid bounds. See: https://github.com/rust-lang/rust/issues/26925
 impl<IO: ReadWriteSeek, TP, OCC> Clone for DirIter<'_, IO, TP, OCC> {
     fn clone(&self) -> Self {
@@ -818,32 +1716,39 @@ impl<IO: ReadWriteSeek, TP, OCC> Clone for DirIter<'_, IO, TP, OCC> {
             fs: self.fs,
             err: self.err,
             skip_volume: self.skip_volume,
+            is_dir: self.is_dir,
         }
     }
 }
 
-#[rustfmt::skip]
 fn validate_long_name<E: IoError>(name: &str) -> Result<(), Error<E>> {
     // check if length is valid
     if name.is_empty() {
         return Err(Error::InvalidFileNameLength);
     }
-    if name.len() > MAX_LONG_NAME_LEN {
+    // the limit applies to UTF-16 code units, characters outside the BMP take two of them
+    if name.encode_utf16().count() > MAX_LONG_NAME_LEN {
         return Err(Error::InvalidFileNameLength);
     }
     // check if there are only valid characters
-    for c in name.chars() {
-        match c {
-            'a'..='z' | 'A'..='Z' | '0'..='9'
-            | '\u{80}'..='\u{FFFF}'
-            | '$' | '%' | '\'' | '-' | '_' | '@' | '~' | '`' | '!' | '(' | ')' | '{' | '}' | '.' | ' ' | '+' | ','
-            | ';' | '=' | '[' | ']' | '^' | '#' | '&' => {},
-            _ => return Err(Error::UnsupportedFileNameCharacter),
-        }
+    if !name.chars().all(is_valid_long_name_char) {
+        return Err(Error::UnsupportedFileNameCharacter);
     }
     Ok(())
 }
 
+// Control characters and `\ / : * ? " < > |` are not allowed, all of them are ASCII characters
+#[rustfmt::skip]
+fn is_valid_long_name_char(c: char) -> bool {
+    matches!(
+        c,
+        'a'..='z' | 'A'..='Z' | '0'..='9'
+        | '\u{80}'..='\u{10FFFF}'
+        | '$' | '%' | '\'' | '-' | '_' | '@' | '~' | '`' | '!' | '(' | ')' | '{' | '}' | '.' | ' ' | '+' | ','
+        | ';' | '=' | '[' | ']' | '^' | '#' | '&'
+    )
+}
+
 fn lfn_checksum(short_name: &[u8; SFN_SIZE]) -> u8 {
     let mut chksum = num::Wrapping(0_u8);
     for b in short_name {
@@ -860,6 +1765,9 @@ pub(crate) struct LfnBuffer {
 
 const MAX_LONG_NAME_LEN: usize = 255;
 
+// A UTF-16 code unit takes at most 3 bytes in UTF-8, a surrogate pair takes 4 bytes
+pub(crate) const MAX_LONG_NAME_BYTES: usize = MAX_LONG_NAME_LEN * 3;
+
 #[cfg(feature = "lfn")]
 const MAX_LONG_DIR_ENTRIES: usize = (MAX_LONG_NAME_LEN + LFN_PART_LEN - 1) / LFN_PART_LEN;
 
@@ -988,11 +1896,12 @@ impl LongNameBuilder {
     }
 
     fn truncate(&mut self) {
-        // Truncate 0 and 0xFFFF characters from LFN buffer
-        let ucs2_units = &self.buf.ucs2_units;
-        let new_len = ucs2_units
+        // The name ends at the first 0 character followed by 0xFFFF padding, a name filling the last entry has neither
+        let ucs2_units = self.buf.as_ucs2_units();
+        let end = ucs2_units.iter().position(|c| *c == 0).unwrap_or(ucs2_units.len());
+        let new_len = ucs2_units[..end]
             .iter()
-            .rposition(|c| *c != 0xFFFF && *c != 0)
+            .rposition(|c| *c != LFN_PADDING)
             .map_or(0, |n| n + 1);
         self.buf.set_len(new_len);
     }
@@ -1037,16 +1946,21 @@ impl LongNameBuilder {
         data.copy_name_to_slice(&mut self.buf.ucs2_units[pos..pos + 13]);
     }
 
-    fn validate_chksum(&mut self, short_name: &[u8; SFN_SIZE]) {
+    /// Validates LFN checksum against the short name and drops the long name on mismatch.
+    ///
+    /// Returns `false` if the checksum did not match.
+    fn validate_chksum(&mut self, short_name: &[u8; SFN_SIZE]) -> bool {
         if self.is_empty() {
             // Nothing to validate - no LFN entries has been processed
-            return;
+            return true;
         }
         let chksum = lfn_checksum(short_name);
         if chksum != self.chksum {
             warn!("checksum mismatch {:x} {:x} {:?}", chksum, self.chksum, short_name);
             self.clear();
+            return false;
         }
+        true
     }
 }
 
@@ -1062,7 +1976,9 @@ impl LongNameBuilder {
     fn into_vec(self) {}
     fn truncate(&mut self) {}
     fn process(&mut self, _data: &DirLfnEntryData) {}
-    fn validate_chksum(&mut self, _short_name: &[u8; SFN_SIZE]) {}
+    fn validate_chksum(&mut self, _short_name: &[u8; SFN_SIZE]) -> bool {
+        true
+    }
 }
 
 #[cfg(feature = "lfn")]
@@ -1158,6 +2074,26 @@ impl Iterator for LfnEntriesGenerator {
 #[cfg(not(feature = "lfn"))]
 impl ExactSizeIterator for LfnEntriesGenerator {}
 
+// Returns whether the base name and the extension of `name` are lowercase if `name` can be restored from
+// `short_name` and the lowercase flags of the entry like Windows NT does. `None` is returned if `name` differs from
+// the short name in more than case or if a part has mixed case, so a long name is needed.
+fn lowercase_name_parts(name: &str, short_name: &[u8; SFN_SIZE]) -> Option<(bool, bool)> {
+    if !name.is_ascii()
+        || !ShortName::new(short_name)
+            .as_bytes()
+            .eq_ignore_ascii_case(name.as_bytes())
+    {
+        return None;
+    }
+    let (basename, ext) = name.rsplit_once('.').unwrap_or((name, ""));
+    let has_lower = |part: &str| part.bytes().any(|b| b.is_ascii_lowercase());
+    let has_upper = |part: &str| part.bytes().any(|b| b.is_ascii_uppercase());
+    if (has_lower(basename) && has_upper(basename)) || (has_lower(ext) && has_upper(ext)) {
+        return None;
+    }
+    Some((has_lower(basename), has_lower(ext)))
+}
+
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[derive(Default, Debug, Clone)]
 struct ShortNameGenerator {
@@ -1172,21 +2108,27 @@ struct ShortNameGenerator {
 }
 
 impl ShortNameGenerator {
-    fn new(name: &str) -> Self {
+    fn new<C: OemCpConverter>(name: &str, oem_cp_converter: &C) -> Self {
         // padded by ' '
         let mut short_name = [SFN_PADDING; SFN_SIZE];
         // find extension after last dot
         // Note: short file name cannot start with the extension
-        let dot_index_opt = name[1..].rfind('.').map(|index| index + 1);
+        let first_char_len = name.chars().next().map_or(0, char::len_utf8);
+        let dot_index_opt = name[first_char_len..].rfind('.').map(|index| index + first_char_len);
         // copy basename (part of filename before a dot)
         let basename_src = dot_index_opt.map_or(name, |dot_index| &name[..dot_index]);
         let (basename_len, basename_fits, basename_lossy) =
-            Self::copy_short_name_part(&mut short_name[0..8], basename_src);
+            Self::copy_short_name_part(&mut short_name[0..8], basename_src, oem_cp_converter);
         // copy file extension if exists
         let (name_fits, lossy_conv) = dot_index_opt.map_or((basename_fits, basename_lossy), |dot_index| {
-            let (_, ext_fits, ext_lossy) = Self::copy_short_name_part(&mut short_name[8..11], &name[dot_index + 1..]);
+            let (_, ext_fits, ext_lossy) =
+                Self::copy_short_name_part(&mut short_name[8..11], &name[dot_index + 1..], oem_cp_converter);
             (basename_fits && ext_fits, basename_lossy || ext_lossy)
         });
+        // FAT encodes character 0xE5 as 0x05 because 0xE5 marks deleted files
+        if short_name[0] == DIR_ENTRY_DELETED_FLAG {
+            short_name[0] = DIR_ENTRY_REALLY_E5_FLAG;
+        }
         let chksum = Self::checksum(name);
         Self {
             chksum,
@@ -1211,7 +2153,7 @@ impl ShortNameGenerator {
         short_name
     }
 
-    fn copy_short_name_part(dst: &mut [u8], src: &str) -> (usize, bool, bool) {
+    fn copy_short_name_part<C: OemCpConverter>(dst: &mut [u8], src: &str, oem_cp_converter: &C) -> (usize, bool, bool) {
         let mut dst_pos = 0;
         let mut lossy_conv = false;
         for c in src.chars() {
@@ -1221,28 +2163,47 @@ impl ShortNameGenerator {
             }
             // Make sure character is allowed in 8.3 name
             #[rustfmt::skip]
-            let fixed_c = match c {
+            let oem_char = match c {
                 // strip spaces and dots
                 ' ' | '.' => {
                     lossy_conv = true;
                     continue;
                 },
-                // copy allowed characters
+                // copy allowed characters, short name is always uppercase
                 'A'..='Z' | 'a'..='z' | '0'..='9'
-                | '!' | '#' | '$' | '%' | '&' | '\'' | '(' | ')' | '-' | '@' | '^' | '_' | '`' | '{' | '}' | '~' => c,
+                | '!' | '#' | '$' | '%' | '&' | '\'' | '(' | ')' | '-' | '@' | '^' | '_' | '`' | '{' | '}' | '~' => {
+                    c.to_ascii_uppercase() as u8 // SAFE: c is in range 0x20-0x7F
+                },
+                // keep non-ASCII characters that exist in the OEM code page
+                _ if !c.is_ascii() => Self::encode_oem_char(c, oem_cp_converter).unwrap_or_else(|| {
+                    lossy_conv = true;
+                    b'_'
+                }),
                 // replace disallowed characters by underscore
-                _ => '_',
+                _ => {
+                    lossy_conv = true;
+                    b'_'
+                },
             };
-            // Update 'lossy conversion' flag
-            lossy_conv = lossy_conv || (fixed_c != c);
-            // short name is always uppercase
-            let upper = fixed_c.to_ascii_uppercase();
-            dst[dst_pos] = upper as u8; // SAFE: upper is in range 0x20-0x7F
+            dst[dst_pos] = oem_char;
             dst_pos += 1;
         }
         (dst_pos, true, lossy_conv)
     }
 
+    // Encodes the uppercase form of a non-ASCII character, ignoring replacement characters of the converter
+    fn encode_oem_char<C: OemCpConverter>(c: char, oem_cp_converter: &C) -> Option<u8> {
+        let mut upper_iter = c.to_uppercase();
+        // a character without a single character uppercase form (e.g. 'ß') is kept like in the simple case mapping
+        let upper = match (upper_iter.next(), upper_iter.next()) {
+            (Some(upper), None) => upper,
+            _ => c,
+        };
+        oem_cp_converter
+            .encode(upper)
+            .filter(|&oem_char| oem_char >= 0x80 && oem_cp_converter.decode(oem_char) == upper)
+    }
+
     fn add_existing(&mut self, short_name: &[u8; SFN_SIZE]) {
         // check for exact match collision
         if short_name == &self.short_name {
@@ -1298,11 +2259,15 @@ impl ShortNameGenerator {
         chksum.0
     }
 
+    fn exact_short_name(&self) -> Option<[u8; SFN_SIZE]> {
+        (!self.lossy_conv && self.name_fits && !self.exact_match).then_some(self.short_name)
+    }
+
     fn generate(&self) -> Result<[u8; SFN_SIZE], Error<()>> {
-        if !self.lossy_conv && self.name_fits && !self.exact_match {
+        if let Some(short_name) = self.exact_short_name() {
             // If there was no lossy conversion and name fits into
             // 8.3 convention and there is no collision return it as is
-            return Ok(self.short_name);
+            return Ok(short_name);
         }
         // Try using long 6-characters prefix
         for i in 1..5 {
@@ -1364,6 +2329,21 @@ impl ShortNameGenerator {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fs::LossyOemCpConverter;
+    use crate::oem_cp::Cp850OemCpConverter;
+
+    #[test]
+    fn test_lowercase_name_parts() {
+        assert_eq!(lowercase_name_parts("readme.txt", b"README  TXT"), Some((true, true)));
+        assert_eq!(lowercase_name_parts("readme.TXT", b"README  TXT"), Some((true, false)));
+        assert_eq!(lowercase_name_parts("README.txt", b"README  TXT"), Some((false, true)));
+        assert_eq!(lowercase_name_parts("README.TXT", b"README  TXT"), Some((false, false)));
+        assert_eq!(lowercase_name_parts("makefile", b"MAKEFILE   "), Some((true, false)));
+        assert_eq!(lowercase_name_parts("..", b"..         "), Some((false, false)));
+        assert_eq!(lowercase_name_parts("ReadMe.txt", b"README  TXT"), None);
+        assert_eq!(lowercase_name_parts("readme.txt", b"README~1TXT"), None);
+        assert_eq!(lowercase_name_parts("read me.txt", b"README  TXT"), None);
+    }
 
     #[test]
     fn test_split_path() {
@@ -1372,27 +2352,103 @@ mod tests {
         assert_eq!(split_path("aaa"), ("aaa", None));
     }
 
+    #[test]
+    fn test_generate_short_name_oem() {
+        let cp850 = Cp850OemCpConverter::new();
+        // U+00D5 is 0xE5 in CP850 which is stored as 0x05
+        assert_eq!(
+            ShortNameGenerator::new("\u{F5}dla.txt", &cp850).generate().ok(),
+            Some(*b"\x05DLA    TXT")
+        );
+        assert_eq!(
+            ShortNameGenerator::new("ab\u{FC}.txt", &cp850).generate().ok(),
+            Some(*b"AB\x9A     TXT")
+        );
+        // characters missing in the code page are replaced
+        assert_eq!(
+            ShortNameGenerator::new("\u{20AC}.txt", &cp850).generate().ok(),
+            Some(*b"_~1     TXT")
+        );
+        assert_eq!(
+            ShortNameGenerator::new("\u{F5}dla.txt", &LossyOemCpConverter::new())
+                .generate()
+                .ok(),
+            Some(*b"_DLA~1  TXT")
+        );
+    }
+
+    #[test]
+    fn test_generate_short_name_unicode() {
+        let cp850 = Cp850OemCpConverter::new();
+        // lowercase characters are uppercased before encoding
+        assert_eq!(
+            ShortNameGenerator::new("caf\u{E9}.txt", &cp850).generate().ok(),
+            Some(*b"CAF\x90    TXT")
+        );
+        assert_eq!(
+            ShortNameGenerator::new("na\u{EF}ve.\u{E7}", &cp850).generate().ok(),
+            Some(*b"NA\xD8VE   \x80  ")
+        );
+        // 'ß' has no single character uppercase form
+        assert_eq!(
+            ShortNameGenerator::new("stra\u{DF}e.txt", &cp850).generate().ok(),
+            Some(*b"STRA\xE1E  TXT")
+        );
+        // characters missing in the code page are replaced and a numeric tail is added
+        assert_eq!(
+            ShortNameGenerator::new("\u{65E5}\u{672C}.txt", &cp850).generate().ok(),
+            Some(*b"__~1    TXT")
+        );
+        assert_eq!(
+            ShortNameGenerator::new("\u{3A9}mega.txt", &cp850).generate().ok(),
+            Some(*b"_MEGA~1 TXT")
+        );
+    }
+
     #[test]
     fn test_generate_short_name() {
-        assert_eq!(ShortNameGenerator::new("Foo").generate().ok(), Some(*b"FOO        "));
-        assert_eq!(ShortNameGenerator::new("Foo.b").generate().ok(), Some(*b"FOO     B  "));
         assert_eq!(
-            ShortNameGenerator::new("Foo.baR").generate().ok(),
+            ShortNameGenerator::new("Foo", &LossyOemCpConverter::new())
+                .generate()
+                .ok(),
+            Some(*b"FOO        ")
+        );
+        assert_eq!(
+            ShortNameGenerator::new("Foo.b", &LossyOemCpConverter::new())
+                .generate()
+                .ok(),
+            Some(*b"FOO     B  ")
+        );
+        assert_eq!(
+            ShortNameGenerator::new("Foo.baR", &LossyOemCpConverter::new())
+                .generate()
+                .ok(),
             Some(*b"FOO     BAR")
         );
         assert_eq!(
-            ShortNameGenerator::new("Foo+1.baR").generate().ok(),
+            ShortNameGenerator::new("Foo+1.baR", &LossyOemCpConverter::new())
+                .generate()
+                .ok(),
             Some(*b"FOO_1~1 BAR")
         );
         assert_eq!(
-            ShortNameGenerator::new("ver +1.2.text").generate().ok(),
+            ShortNameGenerator::new("ver +1.2.text", &LossyOemCpConverter::new())
+                .generate()
+                .ok(),
             Some(*b"VER_12~1TEX")
         );
         assert_eq!(
-            ShortNameGenerator::new(".bashrc.swp").generate().ok(),
+            ShortNameGenerator::new(".bashrc.swp", &LossyOemCpConverter::new())
+                .generate()
+                .ok(),
             Some(*b"BASHRC~1SWP")
         );
-        assert_eq!(ShortNameGenerator::new(".foo").generate().ok(), Some(*b"FOO~1      "));
+        assert_eq!(
+            ShortNameGenerator::new(".foo", &LossyOemCpConverter::new())
+                .generate()
+                .ok(),
+            Some(*b"FOO~1      ")
+        );
     }
 
     #[test]
@@ -1405,10 +2461,59 @@ mod tests {
         lfn_checksum(&[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
     }
 
+    #[test]
+    #[cfg(feature = "lfn")]
+    fn test_lfn_checksum_mismatch_falls_back_to_short_name() {
+        let short_name = *b"LONGFI~1TXT";
+        let lfn_utf16 = "LongFileName.txt".encode_utf16().collect::<Vec<u16>>();
+        // matching checksum - long name is kept
+        let mut builder = LongNameBuilder::new();
+        for lfn_entry in LfnEntriesGenerator::new(&lfn_utf16, lfn_checksum(&short_name)) {
+            builder.process(&lfn_entry);
+        }
+        assert!(builder.validate_chksum(&short_name));
+        assert_eq!(builder.into_buf().as_ucs2_units(), &lfn_utf16[..]);
+        // deliberately mismatched checksum - long name is dropped
+        let mut builder = LongNameBuilder::new();
+        for lfn_entry in LfnEntriesGenerator::new(&lfn_utf16, lfn_checksum(&short_name).wrapping_add(1)) {
+            builder.process(&lfn_entry);
+        }
+        assert!(!builder.validate_chksum(&short_name));
+        assert!(builder.into_buf().as_ucs2_units().is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "lfn")]
+    fn test_lfn_surrogate_pairs_and_padding() {
+        let short_name = *b"ABCDEF~1TXT";
+        let chksum = lfn_checksum(&short_name);
+        // the surrogate pair of the emoji is split between the first and the second entry
+        let name = "abcdefghijkl\u{1F600}.txt";
+        let lfn_utf16 = name.encode_utf16().collect::<Vec<u16>>();
+        let mut builder = LongNameBuilder::new();
+        for lfn_entry in LfnEntriesGenerator::new(&lfn_utf16, chksum) {
+            builder.process(&lfn_entry);
+        }
+        assert!(builder.validate_chksum(&short_name));
+        let buf = builder.into_buf();
+        assert_eq!(
+            char::decode_utf16(buf.as_ucs2_units().iter().copied()).collect::<Result<String, _>>(),
+            Ok(name.into())
+        );
+        // characters after the terminator are ignored, e.g. left from an older longer name
+        let mut lfn_part = [LFN_PADDING; LFN_PART_LEN];
+        lfn_part[..3].copy_from_slice(&[u16::from(b'a'), 0, u16::from(b'x')]);
+        let mut lfn_entry = DirLfnEntryData::new(1 | LFN_ENTRY_LAST_FLAG, chksum);
+        lfn_entry.copy_name_from_slice(&lfn_part);
+        let mut builder = LongNameBuilder::new();
+        builder.process(&lfn_entry);
+        assert_eq!(builder.into_buf().as_ucs2_units(), &[u16::from(b'a')]);
+    }
+
     #[test]
     fn test_generate_short_name_collisions_long() {
         let mut buf: [u8; SFN_SIZE];
-        let mut gen = ShortNameGenerator::new("TextFile.Mine.txt");
+        let mut gen = ShortNameGenerator::new("TextFile.Mine.txt", &LossyOemCpConverter::new());
         buf = gen.generate().unwrap();
         assert_eq!(&buf, b"TEXTFI~1TXT");
         gen.add_existing(&buf);
@@ -1442,10 +2547,25 @@ mod tests {
         assert_eq!(&buf, b"TE527E~1TXT");
     }
 
+    #[test]
+    fn test_generate_short_name_shared_prefix() {
+        let gen = ShortNameGenerator::new("LongFileName1.txt", &LossyOemCpConverter::new());
+        let buf = gen.generate().unwrap();
+        assert_eq!(&buf, b"LONGFI~1TXT");
+        // different long name sharing the same 6-character prefix
+        let mut gen = ShortNameGenerator::new("LongFileName2.txt", &LossyOemCpConverter::new());
+        gen.add_existing(&buf);
+        assert_eq!(&gen.generate().unwrap(), b"LONGFI~2TXT");
+        // different extension does not collide
+        let mut gen = ShortNameGenerator::new("LongFileName3.bin", &LossyOemCpConverter::new());
+        gen.add_existing(&buf);
+        assert_eq!(&gen.generate().unwrap(), b"LONGFI~1BIN");
+    }
+
     #[test]
     fn test_generate_short_name_collisions_short() {
         let mut buf: [u8; SFN_SIZE];
-        let mut gen = ShortNameGenerator::new("x.txt");
+        let mut gen = ShortNameGenerator::new("x.txt", &LossyOemCpConverter::new());
         buf = gen.generate().unwrap();
         assert_eq!(&buf, b"X       TXT");
         gen.add_existing(&buf);
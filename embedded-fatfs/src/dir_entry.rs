@@ -10,11 +10,12 @@ use alloc::string::String;
 
 #[cfg(feature = "lfn")]
 use crate::dir::LfnBuffer;
-use crate::dir::{Dir, DirRawStream};
+use crate::dir::{Dir, DirPosition, DirRawStream};
 use crate::error::{Error, IoError, ReadExactError};
 use crate::file::File;
-use crate::fs::{FatType, FileSystem, OemCpConverter, ReadWriteSeek};
-use crate::io::{self, Read, ReadLeExt, Write, WriteLeExt};
+use crate::fs::{Clusters, FatType, FileSystem, FsIoAdapter, OemCpConverter, ReadWriteSeek};
+use crate::io::{self, Read, ReadLeExt, Seek, Write};
+use crate::table::RESERVED_FAT_ENTRIES;
 use crate::time::{Date, DateTime};
 use crate::FileContext;
 
@@ -105,7 +106,7 @@ impl ShortName {
         }
     }
 
-    fn as_bytes(&self) -> &[u8] {
+    pub(crate) fn as_bytes(&self) -> &[u8] {
         &self.name[..usize::from(self.len)]
     }
 
@@ -228,8 +229,27 @@ impl DirFileEntryData {
         self.reserved_0 & (1 << 4) != 0
     }
 
-    fn created(&self) -> DateTime {
-        DateTime::decode(self.create_date, self.create_time_1, self.create_time_0)
+    pub(crate) fn set_lowercase_parts(&mut self, basename: bool, ext: bool) {
+        self.reserved_0 &= !((1 << 3) | (1 << 4));
+        if basename {
+            self.reserved_0 |= 1 << 3;
+        }
+        if ext {
+            self.reserved_0 |= 1 << 4;
+        }
+    }
+
+    // a zero date is written by implementations not supporting creation time
+    fn created(&self) -> Option<DateTime> {
+        if self.create_date == 0 {
+            None
+        } else {
+            Some(DateTime::decode(
+                self.create_date,
+                self.create_time_1,
+                self.create_time_0,
+            ))
+        }
     }
 
     fn accessed(&self) -> Date {
@@ -257,18 +277,21 @@ impl DirFileEntryData {
     }
 
     pub(crate) async fn serialize<W: Write>(&self, wrt: &mut W) -> Result<(), W::Error> {
-        wrt.write_all(&self.name).await?;
-        wrt.write_u8(self.attrs.bits()).await?;
-        wrt.write_u8(self.reserved_0).await?;
-        wrt.write_u8(self.create_time_0).await?;
-        wrt.write_u16_le(self.create_time_1).await?;
-        wrt.write_u16_le(self.create_date).await?;
-        wrt.write_u16_le(self.access_date).await?;
-        wrt.write_u16_le(self.first_cluster_hi).await?;
-        wrt.write_u16_le(self.modify_time).await?;
-        wrt.write_u16_le(self.modify_date).await?;
-        wrt.write_u16_le(self.first_cluster_lo).await?;
-        wrt.write_u32_le(self.size).await?;
+        // the entry is written at once so a dropped future cannot leave it half-updated
+        let mut buf = [0_u8; DIR_ENTRY_SIZE as usize];
+        buf[0..11].copy_from_slice(&self.name);
+        buf[11] = self.attrs.bits();
+        buf[12] = self.reserved_0;
+        buf[13] = self.create_time_0;
+        buf[14..16].copy_from_slice(&self.create_time_1.to_le_bytes());
+        buf[16..18].copy_from_slice(&self.create_date.to_le_bytes());
+        buf[18..20].copy_from_slice(&self.access_date.to_le_bytes());
+        buf[20..22].copy_from_slice(&self.first_cluster_hi.to_le_bytes());
+        buf[22..24].copy_from_slice(&self.modify_time.to_le_bytes());
+        buf[24..26].copy_from_slice(&self.modify_date.to_le_bytes());
+        buf[26..28].copy_from_slice(&self.first_cluster_lo.to_le_bytes());
+        buf[28..32].copy_from_slice(&self.size.to_le_bytes());
+        wrt.write_all(&buf).await?;
         wrt.flush().await?;
         Ok(())
     }
@@ -328,20 +351,22 @@ impl DirLfnEntryData {
     }
 
     pub(crate) async fn serialize<W: Write>(&self, wrt: &mut W) -> Result<(), W::Error> {
-        wrt.write_u8(self.order).await?;
-        for ch in &self.name_0 {
-            wrt.write_u16_le(*ch).await?;
+        let mut buf = [0_u8; DIR_ENTRY_SIZE as usize];
+        buf[0] = self.order;
+        for (dst, ch) in buf[1..11].chunks_exact_mut(2).zip(&self.name_0) {
+            dst.copy_from_slice(&ch.to_le_bytes());
         }
-        wrt.write_u8(self.attrs.bits()).await?;
-        wrt.write_u8(self.entry_type).await?;
-        wrt.write_u8(self.checksum).await?;
-        for ch in &self.name_1 {
-            wrt.write_u16_le(*ch).await?;
+        buf[11] = self.attrs.bits();
+        buf[12] = self.entry_type;
+        buf[13] = self.checksum;
+        for (dst, ch) in buf[14..26].chunks_exact_mut(2).zip(&self.name_1) {
+            dst.copy_from_slice(&ch.to_le_bytes());
         }
-        wrt.write_u16_le(self.reserved_0).await?;
-        for ch in &self.name_2 {
-            wrt.write_u16_le(*ch).await?;
+        buf[26..28].copy_from_slice(&self.reserved_0.to_le_bytes());
+        for (dst, ch) in buf[28..32].chunks_exact_mut(2).zip(&self.name_2) {
+            dst.copy_from_slice(&ch.to_le_bytes());
         }
+        wrt.write_all(&buf).await?;
         wrt.flush().await?;
         Ok(())
     }
@@ -505,8 +530,15 @@ impl DirEntryEditor {
         }
     }
 
+    // Changes the size of the opened file only, the entry on the storage keeps the old size
+    pub(crate) fn clamp_size(&mut self, size: u32) {
+        if self.data.size().is_some() {
+            self.data.set_size(size);
+        }
+    }
+
     pub(crate) fn set_created(&mut self, date_time: DateTime) {
-        if date_time != self.data.created() {
+        if Some(date_time) != self.data.created() {
             self.data.set_created(date_time);
             self.dirty = true;
         }
@@ -526,6 +558,25 @@ impl DirEntryEditor {
         }
     }
 
+    // Takes the size and the first cluster from the entry on the storage if it describes a bigger file, e.g. after
+    // another handle of the same file has appended data and flushed it
+    pub(crate) async fn reload_size<IO: ReadWriteSeek, TP, OCC>(
+        &mut self,
+        fs: &FileSystem<IO, TP, OCC>,
+    ) -> Result<(), Error<IO::Error>> {
+        let mut buf = [0_u8; DIR_ENTRY_SIZE as usize];
+        let mut disk = FsIoAdapter::new(fs);
+        disk.seek(io::SeekFrom::Start(self.pos)).await?;
+        disk.read_exact(&mut buf).await?;
+        let size = u32::from_le_bytes([buf[28], buf[29], buf[30], buf[31]]);
+        if self.data.size().is_some_and(|n| size > n) {
+            self.data.first_cluster_hi = u16::from_le_bytes([buf[20], buf[21]]);
+            self.data.first_cluster_lo = u16::from_le_bytes([buf[26], buf[27]]);
+            self.data.size = size;
+        }
+        Ok(())
+    }
+
     pub(crate) async fn flush<IO: ReadWriteSeek, TP, OCC>(
         &mut self,
         fs: &FileSystem<IO, TP, OCC>,
@@ -544,6 +595,108 @@ impl DirEntryEditor {
     }
 }
 
+/// Metadata of a file or a directory.
+///
+/// This struct is returned by the `metadata` method on `DirEntry`, `File` and `Dir`. It is a copy of the values, so it
+/// is not updated when the file is changed later. Metadata returned by `DirEntry` reflects the directory entry as it
+/// was read from the storage, while metadata returned by `File` includes changes made through that handle which were
+/// not flushed yet (e.g. the size after a write).
+///
+/// The root directory has no directory entry. Its metadata has the `DIRECTORY` attribute, zero length, no creation
+/// time and the other timestamps set to the earliest date supported by FAT (1980-01-01 00:00:00).
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Metadata {
+    len: u64,
+    attributes: FileAttributes,
+    created: Option<DateTime>,
+    accessed: Date,
+    modified: DateTime,
+    first_cluster: Option<u32>,
+}
+
+impl Metadata {
+    pub(crate) fn from_entry(data: &DirFileEntryData, fat_type: FatType) -> Self {
+        Self {
+            len: u64::from(data.size().unwrap_or(0)),
+            attributes: data.attrs,
+            created: data.created(),
+            accessed: data.accessed(),
+            modified: data.modified(),
+            first_cluster: data.first_cluster(fat_type),
+        }
+    }
+
+    pub(crate) fn root_dir(first_cluster: Option<u32>) -> Self {
+        let data = DirFileEntryData::new([0; SFN_SIZE], FileAttributes::DIRECTORY);
+        Self {
+            first_cluster,
+            ..Self::from_entry(&data, FatType::Fat32)
+        }
+    }
+
+    /// Returns file size or 0 for directory.
+    #[must_use]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Checks if the size is 0.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Checks if this is a directory.
+    #[must_use]
+    pub fn is_dir(&self) -> bool {
+        self.attributes.contains(FileAttributes::DIRECTORY)
+    }
+
+    /// Checks if this is a file.
+    #[must_use]
+    pub fn is_file(&self) -> bool {
+        !self.is_dir()
+    }
+
+    /// Returns file attributes.
+    #[must_use]
+    pub fn attributes(&self) -> FileAttributes {
+        self.attributes
+    }
+
+    /// Returns file creation date and time or `None` if it is not set.
+    ///
+    /// Resolution of the time field is 1/100s.
+    #[must_use]
+    pub fn created(&self) -> Option<DateTime> {
+        self.created
+    }
+
+    /// Returns file last access date.
+    #[must_use]
+    pub fn accessed(&self) -> Date {
+        self.accessed
+    }
+
+    /// Returns file last modification date and time.
+    ///
+    /// Resolution of the time field is 2s.
+    #[must_use]
+    pub fn modified(&self) -> DateTime {
+        self.modified
+    }
+
+    /// Returns the first cluster of the file or `None` if no cluster is allocated.
+    ///
+    /// Empty files have no clusters. The root directory of a FAT12/FAT16 volume is not stored in clusters, so `None`
+    /// is returned for it as well.
+    #[must_use]
+    pub fn first_cluster(&self) -> Option<u32> {
+        self.first_cluster
+    }
+}
+
 /// A FAT directory entry.
 ///
 /// `DirEntry` is returned by `DirIter` when reading a directory.
@@ -560,23 +713,34 @@ pub struct DirEntry<'a, IO: ReadWriteSeek, TP, OCC> {
 
 #[allow(clippy::len_without_is_empty)]
 impl<'a, IO: ReadWriteSeek, TP, OCC: OemCpConverter> DirEntry<'a, IO, TP, OCC> {
-    /// Returns short file name.
+    /// Returns short file name in the `NAME.EXT` form.
     ///
-    /// Non-ASCII characters are replaced by the replacement character (U+FFFD).
+    /// Characters are decoded from the OEM codepage and the ones missing in it are replaced by the replacement
+    /// character (U+FFFD). The base name and the extension are shown in lowercase if the entry is marked so, which
+    /// Windows NT does for names like `readme.txt` that fit in the 8.3 format and differ only in case.
     #[cfg(feature = "alloc")]
     #[must_use]
     pub fn short_file_name(&self) -> String {
-        self.short_name.to_string(&self.fs.options.oem_cp_converter)
+        self.data.lowercase_name().to_string(&self.fs.options.oem_cp_converter)
     }
 
     /// Returns short file name as byte array slice.
     ///
-    /// Characters are encoded in the OEM codepage.
+    /// Characters are encoded in the OEM codepage. The name is always uppercase as stored in the entry.
     #[must_use]
     pub fn short_file_name_as_bytes(&self) -> &[u8] {
         self.short_name.as_bytes()
     }
 
+    /// Returns the short name exactly as stored in the directory entry.
+    ///
+    /// The first 8 bytes are the base name and the last 3 bytes the extension, both padded with spaces and encoded in
+    /// the OEM codepage. A name starting with the character 0xE5 is stored with 0x05 as its first byte.
+    #[must_use]
+    pub fn short_name_raw(&self) -> [u8; SFN_SIZE] {
+        self.data.name
+    }
+
     /// Returns long file name as u16 array slice.
     ///
     /// Characters are encoded in the UCS-2 encoding.
@@ -611,6 +775,15 @@ impl<'a, IO: ReadWriteSeek, TP, OCC: OemCpConverter> DirEntry<'a, IO, TP, OCC> {
         self.data.attrs
     }
 
+    /// Returns metadata of the file or directory.
+    ///
+    /// The metadata is a snapshot of the directory entry taken when the directory was read, so changes made later
+    /// through an open `File` are not visible.
+    #[must_use]
+    pub fn metadata(&self) -> Metadata {
+        Metadata::from_entry(&self.data, self.fs.fat_type())
+    }
+
     /// Checks if entry belongs to directory.
     #[must_use]
     pub fn is_dir(&self) -> bool {
@@ -623,11 +796,37 @@ impl<'a, IO: ReadWriteSeek, TP, OCC: OemCpConverter> DirEntry<'a, IO, TP, OCC> {
         self.data.is_file()
     }
 
+    /// Returns an iterator over the clusters of this entry in the order of its cluster chain.
+    ///
+    /// Nothing is returned for empty files. The first cluster is taken from this entry so clusters allocated for an
+    /// empty file after the entry was read are not returned.
+    #[must_use]
+    pub fn clusters(&self) -> Clusters<'a, IO, TP, OCC> {
+        Clusters::new(self.fs, self.first_cluster())
+    }
+
+    /// Returns the position after this entry in its directory.
+    ///
+    /// Passing it to `Dir::iter_from` continues reading the directory with the next entry. See [`DirPosition`].
+    #[must_use]
+    pub fn position(&self) -> DirPosition {
+        // Note: the FAT12/FAT16 root directory is placed before the data region
+        let cluster = if self.entry_pos < self.fs.offset_from_cluster(RESERVED_FAT_ENTRIES) {
+            None
+        } else {
+            Some(self.fs.cluster_from_offset(self.entry_pos))
+        };
+        DirPosition {
+            cluster,
+            offset: self.offset_range.1 as u32,
+        }
+    }
+
     pub(crate) fn first_cluster(&self) -> Option<u32> {
         self.data.first_cluster(self.fs.fat_type())
     }
 
-    fn editor(&self) -> DirEntryEditor {
+    pub(crate) fn editor(&self) -> DirEntryEditor {
         DirEntryEditor::new(self.data.clone(), self.entry_pos)
     }
 
@@ -637,6 +836,9 @@ impl<'a, IO: ReadWriteSeek, TP, OCC: OemCpConverter> DirEntry<'a, IO, TP, OCC> {
 
     /// Returns `File` struct for this entry.
     ///
+    /// Unlike `Dir::open_file` the size of the file is not checked against its cluster chain, so
+    /// `FsOptions::size_mismatch_policy` is not applied.
+    ///
     /// # Panics
     ///
     /// Will panic if this is not a file.
@@ -699,15 +901,18 @@ impl<'a, IO: ReadWriteSeek, TP, OCC: OemCpConverter> DirEntry<'a, IO, TP, OCC> {
         u64::from(self.data.size)
     }
 
-    /// Returns file creation date and time.
+    /// Returns file creation date and time or `None` if it is not set.
     ///
-    /// Resolution of the time field is 1/100s.
+    /// Resolution of the time field is 1/100s. A zeroed date field (written by implementations not supporting creation
+    /// time) is reported as `None`, other out of range values stored in the entry are clamped to a valid date and time.
     #[must_use]
-    pub fn created(&self) -> DateTime {
+    pub fn created(&self) -> Option<DateTime> {
         self.data.created()
     }
 
     /// Returns file last access date.
+    ///
+    /// Out of range values stored in the entry are clamped to a valid date.
     #[must_use]
     pub fn accessed(&self) -> Date {
         self.data.accessed()
@@ -715,7 +920,8 @@ impl<'a, IO: ReadWriteSeek, TP, OCC: OemCpConverter> DirEntry<'a, IO, TP, OCC> {
 
     /// Returns file last modification date and time.
     ///
-    /// Resolution of the time field is 2s.
+    /// Resolution of the time field is 2s. Out of range values stored in the entry are clamped to a valid date and
+    /// time.
     #[must_use]
     pub fn modified(&self) -> DateTime {
         self.data.modified()
@@ -780,6 +986,7 @@ impl<IO: ReadWriteSeek, TP, OCC> defmt::Format for DirEntry<'_, IO, TP, OCC> {
 mod tests {
     use super::*;
     use crate::fs::LossyOemCpConverter;
+    use crate::time::Time;
 
     #[test]
     fn short_name_with_ext() {
@@ -834,4 +1041,22 @@ mod tests {
         raw_entry.reserved_0 = 0;
         assert_eq!(raw_entry.lowercase_name().to_string(&oem_cp_conv), "FOO.RS");
     }
+
+    #[test]
+    fn unset_creation_time() {
+        let mut raw_entry = DirFileEntryData::new(*b"FOO     RS ", FileAttributes::ARCHIVE);
+        assert_eq!(raw_entry.created(), None);
+        // a set time is not reported as unset even if it is the earliest one
+        let earliest = DateTime::new(Date::new(1980, 1, 1), Time::new(0, 0, 0, 0));
+        raw_entry.set_created(earliest);
+        assert_eq!(raw_entry.created(), Some(earliest));
+        assert_eq!(
+            Metadata::from_entry(&raw_entry, FatType::Fat16).created(),
+            Some(earliest)
+        );
+        // only the date marks the field as unset
+        raw_entry.create_date = 0;
+        raw_entry.create_time_1 = 0x1234;
+        assert_eq!(raw_entry.created(), None);
+    }
 }
@@ -1,14 +1,21 @@
-use core::fmt::Debug;
 pub(crate) use embedded_io_async::{Error as IoError, ErrorKind, ReadExactError};
 
+use crate::fs::FatType;
+
 /// Error enum with all errors that can be returned by functions from this crate
 ///
-/// Generic parameter `T` is a type of external error returned by the user provided storage
+/// Generic parameter `T` is a type of external error returned by the user provided storage. The error does not allocate
+/// or format anything, so it can be matched on in `no_std` code. `Display` provides a human-readable message and
+/// `kind` maps it to an `embedded_io_async::ErrorKind`. With the `std` feature it can be converted into
+/// `std::io::Error`.
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[derive(Debug)]
 #[non_exhaustive]
 pub enum Error<T> {
     /// A user provided storage instance returned an error during an input/output operation.
+    ///
+    /// The error is passed through unchanged, so e.g. a timeout reported by the storage can be told apart from a CRC
+    /// error and the operation can be retried.
     Io(T),
     /// A read operation cannot be completed because an end of a file has been reached prematurely.
     UnexpectedEof,
@@ -26,15 +33,45 @@ pub enum Error<T> {
     CorruptedFileSystem,
     /// There is not enough free space on the storage to finish the requested operation.
     NotEnoughSpace,
+    /// The fixed size root directory of a FAT12/FAT16 volume has no room for the new entries.
+    RootDirectoryFull,
     /// The provided file name is either too long or empty.
     InvalidFileNameLength,
     /// The provided file name contains an invalid character.
     UnsupportedFileNameCharacter,
+    /// A write operation cannot be completed because the filesystem is mounted in read-only mode.
+    ReadOnly,
+    /// Data read from a file is not valid for the requested operation (e.g. not UTF-8 in `File::read_to_string`).
+    InvalidData,
+    /// A volume cannot be formatted with the FAT type requested by `FormatVolumeOptions::fat_type`.
+    ///
+    /// The volume would have `clusters` clusters, which is outside of the range given by `FatType::min_clusters` and
+    /// `FatType::max_clusters`. `suggested_cluster_size` is the smallest cluster size in bytes giving a valid number
+    /// of clusters or `None` if no cluster size does.
+    FatTypeMismatch {
+        fat_type: FatType,
+        clusters: u32,
+        suggested_cluster_size: Option<u32>,
+    },
 }
 
-impl<T: Debug> IoError for Error<T> {
+impl<T: IoError> IoError for Error<T> {
     fn kind(&self) -> ErrorKind {
-        ErrorKind::Other
+        match self {
+            Error::Io(io_error) => io_error.kind(),
+            Error::WriteZero => ErrorKind::WriteZero,
+            Error::InvalidInput
+            | Error::InvalidFileNameLength
+            | Error::UnsupportedFileNameCharacter
+            | Error::FatTypeMismatch { .. } => ErrorKind::InvalidInput,
+            Error::NotFound => ErrorKind::NotFound,
+            Error::AlreadyExists => ErrorKind::AlreadyExists,
+            Error::CorruptedFileSystem | Error::InvalidData => ErrorKind::InvalidData,
+            Error::ReadOnly => ErrorKind::PermissionDenied,
+            Error::UnexpectedEof | Error::DirectoryIsNotEmpty | Error::NotEnoughSpace | Error::RootDirectoryFull => {
+                ErrorKind::Other
+            }
+        }
     }
 }
 
@@ -68,6 +105,7 @@ impl<T: core::fmt::Display> core::fmt::Display for Error<T> {
             Error::Io(io_error) => write!(f, "IO error: {}", io_error),
             Error::UnexpectedEof => write!(f, "Unexpected end of file"),
             Error::NotEnoughSpace => write!(f, "Not enough space"),
+            Error::RootDirectoryFull => write!(f, "No space in root directory"),
             Error::WriteZero => write!(f, "Write zero"),
             Error::InvalidInput => write!(f, "Invalid input"),
             Error::InvalidFileNameLength => write!(f, "Invalid file name length"),
@@ -76,6 +114,26 @@ impl<T: core::fmt::Display> core::fmt::Display for Error<T> {
             Error::NotFound => write!(f, "No such file or directory"),
             Error::AlreadyExists => write!(f, "File or directory already exists"),
             Error::CorruptedFileSystem => write!(f, "Corrupted file system"),
+            Error::ReadOnly => write!(f, "Read-only file system"),
+            Error::InvalidData => write!(f, "Invalid data"),
+            Error::FatTypeMismatch {
+                fat_type,
+                clusters,
+                suggested_cluster_size,
+            } => {
+                write!(
+                    f,
+                    "{:?} requires between {} and {} clusters but the volume has {}",
+                    fat_type,
+                    fat_type.min_clusters(),
+                    fat_type.max_clusters(),
+                    clusters
+                )?;
+                match suggested_cluster_size {
+                    Some(size) => write!(f, ", use clusters of {} bytes", size),
+                    None => write!(f, ", no cluster size fits"),
+                }
+            }
         }
     }
 }
@@ -90,3 +148,48 @@ impl<T: std::error::Error + 'static> std::error::Error for Error<T> {
         }
     }
 }
+
+#[cfg(feature = "std")]
+impl<T: IoError + std::error::Error + Send + Sync + 'static> From<Error<T>> for std::io::Error {
+    fn from(error: Error<T>) -> Self {
+        let kind = match error {
+            Error::UnexpectedEof => std::io::ErrorKind::UnexpectedEof,
+            _ => error.kind().into(),
+        };
+        std::io::Error::new(kind, error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_kind() {
+        let io_error = std::io::Error::from(std::io::ErrorKind::TimedOut);
+        assert_eq!(Error::Io(io_error).kind(), ErrorKind::TimedOut);
+        assert_eq!(Error::<std::io::Error>::NotFound.kind(), ErrorKind::NotFound);
+        assert_eq!(
+            Error::<std::io::Error>::InvalidFileNameLength.kind(),
+            ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            Error::<std::io::Error>::CorruptedFileSystem.kind(),
+            ErrorKind::InvalidData
+        );
+        assert_eq!(Error::<std::io::Error>::ReadOnly.kind(), ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn test_into_std_io_error() {
+        let error = std::io::Error::from(Error::<std::io::Error>::UnexpectedEof);
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+        let error = std::io::Error::from(Error::<std::io::Error>::AlreadyExists);
+        assert_eq!(error.kind(), std::io::ErrorKind::AlreadyExists);
+        assert_eq!(error.to_string(), "File or directory already exists");
+        // the storage error stays available as the source
+        let error = std::io::Error::from(Error::Io(std::io::Error::other("bus fault")));
+        let source = std::error::Error::source(error.get_ref().unwrap()).unwrap();
+        assert_eq!(source.to_string(), "bus fault");
+    }
+}
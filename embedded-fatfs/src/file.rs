@@ -1,20 +1,204 @@
+#[cfg(all(not(feature = "std"), feature = "alloc"))]
+use alloc::{string::String, vec::Vec};
+use core::cell::RefCell;
 use core::cmp;
 
-use crate::dir_entry::DirEntryEditor;
+use crate::dir_entry::{DirEntryEditor, Metadata};
 use crate::error::Error;
-use crate::fs::{FileSystem, ReadWriteSeek};
+use crate::fs::{Clusters, FileSystem, ReadWriteSeek, SizeMismatchPolicy};
 use crate::io::{IoBase, Read, Seek, SeekFrom, Write};
 use crate::time::{Date, DateTime, TimeProvider};
 
-const MAX_FILE_SIZE: u32 = core::u32::MAX;
+pub(crate) const MAX_FILE_SIZE: u32 = core::u32::MAX;
+
+/// Size of the block buffer used to coalesce small file reads and writes.
+///
+/// Clusters are always aligned to the sector size (at least 512 bytes) so a buffered block never spans two clusters.
+const FILE_BUFFER_SIZE: usize = 512;
+
+// Maximal capacity reserved by `File::read_to_end` based on the file size from the directory entry
+#[cfg(feature = "alloc")]
+const READ_TO_END_MAX_RESERVE: usize = 64 * 1024;
 
 /// A FAT filesystem file object used for reading and writing data.
 ///
 /// This struct is created by the `open_file` or `create_file` methods on `Dir`.
+///
+/// Reads and writes smaller than 512 bytes go through a block buffer shared by all files of the filesystem, so small
+/// sequential accesses result in a single device access per block. Buffered data is written back by `flush`,
+/// `close`, when seeking to another position, when a different block is accessed and when clusters are freed.
+/// Dropping a file does not write back its buffered data: it stays in the shared buffer until another file uses it or
+/// the filesystem is flushed or unmounted, and it is lost if the filesystem is dropped before that. Buffering can be
+/// disabled for a file with `set_buffered`. Directories are never buffered.
+///
+/// `flush` writes back the buffered data, then the directory entry holding the size, the first cluster and the
+/// modification time, and finally flushes the underlying storage object. Data written before a successful `flush` can
+/// be read back with the right size even if the filesystem is dropped without `unmount` or power is lost later.
+///
+/// The size of a FAT file is limited to 4 GiB - 1 bytes. A write starting at that limit fails with
+/// `Error::InvalidInput` and a write crossing it is shortened. Seeking past the limit fails with `Error::InvalidInput`.
+/// `FileSystem::max_file_size` returns the largest size a file can currently reach.
+///
+/// Dropping a `write`, `truncate` or `preallocate_contiguous` future keeps the filesystem consistent, but the file size
+/// stored in the directory entry may not include the data written before the future was dropped (see the crate-level
+/// documentation on cancellation).
+// the flags are independent: buffering and the access mode of `OpenOptions`
+#[allow(clippy::struct_excessive_bools)]
 pub struct File<'a, IO: ReadWriteSeek, TP, OCC> {
     context: FileContext,
     // file-system reference
     fs: &'a FileSystem<IO, TP, OCC>,
+    // use the filesystem block buffer for small reads and writes
+    buffered: bool,
+    // access mode selected with `OpenOptions`
+    readable: bool,
+    writable: bool,
+    append: bool,
+}
+
+/// A block of file data kept in memory by the filesystem.
+pub(crate) struct FileBuffer {
+    data: [u8; FILE_BUFFER_SIZE],
+    // position of the buffered block on the storage device - None if nothing is buffered
+    pos: Option<u64>,
+    dirty: bool,
+}
+
+impl FileBuffer {
+    pub(crate) fn new() -> Self {
+        FileBuffer {
+            data: [0; FILE_BUFFER_SIZE],
+            pos: None,
+            dirty: false,
+        }
+    }
+
+    pub(crate) fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    fn block_pos(pos: u64) -> u64 {
+        pos - pos % FILE_BUFFER_SIZE as u64
+    }
+
+    fn contains(&self, pos: u64) -> bool {
+        self.pos == Some(Self::block_pos(pos))
+    }
+
+    fn overlaps(&self, pos: u64, len: usize) -> bool {
+        self.pos
+            .is_some_and(|block_pos| pos < block_pos + FILE_BUFFER_SIZE as u64 && block_pos < pos + len as u64)
+    }
+
+    // The async methods borrow the buffer only between await points and copy blocks in and out of it, so other handles
+    // can still inspect it while the storage is busy. It is only changed while the storage is borrowed, so it cannot
+    // change while waiting for the storage.
+    pub(crate) async fn write_back<IO: ReadWriteSeek>(
+        buffer: &RefCell<Self>,
+        disk: &mut IO,
+    ) -> Result<(), Error<IO::Error>> {
+        let (pos, data) = match &*buffer.borrow() {
+            FileBuffer {
+                data,
+                pos: Some(pos),
+                dirty: true,
+            } => (*pos, *data),
+            _ => return Ok(()),
+        };
+        trace!("write back buffered block at {}", pos);
+        disk.seek(SeekFrom::Start(pos)).await?;
+        disk.write_all(&data).await?;
+        buffer.borrow_mut().dirty = false;
+        Ok(())
+    }
+
+    pub(crate) fn discard(&mut self) {
+        self.pos = None;
+        self.dirty = false;
+    }
+
+    // Makes sure the block containing `pos` is buffered and returns the offset of `pos` in the buffer
+    async fn load<IO: ReadWriteSeek>(
+        buffer: &RefCell<Self>,
+        disk: &mut IO,
+        pos: u64,
+    ) -> Result<usize, Error<IO::Error>> {
+        let block_pos = Self::block_pos(pos);
+        if !buffer.borrow().contains(pos) {
+            Self::write_back(buffer, disk).await?;
+            buffer.borrow_mut().pos = None;
+            let mut data = [0; FILE_BUFFER_SIZE];
+            disk.seek(SeekFrom::Start(block_pos)).await?;
+            disk.read_exact(&mut data).await?;
+            let mut buffer = buffer.borrow_mut();
+            buffer.data = data;
+            buffer.pos = Some(block_pos);
+        }
+        Ok((pos - block_pos) as usize)
+    }
+
+    // Starts buffering a block at `pos` without reading it - its content is replaced by zeros
+    async fn start_block<IO: ReadWriteSeek>(
+        buffer: &RefCell<Self>,
+        disk: &mut IO,
+        pos: u64,
+    ) -> Result<(), Error<IO::Error>> {
+        debug_assert_eq!(pos, Self::block_pos(pos));
+        Self::write_back(buffer, disk).await?;
+        let mut buffer = buffer.borrow_mut();
+        buffer.data = [0; FILE_BUFFER_SIZE];
+        buffer.pos = Some(pos);
+        Ok(())
+    }
+
+    async fn read<IO: ReadWriteSeek>(
+        buffer: &RefCell<Self>,
+        disk: &mut IO,
+        pos: u64,
+        buf: &mut [u8],
+    ) -> Result<usize, Error<IO::Error>> {
+        if buf.len() < FILE_BUFFER_SIZE || buffer.borrow().contains(pos) {
+            let start = Self::load(buffer, disk, pos).await?;
+            let len = cmp::min(buf.len(), FILE_BUFFER_SIZE - start);
+            buf[..len].copy_from_slice(&buffer.borrow().data[start..start + len]);
+            return Ok(len);
+        }
+        // large reads bypass the buffer but must see data that was not written back yet
+        if buffer.borrow().overlaps(pos, buf.len()) {
+            Self::write_back(buffer, disk).await?;
+        }
+        disk.seek(SeekFrom::Start(pos)).await?;
+        Ok(disk.read(buf).await?)
+    }
+
+    // Writes to the block containing `pos` or directly to the storage for large writes. `at_end` tells that the block
+    // holds no file data after `pos`, so a block starting at `pos` does not have to be read first.
+    async fn write<IO: ReadWriteSeek>(
+        buffer: &RefCell<Self>,
+        disk: &mut IO,
+        pos: u64,
+        buf: &[u8],
+        at_end: bool,
+    ) -> Result<usize, Error<IO::Error>> {
+        if buf.len() < FILE_BUFFER_SIZE || buffer.borrow().contains(pos) {
+            if at_end && !buffer.borrow().contains(pos) && pos == Self::block_pos(pos) {
+                Self::start_block(buffer, disk, pos).await?;
+            }
+            let start = Self::load(buffer, disk, pos).await?;
+            let len = cmp::min(buf.len(), FILE_BUFFER_SIZE - start);
+            let mut buffer = buffer.borrow_mut();
+            buffer.data[start..start + len].copy_from_slice(&buf[..len]);
+            buffer.dirty = true;
+            return Ok(len);
+        }
+        // large writes bypass the buffer so the buffered block would become stale
+        if buffer.borrow().overlaps(pos, buf.len()) {
+            Self::write_back(buffer, disk).await?;
+            buffer.borrow_mut().discard();
+        }
+        disk.seek(SeekFrom::Start(pos)).await?;
+        Ok(disk.write(buf).await?)
+    }
 }
 
 /// A context of an existing [`File`].
@@ -38,7 +222,7 @@ pub struct FileContext {
 
 /// An extent containing a file's data on disk.
 ///
-/// This is created by the `extents` method on `File`, and represents
+/// This is created by the `cluster_chunks` method on `File`, and represents
 /// a byte range on the disk that contains a file's data. All values
 /// are in bytes.
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -48,6 +232,37 @@ pub struct Extent {
     pub size: u32,
 }
 
+/// An iterator over the spans of the storage holding the data of a file.
+///
+/// This struct is created by the `cluster_chunks` method on `File`. Each item is an `Extent` covering one cluster.
+pub struct ClusterChunks<'a, IO: ReadWriteSeek, TP, OCC> {
+    fs: &'a FileSystem<IO, TP, OCC>,
+    clusters: Clusters<'a, IO, TP, OCC>,
+    // None for directories which have no size
+    bytes_left: Option<u32>,
+}
+
+impl<IO: ReadWriteSeek, TP, OCC> ClusterChunks<'_, IO, TP, OCC> {
+    pub async fn next(&mut self) -> Option<Result<Extent, Error<IO::Error>>> {
+        if self.bytes_left == Some(0) {
+            return None;
+        }
+        let cluster = match self.clusters.next().await? {
+            Ok(cluster) => cluster,
+            Err(err) => return Some(Err(err)),
+        };
+        let cluster_size = self.fs.cluster_size();
+        let size = self.bytes_left.map_or(cluster_size, |n| cmp::min(n, cluster_size));
+        if let Some(n) = self.bytes_left.as_mut() {
+            *n -= size;
+        }
+        Some(Ok(Extent {
+            offset: self.fs.offset_from_cluster(cluster),
+            size,
+        }))
+    }
+}
+
 impl<'a, IO: ReadWriteSeek, TP, OCC> File<'a, IO, TP, OCC> {
     pub(crate) fn new(
         first_cluster: Option<u32>,
@@ -62,6 +277,10 @@ impl<'a, IO: ReadWriteSeek, TP, OCC> File<'a, IO, TP, OCC> {
                 offset: 0,
             },
             fs,
+            buffered: true,
+            readable: true,
+            writable: true,
+            append: false,
         }
     }
 
@@ -75,20 +294,37 @@ impl<'a, IO: ReadWriteSeek, TP, OCC> File<'a, IO, TP, OCC> {
     /// Prefer using [`DirEntry::try_to_file_with_context`](crate::dir_entry::DirEntry::try_to_file_with_context) where possible because
     /// it does some basic checks to avoid file corruption.
     pub(crate) fn new_from_context(context: FileContext, fs: &'a FileSystem<IO, TP, OCC>) -> Self {
-        File { context, fs }
+        File {
+            context,
+            fs,
+            buffered: true,
+            readable: true,
+            writable: true,
+            append: false,
+        }
     }
 
     /// Truncate file in current position.
     ///
+    /// The file size is set to the current position and clusters past the one holding the last byte are freed, so a
+    /// partially used last cluster is kept. If the position is 0 the whole cluster chain is freed and the first cluster
+    /// stored in the directory entry is cleared. The directory entry is written before any cluster is freed. The rest
+    /// of the kept cluster still holds the old data unless `FsOptions::zero_stale_data` is enabled.
+    ///
     /// # Errors
     ///
-    /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if the file was opened without write access.
+    /// * `Error::ReadOnly` will be returned if the filesystem is mounted in read-only mode.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
     ///
     /// # Panics
     ///
     /// Will panic if this is the root directory.
     pub async fn truncate(&mut self) -> Result<(), Error<IO::Error>> {
         trace!("File::truncate");
+        self.ensure_writable()?;
         if let Some(ref mut e) = self.context.entry {
             e.set_size(self.context.offset);
             if self.context.offset == 0 {
@@ -98,79 +334,133 @@ impl<'a, IO: ReadWriteSeek, TP, OCC> File<'a, IO, TP, OCC> {
             // Note: we cannot handle this case because there is no size field
             panic!("Trying to truncate a file without an entry");
         }
+        // forget the chain before freeing it so a dropped future cannot leave the file pointing to free clusters
+        let first_cluster = if self.context.offset == 0 {
+            self.context.first_cluster.take()
+        } else {
+            None
+        };
+        // write the shrunk entry first so it never describes freed clusters
+        if let Some(ref mut e) = self.context.entry {
+            e.flush(self.fs).await?;
+        }
         if let Some(current_cluster) = self.context.current_cluster {
             // current cluster is none only if offset is 0
             debug_assert!(self.context.offset > 0);
-            self.fs.truncate_cluster_chain(current_cluster).await
+            self.fs.truncate_cluster_chain(current_cluster).await?;
+            let offset_in_cluster = self.context.offset % self.fs.cluster_size();
+            if self.fs.options.zero_stale_data && offset_in_cluster != 0 {
+                let pos = self.fs.offset_from_cluster(current_cluster) + u64::from(offset_in_cluster);
+                let len = self.fs.cluster_size() - offset_in_cluster;
+                self.fs.zero_range(pos, u64::from(len)).await?;
+            }
+            Ok(())
         } else {
             debug_assert!(self.context.offset == 0);
-            if let Some(n) = self.context.first_cluster {
+            if let Some(n) = first_cluster {
                 self.fs.free_cluster_chain(n).await?;
-                self.context.first_cluster = None;
             }
             Ok(())
         }
     }
 
-    /// Get the extents of a file on disk.
-    ///
-    /// This returns an iterator over the byte ranges on-disk occupied by
-    /// this file.
-    // pub fn extents(&mut self) -> impl Iterator<Item = Result<Extent, Error<IO::Error>>> + 'a {
-    // let fs = self.fs;
-    // let cluster_size = fs.cluster_size();
-    // let mut bytes_left = match self.size() {
-    //     Some(s) => s,
-    //     None => return None.into_iter().flatten(),
-    // };
-    // let first = match self.context.first_cluster {
-    //     Some(f) => f,
-    //     None => return None.into_iter().flatten(),
-    // };
-
-    // Some(
-    //     core::iter::once(Ok(first))
-    //         .chain(fs.cluster_iter(first))
-    //         .map(move |cluster_err| match cluster_err {
-    //             Ok(cluster) => {
-    //                 let size = cluster_size.min(bytes_left);
-    //                 bytes_left -= size;
-    //                 Ok(Extent {
-    //                     offset: fs.offset_from_cluster(cluster),
-    //                     size,
-    //                 })
-    //             }
-    //             Err(e) => Err(e),
-    //         }),
-    // )
-    // .into_iter()
-    // .flatten()
-    // todo!("extents needs to be implemented using AsyncIterator");
-    // }
+    /// Returns an iterator over the spans of the storage holding the data of this file.
+    ///
+    /// Each span covers one cluster and is made of its position on the storage and the number of bytes of file data it
+    /// holds. Spans are returned in the order of the cluster chain, so they can be read directly from the storage (e.g.
+    /// by a DMA engine) with cluster aligned transfers. All spans but the last one are `FileSystem::cluster_size` bytes
+    /// long, the last one ends at the file size. Clusters allocated past the file size are not returned. Data written
+    /// to the file is on the storage only after the file is flushed.
+    #[must_use]
+    pub fn cluster_chunks(&self) -> ClusterChunks<'a, IO, TP, OCC> {
+        ClusterChunks {
+            fs: self.fs,
+            clusters: Clusters::new(self.fs, self.context.first_cluster),
+            bytes_left: self.size(),
+        }
+    }
+
+    /// Returns the cluster holding the current position.
+    ///
+    /// If the position is at a cluster boundary, the cluster before the boundary is returned because it is the last
+    /// cluster accessed and the next one may not be allocated yet. At position 0 the first cluster of the file is
+    /// returned, so `None` is returned only for an empty file positioned at 0. This is a diagnostic aid using the
+    /// position cached by the file, it never accesses the storage.
+    #[must_use]
+    pub fn current_cluster(&self) -> Option<u32> {
+        if self.context.offset == 0 {
+            self.context.first_cluster
+        } else {
+            self.context.current_cluster
+        }
+    }
+
+    /// Returns the current position relative to the start of the cluster returned by `current_cluster`.
+    ///
+    /// The value is in range [1, `FileSystem::cluster_size`] for positions other than 0, so it equals the cluster size
+    /// at a cluster boundary. It never accesses the storage.
+    #[must_use]
+    pub fn offset_in_cluster(&self) -> u32 {
+        if self.context.offset == 0 {
+            return 0;
+        }
+        // the position belongs to the previous cluster if it points between clusters
+        (self.context.offset - 1) % self.fs.cluster_size() + 1
+    }
+
+    /// Seeks to the start of the cluster returned by `current_cluster` and returns the new position.
+    ///
+    /// The file only caches the cluster holding the current position, so like any backward seek this follows the
+    /// cluster chain from the first cluster. No cluster has to be read if the current cluster is the first one.
+    ///
+    /// # Errors
+    ///
+    /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub async fn seek_to_cluster_boundary(&mut self) -> Result<u64, Error<IO::Error>> {
+        let offset = self.context.offset - self.offset_in_cluster();
+        self.seek(SeekFrom::Start(u64::from(offset))).await
+    }
 
     pub(crate) fn abs_pos(&self) -> Option<u64> {
         // Returns current position relative to filesystem start
         // Note: when between clusters it returns position after previous cluster
-        match self.context.current_cluster {
-            Some(n) => {
-                let cluster_size = self.fs.cluster_size();
-                let offset_mod_cluster_size = self.context.offset % cluster_size;
-                let offset_in_cluster = if offset_mod_cluster_size == 0 {
-                    // position points between clusters - we are returning previous cluster so
-                    // offset must be set to the cluster size
-                    cluster_size
-                } else {
-                    offset_mod_cluster_size
-                };
-                let offset_in_fs = self.fs.offset_from_cluster(n) + u64::from(offset_in_cluster);
-                Some(offset_in_fs)
+        self.context
+            .current_cluster
+            .map(|n| self.fs.offset_from_cluster(n) + u64::from(self.offset_in_cluster()))
+    }
+
+    pub(crate) fn set_access_mode(&mut self, readable: bool, writable: bool, append: bool) {
+        self.readable = readable;
+        self.writable = writable;
+        self.append = append;
+    }
+
+    fn ensure_writable(&self) -> Result<(), Error<IO::Error>> {
+        self.fs.ensure_writable()?;
+        if !self.writable {
+            error!("File is not opened for writing");
+            return Err(Error::InvalidInput);
+        }
+        Ok(())
+    }
+
+    // Moves to the end of the file which could have been extended and flushed by another handle of the same file
+    async fn seek_to_append_position(&mut self) -> Result<(), Error<IO::Error>> {
+        if let Some(ref mut e) = self.context.entry {
+            e.reload_size(self.fs).await?;
+            if self.context.first_cluster.is_none() {
+                self.context.first_cluster = e.inner().first_cluster(self.fs.fat_type());
             }
-            None => None,
         }
+        Seek::seek(self, SeekFrom::End(0)).await?;
+        Ok(())
     }
 
     async fn flush_dir_entry(&mut self) -> Result<(), Error<IO::Error>> {
         if let Some(ref mut e) = self.context.entry {
+            if e.dirty() {
+                self.fs.ensure_writable()?;
+            }
             e.flush(self.fs).await?;
         }
         Ok(())
@@ -178,35 +468,163 @@ impl<'a, IO: ReadWriteSeek, TP, OCC> File<'a, IO, TP, OCC> {
 
     /// Sets date and time of creation for this file.
     ///
+    /// Creation time is stored with 1/100s resolution (milliseconds are rounded down to tens). The directory entry is
+    /// updated on the storage when the file is flushed.
     /// Note: it is set to a value from the `TimeProvider` when creating a file.
-    /// Deprecated: if needed implement a custom `TimeProvider`.
-    #[deprecated]
-    pub fn set_created(&mut self, date_time: DateTime) {
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if `date_time` is outside of the range supported by FAT (the year must
+    ///   be in the range [1980, 2107]).
+    /// * `Error::ReadOnly` will be returned if the filesystem is mounted in read-only mode.
+    pub fn set_created(&mut self, date_time: DateTime) -> Result<(), Error<IO::Error>> {
+        self.fs.ensure_writable()?;
+        if !date_time.is_valid() {
+            return Err(Error::InvalidInput);
+        }
         if let Some(ref mut e) = self.context.entry {
             e.set_created(date_time);
         }
+        Ok(())
     }
 
     /// Sets date of last access for this file.
     ///
-    /// Note: it is overwritten by a value from the `TimeProvider` on every file read operation.
-    /// Deprecated: if needed implement a custom `TimeProvider`.
-    #[deprecated]
-    pub fn set_accessed(&mut self, date: Date) {
+    /// The directory entry is updated on the storage when the file is flushed.
+    /// Note: it is overwritten by a value from the `TimeProvider` on every file read or write operation if
+    /// `FsOptions::update_accessed_date` is enabled.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if `date` is outside of the range supported by FAT (the year must be
+    ///   in the range [1980, 2107]).
+    /// * `Error::ReadOnly` will be returned if the filesystem is mounted in read-only mode.
+    pub fn set_accessed(&mut self, date: Date) -> Result<(), Error<IO::Error>> {
+        self.fs.ensure_writable()?;
+        if !date.is_valid() {
+            return Err(Error::InvalidInput);
+        }
         if let Some(ref mut e) = self.context.entry {
             e.set_accessed(date);
         }
+        Ok(())
     }
 
     /// Sets date and time of last modification for this file.
     ///
-    /// Note: it is overwritten by a value from the `TimeProvider` on every file write operation.
-    /// Deprecated: if needed implement a custom `TimeProvider`.
-    #[deprecated]
-    pub fn set_modified(&mut self, date_time: DateTime) {
+    /// Modification time is stored with 2s resolution. The directory entry is updated on the storage when the file is
+    /// flushed.
+    /// Note: it is overwritten by a value from the `TimeProvider` on every file write operation so it should be set
+    /// after writing the file content (e.g. when restoring files from an archive).
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if `date_time` is outside of the range supported by FAT (the year must
+    ///   be in the range [1980, 2107]).
+    /// * `Error::ReadOnly` will be returned if the filesystem is mounted in read-only mode.
+    pub fn set_modified(&mut self, date_time: DateTime) -> Result<(), Error<IO::Error>> {
+        self.fs.ensure_writable()?;
+        if !date_time.is_valid() {
+            return Err(Error::InvalidInput);
+        }
         if let Some(ref mut e) = self.context.entry {
             e.set_modified(date_time);
         }
+        Ok(())
+    }
+
+    /// Returns metadata of the file.
+    ///
+    /// Unlike `DirEntry::metadata`, the result includes changes made through this handle which were not flushed yet,
+    /// so the length reflects the current size of the file after writes and truncation. Changes made through other
+    /// handles of the same file are not visible.
+    #[must_use]
+    pub fn metadata(&self) -> Metadata {
+        match self.context.entry {
+            Some(ref e) => Metadata::from_entry(e.inner(), self.fs.fat_type()),
+            None => Metadata::root_dir(self.context.first_cluster),
+        }
+    }
+
+    /// Returns the number of bytes allocated to the file on the storage.
+    ///
+    /// It is the number of clusters in the cluster chain multiplied by the cluster size. The result is the length
+    /// returned by `metadata` rounded up to whole clusters, or more if the chain holds more clusters than the length
+    /// requires (e.g. a `write` future was dropped before the length was updated). An empty file has no clusters and
+    /// `0` is returned. The cluster chain is walked, so the time taken grows with the size of the file.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::CorruptedFileSystem` will be returned if the cluster chain is circular.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub async fn allocated_len(&self) -> Result<u64, Error<IO::Error>> {
+        let Some(first_cluster) = self.context.first_cluster else {
+            return Ok(0);
+        };
+        let mut clusters = 1;
+        let mut iter = self.fs.cluster_iter(first_cluster);
+        while let Some(r) = iter.next().await {
+            r?;
+            clusters += 1;
+        }
+        Ok(self.fs.bytes_from_clusters(clusters))
+    }
+
+    // Applies `FsOptions::size_mismatch_policy` to a file which has just been opened. The cluster chain is only walked
+    // until it is long enough for the size.
+    pub(crate) async fn check_size(&mut self) -> Result<(), Error<IO::Error>> {
+        let policy = self.fs.options.size_mismatch_policy;
+        let size = match self.size() {
+            Some(size) if size > 0 && policy != SizeMismatchPolicy::Ignore => size,
+            _ => return Ok(()),
+        };
+        let needed = self.fs.clusters_from_bytes(u64::from(size));
+        let mut clusters = 0;
+        if let Some(first_cluster) = self.context.first_cluster {
+            clusters = 1;
+            let mut iter = self.fs.cluster_iter(first_cluster);
+            while clusters < needed {
+                match iter.next().await {
+                    Some(r) => r?,
+                    None => break,
+                };
+                clusters += 1;
+            }
+        }
+        if clusters >= needed {
+            return Ok(());
+        }
+        let capacity = u32::try_from(self.fs.bytes_from_clusters(clusters)).unwrap_or(u32::MAX);
+        warn!(
+            "file size {} exceeds the capacity {} of its cluster chain",
+            size, capacity
+        );
+        match policy {
+            SizeMismatchPolicy::Ignore => Ok(()),
+            SizeMismatchPolicy::Error => Err(Error::InvalidData),
+            SizeMismatchPolicy::Clamp => {
+                if let Some(ref mut e) = self.context.entry {
+                    e.clamp_size(capacity);
+                }
+                Ok(())
+            }
+            SizeMismatchPolicy::Fix => {
+                self.fs.ensure_writable()?;
+                if let Some(ref mut e) = self.context.entry {
+                    e.set_size(capacity);
+                    e.flush(self.fs).await?;
+                }
+                Ok(())
+            }
+        }
     }
 
     fn size(&self) -> Option<u32> {
@@ -239,31 +657,486 @@ impl<'a, IO: ReadWriteSeek, TP, OCC> File<'a, IO, TP, OCC> {
         self.context.first_cluster
     }
 
+    // Note: current_cluster must be the cluster containing the byte before offset like in FileContext
+    pub(crate) fn set_position(&mut self, offset: u32, current_cluster: Option<u32>) {
+        self.context.offset = offset;
+        self.context.current_cluster = current_cluster;
+    }
+
+    // Note: the root directory is the only file without a directory entry
+    pub(crate) fn is_root_dir(&self) -> bool {
+        self.context.entry.is_none()
+    }
+
+    /// Returns number of clusters directly following `cluster` in the chain that are also placed directly after it on
+    /// the disk (at most `max_clusters`).
+    async fn contiguous_clusters_after(&self, cluster: u32, max_clusters: u32) -> Result<u32, Error<IO::Error>> {
+        let mut iter = self.fs.cluster_iter(cluster);
+        let mut count = 0;
+        while count < max_clusters {
+            match iter.next().await {
+                Some(Err(err)) => return Err(err),
+                Some(Ok(n)) if n == cluster + count + 1 => count += 1,
+                _ => break,
+            }
+        }
+        Ok(count)
+    }
+
+    /// Enables or disables buffering of reads and writes smaller than 512 bytes for this file.
+    ///
+    /// Buffering is enabled by default. Callers that already buffer data or use a caching storage object can disable
+    /// it to avoid copying data twice. Directories are never buffered.
+    pub fn set_buffered(&mut self, buffered: bool) {
+        self.buffered = buffered;
+    }
+
+    fn is_buffered(&self) -> bool {
+        // directories are accessed by many handles and their entries are written directly to the storage
+        self.buffered && self.context.entry.as_ref().is_some_and(|e| !e.inner().is_dir())
+    }
+
+    // The storage is borrowed for the whole access so the buffered block cannot change while waiting for it, the
+    // shared buffer itself is only borrowed between await points
+    #[allow(clippy::await_holding_refcell_ref)]
+    async fn read_data(&mut self, pos: u64, buf: &mut [u8]) -> Result<usize, Error<IO::Error>> {
+        let mut disk = self.fs.disk.borrow_mut();
+        if self.is_buffered() {
+            return FileBuffer::read(&self.fs.file_buffer, &mut *disk, pos, buf).await;
+        }
+        // unbuffered handles still have to see data buffered by other handles
+        if self.fs.file_buffer.borrow().overlaps(pos, buf.len()) {
+            FileBuffer::write_back(&self.fs.file_buffer, &mut *disk).await?;
+        }
+        disk.seek(SeekFrom::Start(pos)).await?;
+        Ok(disk.read(buf).await?)
+    }
+
+    // Like `read_data` only the storage stays borrowed across await points
+    #[allow(clippy::await_holding_refcell_ref)]
+    async fn write_data(&mut self, pos: u64, buf: &[u8]) -> Result<usize, Error<IO::Error>> {
+        let mut disk = self.fs.disk.borrow_mut();
+        if self.is_buffered() {
+            // nothing after the end of the file has to be preserved
+            let at_end = self.size().is_some_and(|size| self.context.offset >= size);
+            return FileBuffer::write(&self.fs.file_buffer, &mut *disk, pos, buf, at_end).await;
+        }
+        if self.fs.file_buffer.borrow().overlaps(pos, buf.len()) {
+            FileBuffer::write_back(&self.fs.file_buffer, &mut *disk).await?;
+            self.fs.file_buffer.borrow_mut().discard();
+        }
+        disk.seek(SeekFrom::Start(pos)).await?;
+        Ok(disk.write(buf).await?)
+    }
+
     async fn flush(&mut self) -> Result<(), Error<IO::Error>> {
+        self.fs.write_back_file_buffer().await?;
         self.flush_dir_entry().await?;
         let mut disk = self.fs.disk.borrow_mut();
         disk.flush().await?;
         Ok(())
     }
+
+    /// Persists file content and the directory entry to the storage device.
+    ///
+    /// The directory entry containing the file size and the first cluster is written before the underlying storage
+    /// object is flushed, so after this method returns the file can be read back even if power is lost. Allocation
+    /// table changes are written when clusters are allocated, so they always reach the storage before the directory
+    /// entry that references them.
+    /// Note: the directory entry is always written as a whole so timestamps are persisted too.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::ReadOnly` will be returned if the directory entry has changed and the filesystem is mounted in
+    ///   read-only mode.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub async fn sync_data(&mut self) -> Result<(), Error<IO::Error>> {
+        self.flush().await
+    }
+
+    /// Persists file content, the directory entry and filesystem metadata to the storage device.
+    ///
+    /// Works like `sync_data` but additionally writes the FS Information Sector (free cluster count and next free
+    /// cluster hint) before flushing the underlying storage object.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::ReadOnly` will be returned if the directory entry has changed and the filesystem is mounted in
+    ///   read-only mode.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub async fn sync_all(&mut self) -> Result<(), Error<IO::Error>> {
+        self.fs.write_back_file_buffer().await?;
+        self.flush_dir_entry().await?;
+        self.fs.flush().await
+    }
+
+    /// Converts the file into a blocking adaptor implementing `std::io::Read`, `std::io::Write` and `std::io::Seek`.
+    ///
+    /// See `StdFile` for the requirements on the storage object.
+    #[cfg(feature = "std")]
+    #[must_use]
+    pub fn into_std(self) -> StdFile<'a, IO, TP, OCC> {
+        StdFile { file: self }
+    }
 }
 
 impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> File<'_, IO, TP, OCC> {
+    // Writes to a directory stream and persists the new timestamps of the directory right away. The entry changes are
+    // discarded if the future is dropped, so a cancelled directory update never leaves a dirty handle behind.
+    pub(crate) async fn write_dir_data(&mut self, buf: &[u8]) -> Result<usize, Error<IO::Error>> {
+        let guard = DirEntryGuard::new(self);
+        let written_bytes = Write::write(guard.file, buf).await?;
+        guard.file.flush_dir_entry().await?;
+        guard.disarm();
+        Ok(written_bytes)
+    }
+
     fn update_dir_entry_after_write(&mut self) {
         let offset = self.context.offset;
         if let Some(ref mut e) = self.context.entry {
             let now = self.fs.options.time_provider.get_current_date_time();
             e.set_modified(now);
+            if self.fs.options.update_accessed_date {
+                e.set_accessed(now.date);
+            }
             if e.inner().size().map_or(false, |s| offset > s) {
                 e.set_size(offset);
             }
         }
     }
 
+    fn set_size_after_allocation(&mut self, size: u32) {
+        if let Some(ref mut e) = self.context.entry {
+            let now = self.fs.options.time_provider.get_current_date_time();
+            e.set_modified(now);
+            e.set_size(size);
+        }
+    }
+
+    /// Preallocates space for `len` bytes of an empty file as a single run of contiguous clusters.
+    ///
+    /// The file size is set to `len` and the current position is not changed. Content of the allocated space is not
+    /// initialized unless `FsOptions::zero_stale_data` is enabled. The next free cluster hint is moved just past the
+    /// allocated run.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if this is a directory, the file has clusters allocated already or it
+    ///   was opened without write access.
+    /// * `Error::NotEnoughSpace` will be returned if there is no run of free clusters large enough to hold `len` bytes.
+    /// * `Error::ReadOnly` will be returned if the filesystem is mounted in read-only mode.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub async fn preallocate_contiguous(&mut self, len: u32) -> Result<(), Error<IO::Error>> {
+        trace!("File::preallocate_contiguous {}", len);
+        self.ensure_writable()?;
+        if self.context.entry.is_none() || self.is_dir() || self.context.first_cluster.is_some() {
+            return Err(Error::InvalidInput);
+        }
+        if len == 0 {
+            return Ok(());
+        }
+        let clusters = self.fs.clusters_from_bytes(u64::from(len));
+        self.fs.set_dirty_flag(true).await?;
+        let first_cluster = self.fs.alloc_contiguous_clusters(None, clusters).await?;
+        self.set_first_cluster(first_cluster);
+        if self.fs.options.zero_stale_data {
+            let pos = self.fs.offset_from_cluster(first_cluster);
+            self.fs.zero_range(pos, self.fs.bytes_from_clusters(clusters)).await?;
+        }
+        self.set_size_after_allocation(len);
+        Ok(())
+    }
+
+    /// Allocates space for `len` bytes extending the file if it is smaller.
+    ///
+    /// Unlike `preallocate_contiguous` the allocated clusters can be fragmented. The file size is set to `len` if it
+    /// was smaller and the current position is not changed. Content of the allocated space is not initialized unless
+    /// `FsOptions::zero_stale_data` is enabled.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if this is a directory or the file was opened without write access.
+    /// * `Error::NotEnoughSpace` will be returned if there is not enough free space to allocate `len` bytes.
+    /// * `Error::ReadOnly` will be returned if the filesystem is mounted in read-only mode.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub async fn allocate(&mut self, len: u32) -> Result<(), Error<IO::Error>> {
+        trace!("File::allocate {}", len);
+        self.ensure_writable()?;
+        if self.context.entry.is_none() || self.is_dir() {
+            return Err(Error::InvalidInput);
+        }
+        if len <= self.size().unwrap_or(0) {
+            return Ok(());
+        }
+        self.fs.set_dirty_flag(true).await?;
+        let old_size = self.size().unwrap_or(0);
+        let zero = self.fs.options.zero_stale_data;
+        // find the last cluster of the chain - it can be longer than the file size requires
+        let mut allocated = 0;
+        let mut last_cluster = self.context.first_cluster;
+        if let Some(first_cluster) = self.context.first_cluster {
+            let mut iter = self.fs.cluster_iter(first_cluster);
+            let mut cluster = first_cluster;
+            loop {
+                if zero {
+                    self.zero_exposed_part(cluster, allocated, old_size, len).await?;
+                }
+                allocated += 1;
+                match iter.next().await {
+                    Some(r) => cluster = r?,
+                    None => break,
+                }
+            }
+            last_cluster = Some(cluster);
+        }
+        let clusters = self.fs.clusters_from_bytes(u64::from(len));
+        // runs of free clusters are linked at once so the FAT is written block by block
+        while allocated < clusters {
+            let (first_cluster, count) = self
+                .fs
+                .alloc_cluster_run(last_cluster, clusters - allocated, zero)
+                .await?;
+            if self.context.first_cluster.is_none() {
+                self.set_first_cluster(first_cluster);
+            }
+            last_cluster = Some(first_cluster + count - 1);
+            allocated += count;
+        }
+        self.set_size_after_allocation(len);
+        Ok(())
+    }
+
+    // Zeroes the part of the cluster at `index` in the chain which becomes a part of the file when it grows to `len`
+    async fn zero_exposed_part(
+        &self,
+        cluster: u32,
+        index: u32,
+        old_size: u32,
+        len: u32,
+    ) -> Result<(), Error<IO::Error>> {
+        let cluster_size = u64::from(self.fs.cluster_size());
+        let cluster_start = u64::from(index) * cluster_size;
+        let start = cmp::max(u64::from(old_size), cluster_start);
+        if start >= cluster_start + cluster_size || cluster_start >= u64::from(len) {
+            return Ok(());
+        }
+        let pos = self.fs.offset_from_cluster(cluster) + (start - cluster_start);
+        self.fs.zero_range(pos, cluster_start + cluster_size - start).await
+    }
+
+    /// Reads data at the given file offset without changing the current position.
+    ///
+    /// Works like a `seek` followed by a `read`, but the current position stays untouched and the cluster chain is
+    /// walked from the current position instead of the first cluster when `offset` is after it. Returns the number
+    /// of bytes read which is zero if `offset` is at or after the end of the file. The current position is kept also
+    /// if the returned future is dropped before it completes.
+    ///
+    /// The file handle must still be borrowed mutably and all I/O goes through the storage object shared by the
+    /// filesystem, so positional reads from several tasks need the same synchronization as other file operations.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::CorruptedFileSystem` will be returned if the cluster chain is shorter than the file size.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub async fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize, Error<IO::Error>> {
+        trace!("File::read_at {}", offset);
+        let offset = match u32::try_from(offset) {
+            Ok(n) if self.size().map_or(true, |size| n < size) => n,
+            _ => return Ok(0),
+        };
+        let cluster = self.cluster_before(offset).await?;
+        let guard = PositionGuard::new(self, offset, cluster);
+        Read::read(guard.file, buf).await
+    }
+
+    /// Reads the exact number of bytes required to fill `buf` at the given file offset without changing the current
+    /// position.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::UnexpectedEof` will be returned if the end of the file is reached before filling `buf`.
+    /// * `Error::CorruptedFileSystem` will be returned if the cluster chain is shorter than the file size.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub async fn read_exact_at(&mut self, mut offset: u64, mut buf: &mut [u8]) -> Result<(), Error<IO::Error>> {
+        while !buf.is_empty() {
+            match self.read_at(offset, buf).await? {
+                0 => return Err(Error::UnexpectedEof),
+                n => {
+                    buf = &mut buf[n..];
+                    offset += n as u64;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes data at the given file offset without changing the current position.
+    ///
+    /// Works like a `seek` followed by a `write`, but the current position stays untouched. The file is extended
+    /// if the written data ends after the end of the file. Returns the number of bytes written. In append mode the
+    /// data is written at the end of the file like by `write`, regardless of `offset`. The current position is kept
+    /// also if the returned future is dropped before it completes.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if `offset` is after the end of the file, the maximal file size
+    ///   would be exceeded or the file was opened without write access.
+    /// * `Error::NotEnoughSpace` will be returned if there is not enough free space to extend the file.
+    /// * `Error::CorruptedFileSystem` will be returned if the cluster chain is shorter than the file size.
+    /// * `Error::ReadOnly` will be returned if the filesystem is mounted in read-only mode.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub async fn write_at(&mut self, offset: u64, buf: &[u8]) -> Result<usize, Error<IO::Error>> {
+        trace!("File::write_at {}", offset);
+        self.ensure_writable()?;
+        let offset = match u32::try_from(offset) {
+            // writing after the end of the file would leave uninitialized data in the gap
+            Ok(n) if self.size().map_or(true, |size| n <= size) => n,
+            _ => return Err(Error::InvalidInput),
+        };
+        let cluster = self.cluster_before(offset).await?;
+        let guard = PositionGuard::new(self, offset, cluster);
+        Write::write(guard.file, buf).await
+    }
+
+    /// Reads all bytes from the current position until the end of the file and appends them to `buf`.
+    ///
+    /// Capacity for the rest of the file is reserved up front based on the size stored in the directory entry, so
+    /// `buf` is not reallocated while reading. The reservation is capped at 64 KiB to protect against a corrupted
+    /// file size; bigger files grow `buf` as needed. Returns the number of bytes read.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::CorruptedFileSystem` will be returned if the cluster chain is shorter than the file size.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    ///
+    /// Bytes read before an error are left in `buf`.
+    #[cfg(feature = "alloc")]
+    pub async fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize, Error<IO::Error>> {
+        trace!("File::read_to_end");
+        let start_len = buf.len();
+        let remaining = self.size().map_or(0, |size| size.saturating_sub(self.context.offset));
+        buf.reserve(cmp::min(remaining as usize, READ_TO_END_MAX_RESERVE));
+        // read in cluster sized pieces so only the part being read is zero-initialized
+        let chunk_len = self.fs.cluster_size() as usize;
+        loop {
+            let len = buf.len();
+            if len == buf.capacity() {
+                // probe with a small buffer so reaching the end of the file does not grow `buf`
+                let mut probe = [0_u8; 32];
+                match Read::read(self, &mut probe).await? {
+                    0 => return Ok(len - start_len),
+                    n => buf.extend_from_slice(&probe[..n]),
+                }
+                continue;
+            }
+            buf.resize(cmp::min(len + chunk_len, buf.capacity()), 0);
+            match Read::read(self, &mut buf[len..]).await {
+                Ok(0) => {
+                    buf.truncate(len);
+                    return Ok(len - start_len);
+                }
+                Ok(n) => buf.truncate(len + n),
+                Err(err) => {
+                    buf.truncate(len);
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    /// Reads all bytes from the current position until the end of the file and appends them to `buf` as UTF-8.
+    ///
+    /// Works like `read_to_end`. Returns the number of bytes read.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidData` will be returned if the data is not valid UTF-8.
+    /// * `Error::CorruptedFileSystem` will be returned if the cluster chain is shorter than the file size.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    ///
+    /// `buf` is not modified if an error is returned.
+    #[cfg(feature = "alloc")]
+    pub async fn read_to_string(&mut self, buf: &mut String) -> Result<usize, Error<IO::Error>> {
+        let mut bytes = core::mem::take(buf).into_bytes();
+        let start_len = bytes.len();
+        let result = match self.read_to_end(&mut bytes).await {
+            Ok(_) if core::str::from_utf8(&bytes[start_len..]).is_err() => {
+                error!("File content is not valid UTF-8");
+                Err(Error::InvalidData)
+            }
+            r => r,
+        };
+        if result.is_err() {
+            bytes.truncate(start_len);
+        }
+        // the prefix comes from a `String` and the appended part has been validated, so this never fails
+        *buf = String::from_utf8(bytes).unwrap_or_default();
+        result
+    }
+
+    /// Returns the cluster following `cluster` when the current position reaches its end.
+    ///
+    /// A chain cannot have more clusters than the volume so a position past that means the chain is circular.
+    async fn next_cluster(&self, cluster: u32) -> Result<Option<u32>, Error<IO::Error>> {
+        if self.context.offset / self.fs.cluster_size() >= self.fs.total_clusters() {
+            error!("cluster chain is longer than {} clusters", self.fs.total_clusters());
+            return Err(Error::CorruptedFileSystem);
+        }
+        self.fs.cluster_iter(cluster).next().await.transpose()
+    }
+
+    /// Returns the cluster containing the byte before `offset` like `FileContext::current_cluster` does.
+    ///
+    /// The chain is walked from the current position if possible.
+    async fn cluster_before(&self, offset: u32) -> Result<Option<u32>, Error<IO::Error>> {
+        if offset == 0 {
+            return Ok(None);
+        }
+        let cluster_size = self.fs.cluster_size();
+        let index = (offset - 1) / cluster_size;
+        let (mut cluster, mut cluster_index) = match (self.context.current_cluster, self.context.first_cluster) {
+            // Note: current cluster is set only if the current offset is not 0
+            (Some(n), _) if (self.context.offset - 1) / cluster_size <= index => {
+                (n, (self.context.offset - 1) / cluster_size)
+            }
+            (_, Some(n)) => (n, 0),
+            (_, None) => return Err(Error::CorruptedFileSystem),
+        };
+        let mut iter = self.fs.cluster_iter(cluster);
+        while cluster_index < index {
+            cluster = match iter.next().await {
+                Some(r) => r?,
+                None => return Err(Error::CorruptedFileSystem),
+            };
+            cluster_index += 1;
+        }
+        Ok(Some(cluster))
+    }
+
     /// Manually close the file
     ///
-    /// A [`FileContext`] is returned, which can be used in conjunction with the
-    /// `to_file_with_context` API.
+    /// Buffered data is written back to the storage. A [`FileContext`] is returned, which can be used in conjunction
+    /// with the `to_file_with_context` API.
     pub async fn close(self) -> Result<FileContext, Error<IO::Error>> {
+        self.fs.write_back_file_buffer().await?;
         Ok(FileContext {
             first_cluster: self.context.first_cluster,
             current_cluster: self.context.current_cluster,
@@ -287,12 +1160,68 @@ impl<IO: ReadWriteSeek, TP, OCC> Drop for File<'_, IO, TP, OCC> {
     }
 }
 
+// Moves a file to the position of a positional read or write and moves it back when dropped, also if the future doing
+// the I/O is dropped before it completes.
+struct PositionGuard<'f, 'a, IO: ReadWriteSeek, TP, OCC> {
+    file: &'f mut File<'a, IO, TP, OCC>,
+    saved: (u32, Option<u32>),
+}
+
+impl<'f, 'a, IO: ReadWriteSeek, TP, OCC> PositionGuard<'f, 'a, IO, TP, OCC> {
+    fn new(file: &'f mut File<'a, IO, TP, OCC>, offset: u32, cluster: Option<u32>) -> Self {
+        let saved = (file.context.offset, file.context.current_cluster);
+        (file.context.offset, file.context.current_cluster) = (offset, cluster);
+        Self { file, saved }
+    }
+}
+
+impl<IO: ReadWriteSeek, TP, OCC> Drop for PositionGuard<'_, '_, IO, TP, OCC> {
+    fn drop(&mut self) {
+        (self.file.context.offset, self.file.context.current_cluster) = self.saved;
+    }
+}
+
+// Restores the directory entry of a file if dropped before `disarm` is called, e.g. when the future writing a directory
+// stream is dropped before the entry with the new timestamps is written.
+struct DirEntryGuard<'f, 'a, IO: ReadWriteSeek, TP, OCC> {
+    file: &'f mut File<'a, IO, TP, OCC>,
+    saved: Option<DirEntryEditor>,
+    armed: bool,
+}
+
+impl<'f, 'a, IO: ReadWriteSeek, TP, OCC> DirEntryGuard<'f, 'a, IO, TP, OCC> {
+    fn new(file: &'f mut File<'a, IO, TP, OCC>) -> Self {
+        let saved = file.context.entry.clone();
+        Self {
+            file,
+            saved,
+            armed: true,
+        }
+    }
+
+    fn disarm(mut self) {
+        self.armed = false;
+    }
+}
+
+impl<IO: ReadWriteSeek, TP, OCC> Drop for DirEntryGuard<'_, '_, IO, TP, OCC> {
+    fn drop(&mut self) {
+        if self.armed {
+            self.file.context.entry = self.saved.take();
+        }
+    }
+}
+
 // Note: derive cannot be used because of invalid bounds. See: https://github.com/rust-lang/rust/issues/26925
 impl<IO: ReadWriteSeek, TP, OCC> Clone for File<'_, IO, TP, OCC> {
     fn clone(&self) -> Self {
         File {
             context: self.context.clone(),
             fs: self.fs,
+            buffered: self.buffered,
+            readable: self.readable,
+            writable: self.writable,
+            append: self.append,
         }
     }
 }
@@ -304,19 +1233,16 @@ impl<IO: ReadWriteSeek, TP, OCC> IoBase for File<'_, IO, TP, OCC> {
 impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> Read for File<'_, IO, TP, OCC> {
     async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
         trace!("File::read");
+        if !self.readable {
+            error!("File is not opened for reading");
+            return Err(Error::InvalidInput);
+        }
         let cluster_size = self.fs.cluster_size();
         let current_cluster_opt = if self.context.offset % cluster_size == 0 {
             // next cluster
             match self.context.current_cluster {
                 None => self.context.first_cluster,
-                Some(n) => {
-                    let r = self.fs.cluster_iter(n).next().await;
-                    match r {
-                        Some(Err(err)) => return Err(err),
-                        Some(Ok(n)) => Some(n),
-                        None => None,
-                    }
-                }
+                Some(n) => self.next_cluster(n).await?,
             }
         } else {
             self.context.current_cluster
@@ -328,22 +1254,34 @@ impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> Read for File<'_, IO, TP, OCC> {
         let offset_in_cluster = self.context.offset % cluster_size;
         let bytes_left_in_cluster = (cluster_size - offset_in_cluster) as usize;
         let bytes_left_in_file = self.bytes_left_in_file().unwrap_or(bytes_left_in_cluster);
-        let read_size = cmp::min(cmp::min(buf.len(), bytes_left_in_cluster), bytes_left_in_file);
+        let bytes_to_read = cmp::min(buf.len(), bytes_left_in_file);
+        let mut read_size = cmp::min(bytes_to_read, bytes_left_in_cluster);
         if read_size == 0 {
             return Ok(0);
         }
-        trace!("read {} bytes in cluster {}", read_size, current_cluster);
+        if bytes_to_read > bytes_left_in_cluster {
+            // read following clusters in one go if they are contiguous on the disk
+            let mut max_clusters = (bytes_to_read - bytes_left_in_cluster).div_ceil(cluster_size as usize) as u32;
+            let max_run = self.fs.options.max_read_run_clusters;
+            if max_run > 0 {
+                max_clusters = cmp::min(max_clusters, max_run - 1);
+            }
+            let run = self.contiguous_clusters_after(current_cluster, max_clusters).await?;
+            read_size = cmp::min(
+                bytes_to_read,
+                bytes_left_in_cluster + run as usize * cluster_size as usize,
+            );
+        }
+        trace!("read {} bytes starting in cluster {}", read_size, current_cluster);
         let offset_in_fs = self.fs.offset_from_cluster(current_cluster) + u64::from(offset_in_cluster);
-        let read_bytes = {
-            let mut disk = self.fs.disk.borrow_mut();
-            disk.seek(SeekFrom::Start(offset_in_fs)).await?;
-            disk.read(&mut buf[..read_size]).await?
-        };
+        let read_bytes = self.read_data(offset_in_fs, &mut buf[..read_size]).await?;
         if read_bytes == 0 {
             return Ok(0);
         }
         self.context.offset += read_bytes as u32;
-        self.context.current_cluster = Some(current_cluster);
+        // the read could span multiple contiguous clusters - remember the one containing the last byte
+        let clusters_read = (offset_in_cluster + read_bytes as u32 - 1) / cluster_size;
+        self.context.current_cluster = Some(current_cluster + clusters_read);
 
         if let Some(ref mut e) = self.context.entry {
             if self.fs.options.update_accessed_date {
@@ -356,8 +1294,20 @@ impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> Read for File<'_, IO, TP, OCC> {
 }
 
 impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> Write for File<'_, IO, TP, OCC> {
+    /// Writes data at the current position, at most up to the end of the current cluster.
+    ///
+    /// A write crossing the maximal file size of 4 GiB - 1 bytes is shortened and the returned number of bytes
+    /// reports the part written below the limit. A write starting at the limit fails with `Error::InvalidInput`.
     async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
         trace!("File::write");
+        self.ensure_writable()?;
+        if self.append {
+            self.seek_to_append_position().await?;
+        }
+        if !buf.is_empty() && self.context.offset == MAX_FILE_SIZE {
+            error!("Write past the maximal file size of {} bytes", MAX_FILE_SIZE);
+            return Err(Error::InvalidInput);
+        }
         let cluster_size = self.fs.cluster_size();
         let offset_in_cluster = self.context.offset % cluster_size;
         let bytes_left_in_cluster = (cluster_size - offset_in_cluster) as usize;
@@ -375,14 +1325,7 @@ impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> Write for File<'_, IO, TP, OCC> {
             // next cluster
             let next_cluster = match self.context.current_cluster {
                 None => self.context.first_cluster,
-                Some(n) => {
-                    let r = self.fs.cluster_iter(n).next().await;
-                    match r {
-                        Some(Err(err)) => return Err(err),
-                        Some(Ok(n)) => Some(n),
-                        None => None,
-                    }
-                }
+                Some(n) => self.next_cluster(n).await?,
             };
             if let Some(n) = next_cluster {
                 n
@@ -407,11 +1350,7 @@ impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> Write for File<'_, IO, TP, OCC> {
         };
         trace!("write {} bytes in cluster {}", write_size, current_cluster);
         let offset_in_fs = self.fs.offset_from_cluster(current_cluster) + u64::from(offset_in_cluster);
-        let written_bytes = {
-            let mut disk = self.fs.disk.borrow_mut();
-            disk.seek(SeekFrom::Start(offset_in_fs)).await?;
-            disk.write(&buf[..write_size]).await?
-        };
+        let written_bytes = self.write_data(offset_in_fs, &buf[..write_size]).await?;
         if written_bytes == 0 {
             return Ok(0);
         }
@@ -462,6 +1401,9 @@ impl<IO: ReadWriteSeek, TP, OCC> Seek for File<'_, IO, TP, OCC> {
             // position is the same - nothing to do
             return Ok(u64::from(self.context.offset));
         }
+        if self.is_buffered() {
+            self.fs.write_back_file_buffer().await?;
+        }
         let new_offset_in_clusters = self.fs.clusters_from_bytes(u64::from(new_offset));
         let old_offset_in_clusters = self.fs.clusters_from_bytes(u64::from(self.context.offset));
         let new_cluster = if new_offset == 0 {
@@ -481,7 +1423,7 @@ impl<IO: ReadWriteSeek, TP, OCC> Seek for File<'_, IO, TP, OCC> {
                     r?
                 } else {
                     // cluster chain ends before the new position - seek to the end of the last cluster
-                    new_offset = self.fs.bytes_from_clusters(i + 1) as u32;
+                    new_offset = u32::try_from(self.fs.bytes_from_clusters(i + 1)).unwrap_or(MAX_FILE_SIZE);
                     break;
                 };
             }
@@ -496,3 +1438,183 @@ impl<IO: ReadWriteSeek, TP, OCC> Seek for File<'_, IO, TP, OCC> {
         Ok(u64::from(self.context.offset))
     }
 }
+
+/// A blocking adaptor implementing `std::io::Read`, `std::io::Write` and `std::io::Seek` for a `File`.
+///
+/// This struct is created by `File::into_std`. Every call runs the async operation of the file to completion on the
+/// current thread, parking the thread while the operation is pending. It is meant for host tooling and tests using
+/// storage objects which complete operations by themselves, e.g. in-memory images or wrapped blocking files. It cannot
+/// wrap a truly async storage object which needs an executor or a reactor (e.g. a Tokio file) to make progress, the
+/// calls would block forever. It must not be used from inside an async task either.
+///
+/// Errors are converted into `std::io::Error` keeping the original error as the source. Like `File` the adaptor
+/// should be flushed before it is dropped.
+#[cfg(feature = "std")]
+pub struct StdFile<'a, IO: ReadWriteSeek, TP, OCC> {
+    file: File<'a, IO, TP, OCC>,
+}
+
+#[cfg(feature = "std")]
+impl<'a, IO: ReadWriteSeek, TP, OCC> StdFile<'a, IO, TP, OCC> {
+    /// Returns a reference to the wrapped file.
+    #[must_use]
+    pub fn get_ref(&self) -> &File<'a, IO, TP, OCC> {
+        &self.file
+    }
+
+    /// Returns a mutable reference to the wrapped file.
+    #[must_use]
+    pub fn get_mut(&mut self) -> &mut File<'a, IO, TP, OCC> {
+        &mut self.file
+    }
+
+    /// Unwraps the file.
+    #[must_use]
+    pub fn into_inner(self) -> File<'a, IO, TP, OCC> {
+        self.file
+    }
+}
+
+#[cfg(feature = "std")]
+impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> std::io::Read for StdFile<'_, IO, TP, OCC>
+where
+    IO::Error: std::error::Error + Send + Sync + 'static,
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        Ok(block_on(Read::read(&mut self.file, buf))?)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> std::io::Write for StdFile<'_, IO, TP, OCC>
+where
+    IO::Error: std::error::Error + Send + Sync + 'static,
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        Ok(block_on(Write::write(&mut self.file, buf))?)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(block_on(Write::flush(&mut self.file))?)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<IO: ReadWriteSeek, TP, OCC> std::io::Seek for StdFile<'_, IO, TP, OCC>
+where
+    IO::Error: std::error::Error + Send + Sync + 'static,
+{
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        let pos = match pos {
+            std::io::SeekFrom::Start(n) => SeekFrom::Start(n),
+            std::io::SeekFrom::End(n) => SeekFrom::End(n),
+            std::io::SeekFrom::Current(n) => SeekFrom::Current(n),
+        };
+        Ok(block_on(Seek::seek(&mut self.file, pos))?)
+    }
+}
+
+// Polls a future to completion on the current thread, parking it until the future wakes it up
+#[cfg(feature = "std")]
+fn block_on<F: core::future::Future>(fut: F) -> F::Output {
+    struct ThreadWaker(std::thread::Thread);
+
+    impl std::task::Wake for ThreadWaker {
+        fn wake(self: std::sync::Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = std::task::Waker::from(std::sync::Arc::new(ThreadWaker(std::thread::current())));
+    let mut cx = core::task::Context::from_waker(&waker);
+    let mut fut = core::pin::pin!(fut);
+    loop {
+        if let core::task::Poll::Ready(output) = fut.as_mut().poll(&mut cx) {
+            return output;
+        }
+        std::thread::park();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::tests::CountingStream;
+
+    #[tokio::test]
+    async fn test_file_buffer_coalesces_small_accesses() {
+        let data = (0..=255_u8).cycle().take(2048).collect::<Vec<_>>();
+        let mut disk = CountingStream::new(data.clone());
+        let buffer = RefCell::new(FileBuffer::new());
+        let mut byte = [0_u8; 1];
+        for pos in 0..1024 {
+            assert_eq!(FileBuffer::read(&buffer, &mut disk, pos, &mut byte).await.unwrap(), 1);
+            assert_eq!(byte[0], data[pos as usize]);
+        }
+        assert_eq!(disk.reads, 2);
+
+        // small writes only reach the storage when the buffer is written back
+        for pos in 1024..1536 {
+            assert_eq!(
+                FileBuffer::write(&buffer, &mut disk, pos, &[0xAB], false)
+                    .await
+                    .unwrap(),
+                1
+            );
+        }
+        assert_eq!(disk.writes, 0);
+        // a large read overlapping the buffered block sees the written data
+        let mut large = [0_u8; 1024];
+        assert_eq!(
+            FileBuffer::read(&buffer, &mut disk, 1024, &mut large).await.unwrap(),
+            512
+        );
+        assert_eq!(large[..512], [0xAB; 512]);
+        FileBuffer::write_back(&buffer, &mut disk).await.unwrap();
+        assert_eq!(disk.writes, 1);
+        assert_eq!(disk.inner.inner().get_ref()[1024..1536], [0xAB; 512]);
+
+        // a large write bypasses the buffer and drops the overlapped block
+        assert_eq!(
+            FileBuffer::write(&buffer, &mut disk, 1536, &[0xCD; 512], false)
+                .await
+                .unwrap(),
+            512
+        );
+        assert_eq!(FileBuffer::read(&buffer, &mut disk, 1024, &mut byte).await.unwrap(), 1);
+        assert_eq!(FileBuffer::read(&buffer, &mut disk, 2047, &mut byte).await.unwrap(), 1);
+        assert_eq!(byte[0], 0xCD);
+    }
+
+    #[tokio::test]
+    async fn test_file_buffer_skips_reading_block_at_end() {
+        let mut disk = CountingStream::new(vec![0xEE; 2048]);
+        let buffer = RefCell::new(FileBuffer::new());
+        // a write starting a block at the end of the file replaces the stale content by zeros
+        assert_eq!(
+            FileBuffer::write(&buffer, &mut disk, 512, &[0xAB; 10], true)
+                .await
+                .unwrap(),
+            10
+        );
+        assert_eq!(
+            FileBuffer::write(&buffer, &mut disk, 522, &[0xCD; 10], true)
+                .await
+                .unwrap(),
+            10
+        );
+        assert_eq!(disk.reads, 0);
+        FileBuffer::write_back(&buffer, &mut disk).await.unwrap();
+        let written = &disk.inner.inner().get_ref()[512..1024];
+        assert_eq!(written[..20], [[0xAB; 10], [0xCD; 10]].concat());
+        assert!(written[20..].iter().all(|&b| b == 0));
+        // an unaligned write keeps the data placed before it
+        assert_eq!(
+            FileBuffer::write(&buffer, &mut disk, 1100, &[0xAB; 10], true)
+                .await
+                .unwrap(),
+            10
+        );
+        assert_eq!(disk.reads, 1);
+    }
+}
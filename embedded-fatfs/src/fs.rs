@@ -1,24 +1,40 @@
 use core::borrow::BorrowMut;
-use core::cell::{Cell, RefCell};
+use core::cell::{Cell, RefCell, RefMut};
 use core::char;
 use core::cmp;
+use core::convert::Infallible;
 use core::fmt::Debug;
+use core::future::{self, Future};
 use core::marker::PhantomData;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
 use core::u32;
 
-#[cfg(all(not(feature = "std"), feature = "alloc", feature = "lfn"))]
+#[cfg(all(not(feature = "std"), feature = "alloc"))]
+use alloc::boxed::Box;
+#[cfg(all(not(feature = "std"), feature = "alloc"))]
+use alloc::collections::VecDeque;
+#[cfg(all(not(feature = "std"), feature = "alloc"))]
 use alloc::string::String;
+#[cfg(all(not(feature = "std"), feature = "alloc"))]
+use alloc::vec;
+#[cfg(all(not(feature = "std"), feature = "alloc"))]
+use alloc::vec::Vec;
 #[cfg(feature = "std")]
 use embedded_io_adapters::tokio_1::FromTokio;
-
-use crate::boot_sector::{format_boot_sector, BiosParameterBlock, BootSector};
-use crate::dir::{Dir, DirRawStream};
-use crate::dir_entry::{DirFileEntryData, FileAttributes, SFN_PADDING, SFN_SIZE};
-use crate::error::Error;
-use crate::file::File;
+#[cfg(all(feature = "std", feature = "alloc"))]
+use std::collections::VecDeque;
+
+use crate::boot_sector::{format_boot_sector, fs_type_label, BiosParameterBlock, BootSector};
+use crate::dir::{Dir, DirRawStream, MAX_LONG_NAME_BYTES};
+use crate::dir_entry::{DirEntryEditor, DirFileEntryData, FileAttributes, SFN_PADDING, SFN_SIZE};
+use crate::error::{Error, IoError};
+use crate::file::{File, FileBuffer, MAX_FILE_SIZE};
 use crate::io::{self, IoBase, Read, ReadLeExt, Seek, SeekFrom, Write, WriteLeExt};
 use crate::table::{
-    alloc_cluster, count_free_clusters, format_fat, read_fat_flags, ClusterIterator, RESERVED_FAT_ENTRIES,
+    alloc_cluster, alloc_contiguous_clusters, count_free_clusters, find_fat_mismatch, find_free_cluster_from_hint,
+    find_free_run_from_hint, format_fat, link_new_cluster, link_new_run, mark_bad_cluster, read_fat, read_fat_flags,
+    scan_free_clusters, write_fat, ClusterIterator, FatValue, RESERVED_FAT_ENTRIES,
 };
 use crate::time::{DefaultTimeProvider, TimeProvider};
 
@@ -45,7 +61,11 @@ impl FatType {
     const FAT32_MIN_CLUSTERS: u32 = 65525;
     const FAT32_MAX_CLUSTERS: u32 = 0x0FFF_FFF4;
 
-    pub(crate) fn from_clusters(total_clusters: u32) -> Self {
+    /// Returns the FAT type of a volume with the given number of clusters in the data region.
+    ///
+    /// The FAT type is determined only by the number of clusters, the same way as when mounting a volume.
+    #[must_use]
+    pub fn from_clusters(total_clusters: u32) -> Self {
         if total_clusters < Self::FAT16_MIN_CLUSTERS {
             FatType::Fat12
         } else if total_clusters < Self::FAT32_MIN_CLUSTERS {
@@ -55,7 +75,9 @@ impl FatType {
         }
     }
 
-    pub(crate) fn bits_per_fat_entry(self) -> u32 {
+    /// Returns the size of a single File Allocation Table entry in bits.
+    #[must_use]
+    pub fn bits_per_fat_entry(self) -> u32 {
         match self {
             FatType::Fat12 => 12,
             FatType::Fat16 => 16,
@@ -63,7 +85,9 @@ impl FatType {
         }
     }
 
-    pub(crate) fn min_clusters(self) -> u32 {
+    /// Returns the minimal number of clusters in the data region of a volume of this FAT type.
+    #[must_use]
+    pub fn min_clusters(self) -> u32 {
         match self {
             FatType::Fat12 => 0,
             FatType::Fat16 => Self::FAT16_MIN_CLUSTERS,
@@ -71,7 +95,11 @@ impl FatType {
         }
     }
 
-    pub(crate) fn max_clusters(self) -> u32 {
+    /// Returns the maximal number of clusters in the data region of a volume of this FAT type.
+    ///
+    /// Volumes with more clusters are classified as a different FAT type or, for FAT32, cannot be formatted or mounted.
+    #[must_use]
+    pub fn max_clusters(self) -> u32 {
         match self {
             FatType::Fat12 => Self::FAT16_MIN_CLUSTERS - 1,
             FatType::Fat16 => Self::FAT32_MIN_CLUSTERS - 1,
@@ -233,19 +261,289 @@ impl FsInfoSector {
         self.dirty = true;
     }
 
+    // Moves the allocation cursor back to a freed cluster placed before it, so freed space is reused before the
+    // search wraps around
+    fn rewind_next_free_cluster(&mut self, freed_cluster: Option<u32>) {
+        if let (Some(freed), Some(next)) = (freed_cluster, self.next_free_cluster) {
+            if freed < next {
+                self.set_next_free_cluster(freed);
+            }
+        }
+    }
+
     fn set_free_cluster_count(&mut self, free_cluster_count: u32) {
         self.free_cluster_count = Some(free_cluster_count);
         self.dirty = true;
     }
+
+    fn invalidate_free_cluster_count(&mut self) {
+        if self.free_cluster_count.is_some() {
+            self.free_cluster_count = None;
+            self.dirty = true;
+        }
+    }
+}
+
+/// Forgets the free cluster count in the FS Information Sector if dropped before `disarm` is called.
+///
+/// A future modifying the FAT can be dropped between writes or fail half-way. The count cannot be trusted afterwards
+/// and is computed again by `FileSystem::stats` when needed.
+struct FreeClusterCountGuard<'a> {
+    fs_info: &'a RefCell<FsInfoSector>,
+    armed: bool,
+}
+
+impl<'a> FreeClusterCountGuard<'a> {
+    fn new(fs_info: &'a RefCell<FsInfoSector>) -> Self {
+        Self { fs_info, armed: true }
+    }
+
+    fn disarm(mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for FreeClusterCountGuard<'_> {
+    fn drop(&mut self) {
+        if self.armed {
+            if let Ok(mut fs_info) = self.fs_info.try_borrow_mut() {
+                fs_info.invalidate_free_cluster_count();
+            }
+        }
+    }
+}
+
+// Maximal number of tasks queued for the directory update lock without the `alloc` feature
+#[cfg(not(feature = "alloc"))]
+const MAX_DIR_UPDATE_WAITERS: usize = 8;
+
+/// Tasks waiting for the lock taken by `FileSystem::lock_dir_updates`, in the order they started waiting.
+#[derive(Default)]
+struct DirUpdateWaiters {
+    #[cfg(feature = "alloc")]
+    queue: VecDeque<(u32, Waker)>,
+    // queued entries are kept at the front of the array
+    #[cfg(not(feature = "alloc"))]
+    queue: [Option<(u32, Waker)>; MAX_DIR_UPDATE_WAITERS],
+    // a task finding the queue full waits here until a place is freed
+    #[cfg(not(feature = "alloc"))]
+    overflow: Option<Waker>,
+    next_id: u32,
+}
+
+impl DirUpdateWaiters {
+    fn first_id(&self) -> Option<u32> {
+        self.first().map(|(id, _)| *id)
+    }
+
+    #[cfg(feature = "alloc")]
+    fn first(&self) -> Option<&(u32, Waker)> {
+        self.queue.front()
+    }
+
+    #[cfg(not(feature = "alloc"))]
+    fn first(&self) -> Option<&(u32, Waker)> {
+        self.queue[0].as_ref()
+    }
+
+    // Adds a waiter at the end of the queue and returns its id
+    #[cfg(feature = "alloc")]
+    fn push(&mut self, waker: &Waker) -> u32 {
+        let id = self.next_id;
+        self.queue.push_back((id, waker.clone()));
+        self.next_id = id.wrapping_add(1);
+        id
+    }
+
+    // Adds a waiter at the end of the queue and returns its id, `None` if the queue is full
+    #[cfg(not(feature = "alloc"))]
+    fn push(&mut self, waker: &Waker) -> Option<u32> {
+        let id = self.next_id;
+        let slot = self.queue.iter_mut().find(|slot| slot.is_none())?;
+        *slot = Some((id, waker.clone()));
+        self.next_id = id.wrapping_add(1);
+        Some(id)
+    }
+
+    // Registers a task which could not be queued. The fallback slot is shared, so a task registered before with
+    // another waker is woken to register again and tasks which do not fit in the queue take turns there.
+    #[cfg(not(feature = "alloc"))]
+    fn wait_for_place(&mut self, waker: &Waker) {
+        match &mut self.overflow {
+            Some(old_waker) if old_waker.will_wake(waker) => {}
+            Some(old_waker) => core::mem::replace(old_waker, waker.clone()).wake(),
+            None => self.overflow = Some(waker.clone()),
+        }
+    }
+
+    // Wakes the task waiting for a place in the queue
+    #[cfg(not(feature = "alloc"))]
+    fn wake_overflow(&mut self) {
+        if let Some(waker) = self.overflow.take() {
+            waker.wake();
+        }
+    }
+
+    fn update(&mut self, id: u32, waker: &Waker) {
+        #[cfg(feature = "alloc")]
+        let entry = self.queue.iter_mut().find(|(n, _)| *n == id);
+        #[cfg(not(feature = "alloc"))]
+        let entry = self.queue.iter_mut().flatten().find(|(n, _)| *n == id);
+        if let Some((_, old_waker)) = entry {
+            if !old_waker.will_wake(waker) {
+                old_waker.clone_from(waker);
+            }
+        }
+    }
+
+    fn remove(&mut self, id: u32) {
+        #[cfg(feature = "alloc")]
+        self.queue.retain(|(n, _)| *n != id);
+        #[cfg(not(feature = "alloc"))]
+        if let Some(i) = self
+            .queue
+            .iter()
+            .position(|slot| slot.as_ref().is_some_and(|(n, _)| *n == id))
+        {
+            self.queue[i] = None;
+            self.queue[i..].rotate_left(1);
+        }
+        #[cfg(not(feature = "alloc"))]
+        self.wake_overflow();
+    }
+
+    fn wake_first(&self) {
+        if let Some((_, waker)) = self.first() {
+            waker.wake_by_ref();
+        }
+    }
+}
+
+/// Future returned by `FileSystem::lock_dir_updates`.
+///
+/// A waiting future keeps its place in the queue until it takes the lock and leaves it when dropped, so a cancelled
+/// waiter passes its turn on.
+pub(crate) struct LockDirUpdates<'a> {
+    locked: &'a Cell<bool>,
+    waiters: &'a RefCell<DirUpdateWaiters>,
+    name_buf: &'a RefCell<[u8; MAX_LONG_NAME_BYTES]>,
+    id: Option<u32>,
+}
+
+impl<'a> Future for LockDirUpdates<'a> {
+    type Output = DirUpdateGuard<'a>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let waiters = self.waiters;
+        let mut waiters = waiters.borrow_mut();
+        // the lock is taken by the first queued task, or by a new one if nobody is queued
+        if !self.locked.get() && waiters.first_id() == self.id {
+            if let Some(id) = self.id.take() {
+                waiters.remove(id);
+            }
+            self.locked.set(true);
+            return Poll::Ready(DirUpdateGuard {
+                locked: self.locked,
+                waiters: self.waiters,
+                name_buf: self.name_buf.borrow_mut(),
+            });
+        }
+        if let Some(id) = self.id {
+            waiters.update(id, cx.waker());
+        } else {
+            #[cfg(feature = "alloc")]
+            {
+                self.id = Some(waiters.push(cx.waker()));
+            }
+            #[cfg(not(feature = "alloc"))]
+            {
+                self.id = waiters.push(cx.waker());
+                if self.id.is_none() {
+                    waiters.wait_for_place(cx.waker());
+                }
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for LockDirUpdates<'_> {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            let mut waiters = self.waiters.borrow_mut();
+            waiters.remove(id);
+            // this task could have been woken to take the free lock
+            if !self.locked.get() {
+                waiters.wake_first();
+            }
+        }
+    }
+}
+
+/// Releases the lock taken by `FileSystem::lock_dir_updates` when dropped.
+pub(crate) struct DirUpdateGuard<'a> {
+    locked: &'a Cell<bool>,
+    waiters: &'a RefCell<DirUpdateWaiters>,
+    name_buf: RefMut<'a, [u8; MAX_LONG_NAME_BYTES]>,
+}
+
+impl DirUpdateGuard<'_> {
+    /// Returns the buffer for the name of a new entry, it is only used by the holder of the lock.
+    pub(crate) fn name_buf(&mut self) -> &mut [u8; MAX_LONG_NAME_BYTES] {
+        &mut self.name_buf
+    }
+}
+
+impl Drop for DirUpdateGuard<'_> {
+    fn drop(&mut self) {
+        self.locked.set(false);
+        self.waiters.borrow().wake_first();
+        #[cfg(not(feature = "alloc"))]
+        self.waiters.borrow_mut().wake_overflow();
+    }
+}
+
+/// The action taken when an existing file is opened and its size exceeds the capacity of its cluster chain.
+///
+/// Such a size is often left by an interrupted write. It is selected by `FsOptions::size_mismatch_policy` and
+/// applied by `Dir::open_file`, `Dir::create_file` and `Dir::open_file_with` (unless the file is truncated), but not
+/// by `DirEntry::to_file`.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum SizeMismatchPolicy {
+    /// The size is not checked and reads past the end of the cluster chain fail.
+    Ignore,
+    /// Opening the file fails with `Error::InvalidData`.
+    Error,
+    /// The size of the opened file is reduced to the capacity of the chain. The directory entry is not changed.
+    Clamp,
+    /// The size in the directory entry is reduced to the capacity of the chain when the file is opened.
+    Fix,
 }
 
 /// A FAT filesystem mount options.
 ///
-/// Options are specified as an argument for `FileSystem::new` method.
+/// Options are specified as an argument for `FileSystem::new` method. The builder methods can be chained to select
+/// the OEM code page converter and the time provider together with other options, e.g.
+/// `FsOptions::new().oem_cp_converter(Cp850OemCpConverter::new()).time_provider(NullTimeProvider::new())`.
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-#[derive(Copy, Clone, Debug, Default)]
+#[derive(Copy, Clone, Debug)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct FsOptions<TP, OCC> {
     pub(crate) update_accessed_date: bool,
+    pub(crate) strict_lfn_checksum: bool,
+    pub(crate) read_only: bool,
+    pub(crate) skip_dirty_flag: bool,
+    pub(crate) max_read_run_clusters: u32,
+    pub(crate) allow_small_fat: bool,
+    pub(crate) short_names_only: bool,
+    pub(crate) max_path_depth: u32,
+    pub(crate) max_path_component_len: u32,
+    pub(crate) replace_invalid_name_chars: bool,
+    pub(crate) partition_offset: u64,
+    pub(crate) zero_stale_data: bool,
+    pub(crate) check_storage_size: bool,
+    pub(crate) size_mismatch_policy: SizeMismatchPolicy,
     pub(crate) oem_cp_converter: OCC,
     pub(crate) time_provider: TP,
 }
@@ -254,26 +552,207 @@ impl FsOptions<DefaultTimeProvider, LossyOemCpConverter> {
     /// Creates a `FsOptions` struct with default options.
     #[must_use]
     pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<TP: Default, OCC: Default> Default for FsOptions<TP, OCC> {
+    fn default() -> Self {
         Self {
             update_accessed_date: false,
-            oem_cp_converter: LossyOemCpConverter::new(),
-            time_provider: DefaultTimeProvider::new(),
+            strict_lfn_checksum: false,
+            read_only: false,
+            skip_dirty_flag: false,
+            max_read_run_clusters: 0,
+            allow_small_fat: false,
+            short_names_only: false,
+            max_path_depth: 64,
+            max_path_component_len: 255,
+            replace_invalid_name_chars: false,
+            partition_offset: 0,
+            zero_stale_data: false,
+            check_storage_size: true,
+            size_mismatch_policy: SizeMismatchPolicy::Error,
+            oem_cp_converter: OCC::default(),
+            time_provider: TP::default(),
         }
     }
 }
 
 impl<TP: TimeProvider, OCC: OemCpConverter> FsOptions<TP, OCC> {
     /// If enabled accessed date field in directory entry is updated when reading or writing a file.
+    ///
+    /// Modification date and time is always updated when writing a file. Default is `false` so reading a file never
+    /// writes to the storage (like `noatime` mount option) which reduces flash wear.
     #[must_use]
     pub fn update_accessed_date(mut self, enabled: bool) -> Self {
         self.update_accessed_date = enabled;
         self
     }
 
+    /// If enabled a long file name with a checksum not matching its short name entry is treated as an error.
+    ///
+    /// By default such long name is ignored (a warning is logged) and only the short name is returned for the entry.
+    /// In strict mode directory iteration fails with `Error::CorruptedFileSystem` instead.
+    #[must_use]
+    pub fn strict_lfn_checksum(mut self, enabled: bool) -> Self {
+        self.strict_lfn_checksum = enabled;
+        self
+    }
+
+    /// If enabled the filesystem is mounted in read-only mode.
+    ///
+    /// Nothing is ever written to the storage in this mode: the dirty flag, the FS Information Sector and access dates
+    /// are not updated and every operation modifying the filesystem returns `Error::ReadOnly`.
+    /// Note: this option takes precedence over `update_accessed_date`.
+    #[must_use]
+    pub fn read_only(mut self, enabled: bool) -> Self {
+        self.read_only = enabled;
+        self
+    }
+
+    /// If enabled the volume is never marked as dirty when it is modified.
+    ///
+    /// By default the dirty flag is set in the Boot Sector before the first write and cleared on unmount, so an
+    /// unexpected power loss can be detected on the next mount. Skipping it saves boot sector writes for
+    /// read-mostly workloads at the cost of losing that information.
+    #[must_use]
+    pub fn skip_dirty_flag(mut self, enabled: bool) -> Self {
+        self.skip_dirty_flag = enabled;
+        self
+    }
+
+    /// Sets the maximal number of clusters read from the storage by a single `File::read` call.
+    ///
+    /// When a read buffer spans multiple clusters that are contiguous on the disk they are read with a single storage
+    /// read. This option limits the length of such run, e.g. to match the maximal transfer size of the device.
+    /// Value `1` disables multi-cluster reads. Default is `0` which means the run is limited only by the buffer size.
+    #[must_use]
+    pub fn max_read_run_clusters(mut self, clusters: u32) -> Self {
+        self.max_read_run_clusters = clusters;
+        self
+    }
+
+    /// If enabled a volume with a FAT too small to describe all of its clusters can be mounted.
+    ///
+    /// By default mounting such a volume fails with `Error::CorruptedFileSystem`, because allocating the clusters not
+    /// covered by the FAT would write past the FAT region and corrupt the data that follows it. Enable this only for
+    /// recovery tools that do not allocate clusters on such a volume, e.g. together with `read_only`.
+    #[must_use]
+    pub fn allow_small_fat(mut self, enabled: bool) -> Self {
+        self.allow_small_fat = enabled;
+        self
+    }
+
+    /// If enabled the filesystem operates in pure 8.3 mode and long file names are neither written nor read.
+    ///
+    /// Creating or renaming an entry to a name that is not a valid 8.3 name fails with `Error::InvalidInput`. Names
+    /// are still converted to uppercase, so `readme.txt` is stored as `README.TXT`. LFN entries found on the volume
+    /// are ignored, so entries are listed and looked up by their short names only. This saves directory space and
+    /// keeps the volume readable by minimal FAT implementations, at the cost of losing the original case of names
+    /// and of names that do not fit in the 8.3 format. Default is `false`.
+    #[must_use]
+    pub fn short_names_only(mut self, enabled: bool) -> Self {
+        self.short_names_only = enabled;
+        self
+    }
+
+    /// Sets the maximal number of components of a path passed to `Dir` methods.
+    ///
+    /// Paths with more components are rejected with `Error::InvalidInput` before any directory is read, which bounds
+    /// the work done for hostile paths. The depth is counted from the directory the method is called on. Default is
+    /// `64`, pass a bigger value to access deeper directories.
+    #[must_use]
+    pub fn max_path_depth(mut self, depth: u32) -> Self {
+        self.max_path_depth = depth;
+        self
+    }
+
+    /// Sets the maximal number of characters of a single component of a path passed to `Dir` methods.
+    ///
+    /// Paths with a longer component are rejected with `Error::InvalidInput`. Default is `255` which is the longest
+    /// name FAT can store, so only a lower limit changes the behaviour.
+    #[must_use]
+    pub fn max_path_component_len(mut self, len: u32) -> Self {
+        self.max_path_component_len = len;
+        self
+    }
+
+    /// If enabled characters not allowed in FAT long names are replaced by `_` in names of created or renamed entries.
+    ///
+    /// Control characters and `\ / : * ? " < > |` are not allowed. By default creating or renaming an entry to a
+    /// name containing them fails with `Error::UnsupportedFileNameCharacter`. This lenient mode is meant for tools
+    /// importing files from other filesystems, e.g. `a:b.txt` is created as `a_b.txt`. Looking up entries is not
+    /// affected, so the file has to be opened by its new name. Default is `false`.
+    #[must_use]
+    pub fn replace_invalid_name_chars(mut self, enabled: bool) -> Self {
+        self.replace_invalid_name_chars = enabled;
+        self
+    }
+
+    /// Sets the position of the volume on the storage in bytes.
+    ///
+    /// The Boot Sector is read from this position and it is added to every position of the volume, so a partition
+    /// can be mounted without wrapping the storage in a slice. All positions are 64-bit, so a partition can be placed
+    /// anywhere on a storage larger than 4 GiB (e.g. past 2 TiB). Default is `0`.
+    #[must_use]
+    pub fn partition_offset(mut self, offset: u64) -> Self {
+        self.partition_offset = offset;
+        self
+    }
+
+    /// If enabled storage areas which could expose old data are filled with zeros.
+    ///
+    /// `File::truncate` zeroes the rest of the last cluster kept after the new end of the file, and `File::allocate`
+    /// and `File::preallocate_contiguous` zero all space added to the file. This prevents data of removed or shortened
+    /// files from being read through the file or from the free space of its last cluster, but costs additional writes.
+    /// Default is `false`.
+    #[must_use]
+    pub fn zero_stale_data(mut self, enabled: bool) -> Self {
+        self.zero_stale_data = enabled;
+        self
+    }
+
+    /// If enabled `FileSystem::new` checks that the storage is large enough to hold the whole volume.
+    ///
+    /// The storage length is obtained by seeking to its end and compared with the volume size declared in the BPB
+    /// (plus the partition offset). A truncated image is then rejected when mounting instead of causing read errors
+    /// later in file operations. Disable it for storages which cannot report their length (e.g. streaming devices
+    /// not supporting `SeekFrom::End`). Default is `true`.
+    #[must_use]
+    pub fn check_storage_size(mut self, enabled: bool) -> Self {
+        self.check_storage_size = enabled;
+        self
+    }
+
+    /// Selects what opening an existing file does when its size exceeds the capacity of its cluster chain.
+    ///
+    /// The cluster chain of the file is walked up to the size on every open unless `SizeMismatchPolicy::Ignore` is
+    /// selected. A file whose chain has more clusters than its size requires is not affected. See
+    /// `SizeMismatchPolicy` for the methods applying it. Default is `SizeMismatchPolicy::Error`.
+    #[must_use]
+    pub fn size_mismatch_policy(mut self, policy: SizeMismatchPolicy) -> Self {
+        self.size_mismatch_policy = policy;
+        self
+    }
+
     /// Changes default OEM code page encoder-decoder.
     pub fn oem_cp_converter<OCC2: OemCpConverter>(self, oem_cp_converter: OCC2) -> FsOptions<TP, OCC2> {
         FsOptions::<TP, OCC2> {
             update_accessed_date: self.update_accessed_date,
+            strict_lfn_checksum: self.strict_lfn_checksum,
+            read_only: self.read_only,
+            skip_dirty_flag: self.skip_dirty_flag,
+            max_read_run_clusters: self.max_read_run_clusters,
+            allow_small_fat: self.allow_small_fat,
+            short_names_only: self.short_names_only,
+            max_path_depth: self.max_path_depth,
+            max_path_component_len: self.max_path_component_len,
+            replace_invalid_name_chars: self.replace_invalid_name_chars,
+            partition_offset: self.partition_offset,
+            zero_stale_data: self.zero_stale_data,
+            check_storage_size: self.check_storage_size,
+            size_mismatch_policy: self.size_mismatch_policy,
             oem_cp_converter,
             time_provider: self.time_provider,
         }
@@ -283,6 +762,19 @@ impl<TP: TimeProvider, OCC: OemCpConverter> FsOptions<TP, OCC> {
     pub fn time_provider<TP2: TimeProvider>(self, time_provider: TP2) -> FsOptions<TP2, OCC> {
         FsOptions::<TP2, OCC> {
             update_accessed_date: self.update_accessed_date,
+            strict_lfn_checksum: self.strict_lfn_checksum,
+            read_only: self.read_only,
+            skip_dirty_flag: self.skip_dirty_flag,
+            max_read_run_clusters: self.max_read_run_clusters,
+            allow_small_fat: self.allow_small_fat,
+            short_names_only: self.short_names_only,
+            max_path_depth: self.max_path_depth,
+            max_path_component_len: self.max_path_component_len,
+            replace_invalid_name_chars: self.replace_invalid_name_chars,
+            partition_offset: self.partition_offset,
+            zero_stale_data: self.zero_stale_data,
+            check_storage_size: self.check_storage_size,
+            size_mismatch_policy: self.size_mismatch_policy,
             oem_cp_converter: self.oem_cp_converter,
             time_provider,
         }
@@ -290,6 +782,11 @@ impl<TP: TimeProvider, OCC: OemCpConverter> FsOptions<TP, OCC> {
 }
 
 /// A FAT volume statistics.
+///
+/// This is created by the `stats` method on `FileSystem`. All counts refer to data clusters, i.e. clusters numbered
+/// from 2 to `total_clusters() + 1`. The first two FAT entries are reserved and do not describe any cluster, they are
+/// reported by `reserved_clusters` and are not included in any other count. Sectors placed before the data region
+/// (the boot sector, the FATs and the FAT12/FAT16 root directory) are not counted at all.
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub struct FileSystemStats {
@@ -306,6 +803,8 @@ impl FileSystemStats {
     }
 
     /// Number of total clusters in filesystem usable for file allocation
+    ///
+    /// Only data clusters are counted, the two reserved FAT entries are not included.
     #[must_use]
     pub fn total_clusters(&self) -> u32 {
         self.total_clusters
@@ -316,40 +815,279 @@ impl FileSystemStats {
     pub fn free_clusters(&self) -> u32 {
         self.free_clusters
     }
+
+    /// Number of data clusters which are not free, including clusters marked as bad
+    #[must_use]
+    pub fn used_clusters(&self) -> u32 {
+        self.total_clusters.saturating_sub(self.free_clusters)
+    }
+
+    /// Number of reserved FAT entries which do not describe data clusters (always 2)
+    #[must_use]
+    pub fn reserved_clusters(&self) -> u32 {
+        RESERVED_FAT_ENTRIES
+    }
+
+    /// Size of all data clusters in bytes
+    #[must_use]
+    pub fn total_bytes(&self) -> u64 {
+        u64::from(self.total_clusters) * u64::from(self.cluster_size)
+    }
+
+    /// Size of free clusters in bytes
+    #[must_use]
+    pub fn free_bytes(&self) -> u64 {
+        u64::from(self.free_clusters) * u64::from(self.cluster_size)
+    }
+
+    /// Size of used clusters in bytes
+    #[must_use]
+    pub fn used_bytes(&self) -> u64 {
+        u64::from(self.used_clusters()) * u64::from(self.cluster_size)
+    }
 }
 
-/// A FAT filesystem object.
+/// Free space statistics of a FAT volume.
 ///
-/// `FileSystem` struct is representing a state of a mounted FAT volume.
-pub struct FileSystem<IO: Read + Write + Seek, TP, OCC> {
-    pub(crate) disk: RefCell<IO>,
-    pub(crate) options: FsOptions<TP, OCC>,
-    fat_type: FatType,
-    bpb: BiosParameterBlock,
-    first_data_sector: u32,
-    root_dir_sectors: u32,
-    total_clusters: u32,
-    fs_info: RefCell<FsInfoSector>,
-    current_status_flags: Cell<FsStatusFlags>,
+/// This is created by the `free_space` method on `FileSystem`.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct FreeSpaceStats {
+    cluster_size: u32,
+    free_clusters: u32,
+    largest_free_run: u32,
+    largest_free_run_start: Option<u32>,
+    bad_clusters: u32,
 }
 
-/// The underlying storage device
+impl FreeSpaceStats {
+    /// Cluster size in bytes
+    #[must_use]
+    pub fn cluster_size(&self) -> u32 {
+        self.cluster_size
+    }
+
+    /// Number of free clusters
+    #[must_use]
+    pub fn free_clusters(&self) -> u32 {
+        self.free_clusters
+    }
+
+    /// Number of clusters in the longest run of consecutive free clusters
+    #[must_use]
+    pub fn largest_free_run(&self) -> u32 {
+        self.largest_free_run
+    }
+
+    /// First cluster of the longest run of consecutive free clusters or `None` if there are no free clusters
+    #[must_use]
+    pub fn largest_free_run_start(&self) -> Option<u32> {
+        self.largest_free_run_start
+    }
+
+    /// Number of clusters marked as bad
+    #[must_use]
+    pub fn bad_cluster_count(&self) -> u32 {
+        self.bad_clusters
+    }
+
+    /// Size of the longest run of consecutive free clusters in bytes
+    #[must_use]
+    pub fn largest_free_run_bytes(&self) -> u64 {
+        u64::from(self.largest_free_run) * u64::from(self.cluster_size)
+    }
+}
+
+/// A problem found by `FileSystem::check_consistency`.
 ///
-/// Implement this on the underlying storage device, for example, this could be a file or an in-memory buffer.
-pub trait IntoStorage<T: Read + Write + Seek> {
-    fn into_storage(self) -> T;
+/// Paths are '/' separated and start at the root directory, which itself is `/`.
+#[cfg(feature = "alloc")]
+#[derive(Clone, Eq, PartialEq, Debug)]
+#[non_exhaustive]
+pub enum CheckFinding {
+    /// A chain of `clusters` clusters starting at `first_cluster` is allocated in the FAT but not used by any file or
+    /// directory.
+    LostChain { first_cluster: u32, clusters: u32 },
+    /// `cluster` is used by more than one file or directory, or more than once by the file or directory at `path`.
+    CrossLinked { path: String, cluster: u32 },
+    /// The cluster chain of `path` contains `cluster` which is out of range, free, bad or reserved.
+    InvalidChain { path: String, cluster: u32 },
+    /// The size of the file at `path` does not match the length of its cluster chain.
+    SizeMismatch { path: String, size: u64, clusters: u32 },
+    /// The `.` or `..` entry of the directory at `path` is missing or points to a wrong cluster.
+    InvalidDotEntry { path: String },
+    /// The FAT copies differ, `cluster` is the first cluster with a different entry.
+    FatMismatch { cluster: u32 },
 }
 
-impl<T: ReadWriteSeek> IntoStorage<T> for T {
-    fn into_storage(self) -> Self {
+/// Problems fixed by `FileSystem::repair`.
+///
+/// Nothing is fixed by default, every repair has to be enabled explicitly.
+#[cfg(feature = "alloc")]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RepairOptions {
+    free_lost_chains: bool,
+    fix_file_sizes: bool,
+}
+
+#[cfg(feature = "alloc")]
+impl RepairOptions {
+    /// Creates a `RepairOptions` struct with all repairs disabled.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// If enabled cluster chains allocated in the FAT but not used by any file or directory are freed.
+    #[must_use]
+    pub fn free_lost_chains(mut self, enabled: bool) -> Self {
+        self.free_lost_chains = enabled;
+        self
+    }
+
+    /// If enabled files whose size does not match the length of their cluster chain are truncated.
+    #[must_use]
+    pub fn fix_file_sizes(mut self, enabled: bool) -> Self {
+        self.fix_file_sizes = enabled;
         self
     }
 }
 
-#[cfg(feature = "std")]
-impl<T: tokio::io::AsyncRead + tokio::io::AsyncWrite + tokio::io::AsyncSeek + Unpin> IntoStorage<FromTokio<T>> for T {
-    fn into_storage(self) -> FromTokio<Self> {
-        FromTokio::new(self)
+/// An iterator over the clusters of a file or a directory.
+///
+/// This struct is created by the `clusters` method on `DirEntry`. Clusters are returned in the order of the cluster
+/// chain.
+pub struct Clusters<'a, IO: ReadWriteSeek, TP, OCC> {
+    fs: &'a FileSystem<IO, TP, OCC>,
+    next_cluster: Option<u32>,
+    count: u32,
+    err: bool,
+}
+
+impl<'a, IO: ReadWriteSeek, TP, OCC> Clusters<'a, IO, TP, OCC> {
+    pub(crate) fn new(fs: &'a FileSystem<IO, TP, OCC>, first_cluster: Option<u32>) -> Self {
+        Self {
+            fs,
+            next_cluster: first_cluster,
+            count: 0,
+            err: false,
+        }
+    }
+
+    pub async fn next(&mut self) -> Option<Result<u32, Error<IO::Error>>> {
+        if self.err {
+            return None;
+        }
+        let cluster = self.next_cluster?;
+        // a chain cannot be longer than the number of clusters so it must be circular
+        if self.count == self.fs.total_clusters {
+            error!("cluster chain is longer than {} clusters", self.fs.total_clusters);
+            self.err = true;
+            return Some(Err(Error::CorruptedFileSystem));
+        }
+        self.count += 1;
+        match self.fs.cluster_iter(cluster).next().await.transpose() {
+            Ok(n) => self.next_cluster = n,
+            Err(err) => {
+                self.err = true;
+                return Some(Err(err));
+            }
+        }
+        Some(Ok(cluster))
+    }
+}
+
+/// An iterator over the entries of the File Allocation Table.
+///
+/// This struct is created by the `fat_entries` method on `FileSystem`. Each item is a cluster number together with
+/// its decoded FAT entry.
+pub struct FatEntries<'a, IO: ReadWriteSeek, TP, OCC> {
+    fs: &'a FileSystem<IO, TP, OCC>,
+    cluster: u32,
+    end_cluster: u32,
+    err: bool,
+}
+
+impl<IO: ReadWriteSeek, TP, OCC> FatEntries<'_, IO, TP, OCC> {
+    pub async fn next(&mut self) -> Option<Result<(u32, FatValue), Error<IO::Error>>> {
+        if self.err || self.cluster >= self.end_cluster {
+            return None;
+        }
+        let cluster = self.cluster;
+        let mut fat = self.fs.fat_slice();
+        match read_fat(&mut fat, self.fs.fat_type, cluster).await {
+            Ok(value) => {
+                self.cluster += 1;
+                Some(Ok((cluster, value)))
+            }
+            Err(err) => {
+                self.err = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// A FAT filesystem object.
+///
+/// `FileSystem` struct is representing a state of a mounted FAT volume.
+///
+/// Changes made to the volume must be finished by calling `unmount` (after flushing or closing all files). `Drop`
+/// cannot run asynchronous code so dropping a modified filesystem without unmounting it leaves the dirty flag set and
+/// can lose buffered file data and the FS Information Sector update. Such a drop is reported with a warning. `flush`
+/// can be used to reach a consistent state on the storage while the filesystem stays mounted.
+pub struct FileSystem<IO: Read + Write + Seek, TP, OCC> {
+    pub(crate) disk: RefCell<IO>,
+    pub(crate) options: FsOptions<TP, OCC>,
+    fat_type: FatType,
+    bpb: BiosParameterBlock,
+    oem_name: [u8; 8],
+    first_data_sector: u32,
+    root_dir_sectors: u32,
+    total_clusters: u32,
+    fs_info: RefCell<FsInfoSector>,
+    current_status_flags: Cell<FsStatusFlags>,
+    pub(crate) file_buffer: RefCell<FileBuffer>,
+    dir_update_locked: Cell<bool>,
+    dir_update_waiters: RefCell<DirUpdateWaiters>,
+    entry_name_buf: RefCell<[u8; MAX_LONG_NAME_BYTES]>,
+    freed_clusters_listener: ListenerSlot,
+}
+
+/// The underlying storage device
+///
+/// Every type implementing `Read`, `Write` and `Seek` from `embedded-io-async` is a storage itself, so devices
+/// implementing these traits can be passed to `FileSystem::new` directly. With the `std` feature Tokio streams are
+/// converted by wrapping them in `FromTokio`. Implement this trait only for types that have to be converted into a
+/// storage, for example a file or an in-memory buffer using other IO traits.
+pub trait IntoStorage<T: Read + Write + Seek> {
+    fn into_storage(self) -> T;
+}
+
+impl<T: ReadWriteSeek> IntoStorage<T> for T {
+    fn into_storage(self) -> Self {
+        self
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: tokio::io::AsyncRead + tokio::io::AsyncWrite + tokio::io::AsyncSeek + Unpin> IntoStorage<FromTokio<T>> for T {
+    fn into_storage(self) -> FromTokio<Self> {
+        FromTokio::new(self)
+    }
+}
+
+impl<IO: ReadWriteSeek> FileSystem<IO, DefaultTimeProvider, LossyOemCpConverter> {
+    /// Creates a new filesystem object instance with default options.
+    ///
+    /// It is a shortcut for `FileSystem::new(storage, FsOptions::new())`. Non-ASCII characters in short names are
+    /// decoded using `LossyOemCpConverter` and timestamps are provided by `DefaultTimeProvider`.
+    ///
+    /// # Errors
+    ///
+    /// See `FileSystem::new`.
+    pub async fn new_with_defaults<T: IntoStorage<IO>>(storage: T) -> Result<Self, Error<IO::Error>> {
+        Self::new(storage, FsOptions::new()).await
     }
 }
 
@@ -358,7 +1096,10 @@ impl<IO: ReadWriteSeek, TP, OCC> FileSystem<IO, TP, OCC> {
     ///
     /// Supplied `storage` parameter cannot be seeked. If there is a need to read a fragment of disk
     /// image (e.g. partition) library user should wrap the file struct in a struct limiting
-    /// access to partition bytes only e.g. `fscommon::StreamSlice`.
+    /// access to partition bytes only e.g. `fscommon::StreamSlice` or set `FsOptions::partition_offset`.
+    /// The storage can be anything implementing `Read`, `Write` and `Seek` from `embedded-io-async` (see
+    /// `IntoStorage`). Only `SeekFrom::Start` and `SeekFrom::Current(0)` are used, and `SeekFrom::End(0)` unless
+    /// `FsOptions::check_storage_size` is disabled.
     ///
     /// Note: creating multiple filesystem objects with a single underlying storage can
     /// cause a filesystem corruption.
@@ -369,6 +1110,8 @@ impl<IO: ReadWriteSeek, TP, OCC> FileSystem<IO, TP, OCC> {
     ///
     /// * `Error::CorruptedFileSystem` will be returned if the boot sector and/or the file system information sector
     ///   contains invalid values.
+    /// * `Error::UnexpectedEof` will be returned if the storage is smaller than the volume and
+    ///   `FsOptions::check_storage_size` is enabled.
     /// * `Error::Io` will be returned if the provided storage object returned an I/O error.
     ///
     /// # Panics
@@ -379,14 +1122,33 @@ impl<IO: ReadWriteSeek, TP, OCC> FileSystem<IO, TP, OCC> {
         let mut disk = storage.into_storage();
         trace!("FileSystem::new");
         debug_assert!(disk.seek(SeekFrom::Current(0)).await? == 0);
+        let partition_offset = options.partition_offset;
+        if partition_offset != 0 {
+            disk.seek(SeekFrom::Start(partition_offset)).await?;
+        }
 
         // read boot sector
-        let bpb = {
+        let (bpb, oem_name) = {
             let boot = BootSector::deserialize(&mut disk).await?;
-            boot.validate()?;
-            boot.bpb
+            boot.validate(options.allow_small_fat)?;
+            // replace non-printable characters so the name can always be returned as `&str`
+            let oem_name = boot.oem_name.map(|c| if (0x20..0x7F).contains(&c) { c } else { b'?' });
+            (boot.bpb, oem_name)
         };
 
+        // reject a truncated storage before any of the missing sectors is accessed
+        if options.check_storage_size {
+            let volume_end = partition_offset + bpb.bytes_from_sectors(bpb.total_sectors());
+            let storage_len = disk.seek(SeekFrom::End(0)).await?;
+            if storage_len < volume_end {
+                error!(
+                    "storage is too small for the volume: {} < {} bytes",
+                    storage_len, volume_end
+                );
+                return Err(Error::UnexpectedEof);
+            }
+        }
+
         let root_dir_sectors = bpb.root_dir_sectors();
         let first_data_sector = bpb.first_data_sector();
         let total_clusters = bpb.total_clusters();
@@ -394,8 +1156,10 @@ impl<IO: ReadWriteSeek, TP, OCC> FileSystem<IO, TP, OCC> {
 
         // read FSInfo sector if this is FAT32
         let mut fs_info = if fat_type == FatType::Fat32 {
-            disk.seek(SeekFrom::Start(bpb.bytes_from_sectors(bpb.fs_info_sector())))
-                .await?;
+            disk.seek(SeekFrom::Start(
+                partition_offset + bpb.bytes_from_sectors(bpb.fs_info_sector()),
+            ))
+            .await?;
             FsInfoSector::deserialize(&mut disk).await?
         } else {
             FsInfoSector::default()
@@ -409,6 +1173,12 @@ impl<IO: ReadWriteSeek, TP, OCC> FileSystem<IO, TP, OCC> {
         // Validate the numbers stored in the free_cluster_count and next_free_cluster are within bounds for volume
         fs_info.validate_and_fix(total_clusters);
 
+        // never update access dates on a read-only volume
+        let mut options = options;
+        if options.read_only {
+            options.update_accessed_date = false;
+        }
+
         // return FileSystem struct
         let status_flags = bpb.status_flags();
         trace!("FileSystem::new end");
@@ -417,19 +1187,110 @@ impl<IO: ReadWriteSeek, TP, OCC> FileSystem<IO, TP, OCC> {
             options,
             fat_type,
             bpb,
+            oem_name,
             first_data_sector,
             root_dir_sectors,
             total_clusters,
             fs_info: RefCell::new(fs_info),
             current_status_flags: Cell::new(status_flags),
+            file_buffer: RefCell::new(FileBuffer::new()),
+            dir_update_locked: Cell::new(false),
+            dir_update_waiters: RefCell::default(),
+            entry_name_buf: RefCell::new([0; MAX_LONG_NAME_BYTES]),
+            freed_clusters_listener: ListenerSlot::default(),
         })
     }
 
     /// Returns a type of File Allocation Table (FAT) used by this filesystem.
+    ///
+    /// FAT type is determined from the number of clusters when the filesystem is mounted and never changes.
+    #[must_use]
     pub fn fat_type(&self) -> FatType {
         self.fat_type
     }
 
+    /// Returns a cluster size in bytes.
+    ///
+    /// Cluster is the allocation unit of the filesystem so it is a good choice for buffer sizes when doing large
+    /// sequential reads or writes.
+    #[must_use]
+    pub fn cluster_size(&self) -> u32 {
+        self.bpb.cluster_size()
+    }
+
+    /// Returns a number of clusters in the data region usable for file allocation.
+    #[must_use]
+    pub fn total_clusters(&self) -> u32 {
+        self.total_clusters
+    }
+
+    /// Returns a logical sector size in bytes read from BPB in the Boot Sector.
+    #[must_use]
+    pub fn bytes_per_sector(&self) -> u16 {
+        self.bpb.bytes_per_sector
+    }
+
+    /// Checks if the filesystem is mounted in read-only mode.
+    #[must_use]
+    pub fn is_read_only(&self) -> bool {
+        self.options.read_only
+    }
+
+    /// Checks if the volume is currently marked as dirty in the Boot Sector.
+    ///
+    /// The dirty flag is set by the first write after mounting and cleared by `unmount`.
+    /// Note: a dirty flag that was already set when mounting is never cleared.
+    #[must_use]
+    pub fn is_dirty(&self) -> bool {
+        self.current_status_flags.get().dirty
+    }
+
+    /// Checks if the volume was marked as dirty in the Boot Sector when it was mounted.
+    ///
+    /// This usually means the volume was not cleanly unmounted the last time it was used, e.g. because of
+    /// a power loss. Use `read_status_flags` to additionally check the flags stored in the allocation table.
+    #[must_use]
+    pub fn was_dirty_on_mount(&self) -> bool {
+        self.bpb.status_flags().dirty
+    }
+
+    pub(crate) fn root_dir_entries(&self) -> u32 {
+        u32::from(self.bpb.root_entries)
+    }
+
+    pub(crate) fn ensure_writable(&self) -> Result<(), Error<IO::Error>> {
+        if self.options.read_only {
+            error!("Filesystem is mounted in read-only mode");
+            return Err(Error::ReadOnly);
+        }
+        Ok(())
+    }
+
+    // Serializes operations looking for an existing entry and creating a new one, so futures polled concurrently
+    // cannot both see a name as free and both create it. The lock is released when the guard is dropped, also if the
+    // future holding it is cancelled. The guard also lends the buffer used for names of new entries, so the buffer is
+    // not part of every future creating an entry. Waiting tasks are queued and the lock is handed to them in order,
+    // each one is woken only when it is its turn. Without the `alloc` feature at most `MAX_DIR_UPDATE_WAITERS` tasks
+    // are queued and further ones wait to be woken when a place in the queue is freed or the lock is released.
+    pub(crate) fn lock_dir_updates(&self) -> LockDirUpdates<'_> {
+        LockDirUpdates {
+            locked: &self.dir_update_locked,
+            waiters: &self.dir_update_waiters,
+            name_buf: &self.entry_name_buf,
+            id: None,
+        }
+    }
+
+    /// Returns an OEM name read from the Boot Sector with trailing spaces removed.
+    ///
+    /// The name usually identifies the tool that formatted the volume, e.g. `MSWIN4.1` or `mkfs.fat`. Characters
+    /// other than printable ASCII are replaced by `?`.
+    #[must_use]
+    pub fn oem_name(&self) -> &str {
+        // all bytes are ASCII so the conversion cannot fail
+        core::str::from_utf8(&self.oem_name).unwrap_or_default().trim_end()
+    }
+
     /// Returns a volume identifier read from BPB in the Boot Sector.
     pub fn volume_id(&self) -> u32 {
         self.bpb.volume_id
@@ -449,22 +1310,217 @@ impl<IO: ReadWriteSeek, TP, OCC> FileSystem<IO, TP, OCC> {
         &full_label_slice[..len]
     }
 
+    /// Returns the number of sectors preceding the volume on the disk read from BPB in the Boot Sector.
+    ///
+    /// It is usually the start LBA of the partition holding the volume and `0` for unpartitioned media. The value is
+    /// informational only, use `FsOptions::partition_offset` to mount a volume placed at an offset of the storage.
+    #[must_use]
+    pub fn hidden_sectors(&self) -> u32 {
+        self.bpb.hidden_sectors
+    }
+
+    /// Returns the number of sectors per track read from BPB in the Boot Sector (INT 13h CHS geometry).
+    #[must_use]
+    pub fn sectors_per_track(&self) -> u16 {
+        self.bpb.sectors_per_track
+    }
+
+    /// Returns the number of heads read from BPB in the Boot Sector (INT 13h CHS geometry).
+    #[must_use]
+    pub fn heads(&self) -> u16 {
+        self.bpb.heads
+    }
+
     fn offset_from_sector(&self, sector: u32) -> u64 {
-        self.bpb.bytes_from_sectors(sector)
+        self.options.partition_offset + self.bpb.bytes_from_sectors(sector)
     }
 
-    fn sector_from_cluster(&self, cluster: u32) -> u32 {
-        self.first_data_sector + self.bpb.sectors_from_clusters(cluster - RESERVED_FAT_ENTRIES)
+    /// Returns the sector of the backup Boot Sector.
+    ///
+    /// Only FAT32 volumes have a backup Boot Sector (usually sector 6). It is kept in sync with the Boot Sector by all
+    /// operations writing to it. `None` is returned for FAT12 and FAT16 volumes and for FAT32 volumes without a backup.
+    #[must_use]
+    pub fn backup_boot_sector(&self) -> Option<u32> {
+        if self.fat_type == FatType::Fat32 && self.bpb.backup_boot_sector() != 0 {
+            Some(self.bpb.backup_boot_sector())
+        } else {
+            None
+        }
     }
 
-    pub fn cluster_size(&self) -> u32 {
-        self.bpb.cluster_size()
+    // Sectors of the Boot Sector and its backup
+    fn boot_sector_copies(&self) -> impl Iterator<Item = u32> {
+        core::iter::once(0).chain(self.backup_boot_sector())
+    }
+
+    // Sectors of the FS Information Sector and its backup (placed after the backup Boot Sector)
+    fn fs_info_sector_copies(&self) -> impl Iterator<Item = u32> {
+        let fs_info_sector = self.bpb.fs_info_sector();
+        let backup = self
+            .backup_boot_sector()
+            .map(|backup_boot_sector| backup_boot_sector + fs_info_sector)
+            .filter(|&sector| sector < self.bpb.reserved_sectors());
+        core::iter::once(fs_info_sector).chain(backup)
+    }
+
+    fn sector_from_cluster(&self, cluster: u32) -> u32 {
+        self.first_data_sector + self.bpb.sectors_from_clusters(cluster - RESERVED_FAT_ENTRIES)
     }
 
     pub(crate) fn offset_from_cluster(&self, cluster: u32) -> u64 {
         self.offset_from_sector(self.sector_from_cluster(cluster))
     }
 
+    /// Returns the position of the first byte of a data cluster on the storage.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `cluster` is not a data cluster, i.e. it is lower than 2.
+    #[must_use]
+    pub fn cluster_offset(&self, cluster: u32) -> u64 {
+        assert!(cluster >= RESERVED_FAT_ENTRIES, "Not a data cluster");
+        self.offset_from_cluster(cluster)
+    }
+
+    /// Returns the first cluster of the root directory.
+    ///
+    /// Only FAT32 stores the root directory in a cluster chain. `None` is returned for FAT12 and FAT16 volumes which
+    /// keep it in a fixed region placed before the data clusters.
+    #[must_use]
+    pub fn root_dir_cluster(&self) -> Option<u32> {
+        match self.fat_type {
+            FatType::Fat12 | FatType::Fat16 => None,
+            FatType::Fat32 => Some(self.bpb.root_dir_first_cluster),
+        }
+    }
+
+    /// Reads raw content of a data cluster bypassing files and directories.
+    ///
+    /// The first `buf.len()` bytes of the cluster are read. Data written by files and not flushed yet is written to
+    /// the storage first, so the content is up to date. The FAT is not consulted, so the cluster does not have to
+    /// belong to any file.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if `cluster` is not a data cluster of this volume or if `buf` is
+    ///   longer than the cluster size.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub async fn read_cluster(&self, cluster: u32, buf: &mut [u8]) -> Result<(), Error<IO::Error>> {
+        trace!("read_cluster {}", cluster);
+        self.validate_raw_cluster_access(cluster, buf.len())?;
+        self.write_back_file_buffer().await?;
+        let mut disk = FsIoAdapter { fs: self };
+        disk.seek(SeekFrom::Start(self.offset_from_cluster(cluster))).await?;
+        disk.read_exact(buf).await?;
+        Ok(())
+    }
+
+    /// Writes raw content of a data cluster bypassing files and directories.
+    ///
+    /// **WARNING** This method has the power to corrupt the filesystem when misused. Neither the FAT nor any directory
+    /// entry is updated, so the cluster should be allocated and linked by other means (e.g. it belongs to a file
+    /// whose content is being restored). Writing to a cluster used by an open file or directory makes their view of
+    /// the content stale.
+    ///
+    /// The first `buf.len()` bytes of the cluster are overwritten.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if `cluster` is not a data cluster of this volume or if `buf` is
+    ///   longer than the cluster size.
+    /// * `Error::ReadOnly` will be returned if the filesystem was mounted in read-only mode.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub async fn write_cluster(&self, cluster: u32, buf: &[u8]) -> Result<(), Error<IO::Error>> {
+        trace!("write_cluster {}", cluster);
+        self.ensure_writable()?;
+        self.validate_raw_cluster_access(cluster, buf.len())?;
+        // the buffered block may cover the cluster and would overwrite it later
+        self.discard_file_buffer().await?;
+        self.set_dirty_flag(true).await?;
+        let mut disk = FsIoAdapter { fs: self };
+        disk.seek(SeekFrom::Start(self.offset_from_cluster(cluster))).await?;
+        disk.write_all(buf).await?;
+        Ok(())
+    }
+
+    /// Sets the listener called whenever clusters are freed, e.g. when a file is removed or truncated.
+    ///
+    /// The listener is called after the FAT marks the clusters free, consecutive clusters are reported in one call.
+    /// Nothing is reported if no listener is set, which is the default. `None` removes the listener. The filesystem
+    /// owns the listener until it is replaced, the previous listener is returned if it was set by this method.
+    /// Without the `alloc` feature use `set_freed_clusters_listener_ref`.
+    #[cfg(feature = "alloc")]
+    pub fn set_freed_clusters_listener(
+        &self,
+        listener: Option<Box<dyn FreedClustersListener + Send>>,
+    ) -> Option<Box<dyn FreedClustersListener + Send>> {
+        match self.freed_clusters_listener.replace(listener.map(Listener::Owned)) {
+            Some(Listener::Owned(previous)) => Some(previous),
+            _ => None,
+        }
+    }
+
+    /// Sets a borrowed listener called whenever clusters are freed, e.g. when a file is removed or truncated.
+    ///
+    /// Works like `set_freed_clusters_listener` but does not need the `alloc` feature, the listener usually lives in a
+    /// `static` or is leaked at startup. The previous listener is returned if it was set by this method.
+    pub fn set_freed_clusters_listener_ref(
+        &self,
+        listener: Option<&'static mut (dyn FreedClustersListener + Send)>,
+    ) -> Option<&'static mut (dyn FreedClustersListener + Send)> {
+        match self.freed_clusters_listener.replace(listener.map(Listener::Borrowed)) {
+            Some(Listener::Borrowed(previous)) => Some(previous),
+            _ => None,
+        }
+    }
+
+    /// Runs a maintenance task on the underlying storage while the filesystem stays mounted.
+    ///
+    /// **WARNING** Writing to the storage bypasses the filesystem and can corrupt it. This is an advanced hook, e.g.
+    /// for trimming or erasing freed clusters for wear leveling. Use `cluster_offset` to find a cluster on the storage.
+    ///
+    /// The task waits until directory updates in progress are finished and new ones wait until the task is done, so
+    /// they cannot interleave with it. Data written by files and not flushed yet is written to the storage first and
+    /// nothing stays buffered, so the storage is up to date and changes made to it are not overwritten later. The
+    /// filesystem must not be used from the task. The position of the storage may be left anywhere because the
+    /// filesystem seeks before every access.
+    ///
+    /// # Errors
+    ///
+    /// `Error::Io` will be returned if the underlying storage object returned an I/O error while flushing the buffered
+    /// data. Errors of the task are returned in its output.
+    // The task gets the storage itself so it stays borrowed until the task is done. Directory updates wait on the lock
+    // instead of borrowing it and the filesystem must not be used from the task, so nothing else borrows it meanwhile.
+    #[allow(clippy::await_holding_refcell_ref)]
+    pub async fn with_device<T: DeviceTask<IO>>(&self, task: T) -> Result<T::Output, Error<IO::Error>> {
+        trace!("with_device");
+        let _guard = self.lock_dir_updates().await;
+        self.discard_file_buffer().await?;
+        let mut disk = self.disk.borrow_mut();
+        Ok(task.run(&mut disk).await)
+    }
+
+    fn validate_raw_cluster_access(&self, cluster: u32, len: usize) -> Result<(), Error<IO::Error>> {
+        if cluster < RESERVED_FAT_ENTRIES || cluster >= self.total_clusters + RESERVED_FAT_ENTRIES {
+            error!("cluster {} is out of range", cluster);
+            return Err(Error::InvalidInput);
+        }
+        if len > self.cluster_size() as usize {
+            error!("buffer of {} bytes is larger than a cluster", len);
+            return Err(Error::InvalidInput);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn cluster_from_offset(&self, offset: u64) -> u32 {
+        let offset_in_data = offset - self.offset_from_cluster(RESERVED_FAT_ENTRIES);
+        (offset_in_data / u64::from(self.cluster_size())) as u32 + RESERVED_FAT_ENTRIES
+    }
+
     pub(crate) fn bytes_from_clusters(&self, clusters: u32) -> u64 {
         self.bpb.bytes_from_sectors(self.bpb.sectors_from_clusters(clusters))
     }
@@ -475,7 +1531,7 @@ impl<IO: ReadWriteSeek, TP, OCC> FileSystem<IO, TP, OCC> {
 
     fn fat_slice(&self) -> impl ReadWriteSeek<Error = Error<IO::Error>> + '_ {
         let io = FsIoAdapter { fs: self };
-        fat_slice(io, &self.bpb)
+        fat_slice(io, &self.bpb, self.options.partition_offset)
     }
 
     pub(crate) fn cluster_iter(
@@ -483,43 +1539,128 @@ impl<IO: ReadWriteSeek, TP, OCC> FileSystem<IO, TP, OCC> {
         cluster: u32,
     ) -> ClusterIterator<impl ReadWriteSeek<Error = Error<IO::Error>> + '_, IO::Error> {
         let disk_slice = self.fat_slice();
-        ClusterIterator::new(disk_slice, self.fat_type, cluster)
+        ClusterIterator::new(disk_slice, self.fat_type, cluster, self.total_clusters)
     }
 
     pub(crate) async fn truncate_cluster_chain(&self, cluster: u32) -> Result<(), Error<IO::Error>> {
+        self.ensure_writable()?;
+        // clusters after `cluster` are freed so the buffered block cannot be kept
+        self.discard_file_buffer().await?;
+        let guard = FreeClusterCountGuard::new(&self.fs_info);
+        let mut freed = FreedRuns::new(&self.freed_clusters_listener);
         let mut iter = self.cluster_iter(cluster);
-        let num_free = iter.truncate().await?;
+        let num_free = iter.truncate(|n| freed.add(n)).await?;
+        guard.disarm();
         let mut fs_info = self.fs_info.borrow_mut();
         fs_info.map_free_clusters(|n| n + num_free);
+        fs_info.rewind_next_free_cluster(iter.lowest_freed());
+        Ok(())
+    }
+
+    // Fills a range of the data region with zeros, data buffered for the range is written back first
+    pub(crate) async fn zero_range(&self, offset: u64, len: u64) -> Result<(), Error<IO::Error>> {
+        self.discard_file_buffer().await?;
+        let mut disk = FsIoAdapter { fs: self };
+        disk.seek(SeekFrom::Start(offset)).await?;
+        write_zeros(&mut disk, len).await?;
         Ok(())
     }
 
     pub(crate) async fn free_cluster_chain(&self, cluster: u32) -> Result<(), Error<IO::Error>> {
+        self.ensure_writable()?;
+        // the whole chain is freed so the buffered block cannot be kept
+        self.discard_file_buffer().await?;
+        let guard = FreeClusterCountGuard::new(&self.fs_info);
+        let mut freed = FreedRuns::new(&self.freed_clusters_listener);
         let mut iter = self.cluster_iter(cluster);
-        let num_free = iter.free().await?;
+        let num_free = iter.free(|n| freed.add(n)).await?;
+        guard.disarm();
         let mut fs_info = self.fs_info.borrow_mut();
         fs_info.map_free_clusters(|n| n + num_free);
+        fs_info.rewind_next_free_cluster(iter.lowest_freed());
         Ok(())
     }
 
     pub(crate) async fn alloc_cluster(&self, prev_cluster: Option<u32>, zero: bool) -> Result<u32, Error<IO::Error>> {
         trace!("alloc_cluster");
+        self.ensure_writable()?;
         let hint = self.fs_info.borrow().next_free_cluster;
         let cluster = {
             let mut fat = self.fat_slice();
-            alloc_cluster(&mut fat, self.fat_type, prev_cluster, hint, self.total_clusters).await?
+            find_free_cluster_from_hint(&mut fat, self.fat_type, hint, self.total_clusters).await?
         };
+        // zero the cluster while it is still free so a dropped future never links a cluster with stale content
         if zero {
             let mut disk = self.disk.borrow_mut();
             disk.seek(SeekFrom::Start(self.offset_from_cluster(cluster))).await?;
             write_zeros(&mut *disk, u64::from(self.cluster_size())).await?;
         }
+        let guard = FreeClusterCountGuard::new(&self.fs_info);
+        {
+            let mut fat = self.fat_slice();
+            link_new_cluster(&mut fat, self.fat_type, prev_cluster, cluster).await?;
+        }
+        guard.disarm();
         let mut fs_info = self.fs_info.borrow_mut();
         fs_info.set_next_free_cluster(cluster + 1);
         fs_info.map_free_clusters(|n| n - 1);
         Ok(cluster)
     }
 
+    // Allocates a run of at most `max_count` consecutive clusters starting at the first free cluster and appends it to
+    // `prev_cluster`. The FAT entries of the run are written block by block. Returns the first cluster and the length.
+    pub(crate) async fn alloc_cluster_run(
+        &self,
+        prev_cluster: Option<u32>,
+        max_count: u32,
+        zero: bool,
+    ) -> Result<(u32, u32), Error<IO::Error>> {
+        trace!("alloc_cluster_run {}", max_count);
+        self.ensure_writable()?;
+        let hint = self.fs_info.borrow().next_free_cluster;
+        let (first_cluster, count) = {
+            let mut fat = self.fat_slice();
+            find_free_run_from_hint(&mut fat, self.fat_type, hint, max_count, self.total_clusters).await?
+        };
+        // zero the clusters while they are still free, like in `alloc_cluster`
+        if zero {
+            let mut disk = FsIoAdapter { fs: self };
+            disk.seek(SeekFrom::Start(self.offset_from_cluster(first_cluster)))
+                .await?;
+            write_zeros(&mut disk, u64::from(count) * u64::from(self.cluster_size())).await?;
+        }
+        let guard = FreeClusterCountGuard::new(&self.fs_info);
+        {
+            let mut fat = self.fat_slice();
+            link_new_run(&mut fat, self.fat_type, prev_cluster, first_cluster, count).await?;
+        }
+        guard.disarm();
+        let mut fs_info = self.fs_info.borrow_mut();
+        fs_info.set_next_free_cluster(first_cluster + count);
+        fs_info.map_free_clusters(|n| n - count);
+        Ok((first_cluster, count))
+    }
+
+    pub(crate) async fn alloc_contiguous_clusters(
+        &self,
+        prev_cluster: Option<u32>,
+        count: u32,
+    ) -> Result<u32, Error<IO::Error>> {
+        trace!("alloc_contiguous_clusters {}", count);
+        self.ensure_writable()?;
+        let hint = self.fs_info.borrow().next_free_cluster;
+        let guard = FreeClusterCountGuard::new(&self.fs_info);
+        let first_cluster = {
+            let mut fat = self.fat_slice();
+            alloc_contiguous_clusters(&mut fat, self.fat_type, prev_cluster, count, hint, self.total_clusters).await?
+        };
+        guard.disarm();
+        let mut fs_info = self.fs_info.borrow_mut();
+        fs_info.set_next_free_cluster(first_cluster + count);
+        fs_info.map_free_clusters(|n| n - count);
+        Ok(first_cluster)
+    }
+
     /// Returns status flags for this volume.
     ///
     /// # Errors
@@ -538,6 +1679,7 @@ impl<IO: ReadWriteSeek, TP, OCC> FileSystem<IO, TP, OCC> {
     ///
     /// For FAT32 volumes number of free clusters from the FS Information Sector is returned (may be incorrect).
     /// For other FAT variants number is computed on the first call to this method and cached for later use.
+    /// See `FileSystemStats` for the exact meaning of the returned numbers.
     ///
     /// # Errors
     ///
@@ -556,6 +1698,215 @@ impl<IO: ReadWriteSeek, TP, OCC> FileSystem<IO, TP, OCC> {
         })
     }
 
+    /// Returns the size of the largest file which can be written now.
+    ///
+    /// There are two limits. The hard limit of the format is 4 GiB - 1 bytes (`0xFFFF_FFFF`) because the size is
+    /// stored in a 32-bit field of the directory entry. A write crossing it is shortened and returns the number of
+    /// bytes written up to the limit, the next write starting at it fails with `Error::InvalidInput` even if there is
+    /// free space left (see `File`), so `write_all` fails too. Below it a file is limited by the free space, which
+    /// changes as files are written or removed and is bounded by the size of the data region on small volumes. The
+    /// smaller of the free space (see `stats`) and the hard limit is returned. An existing file can also grow into the
+    /// unused part of its last cluster.
+    ///
+    /// # Errors
+    ///
+    /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub async fn max_file_size(&self) -> Result<u64, Error<IO::Error>> {
+        let free_bytes = self.stats().await?.free_bytes();
+        Ok(cmp::min(u64::from(MAX_FILE_SIZE), free_bytes))
+    }
+
+    /// Returns free space statistics including the longest run of consecutive free clusters.
+    ///
+    /// Unlike `stats` this always scans the whole FAT. The number of free clusters is computed in the same pass and
+    /// replaces the cached value used by `stats`, so a possibly incorrect count from the FAT32 FS Information Sector
+    /// gets fixed. The longest run is the largest file that `File::preallocate_contiguous` can currently allocate.
+    ///
+    /// # Errors
+    ///
+    /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub async fn free_space(&self) -> Result<FreeSpaceStats, Error<IO::Error>> {
+        let free = {
+            let mut fat = self.fat_slice();
+            scan_free_clusters(&mut fat, self.fat_type, self.total_clusters).await?
+        };
+        self.fs_info.borrow_mut().set_free_cluster_count(free.count);
+        Ok(FreeSpaceStats {
+            cluster_size: self.cluster_size(),
+            free_clusters: free.count,
+            largest_free_run: free.largest_run_len,
+            largest_free_run_start: free.largest_run_start,
+            bad_clusters: free.bad_count,
+        })
+    }
+
+    /// Marks a free cluster as bad so it is never allocated.
+    ///
+    /// The bad cluster marker is specific to the FAT type (`0xFF7`, `0xFFF7` or `0x0FFFFFF7`) and is understood by
+    /// other FAT implementations. Bad clusters are reported by `free_space` and `fat_entries`.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if `cluster` is not a data cluster of this volume or it is not free.
+    /// * `Error::ReadOnly` will be returned if the filesystem was mounted in read-only mode.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub async fn mark_bad_cluster(&self, cluster: u32) -> Result<(), Error<IO::Error>> {
+        trace!("mark_bad_cluster {}", cluster);
+        self.ensure_writable()?;
+        if cluster < RESERVED_FAT_ENTRIES || cluster >= self.total_clusters + RESERVED_FAT_ENTRIES {
+            error!("cluster {} is out of range", cluster);
+            return Err(Error::InvalidInput);
+        }
+        {
+            let mut fat = self.fat_slice();
+            mark_bad_cluster(&mut fat, self.fat_type, cluster).await?;
+        }
+        self.fs_info.borrow_mut().map_free_clusters(|n| n - 1);
+        Ok(())
+    }
+
+    /// Rewrites the file system type label in the Boot Sector (e.g. `FAT16   `) to match the actual FAT type.
+    ///
+    /// The label is informational only: the FAT type is always determined from the number of clusters and the label is
+    /// never used to validate the volume. Some tools trust it though, so a missing or wrong label (e.g. `FAT     `)
+    /// can confuse them. Only the label field is written, in the Boot Sector and in the backup Boot Sector of a FAT32
+    /// volume. Returns `true` if any of them had to be changed.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if the Boot Sector has no extended boot signature, so there is no label
+    ///   field.
+    /// * `Error::ReadOnly` will be returned if the filesystem was mounted in read-only mode.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub async fn relabel_fstype(&self) -> Result<bool, Error<IO::Error>> {
+        trace!("relabel_fstype");
+        self.ensure_writable()?;
+        if self.bpb.ext_sig != 0x29 {
+            error!("Boot Sector has no extended boot signature");
+            return Err(Error::InvalidInput);
+        }
+        let label = fs_type_label(self.fat_type);
+        // offset of fs_type_label field in the Boot Sector
+        let label_offset = if self.fat_type == FatType::Fat32 { 0x052 } else { 0x036 };
+        let mut changed = false;
+        let mut disk = FsIoAdapter { fs: self };
+        for sector in self.boot_sector_copies() {
+            let offset = self.offset_from_sector(sector) + label_offset;
+            let mut current = [0_u8; 8];
+            disk.seek(SeekFrom::Start(offset)).await?;
+            disk.read_exact(&mut current).await?;
+            if current != label {
+                disk.seek(SeekFrom::Start(offset)).await?;
+                disk.write_all(&label).await?;
+                changed = true;
+            }
+        }
+        disk.flush().await?;
+        Ok(changed)
+    }
+
+    /// Returns an iterator over all entries of the File Allocation Table.
+    ///
+    /// Entries are returned in cluster order starting with the first data cluster (cluster 2). The volume is only read,
+    /// so the iterator can be used to draw a map of used, free and bad clusters while files are open.
+    #[must_use]
+    pub fn fat_entries(&self) -> FatEntries<'_, IO, TP, OCC> {
+        FatEntries {
+            fs: self,
+            cluster: RESERVED_FAT_ENTRIES,
+            end_cluster: self.total_clusters + RESERVED_FAT_ENTRIES,
+            err: false,
+        }
+    }
+
+    /// Compares all copies of the File Allocation Table.
+    ///
+    /// Returns the number of the first cluster which has a different entry in any of the FAT copies or `None` if all
+    /// copies are consistent (which is always the case for volumes with a single FAT). The volume is not modified.
+    /// Note: file operations always read the active FAT (the first one if mirroring is enabled) and write to all
+    /// copies when mirroring is enabled.
+    ///
+    /// # Errors
+    ///
+    /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub async fn check_fats(&self) -> Result<Option<u32>, Error<IO::Error>> {
+        let sectors_per_fat = self.bpb.sectors_per_fat();
+        let fat_copy = |index: u32| {
+            let first_sector = self.bpb.reserved_sectors() + index * sectors_per_fat;
+            let begin = self.offset_from_sector(first_sector);
+            let size = self.bpb.bytes_from_sectors(sectors_per_fat);
+            DiskSlice::new(begin, size, 1, FsIoAdapter { fs: self })
+        };
+        let mut first_fat = fat_copy(0);
+        for i in 1..u32::from(self.bpb.fats) {
+            let mut other_fat = fat_copy(i);
+            let mismatch =
+                find_fat_mismatch(&mut first_fat, &mut other_fat, self.fat_type, self.total_clusters).await?;
+            if let Some(cluster) = mismatch {
+                warn!("FAT {} differs from FAT 0 at cluster {}", i, cluster);
+                return Ok(Some(cluster));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reads raw bytes of the File Allocation Table.
+    ///
+    /// `offset` is relative to the start of the active FAT (the first one if mirroring is enabled). Returns the number
+    /// of bytes read, which is 0 past the end of the FAT. This can be used to save a copy of the FAT before modifying
+    /// the volume, e.g. by `repair`.
+    ///
+    /// # Errors
+    ///
+    /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub async fn read_fat_bytes(&self, offset: u64, buf: &mut [u8]) -> Result<usize, Error<IO::Error>> {
+        let fat_size = u64::from(self.bpb.sectors_per_fat()) * u64::from(self.bpb.bytes_per_sector);
+        if offset >= fat_size {
+            return Ok(0);
+        }
+        let len = cmp::min(buf.len() as u64, fat_size - offset) as usize;
+        let mut fat = self.fat_slice();
+        fat.seek(SeekFrom::Start(offset)).await?;
+        fat.read_exact(&mut buf[..len]).await?;
+        Ok(len)
+    }
+
+    /// Compares the Boot Sector with its backup copy.
+    ///
+    /// Returns the offset of the first byte of the 512 byte Boot Sector structure which differs in the backup or `None`
+    /// if both copies are the same. FAT12 and FAT16 volumes have no backup so `None` is always returned for them. The
+    /// volume is not modified.
+    ///
+    /// # Errors
+    ///
+    /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub async fn verify_backup_boot_sector(&self) -> Result<Option<u32>, Error<IO::Error>> {
+        let Some(backup_boot_sector) = self.backup_boot_sector() else {
+            return Ok(None);
+        };
+        let primary_offset = self.offset_from_sector(0);
+        let backup_offset = self.offset_from_sector(backup_boot_sector);
+        let mut disk = FsIoAdapter { fs: self };
+        let mut primary = [0_u8; 32];
+        let mut backup = [0_u8; 32];
+        for chunk_offset in (0..512).step_by(primary.len()) {
+            disk.seek(SeekFrom::Start(primary_offset + chunk_offset)).await?;
+            disk.read_exact(&mut primary).await?;
+            disk.seek(SeekFrom::Start(backup_offset + chunk_offset)).await?;
+            disk.read_exact(&mut backup).await?;
+            if let Some(i) = primary.iter().zip(&backup).position(|(a, b)| a != b) {
+                let offset = chunk_offset as u32 + i as u32;
+                warn!("backup Boot Sector differs from the Boot Sector at byte {}", offset);
+                return Ok(Some(offset));
+            }
+        }
+        Ok(None)
+    }
+
     /// Forces free clusters recalculation.
     async fn recalc_free_clusters(&self) -> Result<u32, Error<IO::Error>> {
         let mut fat = self.fat_slice();
@@ -566,38 +1917,69 @@ impl<IO: ReadWriteSeek, TP, OCC> FileSystem<IO, TP, OCC> {
 
     /// Unmounts the filesystem.
     ///
-    /// Updates the FS Information Sector if needed.
+    /// Updates the FS Information Sector if needed and clears the dirty flag if it was set by this mount.
+    /// A dirty flag that was already set when mounting is left untouched.
     ///
     /// # Errors
     ///
     /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
     pub async fn unmount(self) -> Result<(), Error<IO::Error>> {
-        self.flush().await
+        self.flush().await?;
+        self.set_dirty_flag(false).await?;
+        Ok(())
     }
 
-    /// Flushes any in memory state to the filesystem
+    /// Flushes filesystem metadata kept in memory to the storage.
+    ///
+    /// Updates the FS Information Sector if needed and flushes the underlying storage object. The filesystem stays
+    /// mounted and can be used afterwards, which makes this method suitable for periodic syncing in long-running
+    /// applications. Buffered file data is written back too, but directory entries of open files are not - use
+    /// `File::flush` to write them back.
+    /// Note: the dirty flag is not cleared by this method. Only `unmount` clears it.
     ///
-    /// Updates the FS Information Sector if needed and clears
-    /// the dirty flag.
+    /// # Errors
+    ///
+    /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
     pub async fn flush(&self) -> Result<(), Error<IO::Error>> {
+        self.write_back_file_buffer().await?;
         self.flush_fs_info().await?;
-        self.set_dirty_flag(false).await?;
+        self.disk.borrow_mut().flush().await?;
+        Ok(())
+    }
+
+    // The storage stays borrowed while the block is written, `FileBuffer::write_back` copies it out of the buffer first
+    #[allow(clippy::await_holding_refcell_ref)]
+    pub(crate) async fn write_back_file_buffer(&self) -> Result<(), Error<IO::Error>> {
+        let mut disk = self.disk.borrow_mut();
+        FileBuffer::write_back(&self.file_buffer, &mut *disk).await
+    }
+
+    // Freed clusters can be reused by directories which are not buffered so the buffered block must not outlive them
+    async fn discard_file_buffer(&self) -> Result<(), Error<IO::Error>> {
+        self.write_back_file_buffer().await?;
+        self.file_buffer.borrow_mut().discard();
         Ok(())
     }
 
     async fn flush_fs_info(&self) -> Result<(), Error<IO::Error>> {
         let mut fs_info = self.fs_info.borrow_mut();
-        if self.fat_type == FatType::Fat32 && fs_info.dirty {
+        // Note: free cluster count recalculated on a read-only volume is only kept in memory
+        if self.fat_type == FatType::Fat32 && fs_info.dirty && !self.options.read_only {
             let mut disk = self.disk.borrow_mut();
-            let fs_info_sector_offset = self.offset_from_sector(u32::from(self.bpb.fs_info_sector));
-            disk.seek(SeekFrom::Start(fs_info_sector_offset)).await?;
-            fs_info.serialize(&mut *disk).await?;
+            for sector in self.fs_info_sector_copies() {
+                disk.seek(SeekFrom::Start(self.offset_from_sector(sector))).await?;
+                fs_info.serialize(&mut *disk).await?;
+            }
             fs_info.dirty = false;
         }
         Ok(())
     }
 
     pub(crate) async fn set_dirty_flag(&self, dirty: bool) -> Result<(), IO::Error> {
+        if self.options.read_only || (dirty && self.options.skip_dirty_flag) {
+            // Never touch the boot sector of a read-only volume
+            return Ok(());
+        }
         // Do not overwrite flags read from BPB on mount
         let mut flags = self.bpb.status_flags();
         flags.dirty |= dirty;
@@ -616,8 +1998,11 @@ impl<IO: ReadWriteSeek, TP, OCC> FileSystem<IO, TP, OCC> {
             0x025
         };
         let mut disk = self.disk.borrow_mut();
-        disk.seek(io::SeekFrom::Start(offset)).await?;
-        disk.write_u8(encoded).await?;
+        for sector in self.boot_sector_copies() {
+            disk.seek(io::SeekFrom::Start(self.offset_from_sector(sector) + offset))
+                .await?;
+            disk.write_u8(encoded).await?;
+        }
         disk.flush().await?;
         self.current_status_flags.set(flags);
         Ok(())
@@ -628,11 +2013,10 @@ impl<IO: ReadWriteSeek, TP, OCC> FileSystem<IO, TP, OCC> {
         trace!("root_dir");
         let root_rdr = {
             match self.fat_type {
-                FatType::Fat12 | FatType::Fat16 => DirRawStream::Root(DiskSlice::from_sectors(
-                    self.first_data_sector - self.root_dir_sectors,
-                    self.root_dir_sectors,
+                FatType::Fat12 | FatType::Fat16 => DirRawStream::Root(DiskSlice::new(
+                    self.offset_from_sector(self.first_data_sector - self.root_dir_sectors),
+                    self.bpb.bytes_from_sectors(self.root_dir_sectors),
                     1,
-                    &self.bpb,
                     FsIoAdapter { fs: self },
                 )),
                 FatType::Fat32 => DirRawStream::File(File::new(Some(self.bpb.root_dir_first_cluster), None, self)),
@@ -656,54 +2040,671 @@ impl<IO: ReadWriteSeek, TP, OCC: OemCpConverter> FileSystem<IO, TP, OCC> {
         // Build string from character iterator
         char_iter.collect()
     }
-}
+}
+
+impl<IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> FileSystem<IO, TP, OCC> {
+    /// Returns a volume label from root directory as `String`.
+    ///
+    /// It finds file with `VOLUME_ID` attribute and returns its short name.
+    ///
+    /// # Errors
+    ///
+    /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    #[cfg(feature = "alloc")]
+    pub async fn read_volume_label_from_root_dir(&self) -> Result<Option<String>, Error<IO::Error>> {
+        // Note: DirEntry::file_short_name() cannot be used because it interprets name as 8.3
+        // (adds dot before an extension)
+        let volume_label_opt = self.read_volume_label_from_root_dir_as_bytes().await?;
+        volume_label_opt.map_or(Ok(None), |volume_label| {
+            // Strip label padding
+            let len = volume_label
+                .iter()
+                .rposition(|b| *b != SFN_PADDING)
+                .map_or(0, |p| p + 1);
+            let label_slice = &volume_label[..len];
+            // Decode volume label from OEM codepage
+            let volume_label_iter = label_slice.iter().copied();
+            let char_iter = volume_label_iter.map(|c| self.options.oem_cp_converter.decode(c));
+            // Build string from character iterator
+            Ok(Some(char_iter.collect::<String>()))
+        })
+    }
+
+    /// Returns a volume label from root directory as byte array.
+    ///
+    /// Label is encoded in the OEM codepage.
+    /// It finds file with `VOLUME_ID` attribute and returns its short name.
+    ///
+    /// # Errors
+    ///
+    /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub async fn read_volume_label_from_root_dir_as_bytes(&self) -> Result<Option<[u8; SFN_SIZE]>, Error<IO::Error>> {
+        let entry_opt = self.root_dir().find_volume_entry().await?;
+        Ok(entry_opt.map(|e| *e.raw_short_name()))
+    }
+
+    /// Checks that no cluster is used by more than one file or directory.
+    ///
+    /// The whole directory tree is walked and the cluster chain of every entry is recorded in a bitmap. Returns the
+    /// number of the first cluster found in more than one chain (cross-linked files) or twice in a single chain
+    /// (circular chain) or `None` if all chains are disjoint. The volume is not modified. This reads all directories
+    /// and the FAT entries of all files so it can take a long time on big volumes. `check_consistency` reports all
+    /// problems instead of the first cross-link.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::CorruptedFileSystem` will be returned if a chain contains an invalid cluster number.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    #[cfg(feature = "alloc")]
+    pub async fn check(&self) -> Result<Option<u32>, Error<IO::Error>> {
+        let mut used = vec![0_u8; (self.total_clusters as usize).div_ceil(8)];
+        if self.fat_type == FatType::Fat32 {
+            let cluster = self.bpb.root_dir_first_cluster;
+            if let Some(n) = self.mark_chain_used(cluster, &mut used).await? {
+                return Ok(Some(n));
+            }
+        }
+        let mut dirs = vec![self.root_dir()];
+        while let Some(dir) = dirs.pop() {
+            let mut iter = dir.iter();
+            while let Some(r) = iter.next().await {
+                let entry = r?;
+                let name = entry.short_file_name_as_bytes();
+                if name == b"." || name == b".." {
+                    continue;
+                }
+                if let Some(cluster) = entry.first_cluster() {
+                    if let Some(n) = self.mark_chain_used(cluster, &mut used).await? {
+                        warn!("cluster {} is used more than once", n);
+                        return Ok(Some(n));
+                    }
+                }
+                if entry.is_dir() {
+                    dirs.push(entry.to_dir());
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Checks the consistency of the volume and returns all problems found.
+    ///
+    /// The whole directory tree and the FAT are read, the volume is never modified. Reported are lost cluster chains,
+    /// cross-linked clusters, chains containing invalid clusters, files whose size does not match their cluster chain,
+    /// directories with invalid `.` or `..` entries and differing FAT copies (see `CheckFinding`). An empty vector
+    /// means no problem has been found. Like `check`, this can take a long time on big volumes.
+    ///
+    /// # Errors
+    ///
+    /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    #[cfg(feature = "alloc")]
+    pub async fn check_consistency(&self) -> Result<Vec<CheckFinding>, Error<IO::Error>> {
+        let mut findings = Vec::new();
+        let (used, _) = self.check_tree(&mut findings).await?;
+        self.find_lost_chains(&used, &mut findings).await?;
+        if let Some(cluster) = self.check_fats().await? {
+            findings.push(CheckFinding::FatMismatch { cluster });
+        }
+        Ok(findings)
+    }
+
+    /// Walks the directory tree and returns a bitmap of the used clusters and whether all directories could be read.
+    #[cfg(feature = "alloc")]
+    async fn check_tree(&self, findings: &mut Vec<CheckFinding>) -> Result<(Vec<u8>, bool), Error<IO::Error>> {
+        let mut used = vec![0_u8; (self.total_clusters as usize).div_ceil(8)];
+        let mut complete = true;
+        let root_cluster = if self.fat_type == FatType::Fat32 {
+            let cluster = self.bpb.root_dir_first_cluster;
+            complete = self.check_chain(cluster, "/", &mut used, findings).await?.is_some();
+            Some(cluster)
+        } else {
+            None
+        };
+        // directories to visit together with their path, first cluster and the first cluster of the parent (`None` for
+        // the root directory as stored in `..` entries)
+        let mut dirs = vec![(self.root_dir(), String::from("/"), root_cluster, None)];
+        while let Some((dir, path, dir_cluster, parent_cluster)) = dirs.pop() {
+            let mut dot_ok = false;
+            let mut dot_dot_ok = false;
+            let mut iter = dir.iter();
+            while let Some(r) = iter.next().await {
+                let entry = r?;
+                let name = entry.short_file_name_as_bytes();
+                if name == b"." {
+                    dot_ok = entry.is_dir() && entry.first_cluster() == dir_cluster;
+                    continue;
+                }
+                if name == b".." {
+                    // `..` pointing to the root directory should contain 0, some systems store the root cluster
+                    let parent_ok = entry.first_cluster() == parent_cluster
+                        || (parent_cluster.is_none() && entry.first_cluster() == root_cluster);
+                    dot_dot_ok = entry.is_dir() && parent_ok;
+                    continue;
+                }
+                let mut entry_path = if path == "/" { String::new() } else { path.clone() };
+                entry_path.push('/');
+                entry_path.push_str(&entry.file_name());
+                let clusters = match entry.first_cluster() {
+                    Some(cluster) => self.check_chain(cluster, &entry_path, &mut used, findings).await?,
+                    None => Some(0),
+                };
+                if entry.is_dir() {
+                    match entry.first_cluster() {
+                        Some(cluster) if clusters.is_some() => {
+                            let parent_cluster = if path == "/" { None } else { dir_cluster };
+                            dirs.push((entry.to_dir(), entry_path, Some(cluster), parent_cluster));
+                        }
+                        // the content of a directory with an invalid chain is unknown
+                        Some(_) => complete = false,
+                        None => findings.push(CheckFinding::InvalidDotEntry { path: entry_path }),
+                    }
+                } else if let Some(clusters) = clusters {
+                    let size = entry.len();
+                    if u64::from(clusters) != size.div_ceil(u64::from(self.cluster_size())) {
+                        warn!(
+                            "size of {} does not match its {} clusters",
+                            entry_path.as_str(),
+                            clusters
+                        );
+                        findings.push(CheckFinding::SizeMismatch {
+                            path: entry_path,
+                            size,
+                            clusters,
+                        });
+                    }
+                }
+            }
+            if dir_cluster.is_some() && dir_cluster != root_cluster && !(dot_ok && dot_dot_ok) {
+                warn!("invalid . or .. entry in {}", path.as_str());
+                findings.push(CheckFinding::InvalidDotEntry { path });
+            }
+        }
+        Ok((used, complete))
+    }
+
+    /// Checks the volume like `check_consistency` and fixes the problems enabled in `options`.
+    ///
+    /// Returns the problems found before repairing. Lost chains are freed only if the whole directory tree could be
+    /// read, so no cluster referenced from a directory with a broken cluster chain is freed. A file whose size does not
+    /// match its cluster chain is truncated to the shorter of both: clusters past the end of the file are freed and a
+    /// size pointing past the end of the chain is reduced. All FAT copies and the free cluster count in the FS
+    /// Information Sector are updated. Cross-linked clusters, invalid chains, `.` and `..` entries and differing FAT
+    /// copies are only reported. Consider saving the FAT with `read_fat_bytes` before repairing.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::ReadOnly` will be returned if a repair is enabled and the filesystem is mounted in read-only mode.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    #[cfg(feature = "alloc")]
+    pub async fn repair(&self, options: RepairOptions) -> Result<Vec<CheckFinding>, Error<IO::Error>> {
+        if options.free_lost_chains || options.fix_file_sizes {
+            self.ensure_writable()?;
+        }
+        let mut findings = Vec::new();
+        let (used, complete) = self.check_tree(&mut findings).await?;
+        self.find_lost_chains(&used, &mut findings).await?;
+        if let Some(cluster) = self.check_fats().await? {
+            findings.push(CheckFinding::FatMismatch { cluster });
+        }
+        if options.fix_file_sizes {
+            for finding in &findings {
+                if let CheckFinding::SizeMismatch { path, size, clusters } = finding {
+                    let capacity = u64::from(*clusters) * u64::from(self.cluster_size());
+                    info!("truncating {} to {} bytes", path.as_str(), cmp::min(*size, capacity));
+                    let mut file = self.root_dir().open_file_unchecked(path).await?;
+                    file.seek(SeekFrom::Start(cmp::min(*size, capacity))).await?;
+                    file.truncate().await?;
+                    file.flush().await?;
+                }
+            }
+        }
+        let lost = findings.iter().any(|f| matches!(f, CheckFinding::LostChain { .. }));
+        if options.free_lost_chains && lost {
+            if complete {
+                self.free_unused_clusters(&used).await?;
+            } else {
+                warn!("not freeing lost chains because some directories could not be read");
+            }
+        }
+        Ok(findings)
+    }
+
+    /// Frees all allocated clusters not marked in `used`.
+    #[cfg(feature = "alloc")]
+    async fn free_unused_clusters(&self, used: &[u8]) -> Result<(), Error<IO::Error>> {
+        self.set_dirty_flag(true).await?;
+        self.discard_file_buffer().await?;
+        let guard = FreeClusterCountGuard::new(&self.fs_info);
+        let mut fat = self.fat_slice();
+        // the free cluster count is recomputed because a corrupted volume often has a wrong one
+        let mut num_freed = 0;
+        let mut num_free = 0;
+        let mut lowest_freed = None;
+        let mut freed = FreedRuns::new(&self.freed_clusters_listener);
+        for cluster in RESERVED_FAT_ENTRIES..self.total_clusters + RESERVED_FAT_ENTRIES {
+            let index = (cluster - RESERVED_FAT_ENTRIES) as usize;
+            // clusters freed when fixing file sizes are still marked as used
+            let is_used = used[index / 8] & (1 << (index % 8)) != 0;
+            match read_fat(&mut fat, self.fat_type, cluster).await? {
+                FatValue::Data(_) | FatValue::EndOfChain | FatValue::Reserved if !is_used => {
+                    write_fat(&mut fat, self.fat_type, cluster, FatValue::Free).await?;
+                    freed.add(cluster);
+                    lowest_freed = lowest_freed.or(Some(cluster));
+                    num_freed += 1;
+                    num_free += 1;
+                }
+                FatValue::Free => num_free += 1,
+                _ => {}
+            }
+        }
+        guard.disarm();
+        info!("freed {} lost clusters", num_freed);
+        let mut fs_info = self.fs_info.borrow_mut();
+        fs_info.set_free_cluster_count(num_free);
+        fs_info.rewind_next_free_cluster(lowest_freed);
+        Ok(())
+    }
+
+    /// Marks the chain starting at `first_cluster` in `used` and returns its length or `None` if it is not valid.
+    #[cfg(feature = "alloc")]
+    async fn check_chain(
+        &self,
+        first_cluster: u32,
+        path: &str,
+        used: &mut [u8],
+        findings: &mut Vec<CheckFinding>,
+    ) -> Result<Option<u32>, Error<IO::Error>> {
+        let mut fat = self.fat_slice();
+        let mut cluster = first_cluster;
+        let mut clusters = 0;
+        loop {
+            if cluster < RESERVED_FAT_ENTRIES || cluster >= self.total_clusters + RESERVED_FAT_ENTRIES {
+                warn!("invalid cluster number {} in the chain of {}", cluster, path);
+                findings.push(CheckFinding::InvalidChain {
+                    path: path.into(),
+                    cluster,
+                });
+                return Ok(None);
+            }
+            let index = (cluster - RESERVED_FAT_ENTRIES) as usize;
+            let mask = 1_u8 << (index % 8);
+            if used[index / 8] & mask != 0 {
+                warn!("cluster {} in the chain of {} is used more than once", cluster, path);
+                findings.push(CheckFinding::CrossLinked {
+                    path: path.into(),
+                    cluster,
+                });
+                return Ok(None);
+            }
+            used[index / 8] |= mask;
+            clusters += 1;
+            match read_fat(&mut fat, self.fat_type, cluster).await? {
+                FatValue::Data(n) => cluster = n,
+                FatValue::EndOfChain => return Ok(Some(clusters)),
+                FatValue::Free | FatValue::Bad | FatValue::Reserved => {
+                    warn!("free, bad or reserved cluster {} in the chain of {}", cluster, path);
+                    findings.push(CheckFinding::InvalidChain {
+                        path: path.into(),
+                        cluster,
+                    });
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    /// Reports allocated clusters not marked in `used` grouped into chains.
+    #[cfg(feature = "alloc")]
+    async fn find_lost_chains(&self, used: &[u8], findings: &mut Vec<CheckFinding>) -> Result<(), Error<IO::Error>> {
+        let is_set = |bitmap: &[u8], index: usize| bitmap[index / 8] & (1 << (index % 8)) != 0;
+        let mut lost = vec![0_u8; used.len()];
+        let mut referenced = vec![0_u8; used.len()];
+        let mut iter = self.fat_entries();
+        while let Some(r) = iter.next().await {
+            let (cluster, value) = r?;
+            let index = (cluster - RESERVED_FAT_ENTRIES) as usize;
+            if is_set(used, index) || matches!(value, FatValue::Free | FatValue::Bad) {
+                continue;
+            }
+            lost[index / 8] |= 1 << (index % 8);
+            if let FatValue::Data(n) = value {
+                if n >= RESERVED_FAT_ENTRIES && n < self.total_clusters + RESERVED_FAT_ENTRIES {
+                    let next_index = (n - RESERVED_FAT_ENTRIES) as usize;
+                    referenced[next_index / 8] |= 1 << (next_index % 8);
+                }
+            }
+        }
+        // start with chain heads, the clusters left after that form cycles
+        let mut fat = self.fat_slice();
+        for heads_only in [true, false] {
+            for index in 0..self.total_clusters as usize {
+                if !is_set(&lost, index) || (heads_only && is_set(&referenced, index)) {
+                    continue;
+                }
+                let first_cluster = index as u32 + RESERVED_FAT_ENTRIES;
+                let mut cluster = first_cluster;
+                let mut clusters = 0;
+                loop {
+                    let index = (cluster - RESERVED_FAT_ENTRIES) as usize;
+                    if !is_set(&lost, index) {
+                        break;
+                    }
+                    lost[index / 8] &= !(1 << (index % 8));
+                    clusters += 1;
+                    match read_fat(&mut fat, self.fat_type, cluster).await? {
+                        FatValue::Data(n)
+                            if n >= RESERVED_FAT_ENTRIES && n < self.total_clusters + RESERVED_FAT_ENTRIES =>
+                        {
+                            cluster = n;
+                        }
+                        _ => break,
+                    }
+                }
+                warn!(
+                    "lost chain of {} clusters starting at cluster {}",
+                    clusters, first_cluster
+                );
+                findings.push(CheckFinding::LostChain {
+                    first_cluster,
+                    clusters,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves the clusters of a file into a single run of contiguous clusters.
+    ///
+    /// `path` is a '/' separated path relative to the root directory. The whole cluster chain is moved even if it is
+    /// longer than the file size requires. With the `alloc` feature the clusters of the file are listed in memory and a
+    /// run made of free clusters and clusters of the file itself is preferred, e.g. a file with a free cluster between
+    /// its two clusters is made contiguous by moving only its second cluster.
+    /// Such a run is filled one cluster at a time: a cluster of the file standing in the way is moved to a free
+    /// cluster first, which needs one free cluster outside of the run if the file already uses all of its clusters.
+    /// Each cluster is copied before it is linked in place of the old one and the old cluster is freed last, so an
+    /// interrupted call leaves the file intact and at most one lost cluster which can be freed by `repair`. Otherwise
+    /// the file is copied to a run of free clusters, the directory entry is updated only after the content was copied
+    /// and the old clusters are freed last. An interrupted call leaves the new run as a lost chain in this case. The
+    /// file should not be open because open handles keep using the old clusters.
+    ///
+    /// Returns `true` if the clusters were moved and `false` if the file is empty or its clusters are already
+    /// contiguous.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::NotFound` will be returned if `path` points to a non-existing directory entry.
+    /// * `Error::InvalidInput` will be returned if `path` points to a directory.
+    /// * `Error::NotEnoughSpace` will be returned if there is no run of free clusters and clusters of the file large
+    ///   enough to hold the file (only free clusters without the `alloc` feature). Nothing is modified in this case.
+    /// * `Error::CorruptedFileSystem` will be returned if the cluster chain is circular.
+    /// * `Error::ReadOnly` will be returned if the filesystem was mounted in read-only mode.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub async fn defragment_file(&self, path: &str) -> Result<bool, Error<IO::Error>> {
+        trace!("FileSystem::defragment_file {}", path);
+        self.ensure_writable()?;
+        let entry = self.root_dir().open_meta(path).await?;
+        if entry.is_dir() {
+            error!("{} is a directory", path);
+            return Err(Error::InvalidInput);
+        }
+        let Some(old_first_cluster) = entry.first_cluster() else {
+            return Ok(false);
+        };
+        let mut count = 1;
+        let mut contiguous = true;
+        #[cfg(feature = "alloc")]
+        let mut chain = vec![old_first_cluster];
+        {
+            let mut iter = self.cluster_iter(old_first_cluster);
+            let mut prev_cluster = old_first_cluster;
+            while let Some(r) = iter.next().await {
+                let cluster = r?;
+                contiguous &= cluster == prev_cluster + 1;
+                prev_cluster = cluster;
+                count += 1;
+                #[cfg(feature = "alloc")]
+                chain.push(cluster);
+            }
+        }
+        if contiguous {
+            return Ok(false);
+        }
+        let mut editor = entry.editor();
+        #[cfg(feature = "alloc")]
+        if let Some(run_start) = self.find_overlapping_run(&chain).await? {
+            let lowest = chain.iter().min().copied().unwrap_or(old_first_cluster);
+            let highest = chain.iter().max().copied().unwrap_or(old_first_cluster);
+            // the file uses every cluster of the run so one of them has to be moved out of the way
+            if lowest >= run_start && highest < run_start + count {
+                let hint = self.fs_info.borrow().next_free_cluster;
+                let mut fat = self.fat_slice();
+                find_free_cluster_from_hint(&mut fat, self.fat_type, hint, self.total_clusters).await?;
+            }
+            self.set_dirty_flag(true).await?;
+            // the buffered block may hold content of the old clusters which was not written yet
+            self.discard_file_buffer().await?;
+            self.move_chain_to_run(&mut editor, chain, run_start).await?;
+            return Ok(true);
+        }
+        self.set_dirty_flag(true).await?;
+        let new_first_cluster = self.alloc_contiguous_clusters(None, count).await?;
+        self.discard_file_buffer().await?;
+        if let Err(err) = self.copy_chain(old_first_cluster, new_first_cluster).await {
+            // the file still uses the old clusters so the new run can be freed
+            self.free_cluster_chain(new_first_cluster).await?;
+            return Err(err);
+        }
+        editor.set_first_cluster(Some(new_first_cluster), self.fat_type);
+        editor.flush(self).await?;
+        self.free_cluster_chain(old_first_cluster).await?;
+        Ok(true)
+    }
+
+    // Returns the first cluster of a run as long as `chain` whose clusters are either free or belong to `chain`. Only
+    // runs holding at least one cluster of the chain are searched.
+    #[cfg(feature = "alloc")]
+    async fn find_overlapping_run(&self, chain: &[u32]) -> Result<Option<u32>, Error<IO::Error>> {
+        let mut sorted = chain.to_vec();
+        sorted.sort_unstable();
+        let (Some(&lowest), Some(&highest)) = (sorted.first(), sorted.last()) else {
+            return Ok(None);
+        };
+        let count = sorted.len() as u32;
+        let start = cmp::max(lowest.saturating_sub(count - 1), RESERVED_FAT_ENTRIES);
+        let end = cmp::min(highest + count, self.total_clusters + RESERVED_FAT_ENTRIES);
+        let mut run_len = 0;
+        for cluster in start..end {
+            let usable = sorted.binary_search(&cluster).is_ok()
+                || read_fat(&mut self.fat_slice(), self.fat_type, cluster).await? == FatValue::Free;
+            if !usable {
+                run_len = 0;
+                continue;
+            }
+            run_len += 1;
+            if run_len == count {
+                return Ok(Some(cluster + 1 - count));
+            }
+        }
+        Ok(None)
+    }
+
+    // Moves the clusters of `chain` one by one into the run of clusters starting at `run_start`. Every cluster of the
+    // run is either free or belongs to the chain.
+    #[cfg(feature = "alloc")]
+    async fn move_chain_to_run(
+        &self,
+        editor: &mut DirEntryEditor,
+        mut chain: Vec<u32>,
+        run_start: u32,
+    ) -> Result<(), Error<IO::Error>> {
+        let count = chain.len();
+        let run_index = |cluster: u32| {
+            cluster
+                .checked_sub(run_start)
+                .map(|n| n as usize)
+                .filter(|&n| n < count)
+        };
+        // the position in the chain of the cluster placed at each cluster of the run, `None` for a free one
+        let mut owners = vec![None; count];
+        for (i, &cluster) in chain.iter().enumerate() {
+            if let Some(n) = run_index(cluster) {
+                owners[n] = Some(i);
+            }
+        }
+        for i in 0..count {
+            let dst = run_start + i as u32;
+            let cluster = chain[i];
+            if cluster == dst {
+                continue;
+            }
+            // clusters before `dst` already hold their part of the file so `dst` can only be used by a later one
+            if let Some(j) = owners[i] {
+                // move it straight to its own place in the run if that one is still free
+                let tmp = if owners[j].is_none() {
+                    run_start + j as u32
+                } else {
+                    let hint = self.fs_info.borrow().next_free_cluster;
+                    let mut fat = self.fat_slice();
+                    find_free_cluster_from_hint(&mut fat, self.fat_type, hint, self.total_clusters).await?
+                };
+                self.move_cluster(editor, Some(chain[j - 1]), chain[j], tmp).await?;
+                chain[j] = tmp;
+                if let Some(n) = run_index(tmp) {
+                    owners[n] = Some(j);
+                }
+            }
+            let prev_cluster = i.checked_sub(1).map(|n| chain[n]);
+            self.move_cluster(editor, prev_cluster, cluster, dst).await?;
+            if let Some(n) = run_index(cluster) {
+                owners[n] = None;
+            }
+            chain[i] = dst;
+            owners[i] = Some(i);
+        }
+        Ok(())
+    }
+
+    // Copies `cluster` to the free cluster `dst` and replaces it in its chain, `prev_cluster` is the cluster before it
+    // or `None` if `editor` holds it as the first cluster. `dst` is linked only after the content was copied and
+    // `cluster` is freed last, so the chain stays valid if the future is dropped.
+    #[cfg(feature = "alloc")]
+    async fn move_cluster(
+        &self,
+        editor: &mut DirEntryEditor,
+        prev_cluster: Option<u32>,
+        cluster: u32,
+        dst: u32,
+    ) -> Result<(), Error<IO::Error>> {
+        trace!("moving cluster {} to {}", cluster, dst);
+        self.copy_cluster(cluster, dst).await?;
+        let guard = FreeClusterCountGuard::new(&self.fs_info);
+        let next = read_fat(&mut self.fat_slice(), self.fat_type, cluster).await?;
+        write_fat(&mut self.fat_slice(), self.fat_type, dst, next).await?;
+        if let Some(n) = prev_cluster {
+            write_fat(&mut self.fat_slice(), self.fat_type, n, FatValue::Data(dst)).await?;
+        } else {
+            editor.set_first_cluster(Some(dst), self.fat_type);
+            editor.flush(self).await?;
+        }
+        write_fat(&mut self.fat_slice(), self.fat_type, cluster, FatValue::Free).await?;
+        FreedRuns::new(&self.freed_clusters_listener).add(cluster);
+        guard.disarm();
+        // one cluster was taken and another one freed
+        self.fs_info.borrow_mut().rewind_next_free_cluster(Some(cluster));
+        Ok(())
+    }
+
+    // Copies the content of all clusters of a chain to the contiguous clusters starting at `dst_cluster`
+    async fn copy_chain(&self, src_cluster: u32, dst_cluster: u32) -> Result<(), Error<IO::Error>> {
+        let mut iter = self.cluster_iter(src_cluster);
+        let mut src = Some(src_cluster);
+        let mut dst = dst_cluster;
+        while let Some(cluster) = src {
+            self.copy_cluster(cluster, dst).await?;
+            src = iter.next().await.transpose()?;
+            dst += 1;
+        }
+        Ok(())
+    }
+
+    async fn copy_cluster(&self, src_cluster: u32, dst_cluster: u32) -> Result<(), Error<IO::Error>> {
+        // one sector sized buffer keeps the future small
+        let mut buf = [0_u8; 512];
+        let cluster_size = u64::from(self.cluster_size());
+        let mut disk = FsIoAdapter { fs: self };
+        let mut copied = 0;
+        while copied < cluster_size {
+            let chunk = &mut buf[..cmp::min(512, cluster_size - copied) as usize];
+            disk.seek(SeekFrom::Start(self.offset_from_cluster(src_cluster) + copied))
+                .await?;
+            disk.read_exact(chunk).await?;
+            disk.seek(SeekFrom::Start(self.offset_from_cluster(dst_cluster) + copied))
+                .await?;
+            disk.write_all(chunk).await?;
+            copied += chunk.len() as u64;
+        }
+        Ok(())
+    }
 
-impl<IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> FileSystem<IO, TP, OCC> {
-    /// Returns a volume label from root directory as `String`.
+    /// Returns the clusters of a file or a directory in the order of its cluster chain.
     ///
-    /// It finds file with `VOLUME_ID` attribute and returns its short name.
+    /// `path` is a '/' separated path relative to the root directory. Empty files have no clusters. Use
+    /// `cluster_offset` to get the positions of the clusters on the storage and `DirEntry::clusters` to iterate
+    /// clusters without allocating.
     ///
     /// # Errors
     ///
-    /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    /// Errors that can be returned:
+    ///
+    /// * `Error::NotFound` will be returned if `path` points to a non-existing directory entry.
+    /// * `Error::CorruptedFileSystem` will be returned if the cluster chain is circular.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
     #[cfg(feature = "alloc")]
-    pub async fn read_volume_label_from_root_dir(&self) -> Result<Option<String>, Error<IO::Error>> {
-        // Note: DirEntry::file_short_name() cannot be used because it interprets name as 8.3
-        // (adds dot before an extension)
-        let volume_label_opt = self.read_volume_label_from_root_dir_as_bytes().await?;
-        volume_label_opt.map_or(Ok(None), |volume_label| {
-            // Strip label padding
-            let len = volume_label
-                .iter()
-                .rposition(|b| *b != SFN_PADDING)
-                .map_or(0, |p| p + 1);
-            let label_slice = &volume_label[..len];
-            // Decode volume label from OEM codepage
-            let volume_label_iter = label_slice.iter().copied();
-            let char_iter = volume_label_iter.map(|c| self.options.oem_cp_converter.decode(c));
-            // Build string from character iterator
-            Ok(Some(char_iter.collect::<String>()))
-        })
+    pub async fn clusters_for_path(&self, path: &str) -> Result<Vec<u32>, Error<IO::Error>> {
+        let entry = self.root_dir().open_meta(path).await?;
+        let mut iter = entry.clusters();
+        let mut clusters = Vec::new();
+        while let Some(r) = iter.next().await {
+            clusters.push(r?);
+        }
+        Ok(clusters)
     }
 
-    /// Returns a volume label from root directory as byte array.
-    ///
-    /// Label is encoded in the OEM codepage.
-    /// It finds file with `VOLUME_ID` attribute and returns its short name.
-    ///
-    /// # Errors
-    ///
-    /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
-    pub async fn read_volume_label_from_root_dir_as_bytes(&self) -> Result<Option<[u8; SFN_SIZE]>, Error<IO::Error>> {
-        let entry_opt = self.root_dir().find_volume_entry().await?;
-        Ok(entry_opt.map(|e| *e.raw_short_name()))
+    /// Sets the bits of all clusters of a chain in `used` and returns the first cluster which was already set.
+    #[cfg(feature = "alloc")]
+    async fn mark_chain_used(&self, first_cluster: u32, used: &mut [u8]) -> Result<Option<u32>, Error<IO::Error>> {
+        let mut iter = self.cluster_iter(first_cluster);
+        let mut cluster = Some(first_cluster);
+        while let Some(n) = cluster {
+            if n < RESERVED_FAT_ENTRIES || n >= self.total_clusters + RESERVED_FAT_ENTRIES {
+                error!("invalid cluster number {} in a chain", n);
+                return Err(Error::CorruptedFileSystem);
+            }
+            let index = (n - RESERVED_FAT_ENTRIES) as usize;
+            let mask = 1_u8 << (index % 8);
+            if used[index / 8] & mask != 0 {
+                return Ok(Some(n));
+            }
+            used[index / 8] |= mask;
+            cluster = iter.next().await.transpose()?;
+        }
+        Ok(None)
     }
 }
 
 /// `Drop` implementation tries to unmount the filesystem when dropping.
 impl<IO: Read + Write + Seek, TP, OCC> Drop for FileSystem<IO, TP, OCC> {
     fn drop(&mut self) {
-        if self.current_status_flags.get().dirty {
+        // Note: a dirty flag that was set when mounting is never cleared so it does not indicate a missing unmount
+        let dirty_flag_set = self.current_status_flags.get().dirty && !self.bpb.status_flags().dirty;
+        let fs_info_dirty = self.fat_type == FatType::Fat32 && !self.options.read_only && self.fs_info.borrow().dirty;
+        if dirty_flag_set || fs_info_dirty || self.file_buffer.borrow().is_dirty() {
             warn!("Dropping FileSytem without unmount");
         }
     }
@@ -713,6 +2714,12 @@ pub(crate) struct FsIoAdapter<'a, IO: ReadWriteSeek, TP, OCC> {
     fs: &'a FileSystem<IO, TP, OCC>,
 }
 
+impl<'a, IO: ReadWriteSeek, TP, OCC> FsIoAdapter<'a, IO, TP, OCC> {
+    pub(crate) fn new(fs: &'a FileSystem<IO, TP, OCC>) -> Self {
+        FsIoAdapter { fs }
+    }
+}
+
 impl<IO: ReadWriteSeek, TP, OCC> IoBase for FsIoAdapter<'_, IO, TP, OCC> {
     type Error = IO::Error;
 }
@@ -753,6 +2760,7 @@ impl<IO: ReadWriteSeek, TP, OCC> Clone for FsIoAdapter<'_, IO, TP, OCC> {
 fn fat_slice<S: ReadWriteSeek, B: BorrowMut<S>>(
     io: B,
     bpb: &BiosParameterBlock,
+    partition_offset: u64,
 ) -> impl ReadWriteSeek<Error = Error<S::Error>> {
     let sectors_per_fat = bpb.sectors_per_fat();
     let mirroring_enabled = bpb.mirroring_enabled();
@@ -763,7 +2771,12 @@ fn fat_slice<S: ReadWriteSeek, B: BorrowMut<S>>(
         let fat_first_sector = (bpb.reserved_sectors()) + active_fat * sectors_per_fat;
         (fat_first_sector, 1)
     };
-    DiskSlice::from_sectors(fat_first_sector, sectors_per_fat, mirrors, bpb, io)
+    DiskSlice::new(
+        partition_offset + bpb.bytes_from_sectors(fat_first_sector),
+        bpb.bytes_from_sectors(sectors_per_fat),
+        mirrors,
+        io,
+    )
 }
 
 pub(crate) struct DiskSlice<B, S = B> {
@@ -787,15 +2800,6 @@ impl<B: BorrowMut<S>, S: ReadWriteSeek> DiskSlice<B, S> {
         }
     }
 
-    fn from_sectors(first_sector: u32, sector_count: u32, mirrors: u8, bpb: &BiosParameterBlock, inner: B) -> Self {
-        Self::new(
-            bpb.bytes_from_sectors(first_sector),
-            bpb.bytes_from_sectors(sector_count),
-            mirrors,
-            inner,
-        )
-    }
-
     pub(crate) fn abs_pos(&self) -> u64 {
         self.begin + self.offset
     }
@@ -900,6 +2904,91 @@ impl<T: OemCpConverter + ?Sized> OemCpConverter for &T {
     }
 }
 
+/// A maintenance task run on the underlying storage by `FileSystem::with_device`.
+///
+/// The storage is lent to the task for the duration of `run`. Implementations can use `async fn run`.
+pub trait DeviceTask<IO> {
+    /// The value returned by the task.
+    type Output;
+
+    /// Runs the task on the storage.
+    fn run(self, device: &mut IO) -> impl future::Future<Output = Self::Output>;
+}
+
+/// A receiver of the clusters freed by the filesystem.
+///
+/// It is registered with `FileSystem::set_freed_clusters_listener` or `FileSystem::set_freed_clusters_listener_ref`
+/// and is meant for flash media, where telling the device that the clusters are no longer needed (TRIM or erase)
+/// improves its lifetime and write speed. The listener can keep per-device state such as a queue of pending erase
+/// commands. A registered listener has to be `Send` so the filesystem can still be sent to another thread.
+pub trait FreedClustersListener {
+    /// Called after `count` clusters starting at `first_cluster` have been marked free in the FAT.
+    ///
+    /// Consecutive clusters freed in ascending order are reported in one call. The filesystem cannot be used from
+    /// this method, e.g. erase commands can be queued and sent with `FileSystem::with_device` later.
+    fn clusters_freed(&mut self, first_cluster: u32, count: u32);
+}
+
+// The listener registered with the filesystem, either owned or borrowed for the lifetime of the program
+enum Listener {
+    #[cfg(feature = "alloc")]
+    Owned(Box<dyn FreedClustersListener + Send>),
+    Borrowed(&'static mut (dyn FreedClustersListener + Send)),
+}
+
+impl Listener {
+    fn get_mut(&mut self) -> &mut dyn FreedClustersListener {
+        match self {
+            #[cfg(feature = "alloc")]
+            Listener::Owned(listener) => listener.as_mut(),
+            Listener::Borrowed(listener) => *listener,
+        }
+    }
+}
+
+type ListenerSlot = RefCell<Option<Listener>>;
+
+// Reports clusters freed by one operation to the listener coalescing consecutive clusters, the last run is reported
+// when dropped so clusters freed before an error are reported too
+struct FreedRuns<'a> {
+    listener: &'a ListenerSlot,
+    first_cluster: u32,
+    count: u32,
+}
+
+impl<'a> FreedRuns<'a> {
+    fn new(listener: &'a ListenerSlot) -> Self {
+        Self {
+            listener,
+            first_cluster: 0,
+            count: 0,
+        }
+    }
+
+    fn add(&mut self, cluster: u32) {
+        if self.count > 0 && cluster == self.first_cluster + self.count {
+            self.count += 1;
+        } else {
+            self.report();
+            self.first_cluster = cluster;
+            self.count = 1;
+        }
+    }
+
+    fn report(&mut self) {
+        if let (Some(listener), true) = (self.listener.borrow_mut().as_mut(), self.count > 0) {
+            listener.get_mut().clusters_freed(self.first_cluster, self.count);
+        }
+        self.count = 0;
+    }
+}
+
+impl Drop for FreedRuns<'_> {
+    fn drop(&mut self) {
+        self.report();
+    }
+}
+
 /// Default implementation of `OemCpConverter` that changes all non-ASCII characters to the replacement character (U+FFFD).
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[derive(Debug, Clone, Copy, Default)]
@@ -962,16 +3051,25 @@ async fn write_zeros_until_end_of_sector<IO: ReadWriteSeek>(
 pub struct FormatVolumeOptions {
     pub(crate) bytes_per_sector: Option<u16>,
     pub(crate) total_sectors: Option<u32>,
+    pub(crate) total_bytes: Option<u64>,
     pub(crate) bytes_per_cluster: Option<u32>,
+    pub(crate) sectors_per_cluster: Option<u8>,
     pub(crate) fat_type: Option<FatType>,
     pub(crate) max_root_dir_entries: Option<u16>,
     pub(crate) fats: Option<u8>,
+    pub(crate) reserved_sectors: Option<u16>,
     pub(crate) media: Option<u8>,
     pub(crate) sectors_per_track: Option<u16>,
     pub(crate) heads: Option<u16>,
+    pub(crate) hidden_sectors: Option<u32>,
     pub(crate) drive_num: Option<u8>,
     pub(crate) volume_id: Option<u32>,
     pub(crate) volume_label: Option<[u8; SFN_SIZE]>,
+    pub(crate) oem_name: Option<[u8; 8]>,
+    pub(crate) bootjmp: Option<[u8; 3]>,
+    // boot code padded with zeros and its length
+    pub(crate) boot_code: Option<([u8; 448], usize)>,
+    pub(crate) wipe_data: bool,
 }
 
 impl FormatVolumeOptions {
@@ -988,7 +3086,10 @@ impl FormatVolumeOptions {
     ///
     /// Cluster size must be a power of two and be greater or equal to sector size.
     /// If option is not specified optimal cluster size is selected based on partition size and
-    /// optionally FAT type override (if specified using `fat_type` method).
+    /// optionally FAT type override (if specified using `fat_type` method). Volumes of a standard floppy disk size
+    /// get the cluster size of the floppy disk format (see `format_volume`).
+    /// The cluster size can be specified in sectors using `sectors_per_cluster` instead. If both options are used
+    /// they must describe the same cluster size.
     ///
     /// # Panics
     ///
@@ -1003,6 +3104,27 @@ impl FormatVolumeOptions {
         self
     }
 
+    /// Set size of cluster in sectors
+    ///
+    /// Sectors per cluster must be a power of two in range 1 - 128. It takes precedence over the automatically
+    /// selected cluster size. If `bytes_per_cluster` is specified as well, `format_volume` fails unless it is equal
+    /// to `sectors_per_cluster` multiplied by the sector size. Formatting also fails if the resulting number of
+    /// clusters does not fit the FAT type (the one specified using `fat_type` method or any FAT type if it is not
+    /// specified).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sectors_per_cluster` is not a power of two or is greater than `128`.
+    #[must_use]
+    pub fn sectors_per_cluster(mut self, sectors_per_cluster: u8) -> Self {
+        assert!(
+            sectors_per_cluster.is_power_of_two() && sectors_per_cluster <= 128,
+            "Invalid sectors_per_cluster"
+        );
+        self.sectors_per_cluster = Some(sectors_per_cluster);
+        self
+    }
+
     /// Set File Allocation Table type
     ///
     /// Option allows to override File Allocation Table (FAT) entry size.
@@ -1036,18 +3158,29 @@ impl FormatVolumeOptions {
     /// Set total number of sectors
     ///
     /// If option is not specified total number of sectors is calculated as storage device size divided by sector size.
+    /// This option takes precedence over `total_bytes`.
     #[must_use]
     pub fn total_sectors(mut self, total_sectors: u32) -> Self {
         self.total_sectors = Some(total_sectors);
         self
     }
 
+    /// Set total size of the volume in bytes
+    ///
+    /// Total number of sectors is calculated as `total_bytes` divided by sector size. The size must be a multiple of
+    /// sector size. Ignored if `total_sectors` is specified.
+    #[must_use]
+    pub fn total_bytes(mut self, total_bytes: u64) -> Self {
+        self.total_bytes = Some(total_bytes);
+        self
+    }
+
     /// Set maximal numer of entries in root directory for FAT12/FAT16 volumes
     ///
     /// Total root directory size should be dividable by sectors size so keep it a multiple of 16 (for default sector
     /// size).
     /// Note: this limit is not used on FAT32 volumes.
-    /// Default is `512`.
+    /// Default is `512` or the value of a floppy disk format (see `format_volume`).
     #[must_use]
     pub fn max_root_dir_entries(mut self, max_root_dir_entries: u16) -> Self {
         self.max_root_dir_entries = Some(max_root_dir_entries);
@@ -1069,9 +3202,26 @@ impl FormatVolumeOptions {
         self
     }
 
+    /// Set number of reserved sectors
+    ///
+    /// Reserved sectors are placed before the first File Allocation Table. On FAT32 volumes they contain the boot
+    /// sector, the FS Information Sector (sector 1) and the backup boot sector (sector 6) so at least 7 reserved
+    /// sectors are required there. Some tools and bootloaders expect 32 reserved sectors on FAT32 volumes.
+    /// Default is `1` for FAT12/FAT16 and `8` for FAT32.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `reserved_sectors` is `0`.
+    #[must_use]
+    pub fn reserved_sectors(mut self, reserved_sectors: u16) -> Self {
+        assert!(reserved_sectors >= 1, "Invalid number of reserved sectors");
+        self.reserved_sectors = Some(reserved_sectors);
+        self
+    }
+
     /// Set media field for Bios Parameters Block
     ///
-    /// Default is `0xF8`.
+    /// Default is `0xF8` or the value of a floppy disk format (see `format_volume`).
     #[must_use]
     pub fn media(mut self, media: u8) -> Self {
         self.media = Some(media);
@@ -1080,7 +3230,7 @@ impl FormatVolumeOptions {
 
     /// Set number of physical sectors per track for Bios Parameters Block (INT 13h CHS geometry)
     ///
-    /// Default is `0x20`.
+    /// Default is `0x20` or the value of a floppy disk format (see `format_volume`).
     #[must_use]
     pub fn sectors_per_track(mut self, sectors_per_track: u16) -> Self {
         self.sectors_per_track = Some(sectors_per_track);
@@ -1089,13 +3239,24 @@ impl FormatVolumeOptions {
 
     /// Set number of heads for Bios Parameters Block (INT 13h CHS geometry)
     ///
-    /// Default is `0x40`.
+    /// Default is `0x40` or `2` for a floppy disk format (see `format_volume`).
     #[must_use]
     pub fn heads(mut self, heads: u16) -> Self {
         self.heads = Some(heads);
         self
     }
 
+    /// Set number of hidden sectors for Bios Parameters Block
+    ///
+    /// It is the number of sectors preceding the volume on the disk, usually the start LBA of its partition. Some
+    /// bootloaders and BIOSes use it to locate the volume. It does not change the layout of the formatted volume.
+    /// Default is `0`.
+    #[must_use]
+    pub fn hidden_sectors(mut self, hidden_sectors: u32) -> Self {
+        self.hidden_sectors = Some(hidden_sectors);
+        self
+    }
+
     /// Set drive number for Bios Parameters Block
     ///
     /// Default is `0` for FAT12, `0x80` for FAT16/FAT32.
@@ -1122,13 +3283,279 @@ impl FormatVolumeOptions {
         self.volume_label = Some(volume_label);
         self
     }
+
+    /// Set OEM name stored in the Boot Sector
+    ///
+    /// The name is padded with spaces to 8 bytes. It should consist of printable ASCII characters.
+    /// Default is `MSWIN4.1` which gives the best compatibility with old FAT drivers.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `oem_name` is longer than 8 bytes.
+    #[must_use]
+    pub fn oem_name(mut self, oem_name: &[u8]) -> Self {
+        assert!(oem_name.len() <= 8, "Invalid oem_name");
+        let mut padded = [b' '; 8];
+        padded[..oem_name.len()].copy_from_slice(oem_name);
+        self.oem_name = Some(padded);
+        self
+    }
+
+    /// Set jump instruction placed at the beginning of the Boot Sector
+    ///
+    /// It should jump to the boot code (at offset `0x5A` on FAT32 and `0x3E` on FAT12/FAT16 volumes).
+    /// Default is a short jump to the boot code.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the first byte is not a jump opcode (`0xEB` or `0xE9`).
+    #[must_use]
+    pub fn bootjmp(mut self, bootjmp: [u8; 3]) -> Self {
+        assert!(bootjmp[0] == 0xEB || bootjmp[0] == 0xE9, "Invalid bootjmp");
+        self.bootjmp = Some(bootjmp);
+        self
+    }
+
+    /// Set boot code stored in the Boot Sector after the Bios Parameters Block
+    ///
+    /// The code can be at most 420 bytes long on FAT32 and 448 bytes on FAT12/FAT16 volumes, the rest of the region
+    /// is filled with zeros. It is placed after the BPB fields of the chosen FAT type so it never overwrites them.
+    /// Default is a stub printing that the disk is not bootable.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `boot_code` is longer than 448 bytes.
+    #[must_use]
+    pub fn boot_code(mut self, boot_code: &[u8]) -> Self {
+        assert!(boot_code.len() <= 448, "Invalid boot_code");
+        let mut padded = [0_u8; 448];
+        padded[..boot_code.len()].copy_from_slice(boot_code);
+        self.boot_code = Some((padded, boot_code.len()));
+        self
+    }
+
+    /// Set if the data region should be zeroed
+    ///
+    /// When enabled every sector after the File Allocation Tables is overwritten with zeros so no data from the
+    /// previous filesystem is left on the storage. It makes formatting much slower.
+    /// Default is `false` (quick format).
+    #[must_use]
+    pub fn wipe_data(mut self, wipe_data: bool) -> Self {
+        self.wipe_data = wipe_data;
+        self
+    }
+
+    /// Computes the layout of the volume `format_volume` would create without touching any storage.
+    ///
+    /// The size of the volume must be set by `total_sectors` or `total_bytes`. The options are validated the same way
+    /// as by `format_volume`, so a plan is returned only if formatting with the same options and size succeeds.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if neither `total_sectors` nor `total_bytes` is set or if
+    ///   `format_volume` would return it for these options, e.g. the volume is too small.
+    /// * `Error::FatTypeMismatch` will be returned if the volume does not fit the requested FAT type.
+    ///
+    /// No I/O is performed so `Error::Io` is never returned.
+    pub fn plan(&self) -> Result<FormatPlan, Error<Infallible>> {
+        let bytes_per_sector = self.bytes_per_sector.unwrap_or(512);
+        let Some(total_sectors) = total_sectors_from_options(self, bytes_per_sector)? else {
+            error!("Volume size is not specified");
+            return Err(Error::InvalidInput);
+        };
+        let (boot, fat_type) = format_boot_sector(self, total_sectors, bytes_per_sector)?;
+        if boot.validate::<Infallible>(false).is_err() {
+            return Err(Error::InvalidInput);
+        }
+        Ok(FormatPlan {
+            bpb: boot.bpb,
+            fat_type,
+        })
+    }
+}
+
+/// A layout of a volume computed by `FormatVolumeOptions::plan`.
+///
+/// It holds the values which `format_volume` would write to the BIOS Parameter Block and the layout derived from
+/// them. The volume starts with the reserved sectors (including the Boot Sector), followed by the FATs, the root
+/// directory (FAT12/FAT16 only) and the data region.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Debug)]
+pub struct FormatPlan {
+    bpb: BiosParameterBlock,
+    fat_type: FatType,
+}
+
+impl FormatPlan {
+    /// Type of the File Allocation Table
+    #[must_use]
+    pub fn fat_type(&self) -> FatType {
+        self.fat_type
+    }
+
+    /// Sector size in bytes
+    #[must_use]
+    pub fn bytes_per_sector(&self) -> u16 {
+        self.bpb.bytes_per_sector
+    }
+
+    /// Number of sectors in a cluster
+    #[must_use]
+    pub fn sectors_per_cluster(&self) -> u8 {
+        self.bpb.sectors_per_cluster
+    }
+
+    /// Cluster size in bytes
+    #[must_use]
+    pub fn cluster_size(&self) -> u32 {
+        self.bpb.cluster_size()
+    }
+
+    /// Total number of sectors of the volume
+    #[must_use]
+    pub fn total_sectors(&self) -> u32 {
+        self.bpb.total_sectors()
+    }
+
+    /// Number of reserved sectors placed before the first FAT
+    #[must_use]
+    pub fn reserved_sectors(&self) -> u16 {
+        self.bpb.reserved_sectors
+    }
+
+    /// Number of FATs
+    #[must_use]
+    pub fn fats(&self) -> u8 {
+        self.bpb.fats
+    }
+
+    /// Number of sectors of a single FAT
+    #[must_use]
+    pub fn sectors_per_fat(&self) -> u32 {
+        self.bpb.sectors_per_fat()
+    }
+
+    /// Maximal number of entries in the root directory - `0` for FAT32 whose root directory is a cluster chain
+    #[must_use]
+    pub fn root_dir_entries(&self) -> u16 {
+        self.bpb.root_entries
+    }
+
+    /// Number of sectors of the FAT12/FAT16 root directory - `0` for FAT32
+    #[must_use]
+    pub fn root_dir_sectors(&self) -> u32 {
+        self.bpb.root_dir_sectors()
+    }
+
+    /// First sector of the data region
+    #[must_use]
+    pub fn first_data_sector(&self) -> u32 {
+        self.bpb.first_data_sector()
+    }
+
+    /// Number of data clusters
+    #[must_use]
+    pub fn total_clusters(&self) -> u32 {
+        self.bpb.total_clusters()
+    }
+
+    /// Size of the data region in bytes
+    ///
+    /// Sectors left after the last whole cluster are not included.
+    #[must_use]
+    pub fn data_region_bytes(&self) -> u64 {
+        u64::from(self.total_clusters()) * u64::from(self.cluster_size())
+    }
+
+    /// Media descriptor
+    #[must_use]
+    pub fn media(&self) -> u8 {
+        self.bpb.media
+    }
+
+    /// Number of sectors per track (INT 13h CHS geometry)
+    #[must_use]
+    pub fn sectors_per_track(&self) -> u16 {
+        self.bpb.sectors_per_track
+    }
+
+    /// Number of heads (INT 13h CHS geometry)
+    #[must_use]
+    pub fn heads(&self) -> u16 {
+        self.bpb.heads
+    }
+
+    /// Number of sectors preceding the volume on the disk
+    #[must_use]
+    pub fn hidden_sectors(&self) -> u32 {
+        self.bpb.hidden_sectors
+    }
+
+    /// Drive number
+    #[must_use]
+    pub fn drive_num(&self) -> u8 {
+        self.bpb.drive_num
+    }
+
+    /// Volume ID
+    #[must_use]
+    pub fn volume_id(&self) -> u32 {
+        self.bpb.volume_id
+    }
+
+    /// Volume label padded with spaces
+    #[must_use]
+    pub fn volume_label(&self) -> &[u8; SFN_SIZE] {
+        &self.bpb.volume_label
+    }
+
+    /// Sector of the FS Information Sector - `None` for FAT12 and FAT16
+    #[must_use]
+    pub fn fs_info_sector(&self) -> Option<u32> {
+        Some(self.bpb.fs_info_sector()).filter(|_| self.bpb.is_fat32())
+    }
+
+    /// Sector of the backup Boot Sector - `None` for FAT12 and FAT16
+    #[must_use]
+    pub fn backup_boot_sector(&self) -> Option<u32> {
+        Some(self.bpb.backup_boot_sector()).filter(|_| self.bpb.is_fat32())
+    }
+}
+
+// Returns the number of sectors of the volume set by `total_sectors` or `total_bytes` options
+fn total_sectors_from_options<E: IoError>(
+    options: &FormatVolumeOptions,
+    bytes_per_sector: u16,
+) -> Result<Option<u32>, Error<E>> {
+    if let Some(total_sectors) = options.total_sectors {
+        return Ok(Some(total_sectors));
+    }
+    let Some(total_bytes) = options.total_bytes else {
+        return Ok(None);
+    };
+    if total_bytes % u64::from(bytes_per_sector) != 0 {
+        error!("Volume size is not a multiple of sector size: {}", total_bytes);
+        return Err(Error::InvalidInput);
+    }
+    let total_sectors_64 = total_bytes / u64::from(bytes_per_sector);
+    if total_sectors_64 > u64::from(u32::MAX) {
+        error!("Volume has too many sectors: {}", total_sectors_64);
+        return Err(Error::InvalidInput);
+    }
+    Ok(Some(total_sectors_64 as u32)) // safe case: possible overflow is handled above
 }
 
 /// Create FAT filesystem on a disk or partition (format a volume)
 ///
 /// Warning: this function overrides internal FAT filesystem structures and causes a loss of all data on provided
 /// partition. Please use it with caution.
-/// Only quick formatting is supported. To achieve a full format zero entire partition before calling this function.
+/// By default only quick formatting is done. To zero the data region too use `FormatVolumeOptions::wipe_data`.
+/// Returns the type of the created File Allocation Table.
+/// A volume with 512 byte sectors and the size of a standard floppy disk (360 KB, 720 KB, 1.2 MB, 1.44 MB or 2.88
+/// MB) gets the cluster size, root directory size, media descriptor and CHS geometry of the floppy disk format like
+/// created by DOS and `mkfs.fat`, unless another FAT type, FAT count or these values are set in `options`.
 /// Supplied `storage` parameter cannot be seeked (internal pointer must be on position 0).
 /// To format a fragment of a disk image (e.g. partition) library user should wrap the file struct in a struct
 /// limiting access to partition bytes only e.g. `fscommon::StreamSlice`.
@@ -1138,9 +3565,11 @@ impl FormatVolumeOptions {
 /// Errors that can be returned:
 ///
 /// * `Error::InvalidInput` will be returned if `options` describes an invalid file system that cannot be created.
-///   Possible reason can be requesting a fat type that is not compatible with the total number of clusters or
-///   formatting a too big storage. If sectors/clusters related options in `options` structure were left set to
-///   defaults this error is very unlikely to happen.
+///   Possible reason can be formatting a too big storage. It is also returned if `total_bytes` option is not a
+///   multiple of sector size or the boot code does not fit in the Boot Sector of the FAT type. If sectors/clusters
+///   related options in `options` structure were left set to defaults this error is very unlikely to happen.
+/// * `Error::FatTypeMismatch` will be returned if the FAT type requested by `FormatVolumeOptions::fat_type` is not
+///   compatible with the number of clusters of the volume. It holds a cluster size that would work, if there is one.
 /// * `Error::Io` will be returned if the provided storage object returned an I/O error.
 ///
 /// # Panics
@@ -1150,12 +3579,12 @@ impl FormatVolumeOptions {
 pub async fn format_volume<S: ReadWriteSeek>(
     storage: &mut S,
     options: FormatVolumeOptions,
-) -> Result<(), Error<S::Error>> {
+) -> Result<FatType, Error<S::Error>> {
     trace!("format_volume");
     debug_assert!(storage.seek(SeekFrom::Current(0)).await? == 0);
 
     let bytes_per_sector = options.bytes_per_sector.unwrap_or(512);
-    let total_sectors = if let Some(total_sectors) = options.total_sectors {
+    let total_sectors = if let Some(total_sectors) = total_sectors_from_options(&options, bytes_per_sector)? {
         total_sectors
     } else {
         let total_bytes: u64 = storage.seek(SeekFrom::End(0)).await?;
@@ -1170,7 +3599,7 @@ pub async fn format_volume<S: ReadWriteSeek>(
 
     // Create boot sector, validate and write to storage device
     let (boot, fat_type) = format_boot_sector(&options, total_sectors, bytes_per_sector)?;
-    if boot.validate::<S::Error>().is_err() {
+    if boot.validate::<S::Error>(false).is_err() {
         return Err(Error::InvalidInput);
     }
     boot.serialize(storage).await?;
@@ -1186,11 +3615,14 @@ pub async fn format_volume<S: ReadWriteSeek>(
             next_free_cluster: None,
             dirty: false,
         };
-        storage
-            .seek(SeekFrom::Start(bpb.bytes_from_sectors(bpb.fs_info_sector())))
-            .await?;
-        fs_info_sector.serialize(storage).await?;
-        write_zeros_until_end_of_sector(storage, bytes_per_sector).await?;
+        // backup FSInfo sector follows the backup boot sector if there is room for it
+        let backup_fs_info_sector =
+            Some(bpb.backup_boot_sector() + bpb.fs_info_sector()).filter(|&sector| sector < bpb.reserved_sectors());
+        for sector in core::iter::once(bpb.fs_info_sector()).chain(backup_fs_info_sector) {
+            storage.seek(SeekFrom::Start(bpb.bytes_from_sectors(sector))).await?;
+            fs_info_sector.serialize(storage).await?;
+            write_zeros_until_end_of_sector(storage, bytes_per_sector).await?;
+        }
 
         // backup boot sector
         storage
@@ -1207,21 +3639,27 @@ pub async fn format_volume<S: ReadWriteSeek>(
     storage.seek(SeekFrom::Start(fat_pos)).await?;
     write_zeros(storage, bpb.bytes_from_sectors(sectors_per_all_fats)).await?;
     {
-        let mut fat_slice = fat_slice::<S, &mut S>(storage, bpb);
+        let mut fat_slice = fat_slice::<S, &mut S>(storage, bpb, 0);
         let sectors_per_fat = bpb.sectors_per_fat();
         let bytes_per_fat = bpb.bytes_from_sectors(sectors_per_fat);
         format_fat(&mut fat_slice, fat_type, bpb.media, bytes_per_fat, bpb.total_clusters()).await?;
     }
 
     // init root directory - zero root directory region for FAT12/16 and alloc first root directory cluster for FAT32
+    // if data should be wiped zero everything after FATs
     let root_dir_first_sector = reserved_sectors + sectors_per_all_fats;
     let root_dir_sectors = bpb.root_dir_sectors();
     let root_dir_pos = bpb.bytes_from_sectors(root_dir_first_sector);
+    let sectors_to_zero = if options.wipe_data {
+        total_sectors - root_dir_first_sector
+    } else {
+        root_dir_sectors
+    };
     storage.seek(SeekFrom::Start(root_dir_pos)).await?;
-    write_zeros(storage, bpb.bytes_from_sectors(root_dir_sectors)).await?;
+    write_zeros(storage, bpb.bytes_from_sectors(sectors_to_zero)).await?;
     if fat_type == FatType::Fat32 {
         let root_dir_first_cluster = {
-            let mut fat_slice = fat_slice::<S, &mut S>(storage, bpb);
+            let mut fat_slice = fat_slice::<S, &mut S>(storage, bpb, 0);
             alloc_cluster(&mut fat_slice, fat_type, None, None, 1).await?
         };
         assert!(root_dir_first_cluster == bpb.root_dir_first_cluster);
@@ -1243,5 +3681,5 @@ pub async fn format_volume<S: ReadWriteSeek>(
     storage.flush().await?;
     storage.seek(SeekFrom::Start(0)).await?;
     trace!("format_volume end");
-    Ok(())
+    Ok(fat_type)
 }
@@ -55,3 +55,56 @@ impl<T: Write> WriteLeExt for T {
         Ok(())
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use embedded_io_adapters::tokio_1::FromTokio;
+
+    use super::{IoBase, Read, Seek, SeekFrom, Write};
+    use std::io::Cursor;
+
+    // In-memory storage counting the read and write calls, used by the unit tests of the I/O batching
+    pub(crate) struct CountingStream {
+        pub(crate) inner: FromTokio<Cursor<Vec<u8>>>,
+        pub(crate) reads: usize,
+        pub(crate) writes: usize,
+    }
+
+    impl CountingStream {
+        pub(crate) fn new(data: Vec<u8>) -> Self {
+            Self {
+                inner: FromTokio::new(Cursor::new(data)),
+                reads: 0,
+                writes: 0,
+            }
+        }
+    }
+
+    impl IoBase for CountingStream {
+        type Error = std::io::Error;
+    }
+
+    impl Read for CountingStream {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            self.reads += 1;
+            self.inner.read(buf).await
+        }
+    }
+
+    impl Write for CountingStream {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.writes += 1;
+            self.inner.write(buf).await
+        }
+
+        async fn flush(&mut self) -> Result<(), Self::Error> {
+            self.inner.flush().await
+        }
+    }
+
+    impl Seek for CountingStream {
+        async fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+            self.inner.seek(pos).await
+        }
+    }
+}
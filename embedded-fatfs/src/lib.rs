@@ -40,10 +40,108 @@
 //!         let entry = r?;
 //!         println!("{}", entry.file_name());
 //!     }
+//!
+//!     // Write the remaining changes and clear the dirty flag
+//!     drop(iter);
+//!     drop(dir);
+//!     drop(file);
+//!     drop(root_dir);
+//!     fs.unmount().await?;
 //!     # fs::remove_file("tmp/fat.img").await?;
 //!     # Ok(())
 //! }
 //! ```
+//!
+//! # Storage
+//!
+//! Any type implementing the `Read`, `Write` and `Seek` traits from `embedded-io-async` can be passed to
+//! `FileSystem::new` and `format_volume` without a wrapper (see `ReadWriteSeek`). With the `std` feature Tokio
+//! `AsyncRead + AsyncWrite + AsyncSeek` types are accepted too. The storage is addressed in bytes, so a block device
+//! has to be wrapped in a type that buffers sectors, e.g. `BufStream` from the `block-device-adapters` crate.
+//!
+//! A volume stored in a partition of a whole-disk storage is mounted by passing the partition offset to
+//! `FsOptions::partition_offset`. The offset of the first FAT partition listed in the MBR or GPT partition table is
+//! returned by `find_fat_partition`.
+//!
+//! The filesystem only seeks with `SeekFrom::Start` and `SeekFrom::Current(0)`. `SeekFrom::End(0)` is used only to
+//! get the storage size, by `FileSystem::new` unless `FsOptions::check_storage_size` is disabled and by
+//! `format_volume` when `FormatVolumeOptions::total_bytes` is not set.
+//!
+//! ```rust
+//! use embedded_io_async::{ErrorType, Read, Seek, SeekFrom, Write};
+//! use std::io;
+//!
+//! // A volume kept in memory
+//! struct RamDisk {
+//!     data: Vec<u8>,
+//!     pos: usize,
+//! }
+//!
+//! impl ErrorType for RamDisk {
+//!     type Error = io::Error;
+//! }
+//!
+//! impl Read for RamDisk {
+//!     async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+//!         let n = buf.len().min(self.data.len() - self.pos);
+//!         buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+//!         self.pos += n;
+//!         Ok(n)
+//!     }
+//! }
+//!
+//! impl Write for RamDisk {
+//!     async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+//!         let n = buf.len().min(self.data.len() - self.pos);
+//!         self.data[self.pos..self.pos + n].copy_from_slice(&buf[..n]);
+//!         self.pos += n;
+//!         Ok(n)
+//!     }
+//! }
+//!
+//! impl Seek for RamDisk {
+//!     async fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+//!         let pos = match pos {
+//!             SeekFrom::Start(n) => n as i64,
+//!             SeekFrom::End(n) => self.data.len() as i64 + n,
+//!             SeekFrom::Current(n) => self.pos as i64 + n,
+//!         };
+//!         self.pos = usize::try_from(pos).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+//!         Ok(self.pos as u64)
+//!     }
+//! }
+//!
+//! #[tokio::main]
+//! async fn main() -> anyhow::Result<()> {
+//!     let mut disk = RamDisk { data: vec![0; 1024 * 1024], pos: 0 };
+//!     embedded_fatfs::format_volume(&mut disk, embedded_fatfs::FormatVolumeOptions::new()).await?;
+//!     disk.seek(SeekFrom::Start(0)).await?;
+//!     let fs = embedded_fatfs::FileSystem::new(disk, embedded_fatfs::FsOptions::new()).await?;
+//!     fs.root_dir().create_dir("foo").await?;
+//!     fs.unmount().await?;
+//!     # Ok(())
+//! }
+//! ```
+//!
+//! # Cancellation
+//!
+//! Futures returned by this crate can be dropped at any await point, e.g. when a `select!` timeout fires. Writing,
+//! truncating and preallocating files, creating files and directories and removing entries are ordered so that a
+//! dropped future never leaves a directory entry or a FAT entry pointing at a free cluster: a new cluster is marked as
+//! the end of a chain before it is linked and a chain is unlinked before it is freed. Each directory entry is written
+//! with a single `write_all` call. The worst outcome is lost clusters (allocated but not referenced) and a file size
+//! not covering all written data. The free cluster count kept in the FS Information Sector is invalidated and
+//! recomputed by the next `FileSystem::stats` call. A directory changed by a dropped future may keep its old
+//! modification time. A `File` used by a dropped future must still be flushed before it is dropped, like after any
+//! other write.
+//!
+//! The FAT copies can differ in one entry if a future is dropped between updating them; only the first FAT is used by
+//! this crate. `Dir::rename` writes the entry with the new name before removing the old one when the file moves to
+//! another directory or the new name does not fit in the slots of the old one. Dropping it between these steps leaves
+//! two entries sharing one cluster chain, which `FileSystem::check_consistency` reports as `CheckFinding::CrossLinked`.
+//! Calling `Dir::rename` again fails with `Error::AlreadyExists` and `Dir::remove` must not be used on either entry
+//! because it frees the clusters still used by the other one. Empty files have no clusters, so their old entry can be
+//! removed. A rename done in place leaves the file under its new name or its old short name.
 
 #![crate_type = "lib"]
 #![crate_name = "embedded_fatfs"]
@@ -72,6 +170,8 @@ mod error;
 mod file;
 mod fs;
 mod io;
+mod oem_cp;
+mod partition;
 mod table;
 mod time;
 
@@ -80,4 +180,7 @@ pub use crate::dir_entry::*;
 pub use crate::error::*;
 pub use crate::file::*;
 pub use crate::fs::*;
+pub use crate::oem_cp::*;
+pub use crate::partition::*;
+pub use crate::table::FatValue;
 pub use crate::time::*;
@@ -0,0 +1,114 @@
+use crate::fs::OemCpConverter;
+
+/// Upper half (bytes `0x80` - `0xFF`) of the IBM code page 850 (Latin-1) mapped to Unicode.
+const CP850_HIGH: [char; 128] = [
+    '\u{00C7}', '\u{00FC}', '\u{00E9}', '\u{00E2}', '\u{00E4}', '\u{00E0}', '\u{00E5}', '\u{00E7}', '\u{00EA}',
+    '\u{00EB}', '\u{00E8}', '\u{00EF}', '\u{00EE}', '\u{00EC}', '\u{00C4}', '\u{00C5}', '\u{00C9}', '\u{00E6}',
+    '\u{00C6}', '\u{00F4}', '\u{00F6}', '\u{00F2}', '\u{00FB}', '\u{00F9}', '\u{00FF}', '\u{00D6}', '\u{00DC}',
+    '\u{00F8}', '\u{00A3}', '\u{00D8}', '\u{00D7}', '\u{0192}', '\u{00E1}', '\u{00ED}', '\u{00F3}', '\u{00FA}',
+    '\u{00F1}', '\u{00D1}', '\u{00AA}', '\u{00BA}', '\u{00BF}', '\u{00AE}', '\u{00AC}', '\u{00BD}', '\u{00BC}',
+    '\u{00A1}', '\u{00AB}', '\u{00BB}', '\u{2591}', '\u{2592}', '\u{2593}', '\u{2502}', '\u{2524}', '\u{00C1}',
+    '\u{00C2}', '\u{00C0}', '\u{00A9}', '\u{2563}', '\u{2551}', '\u{2557}', '\u{255D}', '\u{00A2}', '\u{00A5}',
+    '\u{2510}', '\u{2514}', '\u{2534}', '\u{252C}', '\u{251C}', '\u{2500}', '\u{253C}', '\u{00E3}', '\u{00C3}',
+    '\u{255A}', '\u{2554}', '\u{2569}', '\u{2566}', '\u{2560}', '\u{2550}', '\u{256C}', '\u{00A4}', '\u{00F0}',
+    '\u{00D0}', '\u{00CA}', '\u{00CB}', '\u{00C8}', '\u{0131}', '\u{00CD}', '\u{00CE}', '\u{00CF}', '\u{2518}',
+    '\u{250C}', '\u{2588}', '\u{2584}', '\u{00A6}', '\u{00CC}', '\u{2580}', '\u{00D3}', '\u{00DF}', '\u{00D4}',
+    '\u{00D2}', '\u{00F5}', '\u{00D5}', '\u{00B5}', '\u{00FE}', '\u{00DE}', '\u{00DA}', '\u{00DB}', '\u{00D9}',
+    '\u{00FD}', '\u{00DD}', '\u{00AF}', '\u{00B4}', '\u{00AD}', '\u{00B1}', '\u{2017}', '\u{00BE}', '\u{00B6}',
+    '\u{00A7}', '\u{00F7}', '\u{00B8}', '\u{00B0}', '\u{00A8}', '\u{00B7}', '\u{00B9}', '\u{00B3}', '\u{00B2}',
+    '\u{25A0}', '\u{00A0}',
+];
+
+fn decode_with_table(table: &[char; 128], oem_char: u8) -> char {
+    if oem_char <= 0x7F {
+        char::from(oem_char)
+    } else {
+        table[usize::from(oem_char - 0x80)]
+    }
+}
+
+fn encode_with_table(table: &[char; 128], uni_char: char, replacement: Option<u8>) -> Option<u8> {
+    if uni_char <= '\x7F' {
+        return Some(uni_char as u8); // safe cast: value is in range [0, 0x7F]
+    }
+    table
+        .iter()
+        .position(|&c| c == uni_char)
+        .map(|i| 0x80 + i as u8) // safe cast: table has 128 entries
+        .or(replacement)
+}
+
+/// Implementation of `OemCpConverter` for the IBM code page 850 (Latin-1, Western Europe).
+///
+/// Characters that do not exist in the code page are encoded as the replacement byte (`_` by default).
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy)]
+pub struct Cp850OemCpConverter {
+    replacement: Option<u8>,
+}
+
+impl Cp850OemCpConverter {
+    /// Creates a converter replacing characters that do not exist in the code page with `_`.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            replacement: Some(b'_'),
+        }
+    }
+
+    /// Sets the byte used when encoding a character that does not exist in the code page.
+    ///
+    /// `None` makes `encode` fail for such characters instead.
+    #[must_use]
+    pub fn replacement(mut self, replacement: Option<u8>) -> Self {
+        self.replacement = replacement;
+        self
+    }
+}
+
+impl Default for Cp850OemCpConverter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OemCpConverter for Cp850OemCpConverter {
+    fn decode(&self, oem_char: u8) -> char {
+        decode_with_table(&CP850_HIGH, oem_char)
+    }
+    fn encode(&self, uni_char: char) -> Option<u8> {
+        encode_with_table(&CP850_HIGH, uni_char, self.replacement)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cp850_round_trip() {
+        let conv = Cp850OemCpConverter::new();
+        for b in 0..=255_u8 {
+            assert_eq!(conv.encode(conv.decode(b)), Some(b));
+        }
+    }
+
+    #[test]
+    fn test_cp850_mapping() {
+        let conv = Cp850OemCpConverter::new();
+        assert_eq!(conv.decode(b'A'), 'A');
+        assert_eq!(conv.decode(0x81), '\u{FC}');
+        assert_eq!(conv.decode(0x9A), '\u{DC}');
+        assert_eq!(conv.decode(0xE1), '\u{DF}');
+        assert_eq!(conv.encode('\u{E9}'), Some(0x82));
+        assert_eq!(conv.encode('\u{F1}'), Some(0xA4));
+    }
+
+    #[test]
+    fn test_cp850_replacement() {
+        let conv = Cp850OemCpConverter::new();
+        assert_eq!(conv.encode('\u{20AC}'), Some(b'_'));
+        assert_eq!(conv.replacement(Some(b'~')).encode('\u{20AC}'), Some(b'~'));
+        assert_eq!(conv.replacement(None).encode('\u{20AC}'), None);
+    }
+}
@@ -0,0 +1,327 @@
+use crate::error::Error;
+use crate::io::{Read, Seek, SeekFrom};
+
+// Partition tables address the storage in 512 byte sectors
+const SECTOR_SIZE: u64 = 512;
+
+const MBR_ENTRIES_OFFSET: usize = 446;
+const MBR_ENTRY_SIZE: usize = 16;
+const MBR_ENTRIES: u32 = 4;
+const MBR_TABLE_SIZE: usize = MBR_ENTRY_SIZE * MBR_ENTRIES as usize;
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+
+// Type of the single MBR partition covering a disk partitioned with GPT
+const GPT_PROTECTIVE_TYPE: u8 = 0xEE;
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_MIN_ENTRY_SIZE: u32 = 128;
+// Entries past this limit are ignored, the specification requires space for 128 of them
+const GPT_MAX_ENTRIES: u32 = 1024;
+
+const MBR_FAT_TYPES: [u8; 6] = [0x01, 0x04, 0x06, 0x0B, 0x0C, 0x0E];
+const MBR_EXTENDED_TYPES: [u8; 3] = [0x05, 0x0F, 0x85];
+
+// GUIDs are stored in the mixed-endian on-disk layout
+// C12A7328-F81F-11D2-BA4B-00A0C93EC93B
+const GPT_EFI_SYSTEM_TYPE: [u8; 16] = [
+    0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11, 0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B,
+];
+// EBD0A0A2-B9E5-4433-87C0-68B6B72699C7
+const GPT_BASIC_DATA_TYPE: [u8; 16] = [
+    0xA2, 0xA0, 0xD0, 0xEB, 0xE5, 0xB9, 0x33, 0x44, 0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7,
+];
+
+/// A type of a partition.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum PartitionType {
+    /// A partition from the MBR partition table with its type code (e.g. `0x0C` for FAT32 with LBA addressing).
+    Mbr(u8),
+    /// A partition from the GPT partition table with its type GUID in the on-disk byte order.
+    Gpt([u8; 16]),
+}
+
+/// A partition found in a partition table.
+///
+/// This is returned by `find_fat_partition` and by the `next` method on `Partitions`. The offset can be passed to
+/// `FsOptions::partition_offset` to mount a FAT volume stored in the partition.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Partition {
+    index: u32,
+    kind: PartitionType,
+    offset: u64,
+    len: u64,
+}
+
+impl Partition {
+    /// Index of the entry in the partition table, starting from 0
+    #[must_use]
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Type of the partition
+    #[must_use]
+    pub fn partition_type(&self) -> PartitionType {
+        self.kind
+    }
+
+    /// Position of the first byte of the partition on the storage
+    #[must_use]
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Size of the partition in bytes
+    #[must_use]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Checks if the partition is empty
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Checks if the partition type is used for FAT volumes.
+    ///
+    /// MBR partitions of types `0x01`, `0x04`, `0x06`, `0x0B`, `0x0C` and `0x0E` and GPT EFI System partitions are
+    /// FAT volumes. GPT Basic Data partitions are reported as well, even though they can hold other filesystems (e.g.
+    /// NTFS), in which case `FileSystem::new` fails with `Error::CorruptedFileSystem`.
+    #[must_use]
+    pub fn is_fat(&self) -> bool {
+        match self.kind {
+            PartitionType::Mbr(code) => MBR_FAT_TYPES.contains(&code),
+            PartitionType::Gpt(guid) => guid == GPT_EFI_SYSTEM_TYPE || guid == GPT_BASIC_DATA_TYPE,
+        }
+    }
+
+    /// Checks if this is an MBR extended partition (types `0x05`, `0x0F` and `0x85`).
+    ///
+    /// Logical partitions stored inside of an extended partition are not returned by `Partitions`.
+    #[must_use]
+    pub fn is_extended(&self) -> bool {
+        matches!(self.kind, PartitionType::Mbr(code) if MBR_EXTENDED_TYPES.contains(&code))
+    }
+}
+
+enum PartitionTable {
+    Mbr([u8; MBR_TABLE_SIZE]),
+    Gpt {
+        entries_offset: u64,
+        entries: u32,
+        entry_size: u32,
+    },
+}
+
+/// An iterator over the partitions of a partition table.
+///
+/// This struct is created by the `read_partitions` function. Unused entries of the table are skipped.
+pub struct Partitions<'a, S> {
+    storage: &'a mut S,
+    table: PartitionTable,
+    index: u32,
+    err: bool,
+}
+
+impl<S: Read + Seek> Partitions<'_, S> {
+    pub async fn next(&mut self) -> Option<Result<Partition, Error<S::Error>>> {
+        if self.err {
+            return None;
+        }
+        loop {
+            let r = match self.table {
+                PartitionTable::Mbr(ref entries) => {
+                    if self.index >= MBR_ENTRIES {
+                        return None;
+                    }
+                    let start = self.index as usize * MBR_ENTRY_SIZE;
+                    Ok(parse_mbr_entry(self.index, &entries[start..start + MBR_ENTRY_SIZE]))
+                }
+                PartitionTable::Gpt {
+                    entries_offset,
+                    entries,
+                    entry_size,
+                } => {
+                    if self.index >= entries {
+                        return None;
+                    }
+                    let pos = u64::from(self.index)
+                        .checked_mul(u64::from(entry_size))
+                        .and_then(|n| n.checked_add(entries_offset));
+                    if let Some(pos) = pos {
+                        read_gpt_entry(self.storage, self.index, pos).await
+                    } else {
+                        error!("GPT partition entry {} is out of range", self.index);
+                        Err(Error::CorruptedFileSystem)
+                    }
+                }
+            };
+            self.index += 1;
+            match r {
+                Ok(Some(partition)) => return Some(Ok(partition)),
+                Ok(None) => {}
+                Err(err) => {
+                    self.err = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
+/// Reads the partition table of a whole-disk storage.
+///
+/// The MBR partition table is read from the first sector. If it only contains a GPT protective partition (type
+/// `0xEE`) the GPT partition table is read instead. Checksums of the GPT structures are not verified and the backup
+/// GPT is never used. Sectors are assumed to be 512 bytes long. The table is only read, the storage is never
+/// written. The storage position is changed, so it has to be seeked back to 0 before passing it to `FileSystem::new`.
+///
+/// # Errors
+///
+/// Errors that can be returned:
+///
+/// * `Error::CorruptedFileSystem` will be returned if the first sector does not hold an MBR partition table (e.g. it is
+///   a boot sector of a volume formatted without a partition table) or if the GPT header is invalid.
+/// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+pub async fn read_partitions<S: Read + Seek>(storage: &mut S) -> Result<Partitions<'_, S>, Error<S::Error>> {
+    trace!("read_partitions");
+    let mut mbr = [0_u8; SECTOR_SIZE as usize];
+    storage.seek(SeekFrom::Start(0)).await?;
+    storage.read_exact(&mut mbr).await?;
+    if mbr[mbr.len() - 2..] != MBR_SIGNATURE {
+        error!("MBR signature not found");
+        return Err(Error::CorruptedFileSystem);
+    }
+    let mut entries = [0_u8; MBR_TABLE_SIZE];
+    entries.copy_from_slice(&mbr[MBR_ENTRIES_OFFSET..MBR_ENTRIES_OFFSET + MBR_TABLE_SIZE]);
+    // a boot sector of a volume without a partition table has the same signature but no valid status bytes
+    if entries.chunks(MBR_ENTRY_SIZE).any(|entry| entry[0] & 0x7F != 0) {
+        error!("invalid MBR partition entry status");
+        return Err(Error::CorruptedFileSystem);
+    }
+    let protective = (0..MBR_ENTRIES as usize)
+        .map(|i| entries[i * MBR_ENTRY_SIZE + 4])
+        .any(|code| code == GPT_PROTECTIVE_TYPE);
+    let table = if protective {
+        read_gpt_header(storage).await?
+    } else {
+        PartitionTable::Mbr(entries)
+    };
+    Ok(Partitions {
+        storage,
+        table,
+        index: 0,
+        err: false,
+    })
+}
+
+/// Finds the first FAT partition of a whole-disk storage.
+///
+/// Partitions are read by `read_partitions` and the first one for which `Partition::is_fat` returns `true` is
+/// returned. The storage is seeked back to 0 afterwards, so it can be passed to `FileSystem::new` together with
+/// `FsOptions::partition_offset` set to the offset of the returned partition.
+///
+/// # Errors
+///
+/// Errors that can be returned:
+///
+/// * `Error::NotFound` will be returned if there is no FAT partition.
+/// * `Error::CorruptedFileSystem` will be returned if the first sector does not hold an MBR partition table or if the
+///   GPT header is invalid.
+/// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+pub async fn find_fat_partition<S: Read + Seek>(storage: &mut S) -> Result<Partition, Error<S::Error>> {
+    let found = {
+        let mut partitions = read_partitions(storage).await?;
+        let mut found = None;
+        while let Some(r) = partitions.next().await {
+            let partition = r?;
+            if partition.is_fat() {
+                found = Some(partition);
+                break;
+            }
+        }
+        found
+    };
+    storage.seek(SeekFrom::Start(0)).await?;
+    found.ok_or_else(|| {
+        error!("no FAT partition found");
+        Error::NotFound
+    })
+}
+
+fn parse_mbr_entry(index: u32, entry: &[u8]) -> Option<Partition> {
+    let code = entry[4];
+    let first_sector = u32::from_le_bytes([entry[8], entry[9], entry[10], entry[11]]);
+    let sectors = u32::from_le_bytes([entry[12], entry[13], entry[14], entry[15]]);
+    if code == 0 || sectors == 0 {
+        return None;
+    }
+    Some(Partition {
+        index,
+        kind: PartitionType::Mbr(code),
+        offset: u64::from(first_sector) * SECTOR_SIZE,
+        len: u64::from(sectors) * SECTOR_SIZE,
+    })
+}
+
+async fn read_gpt_header<S: Read + Seek>(storage: &mut S) -> Result<PartitionTable, Error<S::Error>> {
+    let mut header = [0_u8; 92];
+    storage.seek(SeekFrom::Start(SECTOR_SIZE)).await?;
+    storage.read_exact(&mut header).await?;
+    if &header[..8] != GPT_SIGNATURE {
+        error!("GPT signature not found");
+        return Err(Error::CorruptedFileSystem);
+    }
+    let entries_lba = u64::from_le_bytes(header[72..80].try_into().unwrap());
+    let entries = u32::from_le_bytes(header[80..84].try_into().unwrap());
+    let entry_size = u32::from_le_bytes(header[84..88].try_into().unwrap());
+    let entries = entries.min(GPT_MAX_ENTRIES);
+    // the whole partition entry array must be addressable
+    let entries_offset = entries_lba
+        .checked_mul(SECTOR_SIZE)
+        .filter(|offset| offset.checked_add(u64::from(entries) * u64::from(entry_size)).is_some());
+    let Some(entries_offset) = entries_offset.filter(|_| entry_size >= GPT_MIN_ENTRY_SIZE) else {
+        error!("invalid GPT header");
+        return Err(Error::CorruptedFileSystem);
+    };
+    Ok(PartitionTable::Gpt {
+        entries_offset,
+        entries,
+        entry_size,
+    })
+}
+
+async fn read_gpt_entry<S: Read + Seek>(
+    storage: &mut S,
+    index: u32,
+    pos: u64,
+) -> Result<Option<Partition>, Error<S::Error>> {
+    let mut entry = [0_u8; 48];
+    storage.seek(SeekFrom::Start(pos)).await?;
+    storage.read_exact(&mut entry).await?;
+    let mut guid = [0_u8; 16];
+    guid.copy_from_slice(&entry[..16]);
+    if guid == [0; 16] {
+        return Ok(None);
+    }
+    let first_lba = u64::from_le_bytes(entry[32..40].try_into().unwrap());
+    let last_lba = u64::from_le_bytes(entry[40..48].try_into().unwrap());
+    let (Some(offset), Some(len)) = (
+        first_lba.checked_mul(SECTOR_SIZE),
+        last_lba
+            .checked_sub(first_lba)
+            .and_then(|n| n.checked_add(1))
+            .and_then(|n| n.checked_mul(SECTOR_SIZE)),
+    ) else {
+        error!("invalid GPT entry {}", index);
+        return Err(Error::CorruptedFileSystem);
+    };
+    Ok(Some(Partition {
+        index,
+        kind: PartitionType::Gpt(guid),
+        offset,
+        len,
+    }))
+}
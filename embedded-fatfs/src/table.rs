@@ -16,13 +16,102 @@ type Fat32 = Fat<u32>;
 
 pub const RESERVED_FAT_ENTRIES: u32 = 2;
 
+// Size of the FAT part updated by a single read-modify-write when writing a run of entries. It is the smallest sector
+// size and the FAT starts at a sector boundary, so a block never spans two sectors.
+const FAT_BLOCK_SIZE: u64 = 512;
+
+/// A decoded entry of the File Allocation Table.
+///
+/// Values are independent of the FAT type, e.g. a bad cluster is reported as `Bad` on FAT12, FAT16 and FAT32. Use
+/// `from_raw` and `to_raw` to convert them from and to the values stored in the FAT.
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
-enum FatValue {
+pub enum FatValue {
+    /// Cluster is free
     Free,
+    /// Cluster is used and the chain continues with the given cluster
     Data(u32),
+    /// Cluster is marked as bad
     Bad,
+    /// Cluster is the last one of its chain
     EndOfChain,
+    /// Entry holds a value reserved by the specification (`1` or a value placed just before the bad cluster marker)
+    Reserved,
+}
+
+impl FatValue {
+    /// Decodes a raw FAT entry.
+    ///
+    /// FAT32 entries are masked to 28 bits because the highest 4 bits are reserved. Values from `0xFF8` to `0xFFF` on
+    /// FAT12 (`0xFFF8` - `0xFFFF` on FAT16 and `0x0FFFFFF8` - `0x0FFFFFFF` on FAT32) end a chain. Value `1` and the
+    /// seven values before the bad cluster marker (e.g. `0xFF0` - `0xFF6` on FAT12) are reported as `Reserved`.
+    #[must_use]
+    pub fn from_raw(fat_type: FatType, raw: u32) -> Self {
+        let max = fat_entry_mask(fat_type);
+        let bad = max - 8;
+        match raw & max {
+            0 => FatValue::Free,
+            1 => FatValue::Reserved,
+            n if n == bad => FatValue::Bad,
+            n if n > bad => FatValue::EndOfChain,
+            n if n >= bad - 7 => FatValue::Reserved,
+            n => FatValue::Data(n),
+        }
+    }
+
+    /// Encodes the value as a raw FAT entry.
+    ///
+    /// `EndOfChain` is encoded as the highest value of the entry (e.g. `0xFFF` on FAT12) and `Reserved` as the lowest
+    /// reserved value placed before the bad cluster marker. For FAT32 only the lower 28 bits are set, the highest 4
+    /// bits of the stored entry are reserved and have to be preserved when writing it.
+    #[must_use]
+    pub fn to_raw(self, fat_type: FatType) -> u32 {
+        let max = fat_entry_mask(fat_type);
+        match self {
+            FatValue::Free => 0,
+            FatValue::Data(n) => n & max,
+            FatValue::Bad => max - 8,
+            FatValue::EndOfChain => max,
+            FatValue::Reserved => max - 15,
+        }
+    }
+}
+
+// Returns the mask of the entry bits holding its value - FAT32 entries use only the lower 28 bits
+fn fat_entry_mask(fat_type: FatType) -> u32 {
+    match fat_type {
+        FatType::Fat12 | FatType::Fat16 => (1 << fat_type.bits_per_fat_entry()) - 1,
+        FatType::Fat32 => 0x0FFF_FFFF,
+    }
+}
+
+/// Free clusters found by a single pass over the FAT.
+#[derive(Copy, Clone, Default, Eq, PartialEq, Debug)]
+pub(crate) struct FreeClusters {
+    pub(crate) count: u32,
+    pub(crate) bad_count: u32,
+    pub(crate) largest_run_start: Option<u32>,
+    pub(crate) largest_run_len: u32,
+    run_start: u32,
+    run_len: u32,
+}
+
+impl FreeClusters {
+    fn add(&mut self, cluster: u32, free: bool) {
+        if !free {
+            self.run_len = 0;
+            return;
+        }
+        self.count += 1;
+        if self.run_len == 0 {
+            self.run_start = cluster;
+        }
+        self.run_len += 1;
+        if self.run_len > self.largest_run_len {
+            self.largest_run_start = Some(self.run_start);
+            self.largest_run_len = self.run_len;
+        }
+    }
 }
 
 trait FatTrait {
@@ -56,14 +145,14 @@ trait FatTrait {
         E: IoError,
         Error<E>: From<S::Error> + From<ReadExactError<S::Error>>;
 
-    async fn count_free<S, E>(fat: &mut S, end_cluster: u32) -> Result<u32, Error<E>>
+    async fn scan_free<S, E>(fat: &mut S, end_cluster: u32) -> Result<FreeClusters, Error<E>>
     where
         S: Read + Seek,
         E: IoError,
         Error<E>: From<S::Error> + From<ReadExactError<S::Error>>;
 }
 
-async fn read_fat<S, E>(fat: &mut S, fat_type: FatType, cluster: u32) -> Result<FatValue, Error<E>>
+pub(crate) async fn read_fat<S, E>(fat: &mut S, fat_type: FatType, cluster: u32) -> Result<FatValue, Error<E>>
 where
     S: Read + Seek,
     E: IoError,
@@ -76,7 +165,12 @@ where
     }
 }
 
-async fn write_fat<S, E>(fat: &mut S, fat_type: FatType, cluster: u32, value: FatValue) -> Result<(), Error<E>>
+pub(crate) async fn write_fat<S, E>(
+    fat: &mut S,
+    fat_type: FatType,
+    cluster: u32,
+    value: FatValue,
+) -> Result<(), Error<E>>
 where
     S: Read + Write + Seek,
     E: IoError,
@@ -132,25 +226,259 @@ where
     S: Read + Write + Seek,
     E: IoError,
     Error<E>: From<S::Error> + From<ReadExactError<S::Error>>,
+{
+    let new_cluster = find_free_cluster_from_hint(fat, fat_type, hint, total_clusters).await?;
+    link_new_cluster(fat, fat_type, prev_cluster, new_cluster).await?;
+    Ok(new_cluster)
+}
+
+pub(crate) async fn find_free_cluster_from_hint<S, E>(
+    fat: &mut S,
+    fat_type: FatType,
+    hint: Option<u32>,
+    total_clusters: u32,
+) -> Result<u32, Error<E>>
+where
+    S: Read + Seek,
+    E: IoError,
+    Error<E>: From<S::Error> + From<ReadExactError<S::Error>>,
 {
     let end_cluster = total_clusters + RESERVED_FAT_ENTRIES;
     let start_cluster = match hint {
         Some(n) if n < end_cluster => n,
         _ => RESERVED_FAT_ENTRIES,
     };
-    let new_cluster = match find_free_cluster(fat, fat_type, start_cluster, end_cluster).await {
-        Ok(n) => n,
-        Err(_) if start_cluster > RESERVED_FAT_ENTRIES => {
-            find_free_cluster(fat, fat_type, RESERVED_FAT_ENTRIES, start_cluster).await?
+    match find_free_cluster(fat, fat_type, start_cluster, end_cluster).await {
+        Ok(n) => Ok(n),
+        // other errors come from the storage and are returned unchanged
+        Err(Error::NotEnoughSpace) if start_cluster > RESERVED_FAT_ENTRIES => {
+            find_free_cluster(fat, fat_type, RESERVED_FAT_ENTRIES, start_cluster).await
         }
-        Err(e) => return Err(e),
-    };
+        Err(e) => Err(e),
+    }
+}
+
+// Marks a free cluster as the end of a chain and appends it to `prev_cluster`.
+// Note: the new cluster is marked first so the chain never points to a free cluster, even if the future is dropped
+// between the writes.
+pub(crate) async fn link_new_cluster<S, E>(
+    fat: &mut S,
+    fat_type: FatType,
+    prev_cluster: Option<u32>,
+    new_cluster: u32,
+) -> Result<(), Error<E>>
+where
+    S: Read + Write + Seek,
+    E: IoError,
+    Error<E>: From<S::Error> + From<ReadExactError<S::Error>>,
+{
     write_fat(fat, fat_type, new_cluster, FatValue::EndOfChain).await?;
     if let Some(n) = prev_cluster {
         write_fat(fat, fat_type, n, FatValue::Data(new_cluster)).await?;
     }
     trace!("allocated cluster {}", new_cluster);
-    Ok(new_cluster)
+    Ok(())
+}
+
+pub(crate) async fn mark_bad_cluster<S, E>(fat: &mut S, fat_type: FatType, cluster: u32) -> Result<(), Error<E>>
+where
+    S: Read + Write + Seek,
+    E: IoError,
+    Error<E>: From<S::Error> + From<ReadExactError<S::Error>>,
+{
+    let value = read_fat(fat, fat_type, cluster).await?;
+    if value != FatValue::Free {
+        error!("cannot mark cluster {} as bad: it is not free ({:?})", cluster, value);
+        return Err(Error::InvalidInput);
+    }
+    write_fat(fat, fat_type, cluster, FatValue::Bad).await?;
+    trace!("marked cluster {} as bad", cluster);
+    Ok(())
+}
+
+// Returns the first free cluster found from the hint and the length of the run of free clusters starting there, the
+// run is at most `max_count` clusters long
+pub(crate) async fn find_free_run_from_hint<S, E>(
+    fat: &mut S,
+    fat_type: FatType,
+    hint: Option<u32>,
+    max_count: u32,
+    total_clusters: u32,
+) -> Result<(u32, u32), Error<E>>
+where
+    S: Read + Seek,
+    E: IoError,
+    Error<E>: From<S::Error> + From<ReadExactError<S::Error>>,
+{
+    let first_cluster = find_free_cluster_from_hint(fat, fat_type, hint, total_clusters).await?;
+    let end_cluster = total_clusters + RESERVED_FAT_ENTRIES;
+    let mut count = 1;
+    while count < max_count
+        && first_cluster + count < end_cluster
+        && read_fat(fat, fat_type, first_cluster + count).await? == FatValue::Free
+    {
+        count += 1;
+    }
+    Ok((first_cluster, count))
+}
+
+// Links a run of free clusters into a chain and appends it to `prev_cluster`. The run is complete before the previous
+// cluster points to it, like in `link_new_cluster`.
+pub(crate) async fn link_new_run<S, E>(
+    fat: &mut S,
+    fat_type: FatType,
+    prev_cluster: Option<u32>,
+    first_cluster: u32,
+    count: u32,
+) -> Result<(), Error<E>>
+where
+    S: Read + Write + Seek,
+    E: IoError,
+    Error<E>: From<S::Error> + From<ReadExactError<S::Error>>,
+{
+    debug_assert!(count > 0);
+    let last_cluster = first_cluster + count - 1;
+    write_fat_chain(fat, fat_type, first_cluster, last_cluster).await?;
+    if let Some(n) = prev_cluster {
+        write_fat(fat, fat_type, n, FatValue::Data(first_cluster)).await?;
+    }
+    trace!("allocated clusters {}-{}", first_cluster, last_cluster);
+    Ok(())
+}
+
+async fn find_free_run<S, E>(
+    fat: &mut S,
+    fat_type: FatType,
+    count: u32,
+    start_cluster: u32,
+    end_cluster: u32,
+) -> Result<u32, Error<E>>
+where
+    S: Read + Seek,
+    E: IoError,
+    Error<E>: From<S::Error> + From<ReadExactError<S::Error>>,
+{
+    let mut run_start = start_cluster;
+    while end_cluster - run_start >= count {
+        run_start = find_free_cluster(fat, fat_type, run_start, end_cluster).await?;
+        if end_cluster - run_start < count {
+            break;
+        }
+        let mut run_len = 1;
+        while run_len < count && read_fat(fat, fat_type, run_start + run_len).await? == FatValue::Free {
+            run_len += 1;
+        }
+        if run_len == count {
+            return Ok(run_start);
+        }
+        // skip the used cluster that ended the run
+        run_start += run_len + 1;
+    }
+    Err(Error::NotEnoughSpace)
+}
+
+pub(crate) async fn alloc_contiguous_clusters<S, E>(
+    fat: &mut S,
+    fat_type: FatType,
+    prev_cluster: Option<u32>,
+    count: u32,
+    hint: Option<u32>,
+    total_clusters: u32,
+) -> Result<u32, Error<E>>
+where
+    S: Read + Write + Seek,
+    E: IoError,
+    Error<E>: From<S::Error> + From<ReadExactError<S::Error>>,
+{
+    debug_assert!(count > 0);
+    let end_cluster = total_clusters + RESERVED_FAT_ENTRIES;
+    let start_cluster = match hint {
+        Some(n) if n < end_cluster => n,
+        _ => RESERVED_FAT_ENTRIES,
+    };
+    let first_cluster = match find_free_run(fat, fat_type, count, start_cluster, end_cluster).await {
+        Ok(n) => n,
+        Err(Error::NotEnoughSpace) if start_cluster > RESERVED_FAT_ENTRIES => {
+            // a run can cross the hint so search the whole table again
+            find_free_run(fat, fat_type, count, RESERVED_FAT_ENTRIES, end_cluster).await?
+        }
+        Err(e) => return Err(e),
+    };
+    link_new_run(fat, fat_type, prev_cluster, first_cluster, count).await?;
+    Ok(first_cluster)
+}
+
+// Links clusters in range [first_cluster, last_cluster] into a chain ending with the last one. Entries sharing a FAT
+// block are written together (to all FAT copies if the storage mirrors them). The chain is built backwards so no entry
+// ever points to a free cluster.
+async fn write_fat_chain<S, E>(
+    fat: &mut S,
+    fat_type: FatType,
+    first_cluster: u32,
+    last_cluster: u32,
+) -> Result<(), Error<E>>
+where
+    S: Read + Write + Seek,
+    E: IoError,
+    Error<E>: From<S::Error> + From<ReadExactError<S::Error>>,
+{
+    trace!("write FAT - chain {}-{}", first_cluster, last_cluster);
+    let (begin, _, _) = fat_entry_layout(fat_type, first_cluster);
+    let (last_pos, last_len, _) = fat_entry_layout(fat_type, last_cluster);
+    let mut block_end = last_pos + last_len;
+    // the highest entry not written completely yet
+    let mut cluster = last_cluster;
+    let mut buf = [0_u8; FAT_BLOCK_SIZE as usize];
+    while block_end > begin {
+        let block_begin = cmp::max(begin, (block_end - 1) / FAT_BLOCK_SIZE * FAT_BLOCK_SIZE);
+        let block = &mut buf[..(block_end - block_begin) as usize];
+        fat.seek(io::SeekFrom::Start(block_begin)).await?;
+        fat.read_exact(block).await?;
+        loop {
+            let value = if cluster == last_cluster {
+                FatValue::EndOfChain
+            } else {
+                FatValue::Data(cluster + 1)
+            };
+            put_fat_entry(fat_type, block, block_begin, cluster, value);
+            let (pos, _, _) = fat_entry_layout(fat_type, cluster);
+            // a FAT12 entry can start in the previous block, it is finished there
+            if pos < block_begin || cluster == first_cluster {
+                break;
+            }
+            cluster -= 1;
+        }
+        fat.seek(io::SeekFrom::Start(block_begin)).await?;
+        fat.write_all(block).await?;
+        block_end = block_begin;
+    }
+    Ok(())
+}
+
+// Returns the position of the entry in the FAT, the number of bytes it touches and its bit mask within these bytes
+fn fat_entry_layout(fat_type: FatType, cluster: u32) -> (u64, u64, u32) {
+    match fat_type {
+        // even entries take the low 12 bits, odd entries the high 12 bits of the little-endian pair of bytes
+        FatType::Fat12 if cluster & 1 == 0 => (u64::from(cluster + cluster / 2), 2, 0x0FFF),
+        FatType::Fat12 => (u64::from(cluster + cluster / 2), 2, 0xFFF0),
+        FatType::Fat16 => (u64::from(cluster) * 2, 2, 0xFFFF),
+        // the highest 4 bits are reserved and must be preserved
+        FatType::Fat32 => (u64::from(cluster) * 4, 4, 0x0FFF_FFFF),
+    }
+}
+
+// Stores an entry in a buffer holding the FAT bytes starting at `buf_pos`. Bytes of the entry outside of the buffer are
+// skipped.
+fn put_fat_entry(fat_type: FatType, buf: &mut [u8], buf_pos: u64, cluster: u32, value: FatValue) {
+    let (pos, len, mask) = fat_entry_layout(fat_type, cluster);
+    let shifted = value.to_raw(fat_type) << mask.trailing_zeros();
+    for i in 0..len {
+        let Some(byte) = (pos + i).checked_sub(buf_pos).and_then(|n| buf.get_mut(n as usize)) else {
+            continue;
+        };
+        let byte_mask = (mask >> (8 * i)) as u8;
+        *byte = (*byte & !byte_mask) | ((shifted >> (8 * i)) as u8 & byte_mask);
+    }
 }
 
 pub(crate) async fn read_fat_flags<S, E>(fat: &mut S, fat_type: FatType) -> Result<FsStatusFlags, Error<E>>
@@ -183,6 +511,19 @@ pub(crate) async fn count_free_clusters<S, E>(
     fat_type: FatType,
     total_clusters: u32,
 ) -> Result<u32, Error<E>>
+where
+    S: Read + Seek,
+    E: IoError,
+    Error<E>: From<S::Error> + From<ReadExactError<S::Error>>,
+{
+    Ok(scan_free_clusters(fat, fat_type, total_clusters).await?.count)
+}
+
+pub(crate) async fn scan_free_clusters<S, E>(
+    fat: &mut S,
+    fat_type: FatType,
+    total_clusters: u32,
+) -> Result<FreeClusters, Error<E>>
 where
     S: Read + Seek,
     E: IoError,
@@ -190,10 +531,69 @@ where
 {
     let end_cluster = total_clusters + RESERVED_FAT_ENTRIES;
     match fat_type {
-        FatType::Fat12 => Fat12::count_free(fat, end_cluster).await,
-        FatType::Fat16 => Fat16::count_free(fat, end_cluster).await,
-        FatType::Fat32 => Fat32::count_free(fat, end_cluster).await,
+        FatType::Fat12 => Fat12::scan_free(fat, end_cluster).await,
+        FatType::Fat16 => Fat16::scan_free(fat, end_cluster).await,
+        FatType::Fat32 => Fat32::scan_free(fat, end_cluster).await,
+    }
+}
+
+async fn read_fat_raw<S, E>(fat: &mut S, fat_type: FatType, cluster: u32) -> Result<u32, Error<E>>
+where
+    S: Read + Seek,
+    E: IoError,
+    Error<E>: From<S::Error> + From<ReadExactError<S::Error>>,
+{
+    match fat_type {
+        FatType::Fat12 => Fat12::get_raw(fat, cluster).await,
+        FatType::Fat16 => Fat16::get_raw(fat, cluster).await,
+        FatType::Fat32 => Fat32::get_raw(fat, cluster).await,
+    }
+}
+
+pub(crate) async fn find_fat_mismatch<S, E>(
+    fat: &mut S,
+    other_fat: &mut S,
+    fat_type: FatType,
+    total_clusters: u32,
+) -> Result<Option<u32>, Error<E>>
+where
+    S: Read + Seek,
+    E: IoError,
+    Error<E>: From<S::Error> + From<ReadExactError<S::Error>>,
+{
+    const BITS_PER_BYTE: u64 = 8;
+    let bits_per_entry = u64::from(fat_type.bits_per_fat_entry());
+    let end_cluster = total_clusters + RESERVED_FAT_ENTRIES;
+    // reserved entries are skipped - they contain volume flags and not cluster chains
+    let mut pos = u64::from(RESERVED_FAT_ENTRIES) * bits_per_entry / BITS_PER_BYTE;
+    let end_pos = (u64::from(end_cluster) * bits_per_entry).div_ceil(BITS_PER_BYTE);
+    // compare FATs in big chunks first to avoid a seek for every single entry
+    let mut buf = [0_u8; 512];
+    let mut other_buf = [0_u8; 512];
+    let mismatch_pos = loop {
+        if pos >= end_pos {
+            return Ok(None);
+        }
+        let len = cmp::min(end_pos - pos, buf.len() as u64) as usize;
+        fat.seek(io::SeekFrom::Start(pos)).await?;
+        fat.read_exact(&mut buf[..len]).await?;
+        other_fat.seek(io::SeekFrom::Start(pos)).await?;
+        other_fat.read_exact(&mut other_buf[..len]).await?;
+        if let Some(i) = buf[..len].iter().zip(&other_buf[..len]).position(|(a, b)| a != b) {
+            break pos + i as u64;
+        }
+        pos += len as u64;
+    };
+    // find the exact entry - FAT12 entries are not byte aligned so start from the previous one
+    let first_cluster = (mismatch_pos * BITS_PER_BYTE / bits_per_entry).saturating_sub(1) as u32;
+    for cluster in cmp::max(first_cluster, RESERVED_FAT_ENTRIES)..end_cluster {
+        let val = read_fat_raw(fat, fat_type, cluster).await?;
+        let other_val = read_fat_raw(other_fat, fat_type, cluster).await?;
+        if val != other_val {
+            return Ok(Some(cluster));
+        }
     }
+    Ok(None)
 }
 
 pub(crate) async fn format_fat<S, E>(
@@ -263,12 +663,7 @@ impl FatTrait for Fat12 {
         Error<E>: From<S::Error> + From<ReadExactError<S::Error>>,
     {
         let val = Self::get_raw(fat, cluster).await?;
-        Ok(match val {
-            0 => FatValue::Free,
-            0xFF7 => FatValue::Bad,
-            0xFF8..=0xFFF => FatValue::EndOfChain,
-            n => FatValue::Data(n),
-        })
+        Ok(FatValue::from_raw(FatType::Fat12, val))
     }
 
     async fn set<S, E>(fat: &mut S, cluster: u32, value: FatValue) -> Result<(), Error<E>>
@@ -277,13 +672,7 @@ impl FatTrait for Fat12 {
         E: IoError,
         Error<E>: From<S::Error> + From<ReadExactError<S::Error>>,
     {
-        let raw_val = match value {
-            FatValue::Free => 0,
-            FatValue::Bad => 0xFF7,
-            FatValue::EndOfChain => 0xFFF,
-            FatValue::Data(n) => n,
-        };
-        Self::set_raw(fat, cluster, raw_val).await
+        Self::set_raw(fat, cluster, value.to_raw(FatType::Fat12)).await
     }
 
     async fn set_raw<S, E>(fat: &mut S, cluster: u32, raw_val: u32) -> Result<(), Error<E>>
@@ -297,7 +686,8 @@ impl FatTrait for Fat12 {
         let old_packed = fat.read_u16_le().await?;
         fat.seek(io::SeekFrom::Start(u64::from(fat_offset))).await?;
         let new_packed = match cluster & 1 {
-            0 => (old_packed & 0xF000) | raw_val as u16,
+            // even entries take the low 12 bits, odd entries the high 12 bits of the little-endian pair of bytes
+            0 => (old_packed & 0xF000) | (raw_val as u16 & 0x0FFF),
             _ => (old_packed & 0x000F) | ((raw_val as u16) << 4),
         };
         fat.write_u16_le(new_packed).await?;
@@ -335,13 +725,13 @@ impl FatTrait for Fat12 {
         }
     }
 
-    async fn count_free<S, E>(fat: &mut S, end_cluster: u32) -> Result<u32, Error<E>>
+    async fn scan_free<S, E>(fat: &mut S, end_cluster: u32) -> Result<FreeClusters, Error<E>>
     where
         S: Read + Seek,
         E: IoError,
         Error<E>: From<S::Error> + From<ReadExactError<S::Error>>,
     {
-        let mut count = 0;
+        let mut free = FreeClusters::default();
         let mut cluster = RESERVED_FAT_ENTRIES;
         fat.seek(io::SeekFrom::Start(u64::from(cluster * 3 / 2))).await?;
         let mut prev_packed_val = 0_u16;
@@ -354,17 +744,19 @@ impl FatTrait for Fat12 {
                 Err(err) => return Err(err.into()),
                 Ok(n) => n,
             };
+            // an odd entry is the high nibble of the byte shared with the previous entry followed by the next byte
             let val = match cluster & 1 {
                 0 => packed_val & 0x0FFF,
-                _ => (packed_val << 8) | (prev_packed_val >> 12),
+                _ => (packed_val << 4) | (prev_packed_val >> 12),
             };
             prev_packed_val = packed_val;
-            if val == 0 {
-                count += 1;
+            if val == 0xFF7 {
+                free.bad_count += 1;
             }
+            free.add(cluster, val == 0);
             cluster += 1;
         }
-        Ok(count)
+        Ok(free)
     }
 }
 
@@ -386,12 +778,7 @@ impl FatTrait for Fat16 {
         Error<E>: From<S::Error> + From<ReadExactError<S::Error>>,
     {
         let val = Self::get_raw(fat, cluster).await?;
-        Ok(match val {
-            0 => FatValue::Free,
-            0xFFF7 => FatValue::Bad,
-            0xFFF8..=0xFFFF => FatValue::EndOfChain,
-            n => FatValue::Data(n),
-        })
+        Ok(FatValue::from_raw(FatType::Fat16, val))
     }
 
     async fn set<S, E>(fat: &mut S, cluster: u32, value: FatValue) -> Result<(), Error<E>>
@@ -400,13 +787,7 @@ impl FatTrait for Fat16 {
         E: IoError,
         Error<E>: From<S::Error> + From<ReadExactError<S::Error>>,
     {
-        let raw_value = match value {
-            FatValue::Free => 0,
-            FatValue::Bad => 0xFFF7,
-            FatValue::EndOfChain => 0xFFFF,
-            FatValue::Data(n) => n,
-        };
-        Self::set_raw(fat, cluster, raw_value).await
+        Self::set_raw(fat, cluster, value.to_raw(FatType::Fat16)).await
     }
 
     async fn find_free<S, E>(fat: &mut S, start_cluster: u32, end_cluster: u32) -> Result<u32, Error<E>>
@@ -427,23 +808,24 @@ impl FatTrait for Fat16 {
         Err(Error::NotEnoughSpace)
     }
 
-    async fn count_free<S, E>(fat: &mut S, end_cluster: u32) -> Result<u32, Error<E>>
+    async fn scan_free<S, E>(fat: &mut S, end_cluster: u32) -> Result<FreeClusters, Error<E>>
     where
         S: Read + Seek,
         E: IoError,
         Error<E>: From<S::Error> + From<ReadExactError<S::Error>>,
     {
-        let mut count = 0;
+        let mut free = FreeClusters::default();
         let mut cluster = RESERVED_FAT_ENTRIES;
         fat.seek(io::SeekFrom::Start(u64::from(cluster * 2))).await?;
         while cluster < end_cluster {
             let val = fat.read_u16_le().await?;
-            if val == 0 {
-                count += 1;
+            if val == 0xFFF7 {
+                free.bad_count += 1;
             }
+            free.add(cluster, val == 0);
             cluster += 1;
         }
-        Ok(count)
+        Ok(free)
     }
 
     async fn set_raw<S, E>(fat: &mut S, cluster: u32, raw_value: u32) -> Result<(), Error<E>>
@@ -475,9 +857,9 @@ impl FatTrait for Fat32 {
         E: IoError,
         Error<E>: From<S::Error> + From<ReadExactError<S::Error>>,
     {
-        let val = Self::get_raw(fat, cluster).await? & 0x0FFF_FFFF;
-        Ok(match val {
-            0 if (0x0FFF_FFF7..=0x0FFF_FFFF).contains(&cluster) => {
+        let val = Self::get_raw(fat, cluster).await?;
+        Ok(match FatValue::from_raw(FatType::Fat32, val) {
+            FatValue::Free if (0x0FFF_FFF7..=0x0FFF_FFFF).contains(&cluster) => {
                 let tmp = if cluster == 0x0FFF_FFF7 {
                     "BAD_CLUSTER"
                 } else {
@@ -489,10 +871,7 @@ impl FatTrait for Fat32 {
                 );
                 FatValue::Bad // avoid accidental use or allocation into a FAT chain
             }
-            0 => FatValue::Free,
-            0x0FFF_FFF7 => FatValue::Bad,
-            0x0FFF_FFF8..=0x0FFF_FFFF => FatValue::EndOfChain,
-            n if (0x0FFF_FFF7..=0x0FFF_FFFF).contains(&cluster) => {
+            FatValue::Data(n) if (0x0FFF_FFF7..=0x0FFF_FFFF).contains(&cluster) => {
                 let tmp = if cluster == 0x0FFF_FFF7 {
                     "BAD_CLUSTER"
                 } else {
@@ -501,7 +880,7 @@ impl FatTrait for Fat32 {
                 warn!("cluster number {} is a special value in FAT to indicate {}; hiding potential FAT chain value {} and instead reporting as a bad sector", cluster, tmp, n);
                 FatValue::Bad // avoid accidental use or allocation into a FAT chain
             }
-            n => FatValue::Data(n),
+            value => value,
         })
     }
 
@@ -527,13 +906,7 @@ impl FatTrait for Fat32 {
                 cluster, tmp
             );
         };
-        let raw_val = match value {
-            FatValue::Free => 0,
-            FatValue::Bad => 0x0FFF_FFF7,
-            FatValue::EndOfChain => 0x0FFF_FFFF,
-            FatValue::Data(n) => n,
-        };
-        let raw_val = raw_val | old_reserved_bits; // must preserve original reserved values
+        let raw_val = value.to_raw(FatType::Fat32) | old_reserved_bits; // must preserve original reserved values
         Self::set_raw(fat, cluster, raw_val).await
     }
 
@@ -555,23 +928,24 @@ impl FatTrait for Fat32 {
         Err(Error::NotEnoughSpace)
     }
 
-    async fn count_free<S, E>(fat: &mut S, end_cluster: u32) -> Result<u32, Error<E>>
+    async fn scan_free<S, E>(fat: &mut S, end_cluster: u32) -> Result<FreeClusters, Error<E>>
     where
         S: Read + Seek,
         E: IoError,
         Error<E>: From<S::Error> + From<ReadExactError<S::Error>>,
     {
-        let mut count = 0;
+        let mut free = FreeClusters::default();
         let mut cluster = RESERVED_FAT_ENTRIES;
         fat.seek(io::SeekFrom::Start(u64::from(cluster * 4))).await?;
         while cluster < end_cluster {
             let val = fat.read_u32_le().await? & 0x0FFF_FFFF;
-            if val == 0 {
-                count += 1;
+            if val == 0x0FFF_FFF7 {
+                free.bad_count += 1;
             }
+            free.add(cluster, val == 0);
             cluster += 1;
         }
-        Ok(count)
+        Ok(free)
     }
 
     async fn set_raw<S, E>(fat: &mut S, cluster: u32, raw_value: u32) -> Result<(), Error<E>>
@@ -591,6 +965,11 @@ pub(crate) struct ClusterIterator<B, E, S = B> {
     fat_type: FatType,
     cluster: Option<u32>,
     err: bool,
+    // number of steps taken and the limit after which the chain must contain a cycle
+    steps: u32,
+    max_steps: u32,
+    // the lowest cluster freed by `truncate` or `free`
+    lowest_freed: Option<u32>,
     // phantom is needed to add type bounds on the storage type
     phantom_s: PhantomData<S>,
     phantom_e: PhantomData<E>,
@@ -603,40 +982,54 @@ where
     S: Read + Write + Seek,
     Error<E>: From<S::Error> + From<ReadExactError<S::Error>>,
 {
-    pub(crate) fn new(fat: B, fat_type: FatType, cluster: u32) -> Self {
+    pub(crate) fn new(fat: B, fat_type: FatType, cluster: u32, total_clusters: u32) -> Self {
         Self {
             fat,
             fat_type,
             cluster: Some(cluster),
             err: false,
+            steps: 0,
+            max_steps: total_clusters,
+            lowest_freed: None,
             phantom_s: PhantomData,
             phantom_e: PhantomData,
         }
     }
 
-    pub(crate) async fn truncate(&mut self) -> Result<u32, Error<E>> {
+    pub(crate) async fn truncate(&mut self, on_freed: impl FnMut(u32)) -> Result<u32, Error<E>> {
         if let Some(n) = self.cluster {
             // Move to the next cluster
-            self.next().await;
+            if let Some(Err(err)) = self.next().await {
+                return Err(err);
+            }
             // Mark previous cluster as end of chain
             write_fat(self.fat.borrow_mut(), self.fat_type, n, FatValue::EndOfChain).await?;
             // Free rest of chain
-            self.free().await
+            self.free(on_freed).await
         } else {
             Ok(0)
         }
     }
 
-    pub(crate) async fn free(&mut self) -> Result<u32, Error<E>> {
+    // `on_freed` is called with every cluster after it has been marked free
+    pub(crate) async fn free(&mut self, mut on_freed: impl FnMut(u32)) -> Result<u32, Error<E>> {
         let mut num_free = 0;
         while let Some(n) = self.cluster {
-            self.next().await;
+            if let Some(Err(err)) = self.next().await {
+                return Err(err);
+            }
             write_fat(self.fat.borrow_mut(), self.fat_type, n, FatValue::Free).await?;
+            on_freed(n);
             num_free += 1;
+            self.lowest_freed = Some(self.lowest_freed.map_or(n, |m| cmp::min(m, n)));
         }
         Ok(num_free)
     }
 
+    pub(crate) fn lowest_freed(&self) -> Option<u32> {
+        self.lowest_freed
+    }
+
     pub async fn next(&mut self) -> Option<Result<u32, Error<E>>> {
         if self.err {
             return None;
@@ -650,6 +1043,15 @@ where
                 }
             }
         }
+        if self.cluster.is_some() {
+            // a chain cannot be longer than the number of clusters so it must be circular
+            self.steps += 1;
+            if self.steps >= self.max_steps {
+                error!("cluster chain is longer than {} clusters", self.max_steps);
+                self.err = true;
+                return Some(Err(Error::CorruptedFileSystem));
+            }
+        }
         self.cluster.map(Ok)
     }
 }
@@ -659,6 +1061,7 @@ mod tests {
     use embedded_io_adapters::tokio_1::FromTokio;
 
     use super::*;
+    use crate::io::tests::CountingStream;
     use std::io::Cursor;
 
     async fn test_fat<S: Read + Write + Seek + IoBase>(fat_type: FatType, mut cur: S) {
@@ -706,7 +1109,7 @@ mod tests {
         assert_eq!(count_free_clusters(&mut cur, fat_type, 0x1E).await.ok(), Some(3));
         // test reading from iterator
         {
-            let mut iter = ClusterIterator::<&mut S, S::Error, S>::new(&mut cur, fat_type, 0x9);
+            let mut iter = ClusterIterator::<&mut S, S::Error, S>::new(&mut cur, fat_type, 0x9, 0x1E);
             let actual_cluster_numbers = {
                 let mut v = Vec::new();
                 while let Some(i) = iter.next().await {
@@ -724,13 +1127,13 @@ mod tests {
         }
         // test truncating a chain
         {
-            let mut iter = ClusterIterator::<&mut S, S::Error, S>::new(&mut cur, fat_type, 0x9);
+            let mut iter = ClusterIterator::<&mut S, S::Error, S>::new(&mut cur, fat_type, 0x9, 0x1E);
             iter.next().await;
             iter.next().await;
             iter.next().await;
             let value = iter.next().await.unwrap().ok();
             assert_eq!(value, Some(0x16));
-            assert!(iter.truncate().await.is_ok());
+            assert!(iter.truncate(|_| {}).await.is_ok());
         }
         assert_eq!(
             read_fat(&mut cur, fat_type, 0x16).await.ok(),
@@ -740,14 +1143,87 @@ mod tests {
         assert_eq!(read_fat(&mut cur, fat_type, 0x1A).await.ok(), Some(FatValue::Free));
         // test freeing a chain
         {
-            let mut iter = ClusterIterator::<&mut S, S::Error, S>::new(&mut cur, fat_type, 0x9);
-            assert!(iter.free().await.is_ok());
+            let mut iter = ClusterIterator::<&mut S, S::Error, S>::new(&mut cur, fat_type, 0x9, 0x1E);
+            assert!(iter.free(|_| {}).await.is_ok());
         }
         assert_eq!(read_fat(&mut cur, fat_type, 0x9).await.ok(), Some(FatValue::Free));
         assert_eq!(read_fat(&mut cur, fat_type, 0xA).await.ok(), Some(FatValue::Free));
         assert_eq!(read_fat(&mut cur, fat_type, 0x14).await.ok(), Some(FatValue::Free));
         assert_eq!(read_fat(&mut cur, fat_type, 0x15).await.ok(), Some(FatValue::Free));
         assert_eq!(read_fat(&mut cur, fat_type, 0x16).await.ok(), Some(FatValue::Free));
+        // test contiguous allocation - runs after the hint are too short so search wraps around
+        assert_eq!(
+            alloc_contiguous_clusters(&mut cur, fat_type, None, 3, Some(0x17), 0x1E)
+                .await
+                .ok(),
+            Some(0x14)
+        );
+        assert_eq!(
+            read_fat(&mut cur, fat_type, 0x14).await.ok(),
+            Some(FatValue::Data(0x15))
+        );
+        assert_eq!(
+            read_fat(&mut cur, fat_type, 0x15).await.ok(),
+            Some(FatValue::Data(0x16))
+        );
+        assert_eq!(
+            read_fat(&mut cur, fat_type, 0x16).await.ok(),
+            Some(FatValue::EndOfChain)
+        );
+        assert!(matches!(
+            alloc_contiguous_clusters(&mut cur, fat_type, None, 3, None, 0x1E).await,
+            Err(Error::NotEnoughSpace)
+        ));
+    }
+
+    async fn test_free_runs<S: Read + Write + Seek + IoBase>(fat_type: FatType, mut cur: S) {
+        // a run ends at a used cluster, at the end of the FAT or at the requested length
+        assert_eq!(
+            find_free_run_from_hint(&mut cur, fat_type, None, 5, 0x1E).await.ok(),
+            Some((0x12, 1))
+        );
+        assert_eq!(
+            find_free_run_from_hint(&mut cur, fat_type, Some(0x13), 5, 0x1E)
+                .await
+                .ok(),
+            Some((0x1B, 2))
+        );
+        assert_eq!(
+            find_free_run_from_hint(&mut cur, fat_type, Some(0x1D), 5, 0x1E)
+                .await
+                .ok(),
+            Some((0x1E, 2))
+        );
+        assert_eq!(
+            find_free_run_from_hint(&mut cur, fat_type, Some(0x1B), 1, 0x1E)
+                .await
+                .ok(),
+            Some((0x1B, 1))
+        );
+    }
+
+    async fn test_bad_clusters<S: Read + Write + Seek + IoBase>(fat_type: FatType, mut cur: S) {
+        let free = scan_free_clusters(&mut cur, fat_type, 0x1E).await.unwrap();
+        assert_eq!((free.count, free.bad_count), (5, 3));
+        assert!(mark_bad_cluster(&mut cur, fat_type, 0x12).await.is_ok());
+        assert_eq!(read_fat(&mut cur, fat_type, 0x12).await.ok(), Some(FatValue::Bad));
+        // only free clusters can be marked
+        assert!(matches!(
+            mark_bad_cluster(&mut cur, fat_type, 0x12).await,
+            Err(Error::InvalidInput)
+        ));
+        assert!(matches!(
+            mark_bad_cluster(&mut cur, fat_type, 0x4).await,
+            Err(Error::InvalidInput)
+        ));
+        // allocation skips bad clusters
+        assert_eq!(find_free_cluster(&mut cur, fat_type, 2, 0x20).await.ok(), Some(0x1B));
+        assert_eq!(
+            alloc_cluster(&mut cur, fat_type, None, Some(0x12), 0x1E).await.ok(),
+            Some(0x1B)
+        );
+        let free = scan_free_clusters(&mut cur, fat_type, 0x1E).await.unwrap();
+        assert_eq!((free.count, free.bad_count), (3, 4));
     }
 
     #[tokio::test]
@@ -757,7 +1233,9 @@ mod tests {
             0x0D, 0xE0, 0x00, 0x0F, 0x00, 0x01, 0x11, 0xF0, 0xFF, 0x00, 0xF0, 0xFF, 0x15, 0x60, 0x01, 0x19, 0x70, 0xFF,
             0xF7, 0xAF, 0x01, 0xFF, 0x0F, 0x00, 0x00, 0x70, 0xFF, 0x00, 0x00, 0x00,
         ];
-        test_fat(FatType::Fat12, FromTokio::new(Cursor::<Vec<u8>>::new(fat))).await;
+        test_fat(FatType::Fat12, FromTokio::new(Cursor::<Vec<u8>>::new(fat.clone()))).await;
+        test_free_runs(FatType::Fat12, FromTokio::new(Cursor::<Vec<u8>>::new(fat.clone()))).await;
+        test_bad_clusters(FatType::Fat12, FromTokio::new(Cursor::<Vec<u8>>::new(fat))).await;
     }
 
     #[tokio::test]
@@ -768,7 +1246,9 @@ mod tests {
             0x00, 0x00, 0xFF, 0xFF, 0x15, 0x00, 0x16, 0x00, 0x19, 0x00, 0xF7, 0xFF, 0xF7, 0xFF, 0x1A, 0x00, 0xFF, 0xFF,
             0x00, 0x00, 0x00, 0x00, 0xF7, 0xFF, 0x00, 0x00, 0x00, 0x00,
         ];
-        test_fat(FatType::Fat16, FromTokio::new(Cursor::<Vec<u8>>::new(fat))).await;
+        test_fat(FatType::Fat16, FromTokio::new(Cursor::<Vec<u8>>::new(fat.clone()))).await;
+        test_free_runs(FatType::Fat16, FromTokio::new(Cursor::<Vec<u8>>::new(fat.clone()))).await;
+        test_bad_clusters(FatType::Fat16, FromTokio::new(Cursor::<Vec<u8>>::new(fat))).await;
     }
 
     #[tokio::test]
@@ -783,6 +1263,179 @@ mod tests {
             0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xF7, 0xFF, 0xFF, 0x0F, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
             0x00, 0x00,
         ];
-        test_fat(FatType::Fat32, FromTokio::new(Cursor::<Vec<u8>>::new(fat))).await;
+        test_fat(FatType::Fat32, FromTokio::new(Cursor::<Vec<u8>>::new(fat.clone()))).await;
+        test_free_runs(FatType::Fat32, FromTokio::new(Cursor::<Vec<u8>>::new(fat.clone()))).await;
+        test_bad_clusters(FatType::Fat32, FromTokio::new(Cursor::<Vec<u8>>::new(fat))).await;
+    }
+
+    #[tokio::test]
+    async fn test_fat12_scan_free_odd_entries() {
+        let mut cur = FromTokio::new(Cursor::<Vec<u8>>::new(vec![0; 30]));
+        let values = [
+            (2, FatValue::EndOfChain),
+            (3, FatValue::Bad),
+            (4, FatValue::Data(0x010)),
+            (5, FatValue::Data(0x100)),
+            (6, FatValue::Bad),
+            (11, FatValue::Data(0x001)),
+            (13, FatValue::Bad),
+            (16, FatValue::Data(0xFF0)),
+            (19, FatValue::Bad),
+        ];
+        for (cluster, value) in values {
+            write_fat(&mut cur, FatType::Fat12, cluster, value).await.unwrap();
+        }
+        let free = scan_free_clusters(&mut cur, FatType::Fat12, 0x12).await.unwrap();
+        assert_eq!((free.count, free.bad_count), (9, 4));
+        assert_eq!((free.largest_run_start, free.largest_run_len), (Some(7), 4));
+        // the scan decodes every entry like reading it one by one
+        let mut count = 0;
+        let mut bad_count = 0;
+        for cluster in RESERVED_FAT_ENTRIES..0x14 {
+            match read_fat(&mut cur, FatType::Fat12, cluster).await.unwrap() {
+                FatValue::Free => count += 1,
+                FatValue::Bad => bad_count += 1,
+                _ => {}
+            }
+        }
+        assert_eq!((free.count, free.bad_count), (count, bad_count));
+    }
+
+    async fn test_write_fat_chain(fat_type: FatType) {
+        const FAT_SIZE: usize = 4 * FAT_BLOCK_SIZE as usize;
+        // arbitrary content, so bits not belonging to the written entries have to be preserved
+        let fat: Vec<u8> = (0..FAT_SIZE).map(|i| (i * 7 + 0x5A) as u8).collect();
+        let entries = (FAT_SIZE as u64 * 8 / u64::from(fat_type.bits_per_fat_entry())) as u32;
+        // runs crossing block boundaries, FAT12 entries straddle some of them
+        for (first, last) in [
+            (2, entries - 1),
+            (3, entries / 2 + 1),
+            (entries / 3, entries / 3),
+            (340, 343),
+        ] {
+            let mut expected = FromTokio::new(Cursor::new(fat.clone()));
+            write_fat(&mut expected, fat_type, last, FatValue::EndOfChain)
+                .await
+                .unwrap();
+            for cluster in first..last {
+                write_fat(&mut expected, fat_type, cluster, FatValue::Data(cluster + 1))
+                    .await
+                    .unwrap();
+            }
+            let mut storage = CountingStream::new(fat.clone());
+            write_fat_chain(&mut storage, fat_type, first, last).await.unwrap();
+            assert_eq!(
+                storage.inner.into_inner().into_inner(),
+                expected.into_inner().into_inner()
+            );
+            // a single write per block
+            let (begin, _, _) = fat_entry_layout(fat_type, first);
+            let (last_pos, last_len, _) = fat_entry_layout(fat_type, last);
+            let blocks = (last_pos + last_len - 1) / FAT_BLOCK_SIZE - begin / FAT_BLOCK_SIZE + 1;
+            assert_eq!(storage.writes as u64, blocks);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_write_fat_chain_fat12() {
+        test_write_fat_chain(FatType::Fat12).await;
+    }
+
+    #[tokio::test]
+    async fn test_write_fat_chain_fat16() {
+        test_write_fat_chain(FatType::Fat16).await;
+    }
+
+    #[tokio::test]
+    async fn test_write_fat_chain_fat32() {
+        test_write_fat_chain(FatType::Fat32).await;
+    }
+
+    #[test]
+    fn test_free_clusters_largest_run() {
+        let mut free = FreeClusters::default();
+        assert_eq!(free.largest_run_start, None);
+        for (cluster, is_free) in [
+            (2, true),
+            (3, false),
+            (4, true),
+            (5, true),
+            (6, true),
+            (7, false),
+            (8, true),
+        ] {
+            free.add(cluster, is_free);
+        }
+        assert_eq!(free.count, 5);
+        assert_eq!((free.largest_run_start, free.largest_run_len), (Some(4), 3));
+    }
+
+    // Checks the decoding of every raw value from `values` and that encoding the decoded value gives it back
+    fn test_fat_value_raw(fat_type: FatType, values: impl Iterator<Item = u32>) {
+        let max = fat_entry_mask(fat_type);
+        for raw in values {
+            let value = FatValue::from_raw(fat_type, raw);
+            let expected = match raw & max {
+                0 => FatValue::Free,
+                1 => FatValue::Reserved,
+                n if n < max - 15 => FatValue::Data(n),
+                n if n < max - 8 => FatValue::Reserved,
+                n if n == max - 8 => FatValue::Bad,
+                _ => FatValue::EndOfChain,
+            };
+            assert_eq!(value, expected, "raw value {raw:#x}");
+            assert_eq!(
+                FatValue::from_raw(fat_type, value.to_raw(fat_type)),
+                value,
+                "raw value {raw:#x}"
+            );
+            if let FatValue::Data(_) | FatValue::Free = value {
+                assert_eq!(value.to_raw(fat_type), raw & max);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fat_value_raw_fat12() {
+        test_fat_value_raw(FatType::Fat12, 0..=0xFFF);
+        assert_eq!(FatValue::from_raw(FatType::Fat12, 0xFEF), FatValue::Data(0xFEF));
+        assert_eq!(FatValue::from_raw(FatType::Fat12, 0xFF0), FatValue::Reserved);
+        assert_eq!(FatValue::from_raw(FatType::Fat12, 0xFF6), FatValue::Reserved);
+        assert_eq!(FatValue::from_raw(FatType::Fat12, 0xFF7), FatValue::Bad);
+        for raw in 0xFF8..=0xFFF {
+            assert_eq!(FatValue::from_raw(FatType::Fat12, raw), FatValue::EndOfChain);
+        }
+        assert_eq!(FatValue::Bad.to_raw(FatType::Fat12), 0xFF7);
+        assert_eq!(FatValue::EndOfChain.to_raw(FatType::Fat12), 0xFFF);
+    }
+
+    #[test]
+    fn test_fat_value_raw_fat16() {
+        test_fat_value_raw(FatType::Fat16, 0..=0xFFFF);
+        assert_eq!(FatValue::from_raw(FatType::Fat16, 0xFFEF), FatValue::Data(0xFFEF));
+        assert_eq!(FatValue::from_raw(FatType::Fat16, 0xFFF0), FatValue::Reserved);
+        assert_eq!(FatValue::from_raw(FatType::Fat16, 0xFFF7), FatValue::Bad);
+        assert_eq!(FatValue::from_raw(FatType::Fat16, 0xFFF8), FatValue::EndOfChain);
+        assert_eq!(FatValue::Bad.to_raw(FatType::Fat16), 0xFFF7);
+        assert_eq!(FatValue::EndOfChain.to_raw(FatType::Fat16), 0xFFFF);
+    }
+
+    #[test]
+    fn test_fat_value_raw_fat32() {
+        // the whole 28-bit range is too large, check its boundaries with and without the reserved high bits set
+        for high_bits in [0, 0xF000_0000, 0x1000_0000] {
+            test_fat_value_raw(FatType::Fat32, (0..=0xFFFF).map(|n| n | high_bits));
+            test_fat_value_raw(FatType::Fat32, (0x0FFF_0000..=0x0FFF_FFFF).map(|n| n | high_bits));
+        }
+        assert_eq!(FatValue::from_raw(FatType::Fat32, 0xF000_0005), FatValue::Data(5));
+        assert_eq!(
+            FatValue::from_raw(FatType::Fat32, 0x0FFF_FFEF),
+            FatValue::Data(0x0FFF_FFEF)
+        );
+        assert_eq!(FatValue::from_raw(FatType::Fat32, 0x0FFF_FFF0), FatValue::Reserved);
+        assert_eq!(FatValue::from_raw(FatType::Fat32, 0xFFFF_FFF7), FatValue::Bad);
+        assert_eq!(FatValue::from_raw(FatType::Fat32, 0xFFFF_FFF8), FatValue::EndOfChain);
+        assert_eq!(FatValue::Bad.to_raw(FatType::Fat32), 0x0FFF_FFF7);
+        assert_eq!(FatValue::EndOfChain.to_raw(FatType::Fat32), 0x0FFF_FFFF);
     }
 }
@@ -46,12 +46,38 @@ impl Date {
 
     pub(crate) fn decode(dos_date: u16) -> Self {
         let (year, month, day) = ((dos_date >> 9) + MIN_YEAR, (dos_date >> 5) & 0xF, dos_date & 0x1F);
-        Self { year, month, day }
+        // month and day can be out of range in a corrupted or unset (zero) field - clamp them to a valid date
+        let valid_month = month.clamp(MIN_MONTH, MAX_MONTH);
+        let date = Self {
+            year,
+            month: valid_month,
+            day: day.clamp(MIN_DAY, days_in_month(year, valid_month)),
+        };
+        if dos_date != 0 && (date.month != month || date.day != day) {
+            warn!("Invalid date {:#x} clamped to {:?}", dos_date, date);
+        }
+        date
     }
 
     pub(crate) fn encode(self) -> u16 {
         ((self.year - MIN_YEAR) << 9) | (self.month << 5) | self.day
     }
+
+    /// Checks if all fields are in the range supported by FAT (fields are public so they can be modified freely).
+    pub(crate) fn is_valid(self) -> bool {
+        (MIN_YEAR..=MAX_YEAR).contains(&self.year)
+            && (MIN_MONTH..=MAX_MONTH).contains(&self.month)
+            && (MIN_DAY..=MAX_DAY).contains(&self.day)
+    }
+}
+
+fn days_in_month(year: u16, month: u16) -> u16 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => MAX_DAY,
+    }
 }
 
 /// A DOS compatible time.
@@ -96,7 +122,21 @@ impl Time {
         let min = (dos_time >> 5) & 0x3F;
         let sec = (dos_time & 0x1F) * 2 + u16::from(dos_time_hi_res / 100);
         let millis = u16::from(dos_time_hi_res % 100) * 10;
-        Self { hour, min, sec, millis }
+        // hour, minute and second can be out of range in a corrupted field - clamp them to a valid time
+        let time = Self {
+            hour: hour.min(23),
+            min: min.min(59),
+            sec: sec.min(59),
+            millis,
+        };
+        if time.hour != hour || time.min != min || time.sec != sec {
+            warn!("Invalid time {:#x}/{} clamped to {:?}", dos_time, dos_time_hi_res, time);
+        }
+        time
+    }
+
+    pub(crate) fn is_valid(self) -> bool {
+        self.hour <= 23 && self.min <= 59 && self.sec <= 59 && self.millis <= 999
     }
 
     pub(crate) fn encode(self) -> (u16, u8) {
@@ -130,6 +170,10 @@ impl DateTime {
     pub(crate) fn decode(dos_date: u16, dos_time: u16, dos_time_hi_res: u8) -> Self {
         Self::new(Date::decode(dos_date), Time::decode(dos_time, dos_time_hi_res))
     }
+
+    pub(crate) fn is_valid(self) -> bool {
+        self.date.is_valid() && self.time.is_valid()
+    }
 }
 
 #[cfg(feature = "chrono")]
@@ -250,6 +294,49 @@ impl TimeProvider for NullTimeProvider {
     }
 }
 
+/// `TimeProvider` implementation that calls a user supplied function to get the current date and time.
+///
+/// It allows to store real timestamps on targets where `chrono` is not available, e.g. by reading a hardware RTC:
+///
+/// ```rust
+/// use embedded_fatfs::{Date, DateTime, FnTimeProvider, FsOptions, Time};
+///
+/// # fn read_rtc() -> (u16, u16, u16, u16, u16, u16) { (2024, 5, 17, 12, 30, 0) }
+/// let time_provider = FnTimeProvider::new(|| {
+///     let (year, month, day, hour, min, sec) = read_rtc();
+///     DateTime::new(Date::new(year, month, day), Time::new(hour, min, sec, 0))
+/// });
+/// let options = FsOptions::new().time_provider(time_provider);
+/// ```
+#[derive(Clone, Copy)]
+pub struct FnTimeProvider<F> {
+    get_date_time: F,
+}
+
+impl<F: Fn() -> DateTime> FnTimeProvider<F> {
+    /// Creates a new `FnTimeProvider` calling `get_date_time` whenever the current date and time is needed.
+    #[must_use]
+    pub fn new(get_date_time: F) -> Self {
+        Self { get_date_time }
+    }
+}
+
+impl<F> Debug for FnTimeProvider<F> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("FnTimeProvider").finish_non_exhaustive()
+    }
+}
+
+impl<F: Fn() -> DateTime> TimeProvider for FnTimeProvider<F> {
+    fn get_current_date(&self) -> Date {
+        (self.get_date_time)().date
+    }
+
+    fn get_current_date_time(&self) -> DateTime {
+        (self.get_date_time)()
+    }
+}
+
 /// Default time provider implementation.
 ///
 /// Defined as `ChronoTimeProvider` if `chrono` feature is enabled. Otherwise defined as `NullTimeProvider`.
@@ -284,6 +371,36 @@ mod tests {
         let _ = Date::new(2108, 1, 1);
     }
 
+    #[test]
+    fn date_decode_clamps_out_of_range_values() {
+        // zeroed field
+        assert_eq!(Date::decode(0), Date::new(1980, 1, 1));
+        // month 15, day 0
+        assert_eq!(Date::decode((44 << 9) | (15 << 5)), Date::new(2024, 12, 1));
+    }
+
+    #[test]
+    fn date_decode_clamps_day_to_month_length() {
+        // February 30 and 31 in a leap year, a common year and 2100 which is not a leap year
+        assert_eq!(Date::decode((44 << 9) | (2 << 5) | 30), Date::new(2024, 2, 29));
+        assert_eq!(Date::decode((44 << 9) | (2 << 5) | 31), Date::new(2024, 2, 29));
+        assert_eq!(Date::decode((43 << 9) | (2 << 5) | 30), Date::new(2023, 2, 28));
+        assert_eq!(Date::decode((120 << 9) | (2 << 5) | 31), Date::new(2100, 2, 28));
+        assert_eq!(Date::decode((43 << 9) | (4 << 5) | 31), Date::new(2023, 4, 30));
+        assert_eq!(Date::decode((43 << 9) | (2 << 5) | 28), Date::new(2023, 2, 28));
+        assert_eq!(Date::decode((43 << 9) | (12 << 5) | 31), Date::new(2023, 12, 31));
+    }
+
+    #[test]
+    fn time_decode_clamps_out_of_range_values() {
+        // hour 31, minute 63, second 62 + 1
+        assert_eq!(Time::decode(0xFFFF, 199), Time::new(23, 59, 59, 990));
+        assert_eq!(
+            Time::decode((12 << 11) | (30 << 5) | 7, 123),
+            Time::new(12, 30, 15, 230)
+        );
+    }
+
     #[test]
     fn date_encode_decode() {
         let d = Date::new(2055, 7, 23);
@@ -1,7 +1,7 @@
 use std::io;
 
 use embedded_fatfs::{ChronoTimeProvider, LossyOemCpConverter};
-use embedded_io_async::Write;
+use embedded_io_async::{Seek, Write};
 
 const KB: u64 = 1024;
 const MB: u64 = KB * 1024;
@@ -96,13 +96,15 @@ async fn test_format_fs(opts: embedded_fatfs::FormatVolumeOptions, total_bytes:
     let storage_vec: Vec<u8> = vec![0xD1_u8; total_bytes as usize];
     let storage_cur = io::Cursor::new(storage_vec);
     let mut buffered_stream = embedded_io_adapters::tokio_1::FromTokio::new(tokio::io::BufStream::new(storage_cur));
-    embedded_fatfs::format_volume(&mut buffered_stream, opts)
+    let fat_type = embedded_fatfs::format_volume(&mut buffered_stream, opts)
         .await
         .expect("format volume");
 
     let fs = embedded_fatfs::FileSystem::new(buffered_stream, embedded_fatfs::FsOptions::new())
         .await
         .expect("open fs");
+    assert_eq!(fs.fat_type(), fat_type);
+    assert_eq!(fs.verify_backup_boot_sector().await.unwrap(), None);
     basic_fs_test(&fs).await;
     fs
 }
@@ -171,6 +173,704 @@ async fn test_format_volume_label_and_id() {
     assert_eq!(fs.volume_id(), 1234);
 }
 
+#[tokio::test]
+async fn test_format_oem_name() {
+    let fs = test_format_fs(embedded_fatfs::FormatVolumeOptions::new(), MB).await;
+    assert_eq!(fs.oem_name(), "MSWIN4.1");
+    let opts = embedded_fatfs::FormatVolumeOptions::new().oem_name(b"MYTOOL");
+    let fs = test_format_fs(opts, MB).await;
+    assert_eq!(fs.oem_name(), "MYTOOL");
+    // non-printable characters are replaced when reading
+    let opts = embedded_fatfs::FormatVolumeOptions::new().oem_name(b"A\x01B\xE5");
+    let fs = test_format_fs(opts, MB).await;
+    assert_eq!(fs.oem_name(), "A?B?");
+}
+
+#[tokio::test]
+async fn test_format_geometry() {
+    let fs = test_format_fs(embedded_fatfs::FormatVolumeOptions::new(), MB).await;
+    assert_eq!(
+        (fs.hidden_sectors(), fs.sectors_per_track(), fs.heads()),
+        (0, 0x20, 0x40)
+    );
+    let opts = embedded_fatfs::FormatVolumeOptions::new()
+        .hidden_sectors(2048)
+        .sectors_per_track(63)
+        .heads(255);
+    let fs = test_format_fs(opts, MB).await;
+    assert_eq!(
+        (fs.hidden_sectors(), fs.sectors_per_track(), fs.heads()),
+        (2048, 63, 255)
+    );
+}
+
+#[tokio::test]
+#[should_panic(expected = "Invalid oem_name")]
+async fn test_format_oem_name_too_long() {
+    let _ = embedded_fatfs::FormatVolumeOptions::new().oem_name(b"TOO LONG!");
+}
+
+#[tokio::test]
+async fn test_format_wipe_data() {
+    let _ = env_logger::builder().is_test(true).try_init();
+    for wipe_data in [false, true] {
+        let storage_vec: Vec<u8> = vec![0xD1_u8; MB as usize];
+        let storage_cur = io::Cursor::new(storage_vec);
+        let mut buffered_stream = embedded_io_adapters::tokio_1::FromTokio::new(tokio::io::BufStream::new(storage_cur));
+        let opts = embedded_fatfs::FormatVolumeOptions::new().wipe_data(wipe_data);
+        embedded_fatfs::format_volume(&mut buffered_stream, opts)
+            .await
+            .expect("format volume");
+        let storage_vec = buffered_stream.into_inner().into_inner().into_inner();
+        // last sector belongs to the data region
+        let last_sector = &storage_vec[storage_vec.len() - 512..];
+        let expected = if wipe_data { 0 } else { 0xD1 };
+        assert!(last_sector.iter().all(|&b| b == expected));
+    }
+}
+
+#[tokio::test]
+async fn test_format_fat_type_mismatch() {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let storage_cur = io::Cursor::new(vec![0_u8; (64 * MB) as usize]);
+    let mut buffered_stream = embedded_io_adapters::tokio_1::FromTokio::new(tokio::io::BufStream::new(storage_cur));
+    // small clusters make too many of them for FAT12
+    let opts = embedded_fatfs::FormatVolumeOptions::new()
+        .fat_type(embedded_fatfs::FatType::Fat12)
+        .bytes_per_cluster(512);
+    let result = embedded_fatfs::format_volume(&mut buffered_stream, opts).await;
+    // 32 KB clusters are small enough for FAT12
+    assert!(matches!(
+        result,
+        Err(embedded_fatfs::Error::FatTypeMismatch {
+            fat_type: embedded_fatfs::FatType::Fat12,
+            suggested_cluster_size: Some(32768),
+            ..
+        })
+    ));
+}
+
+#[tokio::test]
+async fn test_format_sectors_per_cluster() {
+    let opts = embedded_fatfs::FormatVolumeOptions::new().sectors_per_cluster(4);
+    let fs = test_format_fs(opts, 8 * MB).await;
+    assert_eq!(fs.cluster_size(), 4 * 512);
+    // matching values of both options are accepted
+    let opts = embedded_fatfs::FormatVolumeOptions::new()
+        .bytes_per_sector(4096)
+        .bytes_per_cluster(8192)
+        .sectors_per_cluster(2);
+    let fs = test_format_fs(opts, 64 * MB).await;
+    assert_eq!(fs.cluster_size(), 8192);
+
+    let format = |opts: embedded_fatfs::FormatVolumeOptions| async move {
+        let storage_cur = io::Cursor::new(vec![0_u8; (64 * MB) as usize]);
+        let mut buffered_stream = embedded_io_adapters::tokio_1::FromTokio::new(tokio::io::BufStream::new(storage_cur));
+        embedded_fatfs::format_volume(&mut buffered_stream, opts).await
+    };
+    // bytes_per_cluster disagrees with sectors_per_cluster
+    let opts = embedded_fatfs::FormatVolumeOptions::new()
+        .bytes_per_cluster(4096)
+        .sectors_per_cluster(4);
+    assert!(matches!(format(opts).await, Err(embedded_fatfs::Error::InvalidInput)));
+    // single sector clusters make too many of them for FAT12
+    let opts = embedded_fatfs::FormatVolumeOptions::new()
+        .fat_type(embedded_fatfs::FatType::Fat12)
+        .sectors_per_cluster(1);
+    assert!(matches!(
+        format(opts).await,
+        Err(embedded_fatfs::Error::FatTypeMismatch { .. })
+    ));
+    // and too few for FAT32
+    let opts = embedded_fatfs::FormatVolumeOptions::new()
+        .fat_type(embedded_fatfs::FatType::Fat32)
+        .sectors_per_cluster(64);
+    assert!(matches!(
+        format(opts).await,
+        Err(embedded_fatfs::Error::FatTypeMismatch {
+            suggested_cluster_size: Some(_),
+            ..
+        })
+    ));
+}
+
+#[tokio::test]
+#[should_panic(expected = "Invalid sectors_per_cluster")]
+async fn test_format_sectors_per_cluster_not_power_of_two() {
+    let _ = embedded_fatfs::FormatVolumeOptions::new().sectors_per_cluster(3);
+}
+
+#[tokio::test]
+async fn test_mount_small_fat() {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let storage_cur = io::Cursor::new(vec![0_u8; (8 * MB) as usize]);
+    let mut buffered_stream = embedded_io_adapters::tokio_1::FromTokio::new(tokio::io::BufStream::new(storage_cur));
+    embedded_fatfs::format_volume(&mut buffered_stream, embedded_fatfs::FormatVolumeOptions::new())
+        .await
+        .expect("format volume");
+    let mut storage_vec = buffered_stream.into_inner().into_inner().into_inner();
+    // shrink the FAT to a single sector (sectors_per_fat_16 field of the BPB)
+    storage_vec[22..24].copy_from_slice(&1_u16.to_le_bytes());
+
+    let stream =
+        embedded_io_adapters::tokio_1::FromTokio::new(tokio::io::BufStream::new(io::Cursor::new(storage_vec.clone())));
+    let result = embedded_fatfs::FileSystem::new(stream, embedded_fatfs::FsOptions::new()).await;
+    assert!(matches!(result, Err(embedded_fatfs::Error::CorruptedFileSystem)));
+
+    let stream = embedded_io_adapters::tokio_1::FromTokio::new(tokio::io::BufStream::new(io::Cursor::new(storage_vec)));
+    let options = embedded_fatfs::FsOptions::new().allow_small_fat(true).read_only(true);
+    let fs = embedded_fatfs::FileSystem::new(stream, options).await.expect("open fs");
+    fs.unmount().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_format_total_bytes() {
+    let _ = env_logger::builder().is_test(true).try_init();
+    // format only the first 4 MB of a bigger storage
+    let storage_cur = io::Cursor::new(vec![0_u8; (8 * MB) as usize]);
+    let mut buffered_stream = embedded_io_adapters::tokio_1::FromTokio::new(tokio::io::BufStream::new(storage_cur));
+    let opts = embedded_fatfs::FormatVolumeOptions::new()
+        .bytes_per_sector(4096)
+        .total_bytes(4 * MB);
+    embedded_fatfs::format_volume(&mut buffered_stream, opts)
+        .await
+        .expect("format volume");
+    let fs = embedded_fatfs::FileSystem::new(buffered_stream, embedded_fatfs::FsOptions::new())
+        .await
+        .expect("open fs");
+    let total_bytes = u64::from(fs.stats().await.unwrap().total_clusters()) * u64::from(fs.cluster_size());
+    assert!(total_bytes <= 4 * MB && total_bytes > 3 * MB);
+    basic_fs_test(&fs).await;
+
+    // size must be a multiple of sector size
+    let storage_cur = io::Cursor::new(vec![0_u8; MB as usize]);
+    let mut buffered_stream = embedded_io_adapters::tokio_1::FromTokio::new(tokio::io::BufStream::new(storage_cur));
+    let opts = embedded_fatfs::FormatVolumeOptions::new().total_bytes(MB - 100);
+    let result = embedded_fatfs::format_volume(&mut buffered_stream, opts).await;
+    assert!(matches!(result, Err(embedded_fatfs::Error::InvalidInput)));
+}
+
+#[tokio::test]
+async fn test_format_fat32_reserved_sectors() {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let storage_cur = io::Cursor::new(vec![0_u8; (64 * MB) as usize]);
+    let mut buffered_stream = embedded_io_adapters::tokio_1::FromTokio::new(tokio::io::BufStream::new(storage_cur));
+    let opts = embedded_fatfs::FormatVolumeOptions::new()
+        .fat_type(embedded_fatfs::FatType::Fat32)
+        .bytes_per_cluster(512)
+        .reserved_sectors(32);
+    embedded_fatfs::format_volume(&mut buffered_stream, opts)
+        .await
+        .expect("format volume");
+    let storage_vec = buffered_stream.into_inner().into_inner().into_inner();
+    assert_eq!(u16::from_le_bytes([storage_vec[14], storage_vec[15]]), 32);
+    // backup boot sector is at the conventional location
+    assert_eq!(storage_vec[..512], storage_vec[6 * 512..7 * 512]);
+    let storage_cur = io::Cursor::new(storage_vec);
+    let buffered_stream = embedded_io_adapters::tokio_1::FromTokio::new(tokio::io::BufStream::new(storage_cur));
+    let fs = embedded_fatfs::FileSystem::new(buffered_stream, embedded_fatfs::FsOptions::new())
+        .await
+        .expect("open fs");
+    assert_eq!(fs.fat_type(), embedded_fatfs::FatType::Fat32);
+    basic_fs_test(&fs).await;
+
+    // FSInfo and backup boot sector must fit in reserved sectors
+    let storage_cur = io::Cursor::new(vec![0_u8; (64 * MB) as usize]);
+    let mut buffered_stream = embedded_io_adapters::tokio_1::FromTokio::new(tokio::io::BufStream::new(storage_cur));
+    let opts = embedded_fatfs::FormatVolumeOptions::new()
+        .fat_type(embedded_fatfs::FatType::Fat32)
+        .bytes_per_cluster(512)
+        .reserved_sectors(4);
+    let result = embedded_fatfs::format_volume(&mut buffered_stream, opts).await;
+    assert!(matches!(result, Err(embedded_fatfs::Error::InvalidInput)));
+}
+
+#[tokio::test]
+async fn test_format_plan() {
+    let fat32_opts = embedded_fatfs::FormatVolumeOptions::new()
+        .fat_type(embedded_fatfs::FatType::Fat32)
+        .bytes_per_cluster(512)
+        .hidden_sectors(63);
+    for (opts, total_bytes) in [
+        (embedded_fatfs::FormatVolumeOptions::new(), MB),
+        (embedded_fatfs::FormatVolumeOptions::new(), 50 * MB),
+        (fat32_opts, 64 * MB),
+    ] {
+        let plan = opts.clone().total_bytes(total_bytes).plan().unwrap();
+        let fs = test_format_fs(opts, total_bytes).await;
+        assert_eq!(plan.fat_type(), fs.fat_type());
+        assert_eq!(plan.cluster_size(), fs.cluster_size());
+        assert_eq!(plan.total_clusters(), fs.total_clusters());
+        assert_eq!(plan.bytes_per_sector(), fs.bytes_per_sector());
+        assert_eq!(plan.hidden_sectors(), fs.hidden_sectors());
+        assert_eq!(plan.volume_id(), fs.volume_id());
+        assert_eq!(plan.backup_boot_sector(), fs.backup_boot_sector());
+        assert_eq!(plan.total_sectors(), (total_bytes / 512) as u32);
+        assert_eq!(
+            u64::from(plan.cluster_size()),
+            u64::from(plan.sectors_per_cluster()) * u64::from(plan.bytes_per_sector())
+        );
+        assert_eq!(plan.data_region_bytes(), fs.stats().await.unwrap().total_bytes());
+        // the regions of the volume follow each other
+        let fats_end = u32::from(plan.reserved_sectors()) + u32::from(plan.fats()) * plan.sectors_per_fat();
+        assert_eq!(plan.first_data_sector(), fats_end + plan.root_dir_sectors());
+        assert!(u64::from(plan.first_data_sector()) * 512 + plan.data_region_bytes() <= total_bytes);
+    }
+
+    // the size of the volume is required
+    let result = embedded_fatfs::FormatVolumeOptions::new().plan();
+    assert!(matches!(result, Err(embedded_fatfs::Error::InvalidInput)));
+    // options that make formatting fail are rejected
+    let opts = embedded_fatfs::FormatVolumeOptions::new()
+        .fat_type(embedded_fatfs::FatType::Fat32)
+        .total_bytes(MB);
+    assert!(matches!(
+        opts.plan(),
+        Err(embedded_fatfs::Error::FatTypeMismatch {
+            suggested_cluster_size: None,
+            ..
+        })
+    ));
+}
+
+#[tokio::test]
+async fn test_format_floppy() {
+    // total size, sectors per cluster, root directory entries, media, sectors per FAT and sectors per track
+    for (total_bytes, sectors_per_cluster, root_dir_entries, media, sectors_per_fat, sectors_per_track) in [
+        (360 * KB, 2, 112, 0xFD, 2, 9),
+        (720 * KB, 2, 112, 0xF9, 3, 9),
+        (1200 * KB, 1, 224, 0xF9, 7, 15),
+        (1440 * KB, 1, 224, 0xF0, 9, 18),
+        (2880 * KB, 2, 240, 0xF0, 9, 36),
+    ] {
+        let opts = embedded_fatfs::FormatVolumeOptions::new();
+        let plan = opts.clone().total_bytes(total_bytes).plan().unwrap();
+        assert_eq!(plan.fat_type(), embedded_fatfs::FatType::Fat12);
+        assert_eq!(plan.bytes_per_sector(), 512);
+        assert_eq!(plan.sectors_per_cluster(), sectors_per_cluster);
+        assert_eq!(plan.reserved_sectors(), 1);
+        assert_eq!(plan.fats(), 2);
+        assert_eq!(plan.root_dir_entries(), root_dir_entries);
+        assert_eq!(plan.media(), media);
+        assert_eq!(plan.sectors_per_fat(), sectors_per_fat);
+        assert_eq!(plan.sectors_per_track(), sectors_per_track);
+        assert_eq!(plan.heads(), 2);
+        let fs = test_format_fs(opts, total_bytes).await;
+        assert_eq!(fs.fat_type(), embedded_fatfs::FatType::Fat12);
+        assert_eq!(fs.total_clusters(), plan.total_clusters());
+        assert_eq!(fs.sectors_per_track(), sectors_per_track);
+        assert_eq!(fs.heads(), 2);
+    }
+
+    // values set in the options are kept
+    let plan = embedded_fatfs::FormatVolumeOptions::new()
+        .total_bytes(1440 * KB)
+        .media(0xF8)
+        .max_root_dir_entries(512)
+        .heads(0x40)
+        .plan()
+        .unwrap();
+    assert_eq!(plan.media(), 0xF8);
+    assert_eq!(plan.root_dir_entries(), 512);
+    assert_eq!(plan.heads(), 0x40);
+    assert_eq!(plan.sectors_per_track(), 18);
+    // other sizes are not affected
+    let plan = embedded_fatfs::FormatVolumeOptions::new()
+        .total_bytes(MB)
+        .plan()
+        .unwrap();
+    assert_eq!(plan.media(), 0xF8);
+    assert_eq!(plan.root_dir_entries(), 512);
+}
+
+#[tokio::test]
+async fn test_format_boot_code() {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let fat32_opts = embedded_fatfs::FormatVolumeOptions::new()
+        .fat_type(embedded_fatfs::FatType::Fat32)
+        .bytes_per_cluster(512);
+    for (opts, total_bytes, offset, max_len) in [
+        (embedded_fatfs::FormatVolumeOptions::new(), MB, 0x3E, 448),
+        (fat32_opts, 64 * MB, 0x5A, 420),
+    ] {
+        let boot_code: Vec<u8> = (0..max_len).map(|i| (i % 251) as u8 + 1).collect();
+        let storage_cur = io::Cursor::new(vec![0xD1_u8; total_bytes as usize]);
+        let mut buffered_stream = embedded_io_adapters::tokio_1::FromTokio::new(tokio::io::BufStream::new(storage_cur));
+        embedded_fatfs::format_volume(&mut buffered_stream, opts.clone().boot_code(&boot_code))
+            .await
+            .expect("format volume");
+        let storage_vec = buffered_stream.into_inner().into_inner().into_inner();
+        // the default jump leads to the boot code placed after the BPB
+        assert_eq!(storage_vec[..3], [0xEB, offset as u8 - 2, 0x90]);
+        assert_eq!(storage_vec[offset..offset + max_len], boot_code);
+        assert_eq!(storage_vec[510..512], [0x55, 0xAA]);
+        let storage_cur = io::Cursor::new(storage_vec);
+        let buffered_stream = embedded_io_adapters::tokio_1::FromTokio::new(tokio::io::BufStream::new(storage_cur));
+        let fs = embedded_fatfs::FileSystem::new(buffered_stream, embedded_fatfs::FsOptions::new())
+            .await
+            .expect("open fs");
+        basic_fs_test(&fs).await;
+        drop(fs);
+
+        // a short code is padded with zeros and the jump can be replaced
+        let storage_cur = io::Cursor::new(vec![0xD1_u8; total_bytes as usize]);
+        let mut buffered_stream = embedded_io_adapters::tokio_1::FromTokio::new(tokio::io::BufStream::new(storage_cur));
+        let custom_opts = opts.clone().boot_code(&[0xF4; 2]).bootjmp([0xE9, 0x10, 0x00]);
+        embedded_fatfs::format_volume(&mut buffered_stream, custom_opts)
+            .await
+            .expect("format volume");
+        let storage_vec = buffered_stream.into_inner().into_inner().into_inner();
+        assert_eq!(storage_vec[..3], [0xE9, 0x10, 0x00]);
+        assert_eq!(storage_vec[offset..offset + 2], [0xF4; 2]);
+        assert!(storage_vec[offset + 2..510].iter().all(|&b| b == 0));
+
+        // FAT32 leaves less space for the boot code
+        let storage_cur = io::Cursor::new(vec![0_u8; total_bytes as usize]);
+        let mut buffered_stream = embedded_io_adapters::tokio_1::FromTokio::new(tokio::io::BufStream::new(storage_cur));
+        let result = embedded_fatfs::format_volume(&mut buffered_stream, opts.boot_code(&[0; 448])).await;
+        assert_eq!(result.is_err(), max_len < 448);
+    }
+}
+
+#[tokio::test]
+#[should_panic(expected = "Invalid boot_code")]
+async fn test_format_boot_code_too_long() {
+    let _ = embedded_fatfs::FormatVolumeOptions::new().boot_code(&[0; 449]);
+}
+
+#[tokio::test]
+#[should_panic(expected = "Invalid bootjmp")]
+async fn test_format_invalid_bootjmp() {
+    let _ = embedded_fatfs::FormatVolumeOptions::new().bootjmp([0x90; 3]);
+}
+
+#[tokio::test]
+async fn test_format_single_fat() {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let fat32_opts = embedded_fatfs::FormatVolumeOptions::new()
+        .fat_type(embedded_fatfs::FatType::Fat32)
+        .bytes_per_cluster(512);
+    for (opts, total_bytes) in [(embedded_fatfs::FormatVolumeOptions::new(), MB), (fat32_opts, 64 * MB)] {
+        let storage_cur = io::Cursor::new(vec![0xD1_u8; total_bytes as usize]);
+        let mut buffered_stream = embedded_io_adapters::tokio_1::FromTokio::new(tokio::io::BufStream::new(storage_cur));
+        embedded_fatfs::format_volume(&mut buffered_stream, opts.fats(1))
+            .await
+            .expect("format volume");
+        let storage_vec = buffered_stream.into_inner().into_inner().into_inner();
+        assert_eq!(storage_vec[16], 1);
+
+        // remount the formatted volume
+        let storage_cur = io::Cursor::new(storage_vec);
+        let buffered_stream = embedded_io_adapters::tokio_1::FromTokio::new(tokio::io::BufStream::new(storage_cur));
+        let fs = embedded_fatfs::FileSystem::new(buffered_stream, embedded_fatfs::FsOptions::new())
+            .await
+            .expect("open fs");
+        basic_fs_test(&fs).await;
+        assert_eq!(fs.check_fats().await.unwrap(), None);
+    }
+}
+
+#[tokio::test]
+async fn test_format_4096sec_round_trip() {
+    let _ = env_logger::builder().is_test(true).try_init();
+    for (fat_type, total_bytes) in [
+        (embedded_fatfs::FatType::Fat12, 4 * MB),
+        (embedded_fatfs::FatType::Fat16, 64 * MB),
+        (embedded_fatfs::FatType::Fat32, 300 * MB),
+    ] {
+        let storage_cur = io::Cursor::new(vec![0xD1_u8; total_bytes as usize]);
+        let mut buffered_stream = embedded_io_adapters::tokio_1::FromTokio::new(tokio::io::BufStream::new(storage_cur));
+        let opts = embedded_fatfs::FormatVolumeOptions::new()
+            .bytes_per_sector(4096)
+            .bytes_per_cluster(4096)
+            .fat_type(fat_type);
+        embedded_fatfs::format_volume(&mut buffered_stream, opts)
+            .await
+            .expect("format volume");
+        let data = (0..5 * 4096 + 1000).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+        {
+            let fs = embedded_fatfs::FileSystem::new(&mut buffered_stream, embedded_fatfs::FsOptions::new())
+                .await
+                .expect("open fs");
+            assert_eq!(fs.fat_type(), fat_type);
+            assert_eq!(fs.bytes_per_sector(), 4096);
+            let dir = fs.root_dir().create_dir("dir").await.unwrap();
+            let mut file = dir.create_file("data.bin").await.unwrap();
+            // mix small buffered writes with big ones
+            let (head, tail) = data.split_at(100);
+            for chunk in head.chunks(7) {
+                file.write_all(chunk).await.unwrap();
+            }
+            file.write_all(tail).await.unwrap();
+            file.flush().await.unwrap();
+            drop(file);
+            drop(dir);
+            fs.unmount().await.unwrap();
+        }
+        buffered_stream
+            .seek(embedded_io_async::SeekFrom::Start(0))
+            .await
+            .unwrap();
+        let fs = embedded_fatfs::FileSystem::new(&mut buffered_stream, embedded_fatfs::FsOptions::new())
+            .await
+            .expect("open fs");
+        let mut file = fs.root_dir().open_file("dir/data.bin").await.unwrap();
+        assert_eq!(read_to_end(&mut file).await.unwrap(), data);
+        assert_eq!(fs.clusters_for_path("dir/data.bin").await.unwrap().len(), 6);
+        assert_eq!(fs.check_fats().await.unwrap(), None);
+        assert_eq!(fs.check().await.unwrap(), None);
+        let free_space = fs.free_space().await.unwrap();
+        assert_eq!(free_space.free_clusters(), fs.stats().await.unwrap().free_clusters());
+    }
+}
+
+#[tokio::test]
+async fn test_format_fat12_round_trip() {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let storage_cur = io::Cursor::new(vec![0xD1_u8; (2 * MB) as usize]);
+    let mut buffered_stream = embedded_io_adapters::tokio_1::FromTokio::new(tokio::io::BufStream::new(storage_cur));
+    let opts = embedded_fatfs::FormatVolumeOptions::new()
+        .bytes_per_cluster(1024)
+        .fats(1);
+    let fat_type = embedded_fatfs::format_volume(&mut buffered_stream, opts)
+        .await
+        .expect("format volume");
+    assert_eq!(fat_type, embedded_fatfs::FatType::Fat12);
+
+    // Interleaved writes make neighbouring FAT12 entries (sharing a byte) belong to different files
+    let files = 8;
+    let chunk = 1024;
+    let chunks_per_file = 100;
+    let file_data = |i: usize| {
+        (0..chunk * chunks_per_file)
+            .map(|j| (j % 253 + i) as u8)
+            .collect::<Vec<u8>>()
+    };
+    {
+        let fs = embedded_fatfs::FileSystem::new(&mut buffered_stream, embedded_fatfs::FsOptions::new())
+            .await
+            .expect("open fs");
+        let root_dir = fs.root_dir();
+        let mut handles = Vec::new();
+        for i in 0..files {
+            handles.push(root_dir.create_file(&format!("file{}.bin", i)).await.unwrap());
+        }
+        for c in 0..chunks_per_file {
+            for (i, file) in handles.iter_mut().enumerate() {
+                file.write_all(&file_data(i)[c * chunk..(c + 1) * chunk]).await.unwrap();
+            }
+        }
+        for mut file in handles {
+            file.flush().await.unwrap();
+        }
+        drop(root_dir);
+        fs.unmount().await.unwrap();
+    }
+    buffered_stream
+        .seek(embedded_io_async::SeekFrom::Start(0))
+        .await
+        .unwrap();
+    let fs = embedded_fatfs::FileSystem::new(&mut buffered_stream, embedded_fatfs::FsOptions::new())
+        .await
+        .expect("open fs");
+
+    let mut next_cluster = std::collections::HashMap::new();
+    let mut entries = fs.fat_entries();
+    while let Some(entry) = entries.next().await {
+        let (cluster, value) = entry.unwrap();
+        next_cluster.insert(cluster, value);
+    }
+
+    let mut used_clusters = Vec::new();
+    for i in 0..files {
+        let name = format!("file{}.bin", i);
+        let mut file = fs.root_dir().open_file(&name).await.unwrap();
+        assert_eq!(read_to_end(&mut file).await.unwrap(), file_data(i));
+        let clusters = fs.clusters_for_path(&name).await.unwrap();
+        assert_eq!(clusters.len(), chunks_per_file);
+        for pair in clusters.windows(2) {
+            assert_eq!(next_cluster[&pair[0]], embedded_fatfs::FatValue::Data(pair[1]));
+        }
+        assert_eq!(
+            next_cluster[clusters.last().unwrap()],
+            embedded_fatfs::FatValue::EndOfChain
+        );
+        used_clusters.extend(clusters);
+    }
+    // entries of clusters 341 and 682 straddle the boundaries of the first and second FAT sectors
+    assert!(used_clusters.contains(&341));
+    assert!(used_clusters.contains(&682));
+    assert_eq!(fs.check_fats().await.unwrap(), None);
+    assert_eq!(fs.check().await.unwrap(), None);
+    fs.unmount().await.unwrap();
+
+    // decode the straddling entries from the raw bytes (the FAT starts in sector 1)
+    let storage_vec = buffered_stream.into_inner().into_inner().into_inner();
+    let fat = &storage_vec[512..];
+    let odd = u32::from(fat[511] >> 4) | (u32::from(fat[512]) << 4);
+    let even = u32::from(fat[1023]) | (u32::from(fat[1024] & 0x0F) << 8);
+    assert_eq!(next_cluster[&341], embedded_fatfs::FatValue::Data(odd));
+    assert_eq!(next_cluster[&682], embedded_fatfs::FatValue::Data(even));
+}
+
+async fn check_root_dir_is_empty(total_bytes: u64, fat_type: embedded_fatfs::FatType) {
+    let storage = io::Cursor::new(vec![0_u8; total_bytes as usize]);
+    let mut storage = embedded_io_adapters::tokio_1::FromTokio::new(tokio::io::BufStream::new(storage));
+    let opts = embedded_fatfs::FormatVolumeOptions::new().volume_label(*b"VOLUMELABEL");
+    embedded_fatfs::format_volume(&mut storage, opts).await.unwrap();
+    let fs = embedded_fatfs::FileSystem::new(storage, embedded_fatfs::FsOptions::new())
+        .await
+        .unwrap();
+    assert_eq!(fs.fat_type(), fat_type);
+    let root_dir = fs.root_dir();
+    // the volume label entry is not a child
+    assert!(root_dir.is_empty().await.unwrap());
+    let dir = root_dir.create_dir("subdir").await.unwrap();
+    assert!(!root_dir.is_empty().await.unwrap());
+    assert!(dir.is_empty().await.unwrap());
+    drop(dir.create_file("file.txt").await.unwrap());
+    assert!(!dir.is_empty().await.unwrap());
+    dir.remove("file.txt").await.unwrap();
+    assert!(dir.is_empty().await.unwrap());
+    root_dir.remove("subdir").await.unwrap();
+    assert!(root_dir.is_empty().await.unwrap());
+}
+
+#[tokio::test]
+async fn test_format_root_dir_is_empty() {
+    let _ = env_logger::builder().is_test(true).try_init();
+    check_root_dir_is_empty(MB, embedded_fatfs::FatType::Fat12).await;
+    check_root_dir_is_empty(8 * MB, embedded_fatfs::FatType::Fat16).await;
+    check_root_dir_is_empty(2 * 1024 * MB, embedded_fatfs::FatType::Fat32).await;
+}
+
+// Creates a whole-disk image with a 4 MB FAT16 volume placed at 1 MB and no partition table
+async fn new_disk_image() -> Vec<u8> {
+    let storage_cur = io::Cursor::new(vec![0_u8; (4 * MB) as usize]);
+    let mut buffered_stream = embedded_io_adapters::tokio_1::FromTokio::new(tokio::io::BufStream::new(storage_cur));
+    embedded_fatfs::format_volume(&mut buffered_stream, embedded_fatfs::FormatVolumeOptions::new())
+        .await
+        .expect("format volume");
+    let mut disk = vec![0_u8; MB as usize];
+    disk.extend(buffered_stream.into_inner().into_inner().into_inner());
+    disk
+}
+
+fn set_mbr_entry(disk: &mut [u8], index: usize, code: u8, first_sector: u32, sectors: u32) {
+    let entry = &mut disk[446 + index * 16..][..16];
+    entry[4] = code;
+    entry[8..12].copy_from_slice(&first_sector.to_le_bytes());
+    entry[12..16].copy_from_slice(&sectors.to_le_bytes());
+    disk[510..512].copy_from_slice(&[0x55, 0xAA]);
+}
+
+async fn mount_partition(disk: Vec<u8>) -> FileSystem {
+    let mut stream = embedded_io_adapters::tokio_1::FromTokio::new(tokio::io::BufStream::new(io::Cursor::new(disk)));
+    let partition = embedded_fatfs::find_fat_partition(&mut stream).await.unwrap();
+    assert_eq!(partition.offset(), MB);
+    assert_eq!(partition.len(), 4 * MB);
+    let options = embedded_fatfs::FsOptions::new().partition_offset(partition.offset());
+    embedded_fatfs::FileSystem::new(stream, options).await.expect("open fs")
+}
+
+#[tokio::test]
+async fn test_mbr_partitions() {
+    use embedded_fatfs::{Error, PartitionType};
+    let _ = env_logger::builder().is_test(true).try_init();
+    let mut disk = new_disk_image().await;
+    set_mbr_entry(&mut disk, 0, 0x05, 100, 10);
+    set_mbr_entry(&mut disk, 2, 0x0E, 2048, 8192);
+
+    let mut stream =
+        embedded_io_adapters::tokio_1::FromTokio::new(tokio::io::BufStream::new(io::Cursor::new(disk.clone())));
+    let mut partitions = embedded_fatfs::read_partitions(&mut stream).await.unwrap();
+    let mut found = Vec::new();
+    while let Some(r) = partitions.next().await {
+        let p = r.unwrap();
+        found.push((p.index(), p.partition_type(), p.is_extended(), p.is_fat()));
+    }
+    assert_eq!(
+        found,
+        [
+            (0, PartitionType::Mbr(0x05), true, false),
+            (2, PartitionType::Mbr(0x0E), false, true)
+        ]
+    );
+
+    let fs = mount_partition(disk.clone()).await;
+    basic_fs_test(&fs).await;
+    fs.unmount().await.unwrap();
+
+    // only Linux partitions
+    let mut linux_disk = disk.clone();
+    set_mbr_entry(&mut linux_disk, 2, 0x83, 2048, 8192);
+    let mut stream =
+        embedded_io_adapters::tokio_1::FromTokio::new(tokio::io::BufStream::new(io::Cursor::new(linux_disk)));
+    assert!(matches!(
+        embedded_fatfs::find_fat_partition(&mut stream).await,
+        Err(Error::NotFound)
+    ));
+    // no partition table
+    let volume = disk[MB as usize..].to_vec();
+    let mut stream = embedded_io_adapters::tokio_1::FromTokio::new(tokio::io::BufStream::new(io::Cursor::new(volume)));
+    assert!(embedded_fatfs::find_fat_partition(&mut stream).await.is_err());
+    disk[510] = 0;
+    let mut stream = embedded_io_adapters::tokio_1::FromTokio::new(tokio::io::BufStream::new(io::Cursor::new(disk)));
+    assert!(matches!(
+        embedded_fatfs::find_fat_partition(&mut stream).await,
+        Err(Error::CorruptedFileSystem)
+    ));
+}
+
+#[tokio::test]
+async fn test_gpt_partitions() {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let mut disk = new_disk_image().await;
+    set_mbr_entry(&mut disk, 0, 0xEE, 1, u32::MAX);
+    let header = &mut disk[512..1024];
+    header[..8].copy_from_slice(b"EFI PART");
+    header[72..80].copy_from_slice(&2_u64.to_le_bytes());
+    header[80..84].copy_from_slice(&4_u32.to_le_bytes());
+    header[84..88].copy_from_slice(&128_u32.to_le_bytes());
+    // Linux filesystem data (0FC63DAF-8483-4772-8E79-3D69D8477DE4) followed by an EFI System partition
+    let linux = [
+        0xAF, 0x3D, 0xC6, 0x0F, 0x83, 0x84, 0x72, 0x47, 0x8E, 0x79, 0x3D, 0x69, 0xD8, 0x47, 0x7D, 0xE4,
+    ];
+    let efi_system = [
+        0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11, 0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B,
+    ];
+    for (i, (guid, first, last)) in [(linux, 34_u64, 99_u64), (efi_system, 2048, 2048 + 8191)]
+        .into_iter()
+        .enumerate()
+    {
+        let entry = &mut disk[1024 + i * 128..][..128];
+        entry[..16].copy_from_slice(&guid);
+        entry[32..40].copy_from_slice(&first.to_le_bytes());
+        entry[40..48].copy_from_slice(&last.to_le_bytes());
+    }
+
+    let fs = mount_partition(disk.clone()).await;
+    basic_fs_test(&fs).await;
+    fs.unmount().await.unwrap();
+
+    // a partition entry array ending past the addressable range
+    let mut hostile_disk = disk.clone();
+    hostile_disk[512 + 72..512 + 80].copy_from_slice(&(u64::MAX / 512).to_le_bytes());
+    let mut stream =
+        embedded_io_adapters::tokio_1::FromTokio::new(tokio::io::BufStream::new(io::Cursor::new(hostile_disk)));
+    assert!(matches!(
+        embedded_fatfs::find_fat_partition(&mut stream).await,
+        Err(embedded_fatfs::Error::CorruptedFileSystem)
+    ));
+
+    disk[512] = 0;
+    let mut stream = embedded_io_adapters::tokio_1::FromTokio::new(tokio::io::BufStream::new(io::Cursor::new(disk)));
+    assert!(matches!(
+        embedded_fatfs::find_fat_partition(&mut stream).await,
+        Err(embedded_fatfs::Error::CorruptedFileSystem)
+    ));
+}
+
 async fn read_to_end<IO: embedded_io_async::Read>(io: &mut IO) -> Result<Vec<u8>, IO::Error> {
     let mut buf = Vec::new();
     loop {
@@ -1,7 +1,7 @@
 use std::str;
 
-use embedded_fatfs::{ChronoTimeProvider, FatType, FsOptions, LossyOemCpConverter};
-use embedded_io_async::{Read, Seek, SeekFrom};
+use embedded_fatfs::{ChronoTimeProvider, Date, DateTime, FatType, FsOptions, LossyOemCpConverter, Time};
+use embedded_io_async::{Read, Seek, SeekFrom, Write};
 
 const TEST_TEXT: &str = "Rust is cool!\n";
 const FAT12_IMG: &str = "resources/fat12.img";
@@ -14,12 +14,37 @@ type FileSystem = embedded_fatfs::FileSystem<
     LossyOemCpConverter,
 >;
 
+type DirIter<'a> = embedded_fatfs::DirIter<
+    'a,
+    embedded_io_adapters::tokio_1::FromTokio<tokio::fs::File>,
+    ChronoTimeProvider,
+    LossyOemCpConverter,
+>;
+
 async fn create_fs(name: &str) -> FileSystem {
     let _ = env_logger::builder().is_test(true).try_init();
     let file = tokio::fs::File::open(name).await.unwrap();
     embedded_fatfs::FileSystem::new(file, FsOptions::new()).await.unwrap()
 }
 
+#[tokio::test]
+async fn test_mount_options_builder() {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let file = tokio::fs::File::open(FAT16_IMG).await.unwrap();
+    let fs = FileSystem::new_with_defaults(file).await.unwrap();
+    assert_eq!(fs.fat_type(), FatType::Fat16);
+
+    let file = tokio::fs::File::open(FAT16_IMG).await.unwrap();
+    let options = FsOptions::new()
+        .oem_cp_converter(embedded_fatfs::Cp850OemCpConverter::new())
+        .time_provider(embedded_fatfs::NullTimeProvider::new())
+        .update_accessed_date(false);
+    let fs = embedded_fatfs::FileSystem::new(file, options).await.unwrap();
+    let mut file = fs.root_dir().open_file("short.txt").await.unwrap();
+    let buf = read_to_end(&mut file).await.unwrap();
+    assert_eq!(str::from_utf8(&buf).unwrap(), TEST_TEXT);
+}
+
 async fn test_root_dir(fs: FileSystem) {
     let root_dir = fs.root_dir();
     let entries = root_dir.iter().collect().await;
@@ -54,6 +79,43 @@ async fn test_root_dir_fat32() {
     test_root_dir(create_fs(FAT32_IMG).await).await
 }
 
+async fn entry_names(mut iter: DirIter<'_>) -> Vec<String> {
+    let mut names = Vec::new();
+    while let Some(r) = iter.next().await {
+        names.push(r.unwrap().file_name());
+    }
+    names
+}
+
+async fn test_files_and_dirs(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    assert_eq!(entry_names(root_dir.files()).await, ["long.txt", "short.txt"]);
+    assert_eq!(entry_names(root_dir.dirs()).await, ["very", "very-long-dir-name"]);
+    // `.` and `..` are skipped
+    let dir = root_dir.open_dir("very").await.unwrap();
+    assert_eq!(entry_names(dir.files()).await, Vec::<String>::new());
+    assert_eq!(entry_names(dir.dirs()).await, ["long"]);
+    let dir = root_dir.open_dir("very/long/path").await.unwrap();
+    assert_eq!(entry_names(dir.files()).await, ["test.txt"]);
+    assert_eq!(entry_names(dir.dirs()).await, Vec::<String>::new());
+    assert_eq!(entry_names(dir.iter()).await, [".", "..", "test.txt"]);
+}
+
+#[tokio::test]
+async fn test_files_and_dirs_fat12() {
+    test_files_and_dirs(create_fs(FAT12_IMG).await).await
+}
+
+#[tokio::test]
+async fn test_files_and_dirs_fat16() {
+    test_files_and_dirs(create_fs(FAT16_IMG).await).await
+}
+
+#[tokio::test]
+async fn test_files_and_dirs_fat32() {
+    test_files_and_dirs(create_fs(FAT32_IMG).await).await
+}
+
 async fn test_read_seek_short_file(fs: FileSystem) {
     let root_dir = fs.root_dir();
     let mut short_file = root_dir.open_file("short.txt").await.unwrap();
@@ -151,6 +213,47 @@ async fn test_read_long_file_fat32() {
     test_read_long_file(create_fs(FAT32_IMG).await).await
 }
 
+async fn test_read_long_file_multi_cluster(fs: FileSystem) {
+    let expected = TEST_TEXT.repeat(1000);
+    let root_dir = fs.root_dir();
+    let mut long_file = root_dir.open_file("long.txt").await.unwrap();
+    // start reading in the middle of a cluster so the read spans multiple clusters
+    long_file.seek(SeekFrom::Start(100)).await.unwrap();
+    let mut buf = vec![0; expected.len()];
+    let mut pos = 0;
+    loop {
+        let n = long_file.read(&mut buf[pos..]).await.unwrap();
+        if n == 0 {
+            break;
+        }
+        pos += n;
+    }
+    assert_eq!(str::from_utf8(&buf[..pos]).unwrap(), &expected[100..]);
+    // position is still tracked correctly after a large read
+    assert_eq!(
+        long_file.seek(SeekFrom::Current(-10)).await.unwrap(),
+        expected.len() as u64 - 10
+    );
+    let mut buf2 = [0; 10];
+    long_file.read_exact(&mut buf2).await.unwrap();
+    assert_eq!(str::from_utf8(&buf2).unwrap(), &expected[expected.len() - 10..]);
+}
+
+#[tokio::test]
+async fn test_read_long_file_multi_cluster_fat12() {
+    test_read_long_file_multi_cluster(create_fs(FAT12_IMG).await).await
+}
+
+#[tokio::test]
+async fn test_read_long_file_multi_cluster_fat16() {
+    test_read_long_file_multi_cluster(create_fs(FAT16_IMG).await).await
+}
+
+#[tokio::test]
+async fn test_read_long_file_multi_cluster_fat32() {
+    test_read_long_file_multi_cluster(create_fs(FAT32_IMG).await).await
+}
+
 async fn test_get_dir_by_path(fs: FileSystem) {
     let root_dir = fs.root_dir();
     let dir = root_dir.open_dir("very/long/path/").await.unwrap();
@@ -250,8 +353,13 @@ async fn test_get_file_by_path_fat32() {
 async fn test_volume_metadata(fs: FileSystem, fat_type: FatType) {
     assert_eq!(fs.volume_id(), 0x1234_5678);
     assert_eq!(fs.volume_label(), "Test!");
+    assert_eq!(fs.oem_name(), "mkfs.fat");
     assert_eq!(&fs.read_volume_label_from_root_dir().await.unwrap().unwrap(), "Test!");
     assert_eq!(fs.fat_type(), fat_type);
+    assert_eq!(fs.bytes_per_sector(), 512);
+    assert_eq!(fs.cluster_size(), 512);
+    let stats = fs.stats().await.unwrap();
+    assert_eq!(fs.total_clusters(), stats.total_clusters());
 }
 
 #[tokio::test]
@@ -290,6 +398,95 @@ async fn test_status_flags_fat32() {
     test_status_flags(create_fs(FAT32_IMG).await).await
 }
 
+async fn test_read_only_mount(name: &str) {
+    // the image is opened without write access so any write attempt would fail with an I/O error
+    let file = tokio::fs::File::open(name).await.unwrap();
+    let options = FsOptions::new().update_accessed_date(true).read_only(true);
+    let fs = embedded_fatfs::FileSystem::new(file, options).await.unwrap();
+    assert!(fs.is_read_only());
+    {
+        let root_dir = fs.root_dir();
+        let mut file = root_dir.open_file("short.txt").await.unwrap();
+        let buf = read_to_end(&mut file).await.unwrap();
+        assert_eq!(str::from_utf8(&buf).unwrap(), TEST_TEXT);
+        file.flush().await.unwrap();
+        assert!(matches!(file.write(b"x").await, Err(embedded_fatfs::Error::ReadOnly)));
+        assert!(matches!(file.truncate().await, Err(embedded_fatfs::Error::ReadOnly)));
+        // timestamps are not changed, so the file is not left with an entry which cannot be written
+        let metadata = file.metadata();
+        let date_time = DateTime::new(Date::new(2024, 5, 6), Time::new(7, 8, 10, 0));
+        assert!(matches!(
+            file.set_created(date_time),
+            Err(embedded_fatfs::Error::ReadOnly)
+        ));
+        assert!(matches!(
+            file.set_modified(date_time),
+            Err(embedded_fatfs::Error::ReadOnly)
+        ));
+        assert!(matches!(
+            file.set_accessed(date_time.date),
+            Err(embedded_fatfs::Error::ReadOnly)
+        ));
+        assert_eq!(file.metadata(), metadata);
+        file.flush().await.unwrap();
+        drop(file);
+        assert!(matches!(
+            root_dir.create_file("new.txt").await,
+            Err(embedded_fatfs::Error::ReadOnly)
+        ));
+        assert!(matches!(
+            root_dir.create_dir("new-dir").await,
+            Err(embedded_fatfs::Error::ReadOnly)
+        ));
+        assert!(matches!(
+            root_dir.remove("short.txt").await,
+            Err(embedded_fatfs::Error::ReadOnly)
+        ));
+        assert!(matches!(
+            root_dir.rename("short.txt", &root_dir, "moved.txt").await,
+            Err(embedded_fatfs::Error::ReadOnly)
+        ));
+        // opening an existing file with create_file is still allowed
+        root_dir.create_file("short.txt").await.unwrap();
+    }
+    fs.stats().await.unwrap();
+    fs.unmount().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_read_only_mount_fat12() {
+    test_read_only_mount(FAT12_IMG).await
+}
+
+#[tokio::test]
+async fn test_read_only_mount_fat16() {
+    test_read_only_mount(FAT16_IMG).await
+}
+
+#[tokio::test]
+async fn test_read_only_mount_fat32() {
+    test_read_only_mount(FAT32_IMG).await
+}
+
+async fn test_check_fats(fs: FileSystem) {
+    assert_eq!(fs.check_fats().await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn test_check_fats_fat12() {
+    test_check_fats(create_fs(FAT12_IMG).await).await
+}
+
+#[tokio::test]
+async fn test_check_fats_fat16() {
+    test_check_fats(create_fs(FAT16_IMG).await).await
+}
+
+#[tokio::test]
+async fn test_check_fats_fat32() {
+    test_check_fats(create_fs(FAT32_IMG).await).await
+}
+
 #[tokio::test]
 async fn test_stats_fat12() {
     let fs = create_fs(FAT12_IMG).await;
@@ -297,6 +494,11 @@ async fn test_stats_fat12() {
     assert_eq!(stats.cluster_size(), 512);
     assert_eq!(stats.total_clusters(), 1955); // 1000 * 1024 / 512 = 2000
     assert_eq!(stats.free_clusters(), 1920);
+    assert_eq!(stats.used_clusters(), 35);
+    assert_eq!(stats.reserved_clusters(), 2);
+    assert_eq!(stats.total_bytes(), 1955 * 512);
+    assert_eq!(stats.free_bytes(), 1920 * 512);
+    assert_eq!(stats.used_bytes(), 35 * 512);
 }
 
 #[tokio::test]
@@ -306,6 +508,11 @@ async fn test_stats_fat16() {
     assert_eq!(stats.cluster_size(), 512);
     assert_eq!(stats.total_clusters(), 4927); // 2500 * 1024 / 512 = 5000
     assert_eq!(stats.free_clusters(), 4892);
+    assert_eq!(stats.used_clusters(), 35);
+    assert_eq!(stats.reserved_clusters(), 2);
+    assert_eq!(stats.total_bytes(), 4927 * 512);
+    assert_eq!(stats.free_bytes(), 4892 * 512);
+    assert_eq!(stats.used_bytes(), 35 * 512);
 }
 
 #[tokio::test]
@@ -315,6 +522,11 @@ async fn test_stats_fat32() {
     assert_eq!(stats.cluster_size(), 512);
     assert_eq!(stats.total_clusters(), 66922); // 34000 * 1024 / 512 = 68000
     assert_eq!(stats.free_clusters(), 66886);
+    assert_eq!(stats.used_clusters(), 36);
+    assert_eq!(stats.reserved_clusters(), 2);
+    assert_eq!(stats.total_bytes(), 66922 * 512);
+    assert_eq!(stats.free_bytes(), 66886 * 512);
+    assert_eq!(stats.used_bytes(), 36 * 512);
 }
 
 #[tokio::test]
@@ -337,6 +549,146 @@ async fn test_multi_thread() {
     }
 }
 
+async fn test_read_file_to_end(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    let mut long_file = root_dir.open_file("long.txt").await.unwrap();
+    let mut buf = Vec::new();
+    assert_eq!(long_file.read_to_end(&mut buf).await.unwrap(), TEST_TEXT.len() * 1000);
+    assert_eq!(str::from_utf8(&buf).unwrap(), TEST_TEXT.repeat(1000));
+    // capacity was reserved from the file size
+    assert_eq!(buf.capacity(), buf.len());
+    // nothing is left after the end of the file
+    assert_eq!(long_file.read_to_end(&mut buf).await.unwrap(), 0);
+
+    // data is appended starting from the current position
+    let mut short_file = root_dir.open_file("short.txt").await.unwrap();
+    short_file.seek(SeekFrom::Start(5)).await.unwrap();
+    let mut text = String::from("prefix:");
+    assert_eq!(short_file.read_to_string(&mut text).await.unwrap(), TEST_TEXT.len() - 5);
+    assert_eq!(text, format!("prefix:{}", &TEST_TEXT[5..]));
+}
+
+#[tokio::test]
+async fn test_read_file_to_end_fat12() {
+    test_read_file_to_end(create_fs(FAT12_IMG).await).await
+}
+
+#[tokio::test]
+async fn test_read_file_to_end_fat16() {
+    test_read_file_to_end(create_fs(FAT16_IMG).await).await
+}
+
+#[tokio::test]
+async fn test_read_file_to_end_fat32() {
+    test_read_file_to_end(create_fs(FAT32_IMG).await).await
+}
+
+// A storage holding a volume image placed at `base` on a virtual device, reading anything before it fails
+struct PartitionStorage {
+    base: u64,
+    pos: u64,
+    image: Vec<u8>,
+}
+
+impl PartitionStorage {
+    fn image_range(&self, len: usize) -> Result<std::ops::Range<usize>, std::io::Error> {
+        let start = self
+            .pos
+            .checked_sub(self.base)
+            .ok_or_else(|| std::io::Error::other("access before the partition"))?;
+        let start = std::cmp::min(start as usize, self.image.len());
+        Ok(start..std::cmp::min(start + len, self.image.len()))
+    }
+}
+
+impl embedded_io_async::ErrorType for PartitionStorage {
+    type Error = std::io::Error;
+}
+
+impl Read for PartitionStorage {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let range = self.image_range(buf.len())?;
+        let n = range.len();
+        buf[..n].copy_from_slice(&self.image[range]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Write for PartitionStorage {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let range = self.image_range(buf.len())?;
+        let n = range.len();
+        self.image[range].copy_from_slice(&buf[..n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl Seek for PartitionStorage {
+    async fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+        self.pos = match pos {
+            SeekFrom::Start(n) => n,
+            SeekFrom::End(n) => (self.base + self.image.len() as u64).wrapping_add_signed(n),
+            SeekFrom::Current(n) => self.pos.wrapping_add_signed(n),
+        };
+        Ok(self.pos)
+    }
+}
+
+// Reads the root directory and the content of its files
+async fn read_partition(image: Vec<u8>, base: u64) -> Vec<(String, Vec<u8>)> {
+    let storage = PartitionStorage { base, pos: 0, image };
+    let options = FsOptions::new().partition_offset(base).read_only(true);
+    let fs = embedded_fatfs::FileSystem::new(storage, options).await.unwrap();
+    let root_dir = fs.root_dir();
+    let mut iter = root_dir.iter();
+    let mut files = Vec::new();
+    while let Some(r) = iter.next().await {
+        let e = r.unwrap();
+        let data = if e.is_file() {
+            read_to_end(&mut e.to_file()).await.unwrap()
+        } else {
+            Vec::new()
+        };
+        files.push((e.file_name(), data));
+    }
+    let mut file = root_dir.open_file("very/long/path/test.txt").await.unwrap();
+    files.push(("test.txt".into(), read_to_end(&mut file).await.unwrap()));
+    assert_eq!(fs.check_fats().await.unwrap(), None);
+    files
+}
+
+async fn test_partition_offset(name: &str) {
+    let image = tokio::fs::read(name).await.unwrap();
+    let at_start = read_partition(image.clone(), 0).await;
+    assert!(at_start
+        .iter()
+        .any(|(name, data)| name == "short.txt" && data == TEST_TEXT.as_bytes()));
+    // a partition starting past 2 TiB needs 64-bit positions
+    let far = read_partition(image, 3 << 40).await;
+    assert_eq!(far, at_start);
+}
+
+#[tokio::test]
+async fn test_partition_offset_fat12() {
+    test_partition_offset(FAT12_IMG).await
+}
+
+#[tokio::test]
+async fn test_partition_offset_fat16() {
+    test_partition_offset(FAT16_IMG).await
+}
+
+#[tokio::test]
+async fn test_partition_offset_fat32() {
+    test_partition_offset(FAT32_IMG).await
+}
+
 async fn read_to_end<IO: embedded_io_async::Read>(io: &mut IO) -> Result<Vec<u8>, IO::Error> {
     let mut buf = Vec::new();
     loop {
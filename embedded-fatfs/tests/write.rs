@@ -2,7 +2,7 @@ use std::future::Future;
 use std::str;
 use tokio::fs;
 
-use embedded_fatfs::{ChronoTimeProvider, FsOptions, LossyOemCpConverter};
+use embedded_fatfs::{ChronoTimeProvider, FatValue, FileAttributes, FsOptions, LossyOemCpConverter};
 use embedded_io_async::{Seek, SeekFrom, Write};
 
 const FAT12_IMG: &str = "fat12.img";
@@ -11,6 +11,7 @@ const FAT32_IMG: &str = "fat32.img";
 const IMG_DIR: &str = "resources";
 const TMP_DIR: &str = "tmp";
 const TEST_STR: &str = "Hi there Rust programmer!\n";
+const SFN_SIZE: usize = 11;
 const TEST_STR2: &str = "Rust is cool!\n";
 
 type FileSystem = embedded_fatfs::FileSystem<
@@ -489,34 +490,4851 @@ async fn test_dirty_flag_fat32() {
     call_with_tmp_img(test_dirty_flag, FAT32_IMG, 7).await
 }
 
-async fn test_multiple_files_in_directory(fs: FileSystem) {
-    let dir = fs.root_dir().create_dir("/TMP").await.unwrap();
-    for i in 0..8 {
-        let name = format!("T{}.TXT", i);
-        let mut file = dir.create_file(&name).await.unwrap();
+async fn test_unmount_before_drop(tmp_path: String) {
+    // Unmounting clears the dirty flag set by the write
+    let fs = open_filesystem_rw(tmp_path.clone()).await;
+    let mut file = fs.root_dir().create_file("abc.txt").await.unwrap();
+    file.write_all(TEST_STR2.as_bytes()).await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+    fs.unmount().await.unwrap();
+    let fs = open_filesystem_rw(tmp_path.clone()).await;
+    assert!(!fs.read_status_flags().await.unwrap().dirty());
+    let mut file = fs.root_dir().open_file("abc.txt").await.unwrap();
+    assert_eq!(read_to_end(&mut file).await.unwrap(), TEST_STR2.as_bytes());
+    // Dropping a modified filesystem only warns and leaves the volume dirty
+    file.write_all(TEST_STR2.as_bytes()).await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+    drop(fs);
+    let fs = open_filesystem_rw(tmp_path).await;
+    assert!(fs.read_status_flags().await.unwrap().dirty());
+}
+
+#[tokio::test]
+async fn test_unmount_before_drop_fat32() {
+    call_with_tmp_img(test_unmount_before_drop, FAT32_IMG, 70).await
+}
+
+async fn test_dirty_flag_accessors(tmp_path: String) {
+    // Reading does not mark the volume as dirty
+    let fs = open_filesystem_rw(tmp_path.clone()).await;
+    assert!(!fs.was_dirty_on_mount());
+    fs.root_dir().open_file("short.txt").await.unwrap();
+    assert!(!fs.is_dirty());
+    // Writing with dirty flag updates skipped leaves the flag untouched
+    fs.unmount().await.unwrap();
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&tmp_path)
+        .await
+        .unwrap();
+    let options = FsOptions::new().skip_dirty_flag(true);
+    let fs = FileSystem::new(file, options).await.unwrap();
+    fs.root_dir().create_file("abc.txt").await.unwrap();
+    assert!(!fs.is_dirty());
+    core::mem::forget(fs);
+    // Writing marks the volume as dirty and unmount clears the flag set by this mount
+    let fs = open_filesystem_rw(tmp_path.clone()).await;
+    assert!(!fs.was_dirty_on_mount());
+    fs.root_dir().create_file("def.txt").await.unwrap();
+    assert!(fs.is_dirty());
+    fs.unmount().await.unwrap();
+    let fs = open_filesystem_rw(tmp_path).await;
+    assert!(!fs.was_dirty_on_mount());
+    assert!(!fs.is_dirty());
+}
+
+#[tokio::test]
+async fn test_dirty_flag_accessors_fat12() {
+    call_with_tmp_img(test_dirty_flag_accessors, FAT12_IMG, 11).await
+}
+
+#[tokio::test]
+async fn test_dirty_flag_accessors_fat16() {
+    call_with_tmp_img(test_dirty_flag_accessors, FAT16_IMG, 11).await
+}
+
+#[tokio::test]
+async fn test_dirty_flag_accessors_fat32() {
+    call_with_tmp_img(test_dirty_flag_accessors, FAT32_IMG, 11).await
+}
+
+async fn test_flush_keeps_dirty_flag(tmp_path: String) {
+    let fs = open_filesystem_rw(tmp_path.clone()).await;
+    {
+        let mut file = fs.root_dir().create_file("abc.txt").await.unwrap();
         file.write_all(TEST_STR.as_bytes()).await.unwrap();
         file.flush().await.unwrap();
+    }
+    fs.flush().await.unwrap();
+    assert!(fs.is_dirty());
+    // filesystem is still usable after flush
+    fs.root_dir().create_dir("def").await.unwrap();
+    fs.flush().await.unwrap();
+    core::mem::forget(fs);
+    // changes are persisted but the volume is still marked as dirty
+    let fs = open_filesystem_rw(tmp_path).await;
+    assert!(fs.was_dirty_on_mount());
+    assert_eq!(
+        fs.root_dir().open_meta("abc.txt").await.unwrap().len(),
+        TEST_STR.len() as u64
+    );
+    assert!(fs.root_dir().open_meta("def").await.unwrap().is_dir());
+}
 
-        let files = dir.iter().collect().await;
-        let files = files.iter().map(|r| r.as_ref().unwrap()).collect::<Vec<_>>();
-        let file = files.iter().find(|e| e.file_name() == name).unwrap();
-        assert_eq!(TEST_STR.len() as u64, file.len(), "Wrong file len on iteration {}", i);
+#[tokio::test]
+async fn test_flush_keeps_dirty_flag_fat12() {
+    call_with_tmp_img(test_flush_keeps_dirty_flag, FAT12_IMG, 12).await
+}
+
+#[tokio::test]
+async fn test_flush_keeps_dirty_flag_fat16() {
+    call_with_tmp_img(test_flush_keeps_dirty_flag, FAT16_IMG, 12).await
+}
+
+#[tokio::test]
+async fn test_flush_keeps_dirty_flag_fat32() {
+    call_with_tmp_img(test_flush_keeps_dirty_flag, FAT32_IMG, 12).await
+}
+
+async fn test_sync_file(tmp_path: String) {
+    let fs = open_filesystem_rw(tmp_path.clone()).await;
+    let root_dir = fs.root_dir();
+    let mut data_file = root_dir.create_file("data.txt").await.unwrap();
+    data_file.write_all(TEST_STR.as_bytes()).await.unwrap();
+    data_file.sync_data().await.unwrap();
+    let mut all_file = root_dir.create_file("all.txt").await.unwrap();
+    all_file.write_all(TEST_STR2.as_bytes()).await.unwrap();
+    all_file.sync_all().await.unwrap();
+    // simulate a power loss - nothing is flushed on drop
+    core::mem::forget(data_file);
+    core::mem::forget(all_file);
+    core::mem::forget(root_dir);
+    core::mem::forget(fs);
+    let fs = open_filesystem_rw(tmp_path).await;
+    let root_dir = fs.root_dir();
+    let mut file = root_dir.open_file("data.txt").await.unwrap();
+    assert_eq!(TEST_STR.as_bytes(), read_to_end(&mut file).await.unwrap());
+    let mut file = root_dir.open_file("all.txt").await.unwrap();
+    assert_eq!(TEST_STR2.as_bytes(), read_to_end(&mut file).await.unwrap());
+}
+
+#[tokio::test]
+async fn test_sync_file_fat12() {
+    call_with_tmp_img(test_sync_file, FAT12_IMG, 13).await
+}
+
+#[tokio::test]
+async fn test_sync_file_fat16() {
+    call_with_tmp_img(test_sync_file, FAT16_IMG, 13).await
+}
+
+#[tokio::test]
+async fn test_sync_file_fat32() {
+    call_with_tmp_img(test_sync_file, FAT32_IMG, 13).await
+}
+
+async fn test_read_fragmented_file(tmp_path: String) {
+    let mut expected = Vec::new();
+    {
+        // interleave writes so clusters of both files are mixed on the disk
+        let fs = open_filesystem_rw(tmp_path.clone()).await;
+        let cluster_size = fs.cluster_size() as usize;
+        {
+            let root_dir = fs.root_dir();
+            let mut file = root_dir.create_file("frag.bin").await.unwrap();
+            let mut other = root_dir.create_file("other.bin").await.unwrap();
+            for i in 0..8_u8 {
+                let chunk = vec![i; cluster_size * if i % 3 == 0 { 2 } else { 1 }];
+                file.write_all(&chunk).await.unwrap();
+                expected.extend(chunk);
+                other.write_all(&vec![0xFF; cluster_size]).await.unwrap();
+            }
+            file.flush().await.unwrap();
+            other.flush().await.unwrap();
+        }
+        fs.unmount().await.unwrap();
+    }
+    for max_run in [0, 1, 2] {
+        let file = fs::OpenOptions::new().read(true).open(&tmp_path).await.unwrap();
+        let options = FsOptions::new().read_only(true).max_read_run_clusters(max_run);
+        let fs = FileSystem::new(file, options).await.unwrap();
+        let root_dir = fs.root_dir();
+        let mut file = root_dir.open_file("frag.bin").await.unwrap();
+        file.seek(SeekFrom::Start(7)).await.unwrap();
+        let mut buf = vec![0; expected.len()];
+        let mut pos = 0;
+        loop {
+            let n = embedded_io_async::Read::read(&mut file, &mut buf[pos..]).await.unwrap();
+            if n == 0 {
+                break;
+            }
+            pos += n;
+        }
+        assert_eq!(&buf[..pos], &expected[7..], "max_run {}", max_run);
     }
 }
 
 #[tokio::test]
-async fn test_multiple_files_in_directory_fat12() {
-    call_with_fs(&test_multiple_files_in_directory, FAT12_IMG, 8).await
+async fn test_read_fragmented_file_fat12() {
+    call_with_tmp_img(test_read_fragmented_file, FAT12_IMG, 14).await
 }
 
 #[tokio::test]
-async fn test_multiple_files_in_directory_fat16() {
-    call_with_fs(&test_multiple_files_in_directory, FAT16_IMG, 8).await
+async fn test_read_fragmented_file_fat16() {
+    call_with_tmp_img(test_read_fragmented_file, FAT16_IMG, 14).await
 }
 
 #[tokio::test]
-async fn test_multiple_files_in_directory_fat32() {
-    call_with_fs(&test_multiple_files_in_directory, FAT32_IMG, 8).await
+async fn test_read_fragmented_file_fat32() {
+    call_with_tmp_img(test_read_fragmented_file, FAT32_IMG, 14).await
+}
+
+async fn test_preallocate(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    let cluster_size = fs.cluster_size();
+    let free_before = fs.stats().await.unwrap().free_clusters();
+    {
+        let mut file = root_dir.create_file("stream.bin").await.unwrap();
+        file.preallocate_contiguous(cluster_size * 3 + 1).await.unwrap();
+        // file must be empty
+        assert!(matches!(
+            file.preallocate_contiguous(cluster_size).await,
+            Err(embedded_fatfs::Error::InvalidInput)
+        ));
+        assert_eq!(
+            file.seek(SeekFrom::End(0)).await.unwrap(),
+            u64::from(cluster_size * 3 + 1)
+        );
+        file.seek(SeekFrom::Start(0)).await.unwrap();
+        let data = vec![0xAB; (cluster_size * 3 + 1) as usize];
+        file.write_all(&data).await.unwrap();
+        file.flush().await.unwrap();
+    }
+    assert_eq!(fs.stats().await.unwrap().free_clusters(), free_before - 4);
+    {
+        let mut file = root_dir.open_file("stream.bin").await.unwrap();
+        let buf = read_to_end(&mut file).await.unwrap();
+        assert_eq!(buf, vec![0xAB; (cluster_size * 3 + 1) as usize]);
+    }
+    {
+        let mut file = root_dir.create_file("alloc.bin").await.unwrap();
+        file.write_all(b"abc").await.unwrap();
+        file.allocate(cluster_size * 2).await.unwrap();
+        // allocating less than the file size is a no-op
+        file.allocate(1).await.unwrap();
+        assert_eq!(file.seek(SeekFrom::Current(0)).await.unwrap(), 3);
+        assert_eq!(file.seek(SeekFrom::End(0)).await.unwrap(), u64::from(cluster_size * 2));
+        file.flush().await.unwrap();
+    }
+    assert_eq!(fs.stats().await.unwrap().free_clusters(), free_before - 6);
+    let mut file = root_dir.create_file("big.bin").await.unwrap();
+    let too_big = (free_before - 5) * cluster_size;
+    assert!(matches!(
+        file.preallocate_contiguous(too_big).await,
+        Err(embedded_fatfs::Error::NotEnoughSpace)
+    ));
+    assert_eq!(fs.stats().await.unwrap().free_clusters(), free_before - 6);
+}
+
+#[tokio::test]
+async fn test_preallocate_fat12() {
+    call_with_fs(test_preallocate, FAT12_IMG, 15).await
+}
+
+#[tokio::test]
+async fn test_preallocate_fat16() {
+    call_with_fs(test_preallocate, FAT16_IMG, 15).await
+}
+
+#[tokio::test]
+async fn test_preallocate_fat32() {
+    call_with_fs(test_preallocate, FAT32_IMG, 15).await
+}
+
+async fn test_free_space(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    let free_space = fs.free_space().await.unwrap();
+    assert_eq!(free_space.cluster_size(), fs.cluster_size());
+    assert_eq!(free_space.free_clusters(), fs.stats().await.unwrap().free_clusters());
+    assert!(free_space.largest_free_run() > 0);
+    assert!(free_space.largest_free_run() <= free_space.free_clusters());
+    assert!(free_space.largest_free_run_start().is_some());
+    let largest = free_space.largest_free_run_bytes() as u32;
+    let mut file = root_dir.create_file("largest.bin").await.unwrap();
+    assert!(matches!(
+        file.preallocate_contiguous(largest + 1).await,
+        Err(embedded_fatfs::Error::NotEnoughSpace)
+    ));
+    file.preallocate_contiguous(largest).await.unwrap();
+    file.flush().await.unwrap();
+    let after = fs.free_space().await.unwrap();
+    assert_eq!(
+        after.free_clusters(),
+        free_space.free_clusters() - free_space.largest_free_run()
+    );
+    assert!(after.largest_free_run() <= free_space.largest_free_run());
+}
+
+#[tokio::test]
+async fn test_free_space_fat12() {
+    call_with_fs(test_free_space, FAT12_IMG, 20).await
+}
+
+#[tokio::test]
+async fn test_free_space_fat16() {
+    call_with_fs(test_free_space, FAT16_IMG, 20).await
+}
+
+#[tokio::test]
+async fn test_free_space_fat32() {
+    call_with_fs(test_free_space, FAT32_IMG, 20).await
+}
+
+async fn test_small_buffered_io(tmp_path: String) {
+    let data = TEST_STR.repeat(100);
+    {
+        let fs = open_filesystem_rw(tmp_path.clone()).await;
+        {
+            let root_dir = fs.root_dir();
+            let mut file = root_dir.create_file("small.txt").await.unwrap();
+            for chunk in data.as_bytes().chunks(7) {
+                file.write_all(chunk).await.unwrap();
+            }
+            // a cloned handle sees data that was not written back yet even if it is not buffered
+            let mut reader = file.clone();
+            reader.set_buffered(false);
+            reader.seek(SeekFrom::Start(0)).await.unwrap();
+            assert_eq!(read_to_end(&mut reader).await.unwrap(), data.as_bytes());
+            reader.flush().await.unwrap();
+            drop(reader);
+            // overwrite single bytes in the middle of the file
+            file.seek(SeekFrom::Start(600)).await.unwrap();
+            file.write_all(b"X").await.unwrap();
+            file.seek(SeekFrom::Start(3)).await.unwrap();
+            file.write_all(b"Y").await.unwrap();
+            file.flush().await.unwrap();
+        }
+        fs.unmount().await.unwrap();
+    }
+    let mut expected = data.into_bytes();
+    expected[600] = b'X';
+    expected[3] = b'Y';
+    let fs = open_filesystem_rw(tmp_path).await;
+    let mut file = fs.root_dir().open_file("small.txt").await.unwrap();
+    let mut buf = Vec::new();
+    let mut byte = [0; 1];
+    while embedded_io_async::Read::read(&mut file, &mut byte).await.unwrap() == 1 {
+        buf.push(byte[0]);
+    }
+    assert_eq!(buf, expected);
+    file.flush().await.unwrap();
+    drop(file);
+    fs.unmount().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_small_buffered_io_fat12() {
+    call_with_tmp_img(test_small_buffered_io, FAT12_IMG, 21).await
+}
+
+#[tokio::test]
+async fn test_small_buffered_io_fat16() {
+    call_with_tmp_img(test_small_buffered_io, FAT16_IMG, 21).await
+}
+
+#[tokio::test]
+async fn test_small_buffered_io_fat32() {
+    call_with_tmp_img(test_small_buffered_io, FAT32_IMG, 21).await
+}
+
+async fn test_positional_io(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    let cluster_size = fs.cluster_size() as usize;
+    let mut data = (0..=255_u8).cycle().take(cluster_size * 3 + 100).collect::<Vec<_>>();
+    let mut file = root_dir.create_file("positional.bin").await.unwrap();
+    file.write_all(&data).await.unwrap();
+    file.seek(SeekFrom::Start(10)).await.unwrap();
+    let mut buf = vec![0; cluster_size + 2];
+    for offset in [0, cluster_size - 1, cluster_size * 2, 5] {
+        file.read_exact_at(offset as u64, &mut buf).await.unwrap();
+        assert_eq!(buf, data[offset..offset + buf.len()]);
+    }
+    let size = data.len() as u64;
+    assert_eq!(file.read_at(size - 1, &mut buf).await.unwrap(), 1);
+    assert_eq!(file.read_at(size, &mut buf).await.unwrap(), 0);
+    assert!(matches!(
+        file.read_exact_at(size - 1, &mut buf).await,
+        Err(embedded_fatfs::Error::UnexpectedEof)
+    ));
+
+    // overwrite data across a cluster boundary and extend the file
+    // a single write never crosses a cluster boundary
+    assert_eq!(file.write_at(cluster_size as u64 - 2, b"abcd").await.unwrap(), 2);
+    assert_eq!(file.write_at(cluster_size as u64, b"cd").await.unwrap(), 2);
+    data[cluster_size - 2..cluster_size + 2].copy_from_slice(b"abcd");
+    let mut written = 0;
+    while written < 200 {
+        written += file
+            .write_at(size - 100 + written as u64, &[0xEE; 200][written..])
+            .await
+            .unwrap();
+    }
+    data.truncate(data.len() - 100);
+    data.extend([0xEE; 200]);
+    assert!(matches!(
+        file.write_at(data.len() as u64 + 1, b"gap").await,
+        Err(embedded_fatfs::Error::InvalidInput)
+    ));
+    // the current position is not affected by positional operations
+    assert_eq!(file.stream_position().await.unwrap(), 10);
+    file.seek(SeekFrom::Start(0)).await.unwrap();
+    assert_eq!(read_to_end(&mut file).await.unwrap(), data);
+    file.flush().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_positional_io_fat12() {
+    call_with_fs(test_positional_io, FAT12_IMG, 22).await
+}
+
+#[tokio::test]
+async fn test_positional_io_fat16() {
+    call_with_fs(test_positional_io, FAT16_IMG, 22).await
+}
+
+#[tokio::test]
+async fn test_positional_io_fat32() {
+    call_with_fs(test_positional_io, FAT32_IMG, 22).await
+}
+
+async fn test_create_file_all(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    {
+        let mut file = root_dir.create_file_all("/a/b/c/file.txt").await.unwrap();
+        file.write_all(TEST_STR.as_bytes()).await.unwrap();
+        file.flush().await.unwrap();
+    }
+    // existing directories and the existing file are reused
+    {
+        let mut file = root_dir.create_file_all("a/b/c/file.txt").await.unwrap();
+        assert_eq!(read_to_end(&mut file).await.unwrap(), TEST_STR.as_bytes());
+        let mut file = root_dir.create_file_all("a/b/other.txt").await.unwrap();
+        file.write_all(TEST_STR2.as_bytes()).await.unwrap();
+        file.flush().await.unwrap();
+    }
+    for (path, expected) in [
+        ("a/b", vec![".", "..", "c", "other.txt"]),
+        ("a/b/c", vec![".", "..", "file.txt"]),
+    ] {
+        let names = root_dir
+            .open_dir(path)
+            .await
+            .unwrap()
+            .iter()
+            .collect()
+            .await
+            .iter()
+            .map(|r| r.as_ref().unwrap().file_name())
+            .collect::<Vec<String>>();
+        assert_eq!(names, expected);
+    }
+    assert!(matches!(
+        root_dir.create_file_all("a/b/other.txt/x.txt").await,
+        Err(embedded_fatfs::Error::InvalidInput)
+    ));
+    assert!(matches!(
+        root_dir.create_file_all("a/b").await,
+        Err(embedded_fatfs::Error::InvalidInput)
+    ));
+}
+
+#[tokio::test]
+async fn test_create_file_all_fat12() {
+    call_with_fs(test_create_file_all, FAT12_IMG, 23).await
+}
+
+#[tokio::test]
+async fn test_create_file_all_fat16() {
+    call_with_fs(test_create_file_all, FAT16_IMG, 23).await
+}
+
+#[tokio::test]
+async fn test_create_file_all_fat32() {
+    call_with_fs(test_create_file_all, FAT32_IMG, 23).await
+}
+
+async fn test_create_dir_all(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    root_dir.create_dir("logs").await.unwrap();
+    let dir = root_dir.create_dir_all("logs/2024/06").await.unwrap();
+    let mut file = dir.create_file("log.txt").await.unwrap();
+    file.write_all(TEST_STR.as_bytes()).await.unwrap();
+    file.flush().await.unwrap();
+    // existing directories are reused
+    let dir = root_dir.create_dir_all("/logs/2024/06/").await.unwrap();
+    let mut file = dir.open_file("log.txt").await.unwrap();
+    assert_eq!(read_to_end(&mut file).await.unwrap(), TEST_STR.as_bytes());
+    root_dir.create_dir_all("logs/2024/07").await.unwrap();
+    // "." and ".." entries of new directories point to the directory itself and its parent
+    for (path, expected) in [
+        ("logs/2024/06/.", vec![".", "..", "log.txt"]),
+        ("logs/2024/06/..", vec![".", "..", "06", "07"]),
+        ("logs/2024/..", vec![".", "..", "2024"]),
+    ] {
+        let names = root_dir
+            .open_dir(path)
+            .await
+            .unwrap()
+            .iter()
+            .collect()
+            .await
+            .iter()
+            .map(|r| r.as_ref().unwrap().file_name())
+            .collect::<Vec<String>>();
+        assert_eq!(names, expected);
+    }
+    assert!(matches!(
+        root_dir.create_dir_all("logs/2024/06/log.txt/x").await,
+        Err(embedded_fatfs::Error::InvalidInput)
+    ));
+}
+
+#[tokio::test]
+async fn test_create_dir_all_fat12() {
+    call_with_fs(test_create_dir_all, FAT12_IMG, 24).await
+}
+
+#[tokio::test]
+async fn test_create_dir_all_fat16() {
+    call_with_fs(test_create_dir_all, FAT16_IMG, 24).await
+}
+
+#[tokio::test]
+async fn test_create_dir_all_fat32() {
+    call_with_fs(test_create_dir_all, FAT32_IMG, 24).await
+}
+
+async fn test_dotdot_entry_of_root_subdir(tmp_path: String) {
+    {
+        let fs = open_filesystem_rw(tmp_path.clone()).await;
+        {
+            let root_dir = fs.root_dir();
+            root_dir.create_dir_all("DOTDOT1/DOTDOT2").await.unwrap();
+            // ".." of a directory in the root directory leads back to the root directory
+            let mut names = Vec::new();
+            for dir in [root_dir.clone(), root_dir.open_dir("DOTDOT1/..").await.unwrap()] {
+                let entries = dir.iter().collect().await;
+                names.push(
+                    entries
+                        .iter()
+                        .map(|r| r.as_ref().unwrap().file_name())
+                        .collect::<Vec<String>>(),
+                );
+            }
+            assert_eq!(names[0], names[1]);
+        }
+        fs.unmount().await.unwrap();
+    }
+    let image = fs::read(&tmp_path).await.unwrap();
+    let entry_cluster = |pos: usize| {
+        u32::from(u16::from_le_bytes([image[pos + 26], image[pos + 27]]))
+            | (u32::from(u16::from_le_bytes([image[pos + 20], image[pos + 21]])) << 16)
+    };
+    // skips long name entries
+    let next_sfn_entry = |mut pos: usize| {
+        while image[pos + 11] == 0x0F {
+            pos += 32;
+        }
+        pos
+    };
+    // "." and ".." are the first two entries of a new directory and have no long names
+    let dotdot1 = (0..image.len() - 512)
+        .step_by(32)
+        .find(|&pos| {
+            &image[pos..pos + 11] == b".          "
+                && &image[pos + 32..pos + 43] == b"..         "
+                && &image[next_sfn_entry(pos + 64)..][..11] == b"DOTDOT2    "
+        })
+        .unwrap();
+    assert_eq!(entry_cluster(dotdot1 + 32), 0);
+    let dotdot2_cluster = entry_cluster(next_sfn_entry(dotdot1 + 64));
+    let dotdot2 = (0..image.len() - 64)
+        .step_by(32)
+        .find(|&pos| &image[pos..pos + 11] == b".          " && entry_cluster(pos) == dotdot2_cluster)
+        .unwrap();
+    assert_eq!(entry_cluster(dotdot2 + 32), entry_cluster(dotdot1));
+}
+
+#[tokio::test]
+async fn test_dotdot_entry_of_root_subdir_fat12() {
+    call_with_tmp_img(test_dotdot_entry_of_root_subdir, FAT12_IMG, 25).await
+}
+
+#[tokio::test]
+async fn test_dotdot_entry_of_root_subdir_fat16() {
+    call_with_tmp_img(test_dotdot_entry_of_root_subdir, FAT16_IMG, 25).await
+}
+
+#[tokio::test]
+async fn test_dotdot_entry_of_root_subdir_fat32() {
+    call_with_tmp_img(test_dotdot_entry_of_root_subdir, FAT32_IMG, 25).await
+}
+
+async fn test_fn_time_provider(tmp_path: String) {
+    use embedded_fatfs::{Date, DateTime, FnTimeProvider, Time};
+
+    let now = DateTime::new(Date::new(2024, 5, 17), Time::new(12, 30, 14, 0));
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&tmp_path)
+        .await
+        .unwrap();
+    let options = FsOptions::new()
+        .time_provider(FnTimeProvider::new(|| now))
+        .update_accessed_date(true);
+    let fs = embedded_fatfs::FileSystem::new(file, options).await.unwrap();
+    let root_dir = fs.root_dir();
+    root_dir.create_dir("stamped").await.unwrap();
+    {
+        let mut file = root_dir.create_file("stamped.txt").await.unwrap();
+        file.write_all(TEST_STR.as_bytes()).await.unwrap();
+        file.flush().await.unwrap();
+    }
+    {
+        // modification time is updated on write
+        let mut file = root_dir.open_file("short.txt").await.unwrap();
+        file.write_all(b"x").await.unwrap();
+        file.flush().await.unwrap();
+    }
+    for name in ["stamped", "stamped.txt"] {
+        let entry = root_dir
+            .iter()
+            .collect()
+            .await
+            .into_iter()
+            .map(Result::unwrap)
+            .find(|e| e.file_name() == name)
+            .unwrap();
+        assert_eq!(entry.created(), Some(now));
+        assert_eq!(entry.modified(), now);
+        assert_eq!(entry.accessed(), now.date);
+    }
+    let entry = root_dir
+        .iter()
+        .collect()
+        .await
+        .into_iter()
+        .map(Result::unwrap)
+        .find(|e| e.file_name() == "short.txt")
+        .unwrap();
+    assert_ne!(entry.created(), Some(now));
+    assert_eq!(entry.modified(), now);
+}
+
+#[tokio::test]
+async fn test_fn_time_provider_fat12() {
+    call_with_tmp_img(test_fn_time_provider, FAT12_IMG, 16).await
+}
+
+#[tokio::test]
+async fn test_fn_time_provider_fat16() {
+    call_with_tmp_img(test_fn_time_provider, FAT16_IMG, 16).await
+}
+
+#[tokio::test]
+async fn test_fn_time_provider_fat32() {
+    call_with_tmp_img(test_fn_time_provider, FAT32_IMG, 16).await
+}
+
+async fn test_timestamps_updated_on_access(tmp_path: String) {
+    use embedded_fatfs::{Date, DateTime, FnTimeProvider, Time};
+
+    async fn open_fs(
+        tmp_path: &str,
+        now: DateTime,
+        update_accessed_date: bool,
+    ) -> embedded_fatfs::FileSystem<
+        embedded_io_adapters::tokio_1::FromTokio<fs::File>,
+        FnTimeProvider<impl Fn() -> DateTime>,
+        LossyOemCpConverter,
+    > {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(tmp_path)
+            .await
+            .unwrap();
+        let options = FsOptions::new()
+            .time_provider(FnTimeProvider::new(move || now))
+            .update_accessed_date(update_accessed_date);
+        embedded_fatfs::FileSystem::new(file, options).await.unwrap()
+    }
+
+    let read_time = DateTime::new(Date::new(2020, 2, 3), Time::new(4, 5, 6, 0));
+    let write_time = DateTime::new(Date::new(2021, 3, 4), Time::new(5, 6, 8, 0));
+    let noatime_time = DateTime::new(Date::new(2022, 4, 5), Time::new(6, 7, 10, 0));
+
+    // reading updates only the accessed date
+    let fs = open_fs(&tmp_path, read_time, true).await;
+    let modified = fs.root_dir().iter().next().await.unwrap().unwrap().modified();
+    {
+        let mut file = fs.root_dir().open_file("long.txt").await.unwrap();
+        read_to_end(&mut file).await.unwrap();
+        file.flush().await.unwrap();
+    }
+    fs.unmount().await.unwrap();
+    let fs = open_fs(&tmp_path, write_time, true).await;
+    let entry = fs.root_dir().iter().next().await.unwrap().unwrap();
+    assert_eq!(entry.file_name(), "long.txt");
+    assert_eq!(entry.accessed(), read_time.date);
+    assert_eq!(entry.modified(), modified);
+
+    // writing updates modified date and time and accessed date
+    {
+        let mut file = fs.root_dir().open_file("long.txt").await.unwrap();
+        file.write_all(b"abc").await.unwrap();
+        file.flush().await.unwrap();
+    }
+    fs.unmount().await.unwrap();
+    let fs = open_fs(&tmp_path, noatime_time, false).await;
+    let entry = fs.root_dir().iter().next().await.unwrap().unwrap();
+    assert_eq!(entry.accessed(), write_time.date);
+    assert_eq!(entry.modified(), write_time);
+
+    // reading with accessed date updates disabled leaves the entry untouched
+    {
+        let mut file = fs.root_dir().open_file("long.txt").await.unwrap();
+        read_to_end(&mut file).await.unwrap();
+        file.flush().await.unwrap();
+    }
+    fs.unmount().await.unwrap();
+    let fs = open_fs(&tmp_path, noatime_time, false).await;
+    let entry = fs.root_dir().iter().next().await.unwrap().unwrap();
+    assert_eq!(entry.accessed(), write_time.date);
+    assert_eq!(entry.modified(), write_time);
+}
+
+#[tokio::test]
+async fn test_timestamps_updated_on_access_fat12() {
+    call_with_tmp_img(test_timestamps_updated_on_access, FAT12_IMG, 17).await
+}
+
+#[tokio::test]
+async fn test_timestamps_updated_on_access_fat16() {
+    call_with_tmp_img(test_timestamps_updated_on_access, FAT16_IMG, 17).await
+}
+
+#[tokio::test]
+async fn test_timestamps_updated_on_access_fat32() {
+    call_with_tmp_img(test_timestamps_updated_on_access, FAT32_IMG, 17).await
+}
+
+async fn test_set_file_times(fs: FileSystem) {
+    use embedded_fatfs::{Date, DateTime, Time};
+
+    let created = DateTime::new(Date::new(1999, 12, 31), Time::new(23, 59, 58, 230));
+    let modified = DateTime::new(Date::new(2005, 6, 7), Time::new(8, 9, 10, 0));
+    let accessed = Date::new(2010, 11, 12);
+    let root_dir = fs.root_dir();
+    {
+        let mut file = root_dir.create_file("restored.txt").await.unwrap();
+        file.write_all(TEST_STR.as_bytes()).await.unwrap();
+        file.set_created(created).unwrap();
+        file.set_modified(modified).unwrap();
+        file.set_accessed(accessed).unwrap();
+        let mut invalid = Date::new(1980, 1, 1);
+        invalid.year = 1979;
+        assert!(matches!(
+            file.set_accessed(invalid),
+            Err(embedded_fatfs::Error::InvalidInput)
+        ));
+        assert!(matches!(
+            file.set_modified(DateTime::new(invalid, Time::new(0, 0, 0, 0))),
+            Err(embedded_fatfs::Error::InvalidInput)
+        ));
+        file.flush().await.unwrap();
+    }
+    let entry = root_dir
+        .iter()
+        .collect()
+        .await
+        .into_iter()
+        .map(Result::unwrap)
+        .find(|e| e.file_name() == "restored.txt")
+        .unwrap();
+    assert_eq!(entry.created(), Some(created));
+    assert_eq!(entry.modified(), modified);
+    assert_eq!(entry.accessed(), accessed);
+}
+
+#[tokio::test]
+async fn test_set_file_times_fat12() {
+    call_with_fs(test_set_file_times, FAT12_IMG, 18).await
+}
+
+#[tokio::test]
+async fn test_set_file_times_fat16() {
+    call_with_fs(test_set_file_times, FAT16_IMG, 18).await
+}
+
+#[tokio::test]
+async fn test_set_file_times_fat32() {
+    call_with_fs(test_set_file_times, FAT32_IMG, 18).await
+}
+
+#[tokio::test]
+async fn test_max_file_size() {
+    const MAX_FILE_SIZE: u64 = 0xFFFF_FFFF;
+    let _ = env_logger::builder().is_test(true).try_init();
+    fs::create_dir(TMP_DIR).await.ok();
+    let tmp_path = format!("{}/19-max-file-size.img", TMP_DIR);
+    {
+        // sparse image just big enough to hold a file of the maximal size
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)
+            .await
+            .unwrap();
+        file.set_len(MAX_FILE_SIZE + 64 * 1024 * 1024).await.unwrap();
+        let mut storage = embedded_io_adapters::tokio_1::FromTokio::new(file);
+        let options = embedded_fatfs::FormatVolumeOptions::new().bytes_per_cluster(64 * 1024);
+        embedded_fatfs::format_volume(&mut storage, options).await.unwrap();
+        storage.flush().await.unwrap();
+    }
+    let fs = open_filesystem_rw(tmp_path.clone()).await;
+    // the free space exceeds the hard limit
+    assert!(fs.stats().await.unwrap().free_bytes() > MAX_FILE_SIZE);
+    assert_eq!(fs.max_file_size().await.unwrap(), MAX_FILE_SIZE);
+    {
+        let root_dir = fs.root_dir();
+        let mut file = root_dir.create_file("big.bin").await.unwrap();
+        file.preallocate_contiguous(MAX_FILE_SIZE as u32).await.unwrap();
+        assert!(matches!(
+            file.seek(SeekFrom::Start(MAX_FILE_SIZE + 1)).await,
+            Err(embedded_fatfs::Error::InvalidInput)
+        ));
+        assert_eq!(
+            file.seek(SeekFrom::Start(MAX_FILE_SIZE - 1)).await.unwrap(),
+            MAX_FILE_SIZE - 1
+        );
+        // only the byte below the limit can be written
+        assert_eq!(file.write(b"ab").await.unwrap(), 1);
+        assert_eq!(file.stream_position().await.unwrap(), MAX_FILE_SIZE);
+        assert!(matches!(
+            file.write(b"b").await,
+            Err(embedded_fatfs::Error::InvalidInput)
+        ));
+        // write_all reports the short write by failing the write at the limit
+        file.seek(SeekFrom::Start(MAX_FILE_SIZE - 1)).await.unwrap();
+        assert!(matches!(
+            file.write_all(b"ab").await,
+            Err(embedded_fatfs::Error::InvalidInput)
+        ));
+        assert!(matches!(
+            file.seek(SeekFrom::Current(1)).await,
+            Err(embedded_fatfs::Error::InvalidInput)
+        ));
+        assert_eq!(file.seek(SeekFrom::End(-1)).await.unwrap(), MAX_FILE_SIZE - 1);
+        let mut buf = [0; 2];
+        assert_eq!(embedded_io_async::Read::read(&mut file, &mut buf).await.unwrap(), 1);
+        assert_eq!(buf[0], b'a');
+        file.flush().await.unwrap();
+        assert_eq!(file.seek(SeekFrom::End(0)).await.unwrap(), MAX_FILE_SIZE);
+    }
+    fs.unmount().await.unwrap();
+    fs::remove_file(tmp_path).await.unwrap();
+}
+
+async fn test_multiple_files_in_directory(fs: FileSystem) {
+    let dir = fs.root_dir().create_dir("/TMP").await.unwrap();
+    for i in 0..8 {
+        let name = format!("T{}.TXT", i);
+        let mut file = dir.create_file(&name).await.unwrap();
+        file.write_all(TEST_STR.as_bytes()).await.unwrap();
+        file.flush().await.unwrap();
+
+        let files = dir.iter().collect().await;
+        let files = files.iter().map(|r| r.as_ref().unwrap()).collect::<Vec<_>>();
+        let file = files.iter().find(|e| e.file_name() == name).unwrap();
+        assert_eq!(TEST_STR.len() as u64, file.len(), "Wrong file len on iteration {}", i);
+    }
+}
+
+#[tokio::test]
+async fn test_multiple_files_in_directory_fat12() {
+    call_with_fs(&test_multiple_files_in_directory, FAT12_IMG, 8).await
+}
+
+#[tokio::test]
+async fn test_multiple_files_in_directory_fat16() {
+    call_with_fs(&test_multiple_files_in_directory, FAT16_IMG, 8).await
+}
+
+#[tokio::test]
+async fn test_multiple_files_in_directory_fat32() {
+    call_with_fs(&test_multiple_files_in_directory, FAT32_IMG, 8).await
+}
+
+async fn test_create_files_with_shared_prefix(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    let mut file = root_dir.create_file("LongFileName1.txt").await.unwrap();
+    file.flush().await.unwrap();
+    let mut file = root_dir.create_file("LongFileName2.txt").await.unwrap();
+    file.flush().await.unwrap();
+    let first = root_dir.open_meta("LongFileName1.txt").await.unwrap();
+    assert_eq!(first.short_file_name(), "LONGFI~1.TXT");
+    let second = root_dir.open_meta("LongFileName2.txt").await.unwrap();
+    assert_eq!(second.short_file_name(), "LONGFI~2.TXT");
+    // both files must be reachable by their long names after the collision
+    let names = root_dir
+        .iter()
+        .collect()
+        .await
+        .iter()
+        .map(|r| r.as_ref().unwrap().file_name())
+        .filter(|n| n.starts_with("LongFileName"))
+        .collect::<Vec<String>>();
+    assert_eq!(names, ["LongFileName1.txt", "LongFileName2.txt"]);
+}
+
+#[tokio::test]
+async fn test_create_files_with_shared_prefix_fat12() {
+    call_with_fs(test_create_files_with_shared_prefix, FAT12_IMG, 9).await
+}
+
+#[tokio::test]
+async fn test_create_files_with_shared_prefix_fat16() {
+    call_with_fs(test_create_files_with_shared_prefix, FAT16_IMG, 9).await
+}
+
+#[tokio::test]
+async fn test_create_files_with_shared_prefix_fat32() {
+    call_with_fs(test_create_files_with_shared_prefix, FAT32_IMG, 9).await
+}
+
+async fn test_check_fats_mismatch(tmp_path: String) {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+    const CLUSTER: u64 = 100;
+    // locate the second FAT using raw BPB fields
+    let mut boot = [0_u8; 512];
+    {
+        let mut file = fs::File::open(&tmp_path).await.unwrap();
+        file.read_exact(&mut boot).await.unwrap();
+    }
+    let bytes_per_sector = u64::from(u16::from_le_bytes([boot[11], boot[12]]));
+    let reserved_sectors = u64::from(u16::from_le_bytes([boot[14], boot[15]]));
+    let sectors_per_fat_16 = u64::from(u16::from_le_bytes([boot[22], boot[23]]));
+    let (sectors_per_fat, entry_offset) = if sectors_per_fat_16 == 0 {
+        (
+            u64::from(u32::from_le_bytes([boot[36], boot[37], boot[38], boot[39]])),
+            CLUSTER * 4,
+        )
+    } else if tmp_path.ends_with(FAT12_IMG) {
+        (sectors_per_fat_16, CLUSTER * 3 / 2)
+    } else {
+        (sectors_per_fat_16, CLUSTER * 2)
+    };
+    let fs = open_filesystem_rw(tmp_path.clone()).await;
+    assert_eq!(fs.check_fats().await.unwrap(), None);
+    fs.unmount().await.unwrap();
+    // corrupt an entry in the second FAT only
+    {
+        let mut file = fs::OpenOptions::new().write(true).open(&tmp_path).await.unwrap();
+        let offset = (reserved_sectors + sectors_per_fat) * bytes_per_sector + entry_offset;
+        file.seek(std::io::SeekFrom::Start(offset)).await.unwrap();
+        file.write_all(&[0x5A]).await.unwrap();
+        file.flush().await.unwrap();
+    }
+    let fs = open_filesystem_rw(tmp_path).await;
+    assert_eq!(fs.check_fats().await.unwrap(), Some(CLUSTER as u32));
+}
+
+#[tokio::test]
+async fn test_check_fats_mismatch_fat12() {
+    call_with_tmp_img(test_check_fats_mismatch, FAT12_IMG, 10).await
+}
+
+#[tokio::test]
+async fn test_check_fats_mismatch_fat16() {
+    call_with_tmp_img(test_check_fats_mismatch, FAT16_IMG, 10).await
+}
+
+#[tokio::test]
+async fn test_check_fats_mismatch_fat32() {
+    call_with_tmp_img(test_check_fats_mismatch, FAT32_IMG, 10).await
+}
+
+async fn fat_entries(fs: &FileSystem) -> Vec<(u32, FatValue)> {
+    let mut entries = Vec::new();
+    let mut iter = fs.fat_entries();
+    while let Some(r) = iter.next().await {
+        entries.push(r.unwrap());
+    }
+    entries
+}
+
+async fn test_fat_entries(fs: FileSystem) {
+    let before = fat_entries(&fs).await;
+    assert_eq!(before.len() as u32, fs.stats().await.unwrap().total_clusters());
+    assert_eq!(before[0].0, 2);
+    let free = before.iter().filter(|(_, v)| *v == FatValue::Free).count() as u32;
+    assert_eq!(free, fs.stats().await.unwrap().free_clusters());
+
+    let cluster_size = fs.cluster_size() as usize;
+    let mut file = fs.root_dir().create_file("chain.bin").await.unwrap();
+    file.write_all(&vec![0xAB; cluster_size * 3]).await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    let after = fat_entries(&fs).await;
+    let allocated = before
+        .iter()
+        .zip(&after)
+        .filter(|(b, a)| b.1 == FatValue::Free && a.1 != FatValue::Free)
+        .map(|(_, a)| *a)
+        .collect::<Vec<_>>();
+    assert_eq!(allocated.len(), 3);
+    // the chain starts at the only allocated cluster not pointed to by another one and ends after 3 clusters
+    let (mut cluster, _) = *allocated
+        .iter()
+        .find(|(n, _)| !allocated.iter().any(|(_, v)| *v == FatValue::Data(*n)))
+        .unwrap();
+    for _ in 0..2 {
+        match after[(cluster - 2) as usize].1 {
+            FatValue::Data(next) => cluster = next,
+            v => panic!("unexpected FAT entry {:?}", v),
+        }
+    }
+    assert_eq!(after[(cluster - 2) as usize].1, FatValue::EndOfChain);
+}
+
+#[tokio::test]
+async fn test_fat_entries_fat12() {
+    call_with_fs(test_fat_entries, FAT12_IMG, 26).await
+}
+
+#[tokio::test]
+async fn test_fat_entries_fat16() {
+    call_with_fs(test_fat_entries, FAT16_IMG, 26).await
+}
+
+#[tokio::test]
+async fn test_fat_entries_fat32() {
+    call_with_fs(test_fat_entries, FAT32_IMG, 26).await
+}
+
+async fn test_mark_bad_cluster(fs: FileSystem) {
+    let before = fs.free_space().await.unwrap();
+    assert_eq!(before.bad_cluster_count(), 0);
+    let cluster = before.largest_free_run_start().unwrap();
+    fs.mark_bad_cluster(cluster).await.unwrap();
+    assert!(matches!(
+        fs.mark_bad_cluster(cluster).await,
+        Err(embedded_fatfs::Error::InvalidInput)
+    ));
+    assert!(matches!(
+        fs.mark_bad_cluster(1).await,
+        Err(embedded_fatfs::Error::InvalidInput)
+    ));
+    assert_eq!(fs.stats().await.unwrap().free_clusters(), before.free_clusters() - 1);
+    // files allocated over the bad cluster skip it
+    let mut file = fs.root_dir().create_file("around.bin").await.unwrap();
+    file.write_all(&vec![0xCD; fs.cluster_size() as usize * 3])
+        .await
+        .unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+    let entries = fat_entries(&fs).await;
+    assert_eq!(entries[(cluster - 2) as usize], (cluster, FatValue::Bad));
+    let after = fs.free_space().await.unwrap();
+    assert_eq!(after.bad_cluster_count(), 1);
+    assert_eq!(after.free_clusters(), before.free_clusters() - 4);
+}
+
+#[tokio::test]
+async fn test_mark_bad_cluster_fat12() {
+    call_with_fs(test_mark_bad_cluster, FAT12_IMG, 27).await
+}
+
+#[tokio::test]
+async fn test_mark_bad_cluster_fat16() {
+    call_with_fs(test_mark_bad_cluster, FAT16_IMG, 27).await
+}
+
+#[tokio::test]
+async fn test_mark_bad_cluster_fat32() {
+    call_with_fs(test_mark_bad_cluster, FAT32_IMG, 27).await
+}
+
+// Writes a raw entry to the first FAT of an image
+async fn write_raw_fat_entry(tmp_path: &str, cluster: u32, value: u32) {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(tmp_path)
+        .await
+        .unwrap();
+    let mut boot = [0_u8; 512];
+    file.read_exact(&mut boot).await.unwrap();
+    let bytes_per_sector = u64::from(u16::from_le_bytes([boot[11], boot[12]]));
+    let reserved_sectors = u64::from(u16::from_le_bytes([boot[14], boot[15]]));
+    let fat_offset = reserved_sectors * bytes_per_sector;
+    let cluster = u64::from(cluster);
+    if tmp_path.ends_with(FAT12_IMG) {
+        let offset = fat_offset + cluster * 3 / 2;
+        let mut packed = [0_u8; 2];
+        file.seek(std::io::SeekFrom::Start(offset)).await.unwrap();
+        file.read_exact(&mut packed).await.unwrap();
+        let old = u16::from_le_bytes(packed);
+        let new = if cluster % 2 == 0 {
+            (old & 0xF000) | value as u16
+        } else {
+            (old & 0x000F) | ((value as u16) << 4)
+        };
+        file.seek(std::io::SeekFrom::Start(offset)).await.unwrap();
+        file.write_all(&new.to_le_bytes()).await.unwrap();
+    } else if tmp_path.ends_with(FAT16_IMG) {
+        file.seek(std::io::SeekFrom::Start(fat_offset + cluster * 2))
+            .await
+            .unwrap();
+        file.write_all(&(value as u16).to_le_bytes()).await.unwrap();
+    } else {
+        file.seek(std::io::SeekFrom::Start(fat_offset + cluster * 4))
+            .await
+            .unwrap();
+        file.write_all(&value.to_le_bytes()).await.unwrap();
+    }
+    file.flush().await.unwrap();
+}
+
+async fn test_circular_chain(tmp_path: String) {
+    let cluster = {
+        let fs = open_filesystem_rw(tmp_path.clone()).await;
+        let before = fat_entries(&fs).await;
+        let dir = fs.root_dir().create_dir("loop").await.unwrap();
+        let after = fat_entries(&fs).await;
+        let (cluster, _) = *before.iter().zip(&after).find(|(b, a)| b != a).unwrap().1;
+        // fill the first cluster so the directory has no end marker (each file takes a LFN and a short entry)
+        let entries_per_cluster = fs.cluster_size() / 32;
+        for i in 0..(entries_per_cluster - 2) / 2 {
+            dir.create_file(&format!("File{}.txt", i)).await.unwrap();
+        }
+        drop(dir);
+        assert_eq!(fat_entries(&fs).await, after);
+        assert_eq!(fs.check().await.unwrap(), None);
+        fs.unmount().await.unwrap();
+        cluster
+    };
+    // link the cluster to itself
+    write_raw_fat_entry(&tmp_path, cluster, cluster).await;
+    let fs = open_filesystem_rw(tmp_path.clone()).await;
+    assert_eq!(fs.check().await.unwrap(), Some(cluster));
+    // reading stops once the directory is bigger than the volume which takes long on the bigger images
+    if !tmp_path.ends_with(FAT12_IMG) {
+        return;
+    }
+    let dir = fs.root_dir().open_dir("loop").await.unwrap();
+    let mut iter = dir.iter();
+    let err = loop {
+        match iter.next().await {
+            Some(Ok(_)) => {}
+            Some(Err(err)) => break err,
+            None => panic!("circular chain was not detected"),
+        }
+    };
+    assert!(matches!(err, embedded_fatfs::Error::CorruptedFileSystem));
+}
+
+#[tokio::test]
+async fn test_circular_chain_fat12() {
+    call_with_tmp_img(test_circular_chain, FAT12_IMG, 28).await
+}
+
+#[tokio::test]
+async fn test_circular_chain_fat16() {
+    call_with_tmp_img(test_circular_chain, FAT16_IMG, 28).await
+}
+
+#[tokio::test]
+async fn test_circular_chain_fat32() {
+    call_with_tmp_img(test_circular_chain, FAT32_IMG, 28).await
+}
+
+async fn list_in_pages(
+    dir: &embedded_fatfs::Dir<
+        '_,
+        embedded_io_adapters::tokio_1::FromTokio<fs::File>,
+        ChronoTimeProvider,
+        LossyOemCpConverter,
+    >,
+) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut position = None;
+    loop {
+        // the iterator is dropped after each page
+        let mut iter = match position {
+            Some(p) => dir.iter_from(p).await.unwrap(),
+            None => dir.iter(),
+        };
+        let mut page = 0;
+        while page < 7 {
+            match iter.next().await {
+                Some(r) => {
+                    let entry = r.unwrap();
+                    names.push(entry.file_name());
+                    position = Some(entry.position());
+                    page += 1;
+                }
+                None => return names,
+            }
+        }
+    }
+}
+
+async fn test_dir_iter_from(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    let dir = root_dir.create_dir("paged").await.unwrap();
+    for i in 0..40 {
+        dir.create_file(&format!("entry-{:02}.txt", i)).await.unwrap();
+    }
+    // deleted entries are skipped when resuming too
+    dir.remove("entry-39.txt").await.unwrap();
+    for d in [&root_dir, &dir] {
+        let expected = d
+            .iter()
+            .collect()
+            .await
+            .iter()
+            .map(|r| r.as_ref().unwrap().file_name())
+            .collect::<Vec<String>>();
+        assert_eq!(list_in_pages(d).await, expected);
+    }
+    let names = list_in_pages(&dir).await;
+    assert_eq!(names.len(), 2 + 39);
+    assert_eq!(names[2], "entry-00.txt");
+}
+
+#[tokio::test]
+async fn test_dir_iter_from_fat12() {
+    call_with_fs(test_dir_iter_from, FAT12_IMG, 29).await
+}
+
+#[tokio::test]
+async fn test_dir_iter_from_fat16() {
+    call_with_fs(test_dir_iter_from, FAT16_IMG, 29).await
+}
+
+#[tokio::test]
+async fn test_dir_iter_from_fat32() {
+    call_with_fs(test_dir_iter_from, FAT32_IMG, 29).await
+}
+
+async fn test_clusters_for_path(tmp_path: String) {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+    let fs = open_filesystem_rw(tmp_path.clone()).await;
+    let cluster_size = fs.cluster_size() as usize;
+    {
+        let dir = fs.root_dir().create_dir("map").await.unwrap();
+        let mut file = dir.create_file("data.bin").await.unwrap();
+        for i in 0..3 {
+            file.write_all(&vec![i + 1; cluster_size]).await.unwrap();
+        }
+        file.flush().await.unwrap();
+        dir.create_file("empty.bin").await.unwrap();
+    }
+    let clusters = fs.clusters_for_path("map/data.bin").await.unwrap();
+    assert_eq!(clusters.len(), 3);
+    assert_eq!(fs.clusters_for_path("map").await.unwrap().len(), 1);
+    assert_eq!(fs.clusters_for_path("map/empty.bin").await.unwrap(), Vec::<u32>::new());
+    assert!(matches!(
+        fs.clusters_for_path("map/missing.bin").await,
+        Err(embedded_fatfs::Error::NotFound)
+    ));
+    let offsets = clusters.iter().map(|&n| fs.cluster_offset(n)).collect::<Vec<_>>();
+    fs.unmount().await.unwrap();
+    // the data is found at the reported positions on the storage
+    let mut img = fs::File::open(&tmp_path).await.unwrap();
+    for (i, offset) in offsets.into_iter().enumerate() {
+        let mut buf = vec![0; cluster_size];
+        img.seek(std::io::SeekFrom::Start(offset)).await.unwrap();
+        img.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, vec![i as u8 + 1; cluster_size]);
+    }
+}
+
+#[tokio::test]
+async fn test_clusters_for_path_fat12() {
+    call_with_tmp_img(test_clusters_for_path, FAT12_IMG, 30).await
+}
+
+#[tokio::test]
+async fn test_clusters_for_path_fat16() {
+    call_with_tmp_img(test_clusters_for_path, FAT16_IMG, 30).await
+}
+
+#[tokio::test]
+async fn test_clusters_for_path_fat32() {
+    call_with_tmp_img(test_clusters_for_path, FAT32_IMG, 30).await
+}
+
+async fn test_relabel_fstype(tmp_path: String) {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+    // offsets of the fs_type_label field in the boot sector and the backup boot sector
+    let is_fat32 = tmp_path.ends_with(FAT32_IMG);
+    let (offsets, expected): (Vec<u64>, &[u8]) = if is_fat32 {
+        (vec![0x52, 6 * 512 + 0x52], b"FAT32   ")
+    } else if tmp_path.ends_with(FAT16_IMG) {
+        (vec![0x36], b"FAT16   ")
+    } else {
+        (vec![0x36], b"FAT12   ")
+    };
+    {
+        let mut file = fs::OpenOptions::new().write(true).open(&tmp_path).await.unwrap();
+        for &offset in &offsets {
+            file.seek(std::io::SeekFrom::Start(offset)).await.unwrap();
+            file.write_all(b"FAT     ").await.unwrap();
+        }
+    }
+
+    let fs = open_filesystem_rw(tmp_path.clone()).await;
+    assert!(fs.relabel_fstype().await.unwrap());
+    assert!(!fs.relabel_fstype().await.unwrap());
+    fs.unmount().await.unwrap();
+
+    let mut file = fs::File::open(&tmp_path).await.unwrap();
+    for &offset in &offsets {
+        let mut label = [0_u8; 8];
+        file.seek(std::io::SeekFrom::Start(offset)).await.unwrap();
+        file.read_exact(&mut label).await.unwrap();
+        assert_eq!(&label, expected);
+    }
+    // a read-only volume is never relabeled
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&tmp_path)
+        .await
+        .unwrap();
+    let fs = FileSystem::new(file, FsOptions::new().read_only(true)).await.unwrap();
+    assert!(matches!(
+        fs.relabel_fstype().await,
+        Err(embedded_fatfs::Error::ReadOnly)
+    ));
+}
+
+#[tokio::test]
+async fn test_relabel_fstype_fat12() {
+    call_with_tmp_img(test_relabel_fstype, FAT12_IMG, 31).await
+}
+
+#[tokio::test]
+async fn test_relabel_fstype_fat16() {
+    call_with_tmp_img(test_relabel_fstype, FAT16_IMG, 31).await
+}
+
+#[tokio::test]
+async fn test_relabel_fstype_fat32() {
+    call_with_tmp_img(test_relabel_fstype, FAT32_IMG, 31).await
+}
+
+async fn test_backup_boot_sector(tmp_path: String) {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+    let is_fat32 = tmp_path.ends_with(FAT32_IMG);
+    {
+        let fs = open_filesystem_rw(tmp_path.clone()).await;
+        assert_eq!(fs.backup_boot_sector(), if is_fat32 { Some(6) } else { None });
+        assert_eq!(fs.verify_backup_boot_sector().await.unwrap(), None);
+        // writing a file sets the dirty flag and changes the free cluster count
+        let mut file = fs.root_dir().create_file("new.txt").await.unwrap();
+        file.write_all(TEST_STR.as_bytes()).await.unwrap();
+        file.flush().await.unwrap();
+        drop(file);
+        assert!(fs.is_dirty());
+        assert_eq!(fs.verify_backup_boot_sector().await.unwrap(), None);
+        fs.unmount().await.unwrap();
+    }
+
+    let mut image = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&tmp_path)
+        .await
+        .unwrap();
+    if is_fat32 {
+        // the backup FS Information Sector follows the backup Boot Sector
+        let mut fs_info = [0_u8; 512];
+        let mut backup_fs_info = [0_u8; 512];
+        image.seek(std::io::SeekFrom::Start(512)).await.unwrap();
+        image.read_exact(&mut fs_info).await.unwrap();
+        image.seek(std::io::SeekFrom::Start(7 * 512)).await.unwrap();
+        image.read_exact(&mut backup_fs_info).await.unwrap();
+        assert_eq!(fs_info, backup_fs_info);
+        // corrupt the volume id in the backup
+        image.seek(std::io::SeekFrom::Start(6 * 512 + 0x43)).await.unwrap();
+        image.write_all(&[0xAB]).await.unwrap();
+    }
+    drop(image);
+
+    let fs = open_filesystem_rw(tmp_path).await;
+    let expected = if is_fat32 { Some(0x43) } else { None };
+    assert_eq!(fs.verify_backup_boot_sector().await.unwrap(), expected);
+}
+
+#[tokio::test]
+async fn test_backup_boot_sector_fat12() {
+    call_with_tmp_img(test_backup_boot_sector, FAT12_IMG, 32).await
+}
+
+#[tokio::test]
+async fn test_backup_boot_sector_fat16() {
+    call_with_tmp_img(test_backup_boot_sector, FAT16_IMG, 32).await
+}
+
+#[tokio::test]
+async fn test_backup_boot_sector_fat32() {
+    call_with_tmp_img(test_backup_boot_sector, FAT32_IMG, 32).await
+}
+
+async fn test_copy_file(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    let mut file = root_dir.open_file("long.txt").await.unwrap();
+    let long_content = read_to_end(&mut file).await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+    let mut file = root_dir.open_file("short.txt").await.unwrap();
+    let short_content = read_to_end(&mut file).await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+    let free_before = fs.stats().await.unwrap().free_clusters();
+
+    root_dir
+        .copy_file("long.txt", &root_dir, "very/long/path/copy.txt", false)
+        .await
+        .unwrap();
+    let mut copy = root_dir.open_file("very/long/path/copy.txt").await.unwrap();
+    assert_eq!(read_to_end(&mut copy).await.unwrap(), long_content);
+    copy.flush().await.unwrap();
+    drop(copy);
+    let src_meta = root_dir.open_meta("long.txt").await.unwrap();
+    let dst_meta = root_dir.open_meta("very/long/path/copy.txt").await.unwrap();
+    assert_eq!(dst_meta.attributes(), src_meta.attributes());
+    assert_eq!(dst_meta.len(), src_meta.len());
+    // the copy has its own cluster chain
+    let src_clusters = fs.clusters_for_path("long.txt").await.unwrap();
+    let dst_clusters = fs.clusters_for_path("very/long/path/copy.txt").await.unwrap();
+    assert_eq!(dst_clusters.len(), src_clusters.len());
+    assert!(dst_clusters.iter().all(|c| !src_clusters.contains(c)));
+    assert_eq!(
+        fs.stats().await.unwrap().free_clusters(),
+        free_before - dst_clusters.len() as u32
+    );
+
+    let dst_dir = root_dir.open_dir("very/long/path").await.unwrap();
+    assert!(matches!(
+        root_dir.copy_file("short.txt", &dst_dir, "copy.txt", false).await,
+        Err(embedded_fatfs::Error::AlreadyExists)
+    ));
+    root_dir
+        .copy_file("short.txt", &dst_dir, "copy.txt", true)
+        .await
+        .unwrap();
+    let mut copy = dst_dir.open_file("copy.txt").await.unwrap();
+    assert_eq!(read_to_end(&mut copy).await.unwrap(), short_content);
+    copy.flush().await.unwrap();
+    drop(copy);
+    assert!(matches!(
+        root_dir.copy_file("long.txt", &root_dir, "LONG.TXT", true).await,
+        Err(embedded_fatfs::Error::InvalidInput)
+    ));
+    assert!(matches!(
+        root_dir.copy_file("very", &root_dir, "very-copy", false).await,
+        Err(embedded_fatfs::Error::InvalidInput)
+    ));
+    assert!(matches!(
+        root_dir.copy_file("missing.txt", &root_dir, "copy.txt", false).await,
+        Err(embedded_fatfs::Error::NotFound)
+    ));
+    // a copy failing when the volume becomes full is removed
+    let mut file = root_dir.create_file("two-clusters.bin").await.unwrap();
+    file.write_all(&vec![0x5A; fs.cluster_size() as usize * 2])
+        .await
+        .unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+    let mut filler = root_dir.create_file("filler.bin").await.unwrap();
+    let free_clusters = fs.stats().await.unwrap().free_clusters();
+    filler.allocate((free_clusters - 1) * fs.cluster_size()).await.unwrap();
+    filler.flush().await.unwrap();
+    drop(filler);
+    assert!(matches!(
+        root_dir
+            .copy_file("two-clusters.bin", &root_dir, "full-copy.bin", false)
+            .await,
+        Err(embedded_fatfs::Error::NotEnoughSpace)
+    ));
+    assert!(root_dir.try_open_file("full-copy.bin").await.unwrap().is_none());
+    assert_eq!(fs.stats().await.unwrap().free_clusters(), 1);
+    root_dir.remove("filler.bin").await.unwrap();
+    root_dir.remove("two-clusters.bin").await.unwrap();
+    assert_eq!(fs.check().await.unwrap(), None);
+    let free_space = fs.free_space().await.unwrap();
+    assert_eq!(free_space.free_clusters(), fs.stats().await.unwrap().free_clusters());
+}
+
+#[tokio::test]
+async fn test_copy_file_fat12() {
+    call_with_fs(test_copy_file, FAT12_IMG, 33).await
+}
+
+#[tokio::test]
+async fn test_copy_file_fat16() {
+    call_with_fs(test_copy_file, FAT16_IMG, 33).await
+}
+
+#[tokio::test]
+async fn test_copy_file_fat32() {
+    call_with_fs(test_copy_file, FAT32_IMG, 33).await
+}
+
+async fn test_read_to_string_invalid_utf8(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    let mut file = root_dir.create_file("binary.bin").await.unwrap();
+    file.write_all(&[b'a', 0xFF, 0xFE]).await.unwrap();
+    file.flush().await.unwrap();
+    file.seek(SeekFrom::Start(0)).await.unwrap();
+    let mut text = String::from("unchanged");
+    assert!(matches!(
+        file.read_to_string(&mut text).await,
+        Err(embedded_fatfs::Error::InvalidData)
+    ));
+    assert_eq!(text, "unchanged");
+    file.seek(SeekFrom::Start(0)).await.unwrap();
+    let mut buf = Vec::new();
+    assert_eq!(file.read_to_end(&mut buf).await.unwrap(), 3);
+    assert_eq!(buf, [b'a', 0xFF, 0xFE]);
+    file.flush().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_read_to_string_invalid_utf8_fat12() {
+    call_with_fs(test_read_to_string_invalid_utf8, FAT12_IMG, 34).await
+}
+
+#[tokio::test]
+async fn test_read_to_string_invalid_utf8_fat16() {
+    call_with_fs(test_read_to_string_invalid_utf8, FAT16_IMG, 34).await
+}
+
+#[tokio::test]
+async fn test_read_to_string_invalid_utf8_fat32() {
+    call_with_fs(test_read_to_string_invalid_utf8, FAT32_IMG, 34).await
+}
+
+async fn test_create_file_with_attributes(tmp_path: String) {
+    let attrs = FileAttributes::HIDDEN | FileAttributes::SYSTEM;
+    {
+        let fs = open_filesystem_rw(tmp_path.clone()).await;
+        let root_dir = fs.root_dir();
+        let mut file = root_dir.create_file_with_attributes(".update", attrs).await.unwrap();
+        file.write_all(TEST_STR.as_bytes()).await.unwrap();
+        file.flush().await.unwrap();
+        drop(file);
+        // attributes of an existing file are kept
+        let file = root_dir
+            .create_file_with_attributes(".update", FileAttributes::READ_ONLY)
+            .await
+            .unwrap();
+        drop(file);
+        for invalid in [
+            FileAttributes::DIRECTORY,
+            FileAttributes::VOLUME_ID,
+            FileAttributes::LFN,
+        ] {
+            assert!(matches!(
+                root_dir.create_file_with_attributes("invalid", invalid).await,
+                Err(embedded_fatfs::Error::InvalidInput)
+            ));
+        }
+        root_dir.create_file("plain").await.unwrap().close().await.unwrap();
+        drop(root_dir);
+        fs.unmount().await.unwrap();
+    }
+
+    let fs = open_filesystem_rw(tmp_path).await;
+    let root_dir = fs.root_dir();
+    assert_eq!(root_dir.open_meta(".update").await.unwrap().attributes(), attrs);
+    assert_eq!(
+        root_dir.open_meta("plain").await.unwrap().attributes(),
+        FileAttributes::empty()
+    );
+    assert!(matches!(
+        root_dir.open_meta("invalid").await,
+        Err(embedded_fatfs::Error::NotFound)
+    ));
+    let mut file = root_dir.open_file(".update").await.unwrap();
+    assert_eq!(read_to_end(&mut file).await.unwrap(), TEST_STR.as_bytes());
+}
+
+#[tokio::test]
+async fn test_create_file_with_attributes_fat12() {
+    call_with_tmp_img(test_create_file_with_attributes, FAT12_IMG, 35).await
+}
+
+#[tokio::test]
+async fn test_create_file_with_attributes_fat16() {
+    call_with_tmp_img(test_create_file_with_attributes, FAT16_IMG, 35).await
+}
+
+#[tokio::test]
+async fn test_create_file_with_attributes_fat32() {
+    call_with_tmp_img(test_create_file_with_attributes, FAT32_IMG, 35).await
+}
+
+async fn test_short_name_starting_with_e5(tmp_path: String) {
+    use tokio::io::AsyncReadExt;
+    let open_fs = || async {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&tmp_path)
+            .await
+            .unwrap();
+        let options = FsOptions::new().oem_cp_converter(embedded_fatfs::Cp850OemCpConverter::new());
+        embedded_fatfs::FileSystem::new(file, options).await.unwrap()
+    };
+    // U+00D5 is encoded as 0xE5 in the code page 850
+    let name = "\u{D5}dla.txt";
+    let position = {
+        let fs = open_fs().await;
+        let root_dir = fs.root_dir();
+        let mut file = root_dir.create_file(name).await.unwrap();
+        file.write_all(TEST_STR.as_bytes()).await.unwrap();
+        file.flush().await.unwrap();
+        drop(file);
+        let entry = root_dir.open_meta(name).await.unwrap();
+        assert_eq!(entry.short_file_name(), "\u{D5}DLA.TXT");
+        assert_eq!(entry.short_file_name_as_bytes(), b"\xE5DLA.TXT");
+        let position = entry.position();
+        drop(root_dir);
+        fs.unmount().await.unwrap();
+        position
+    };
+
+    // the first byte is stored as 0x05 so the entry is not treated as deleted
+    let mut image = Vec::new();
+    fs::File::open(&tmp_path)
+        .await
+        .unwrap()
+        .read_to_end(&mut image)
+        .await
+        .unwrap();
+    assert!(image.windows(SFN_SIZE).any(|w| w == b"\x05DLA    TXT"));
+
+    let fs = open_fs().await;
+    let root_dir = fs.root_dir();
+    let mut file = root_dir.open_file("\u{D5}DLA.TXT").await.unwrap();
+    assert_eq!(read_to_end(&mut file).await.unwrap(), TEST_STR.as_bytes());
+    drop(file);
+    let mut iter = root_dir.iter();
+    let mut found = false;
+    while let Some(entry) = iter.next().await {
+        found |= entry.unwrap().file_name() == name;
+    }
+    assert!(found);
+
+    // entries of a removed file are reused
+    root_dir.remove(name).await.unwrap();
+    let file = root_dir.create_file("New1.txt").await.unwrap();
+    drop(file);
+    assert_eq!(root_dir.open_meta("New1.txt").await.unwrap().position(), position);
+}
+
+#[tokio::test]
+async fn test_short_name_starting_with_e5_fat12() {
+    call_with_tmp_img(test_short_name_starting_with_e5, FAT12_IMG, 36).await
+}
+
+#[tokio::test]
+async fn test_short_name_starting_with_e5_fat16() {
+    call_with_tmp_img(test_short_name_starting_with_e5, FAT16_IMG, 36).await
+}
+
+#[tokio::test]
+async fn test_short_name_starting_with_e5_fat32() {
+    call_with_tmp_img(test_short_name_starting_with_e5, FAT32_IMG, 36).await
+}
+
+async fn test_create_reuses_deleted_entries(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    let dir = root_dir.create_dir("reuse").await.unwrap();
+    // 40 characters need 4 LFN entries and the short entry
+    let name_a = "a".repeat(40);
+    let name_b = "b".repeat(53);
+    let name_c = "c".repeat(40);
+    drop(dir.create_file(&name_a).await.unwrap());
+    drop(dir.create_file("LAST.TXT").await.unwrap());
+    let position_a = dir.open_meta(&name_a).await.unwrap().position();
+    dir.remove(&name_a).await.unwrap();
+
+    // 6 entries do not fit in the gap of 5 entries
+    drop(dir.create_file(&name_b).await.unwrap());
+    drop(dir.create_file(&name_c).await.unwrap());
+    assert_eq!(dir.open_meta(&name_c).await.unwrap().position(), position_a);
+
+    let mut names = Vec::new();
+    let mut iter = dir.iter();
+    while let Some(entry) = iter.next().await {
+        names.push(entry.unwrap().file_name());
+    }
+    assert_eq!(names, [".", "..", name_c.as_str(), "LAST.TXT", name_b.as_str()]);
+}
+
+#[tokio::test]
+async fn test_create_reuses_deleted_entries_fat12() {
+    call_with_fs(test_create_reuses_deleted_entries, FAT12_IMG, 37).await
+}
+
+#[tokio::test]
+async fn test_create_reuses_deleted_entries_fat16() {
+    call_with_fs(test_create_reuses_deleted_entries, FAT16_IMG, 37).await
+}
+
+#[tokio::test]
+async fn test_create_reuses_deleted_entries_fat32() {
+    call_with_fs(test_create_reuses_deleted_entries, FAT32_IMG, 37).await
+}
+
+async fn test_short_names_only(tmp_path: String) {
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&tmp_path)
+        .await
+        .unwrap();
+    let fs = FileSystem::new(file, FsOptions::new().short_names_only(true))
+        .await
+        .unwrap();
+    {
+        let root_dir = fs.root_dir();
+        // long names are ignored
+        let mut names = Vec::new();
+        let mut iter = root_dir.iter();
+        while let Some(entry) = iter.next().await {
+            names.push(entry.unwrap().file_name());
+        }
+        assert_eq!(names, ["LONG.TXT", "SHORT.TXT", "VERY", "VERY-L~1"]);
+        assert!(root_dir.open_dir("VERY-L~1").await.is_ok());
+        assert!(matches!(
+            root_dir.open_dir("very-long-dir-name").await,
+            Err(embedded_fatfs::Error::NotFound)
+        ));
+
+        // names not fitting in 8.3 are rejected
+        for name in ["long-name.txt", "name.text", "a.b.c", "with space.txt", ".hidden"] {
+            assert!(matches!(
+                root_dir.create_file(name).await,
+                Err(embedded_fatfs::Error::InvalidInput)
+            ));
+            assert!(matches!(
+                root_dir.create_dir(name).await,
+                Err(embedded_fatfs::Error::InvalidInput)
+            ));
+        }
+        assert!(matches!(
+            root_dir.rename("short.txt", &root_dir, "renamed-file.txt").await,
+            Err(embedded_fatfs::Error::InvalidInput)
+        ));
+
+        // names are uppercased and no LFN entries are written
+        let mut file = root_dir.create_file("readme.txt").await.unwrap();
+        file.write_all(TEST_STR.as_bytes()).await.unwrap();
+        file.flush().await.unwrap();
+        drop(file);
+        let dir = root_dir.create_dir("newdir").await.unwrap();
+        assert_eq!(dir.iter().collect().await.len(), 2);
+        let position = root_dir.open_meta("readme.txt").await.unwrap().position();
+        root_dir.remove("readme.txt").await.unwrap();
+        drop(root_dir.create_file("NOTES.TXT").await.unwrap());
+        // a single entry is reused
+        assert_eq!(root_dir.open_meta("notes.txt").await.unwrap().position(), position);
+    }
+    fs.unmount().await.unwrap();
+
+    let fs = open_filesystem_rw(tmp_path).await;
+    let root_dir = fs.root_dir();
+    let entry = root_dir.open_meta("newdir").await.unwrap();
+    assert_eq!(entry.file_name(), "NEWDIR");
+    assert_eq!(entry.long_file_name_as_ucs2_units(), None);
+    let entry = root_dir.open_meta("notes.txt").await.unwrap();
+    assert_eq!(entry.file_name(), "NOTES.TXT");
+    assert_eq!(entry.long_file_name_as_ucs2_units(), None);
+}
+
+#[tokio::test]
+async fn test_short_names_only_fat12() {
+    call_with_tmp_img(test_short_names_only, FAT12_IMG, 38).await
+}
+
+#[tokio::test]
+async fn test_short_names_only_fat16() {
+    call_with_tmp_img(test_short_names_only, FAT16_IMG, 38).await
+}
+
+#[tokio::test]
+async fn test_short_names_only_fat32() {
+    call_with_tmp_img(test_short_names_only, FAT32_IMG, 38).await
+}
+
+async fn test_check_consistency(tmp_path: String) {
+    use embedded_fatfs::CheckFinding;
+    use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+    let (a, b, free, dot_dot_offset) = {
+        let fs = open_filesystem_rw(tmp_path.clone()).await;
+        assert_eq!(fs.check_consistency().await.unwrap(), vec![]);
+        let cluster_size = fs.cluster_size() as usize;
+        let root_dir = fs.root_dir();
+        for name in ["a.bin", "b.bin"] {
+            let mut file = root_dir.create_file(name).await.unwrap();
+            file.write_all(&vec![0x11; cluster_size * 2]).await.unwrap();
+            file.flush().await.unwrap();
+        }
+        root_dir.create_dir("d").await.unwrap();
+        assert_eq!(fs.check_consistency().await.unwrap(), vec![]);
+        let a = fs.clusters_for_path("a.bin").await.unwrap();
+        let b = fs.clusters_for_path("b.bin").await.unwrap();
+        let d = fs.clusters_for_path("d").await.unwrap();
+        let free = fat_entries(&fs)
+            .await
+            .iter()
+            .find(|(_, v)| *v == FatValue::Free)
+            .unwrap()
+            .0;
+        // first cluster field of the second entry in the directory
+        let dot_dot_offset = fs.cluster_offset(d[0]) + 32 + 26;
+        drop(root_dir);
+        fs.unmount().await.unwrap();
+        (a, b, free, dot_dot_offset)
+    };
+    let end_of_chain = if tmp_path.ends_with(FAT12_IMG) {
+        0xFFF
+    } else if tmp_path.ends_with(FAT16_IMG) {
+        0xFFFF
+    } else {
+        0x0FFF_FFFF
+    };
+    // cut a.bin after the first cluster, link b.bin into a.bin and allocate a free cluster (only in the first FAT)
+    write_raw_fat_entry(&tmp_path, a[0], end_of_chain).await;
+    write_raw_fat_entry(&tmp_path, b[1], a[0]).await;
+    write_raw_fat_entry(&tmp_path, free, end_of_chain).await;
+    // point `..` of d to a.bin
+    let mut file = fs::OpenOptions::new().write(true).open(&tmp_path).await.unwrap();
+    file.seek(std::io::SeekFrom::Start(dot_dot_offset)).await.unwrap();
+    file.write_all(&(a[0] as u16).to_le_bytes()).await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    let fs = open_filesystem_rw(tmp_path.clone()).await;
+    let mut lost = vec![
+        CheckFinding::LostChain {
+            first_cluster: a[1],
+            clusters: 1,
+        },
+        CheckFinding::LostChain {
+            first_cluster: free,
+            clusters: 1,
+        },
+    ];
+    lost.sort_by_key(|f| match f {
+        CheckFinding::LostChain { first_cluster, .. } => *first_cluster,
+        _ => unreachable!(),
+    });
+    let mut expected = vec![
+        CheckFinding::SizeMismatch {
+            path: "/a.bin".into(),
+            size: u64::from(fs.cluster_size()) * 2,
+            clusters: 1,
+        },
+        CheckFinding::CrossLinked {
+            path: "/b.bin".into(),
+            cluster: a[0],
+        },
+        CheckFinding::InvalidDotEntry { path: "/d".into() },
+    ];
+    expected.extend(lost);
+    expected.push(CheckFinding::FatMismatch {
+        cluster: a[0].min(b[1]).min(free),
+    });
+    assert_eq!(fs.check_consistency().await.unwrap(), expected);
+    // the volume is not modified
+    assert_eq!(fs.check_consistency().await.unwrap(), expected);
+}
+
+#[tokio::test]
+async fn test_check_consistency_fat12() {
+    call_with_tmp_img(test_check_consistency, FAT12_IMG, 39).await
+}
+
+#[tokio::test]
+async fn test_check_consistency_fat16() {
+    call_with_tmp_img(test_check_consistency, FAT16_IMG, 39).await
+}
+
+#[tokio::test]
+async fn test_check_consistency_fat32() {
+    call_with_tmp_img(test_check_consistency, FAT32_IMG, 39).await
+}
+
+async fn test_repair(tmp_path: String) {
+    use embedded_fatfs::RepairOptions;
+    let (a, b, free) = {
+        let fs = open_filesystem_rw(tmp_path.clone()).await;
+        let cluster_size = fs.cluster_size() as usize;
+        let root_dir = fs.root_dir();
+        for name in ["a.bin", "b.bin"] {
+            let mut file = root_dir.create_file(name).await.unwrap();
+            file.write_all(&vec![0x11; cluster_size * 2]).await.unwrap();
+            file.flush().await.unwrap();
+        }
+        let a = fs.clusters_for_path("a.bin").await.unwrap();
+        let b = fs.clusters_for_path("b.bin").await.unwrap();
+        let free = fat_entries(&fs)
+            .await
+            .iter()
+            .filter(|(_, v)| *v == FatValue::Free)
+            .map(|(n, _)| *n)
+            .take(2)
+            .collect::<Vec<_>>();
+        drop(root_dir);
+        fs.unmount().await.unwrap();
+        (a, b, free)
+    };
+    let end_of_chain = if tmp_path.ends_with(FAT12_IMG) {
+        0xFFF
+    } else if tmp_path.ends_with(FAT16_IMG) {
+        0xFFFF
+    } else {
+        0x0FFF_FFFF
+    };
+    // cut a.bin after the first cluster, append a cluster to b.bin and allocate a lost cluster
+    write_raw_fat_entry(&tmp_path, a[0], end_of_chain).await;
+    write_raw_fat_entry(&tmp_path, b[1], free[0]).await;
+    write_raw_fat_entry(&tmp_path, free[0], end_of_chain).await;
+    write_raw_fat_entry(&tmp_path, free[1], end_of_chain).await;
+
+    let fs = open_filesystem_rw(tmp_path.clone()).await;
+    let cluster_size = fs.cluster_size();
+    let mut fat_start = [0_u8; 4];
+    assert_eq!(fs.read_fat_bytes(0, &mut fat_start).await.unwrap(), 4);
+    assert_eq!(fat_start[0], 0xF8);
+    assert_eq!(fs.read_fat_bytes(u64::MAX, &mut fat_start).await.unwrap(), 0);
+
+    let findings = fs.check_consistency().await.unwrap();
+    assert_eq!(findings.len(), 5);
+    // nothing is changed without enabling a repair
+    assert_eq!(fs.repair(RepairOptions::new()).await.unwrap(), findings);
+    assert_eq!(fs.check_consistency().await.unwrap(), findings);
+
+    let options = RepairOptions::new().free_lost_chains(true).fix_file_sizes(true);
+    assert_eq!(fs.repair(options).await.unwrap(), findings);
+    // truncating a.bin rewrote the end of its chain in all FAT copies too
+    assert_eq!(fs.check_consistency().await.unwrap(), vec![]);
+    let root_dir = fs.root_dir();
+    let mut file = root_dir.open_file("a.bin").await.unwrap();
+    assert_eq!(read_to_end(&mut file).await.unwrap(), vec![0x11; cluster_size as usize]);
+    drop(file);
+    let mut file = root_dir.open_file("b.bin").await.unwrap();
+    assert_eq!(
+        read_to_end(&mut file).await.unwrap(),
+        vec![0x11; cluster_size as usize * 2]
+    );
+    drop(file);
+    drop(root_dir);
+    assert_eq!(fs.clusters_for_path("b.bin").await.unwrap(), b);
+    let free_count = fat_entries(&fs)
+        .await
+        .iter()
+        .filter(|(_, v)| *v == FatValue::Free)
+        .count();
+    assert_eq!(fs.stats().await.unwrap().free_clusters(), free_count as u32);
+    fs.unmount().await.unwrap();
+
+    // repairs need a writable filesystem
+    let file = fs::OpenOptions::new().read(true).open(&tmp_path).await.unwrap();
+    let fs = FileSystem::new(file, FsOptions::new().read_only(true)).await.unwrap();
+    let options = RepairOptions::new().free_lost_chains(true);
+    assert!(matches!(fs.repair(options).await, Err(embedded_fatfs::Error::ReadOnly)));
+}
+
+#[tokio::test]
+async fn test_repair_fat12() {
+    call_with_tmp_img(test_repair, FAT12_IMG, 40).await
+}
+
+#[tokio::test]
+async fn test_repair_fat16() {
+    call_with_tmp_img(test_repair, FAT16_IMG, 40).await
+}
+
+#[tokio::test]
+async fn test_repair_fat32() {
+    call_with_tmp_img(test_repair, FAT32_IMG, 40).await
+}
+
+async fn test_open_file_with(tmp_path: String) {
+    use embedded_fatfs::{Error, OpenOptions};
+    let fs = open_filesystem_rw(tmp_path).await;
+    let root_dir = fs.root_dir();
+    let read_write = OpenOptions::new().read(true).write(true);
+
+    // invalid combinations of options
+    for options in [
+        OpenOptions::new(),
+        OpenOptions::new().read(true).create(true),
+        OpenOptions::new().read(true).truncate(true),
+        OpenOptions::new().append(true).truncate(true),
+    ] {
+        assert!(matches!(
+            root_dir.open_file_with("new.txt", options).await,
+            Err(Error::InvalidInput)
+        ));
+    }
+    assert!(matches!(
+        root_dir.open_file_with("new.txt", read_write).await,
+        Err(Error::NotFound)
+    ));
+    assert!(matches!(
+        root_dir.open_file_with("short.txt", read_write.create_new(true)).await,
+        Err(Error::AlreadyExists)
+    ));
+    assert!(matches!(
+        root_dir.open_file_with("very", read_write.create_new(true)).await,
+        Err(Error::AlreadyExists)
+    ));
+
+    let mut file = root_dir
+        .open_file_with("new.txt", read_write.create_new(true))
+        .await
+        .unwrap();
+    file.write_all(TEST_STR.as_bytes()).await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    // truncate empties an existing file
+    let mut file = root_dir
+        .open_file_with("short.txt", read_write.truncate(true))
+        .await
+        .unwrap();
+    assert_eq!(read_to_end(&mut file).await.unwrap(), b"");
+    drop(file);
+    let mut file = root_dir.open_file("short.txt").await.unwrap();
+    assert_eq!(read_to_end(&mut file).await.unwrap(), b"");
+    drop(file);
+
+    // writes in append mode go to the end of the file even after seeking or growing the file by another handle
+    let mut file = root_dir
+        .open_file_with("new.txt", OpenOptions::new().read(true).append(true))
+        .await
+        .unwrap();
+    file.seek(SeekFrom::Start(0)).await.unwrap();
+    file.write_all(TEST_STR2.as_bytes()).await.unwrap();
+    file.flush().await.unwrap();
+    let mut other = root_dir.open_file("new.txt").await.unwrap();
+    other.seek(SeekFrom::End(0)).await.unwrap();
+    other.write_all(TEST_STR.as_bytes()).await.unwrap();
+    other.flush().await.unwrap();
+    drop(other);
+    file.write_all(TEST_STR2.as_bytes()).await.unwrap();
+    file.seek(SeekFrom::Start(0)).await.unwrap();
+    let expected = [TEST_STR, TEST_STR2, TEST_STR, TEST_STR2].concat();
+    assert_eq!(
+        str::from_utf8(&read_to_end(&mut file).await.unwrap()).unwrap(),
+        expected
+    );
+    file.flush().await.unwrap();
+    drop(file);
+
+    // the access mode is enforced
+    let mut file = root_dir
+        .open_file_with("new.txt", OpenOptions::new().read(true))
+        .await
+        .unwrap();
+    assert!(matches!(file.write(b"x").await, Err(Error::InvalidInput)));
+    assert!(matches!(file.truncate().await, Err(Error::InvalidInput)));
+    drop(file);
+    let mut file = root_dir
+        .open_file_with("new.txt", OpenOptions::new().write(true))
+        .await
+        .unwrap();
+    let mut buf = [0_u8; 4];
+    assert!(matches!(
+        embedded_io_async::Read::read(&mut file, &mut buf).await,
+        Err(Error::InvalidInput)
+    ));
+    drop(file);
+    drop(root_dir);
+    fs.unmount().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_open_file_with_fat12() {
+    call_with_tmp_img(test_open_file_with, FAT12_IMG, 41).await
+}
+
+#[tokio::test]
+async fn test_open_file_with_fat16() {
+    call_with_tmp_img(test_open_file_with, FAT16_IMG, 41).await
+}
+
+#[tokio::test]
+async fn test_open_file_with_fat32() {
+    call_with_tmp_img(test_open_file_with, FAT32_IMG, 41).await
+}
+
+async fn test_create_new(tmp_path: String) {
+    use embedded_fatfs::{Error, OpenOptions};
+    let fs = open_filesystem_rw(tmp_path).await;
+    let root_dir = fs.root_dir();
+    let options = OpenOptions::new().write(true).create_new(true);
+
+    let file = root_dir.open_file_with("lock.txt", options).await.unwrap();
+    drop(file);
+    assert!(matches!(
+        root_dir.open_file_with("lock.txt", options).await,
+        Err(Error::AlreadyExists)
+    ));
+    assert!(matches!(
+        root_dir.create_new_dir("lock.txt").await,
+        Err(Error::AlreadyExists)
+    ));
+
+    // only one of the creations polled concurrently succeeds
+    let (a, b) = tokio::join!(
+        root_dir.open_file_with("race.txt", options),
+        root_dir.open_file_with("race.txt", options)
+    );
+    assert!(a.is_ok() != b.is_ok());
+    assert!(matches!(a.err().or(b.err()), Some(Error::AlreadyExists)));
+    let (a, b) = tokio::join!(root_dir.create_new_dir("race-dir"), root_dir.create_new_dir("race-dir"));
+    assert!(a.is_ok() != b.is_ok());
+    assert!(matches!(a.err().or(b.err()), Some(Error::AlreadyExists)));
+    let (a, b) = tokio::join!(root_dir.create_dir("race-dir2"), root_dir.create_dir("race-dir2"));
+    assert!(a.is_ok() && b.is_ok());
+    drop((a, b));
+
+    let mut names = Vec::new();
+    let mut iter = root_dir.iter();
+    while let Some(r) = iter.next().await {
+        names.push(r.unwrap().file_name());
+    }
+    for name in ["lock.txt", "race.txt", "race-dir", "race-dir2"] {
+        assert_eq!(names.iter().filter(|n| n.as_str() == name).count(), 1);
+    }
+    drop(iter);
+    drop(root_dir);
+    fs.unmount().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_create_new_fat12() {
+    call_with_tmp_img(test_create_new, FAT12_IMG, 42).await
+}
+
+#[tokio::test]
+async fn test_create_new_fat16() {
+    call_with_tmp_img(test_create_new, FAT16_IMG, 42).await
+}
+
+#[tokio::test]
+async fn test_create_new_fat32() {
+    call_with_tmp_img(test_create_new, FAT32_IMG, 42).await
+}
+
+async fn test_short_name_case(tmp_path: String) {
+    let fs = open_filesystem_rw(tmp_path).await;
+    let root_dir = fs.root_dir();
+    for (name, short_name) in [
+        ("readme.txt", "readme.txt"),
+        ("notes.TXT", "notes.TXT"),
+        ("Mixed.txt", "MIXED.TXT"),
+        ("makefile", "makefile"),
+        ("long file name.txt", "LONGFI~1.TXT"),
+    ] {
+        root_dir.create_file(name).await.unwrap();
+        let entry = root_dir.open_meta(name).await.unwrap();
+        assert_eq!(entry.file_name(), name);
+        assert_eq!(entry.short_file_name(), short_name);
+    }
+    let entry = root_dir.open_meta("readme.txt").await.unwrap();
+    assert_eq!(&entry.short_name_raw(), b"README  TXT");
+    assert_eq!(entry.short_file_name_as_bytes(), b"README.TXT");
+    // the case of the old name is not kept
+    root_dir.rename("readme.txt", &root_dir, "README.MD").await.unwrap();
+    let entry = root_dir.open_meta("readme.md").await.unwrap();
+    assert_eq!(&entry.short_name_raw(), b"README  MD ");
+    assert_eq!(entry.short_file_name(), "README.MD");
+    drop(root_dir);
+    fs.unmount().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_short_name_case_fat12() {
+    call_with_tmp_img(test_short_name_case, FAT12_IMG, 44).await
+}
+
+#[tokio::test]
+async fn test_short_name_case_fat16() {
+    call_with_tmp_img(test_short_name_case, FAT16_IMG, 44).await
+}
+
+#[tokio::test]
+async fn test_short_name_case_fat32() {
+    call_with_tmp_img(test_short_name_case, FAT32_IMG, 44).await
+}
+
+async fn test_short_name_case_round_trip(tmp_path: String) {
+    // the names differ only in case so every one is created in its own directory
+    let names = ["readme.txt", "Readme.TXT", "README.TXT"];
+    let fs = open_filesystem_rw(tmp_path.clone()).await;
+    for (i, name) in names.iter().enumerate() {
+        let dir = fs.root_dir().create_dir(&format!("case{}", i)).await.unwrap();
+        dir.create_file(name).await.unwrap();
+    }
+    fs.unmount().await.unwrap();
+
+    let fs = open_filesystem_rw(tmp_path).await;
+    let mut entries = Vec::new();
+    for i in 0..names.len() {
+        let dir = fs.root_dir().open_dir(&format!("case{}", i)).await.unwrap();
+        let mut iter = dir.iter();
+        while let Some(r) = iter.next().await {
+            let e = r.unwrap();
+            if !e.is_dir() {
+                let has_lfn = e.long_file_name_as_ucs2_units().is_some();
+                entries.push((e.file_name(), e.short_file_name(), has_lfn));
+            }
+        }
+    }
+    // only the name with mixed case needs a long name
+    assert_eq!(
+        entries,
+        [
+            ("readme.txt".to_string(), "readme.txt".to_string(), false),
+            ("Readme.TXT".to_string(), "README.TXT".to_string(), true),
+            ("README.TXT".to_string(), "README.TXT".to_string(), false),
+        ]
+    );
+    fs.unmount().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_short_name_case_round_trip_fat12() {
+    call_with_tmp_img(test_short_name_case_round_trip, FAT12_IMG, 45).await
+}
+
+#[tokio::test]
+async fn test_short_name_case_round_trip_fat16() {
+    call_with_tmp_img(test_short_name_case_round_trip, FAT16_IMG, 45).await
+}
+
+#[tokio::test]
+async fn test_short_name_case_round_trip_fat32() {
+    call_with_tmp_img(test_short_name_case_round_trip, FAT32_IMG, 45).await
+}
+
+async fn test_path_limits(tmp_path: String) {
+    use embedded_fatfs::Error;
+    {
+        let fs = open_filesystem_rw(tmp_path.clone()).await;
+        let root_dir = fs.root_dir();
+        // the default depth is generous but limited
+        let deep_path = vec!["a"; 65].join("/");
+        assert!(matches!(root_dir.open_dir(&deep_path).await, Err(Error::InvalidInput)));
+        assert!(matches!(root_dir.open_dir(&deep_path[2..]).await, Err(Error::NotFound)));
+        drop(root_dir);
+        fs.unmount().await.unwrap();
+    }
+
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&tmp_path)
+        .await
+        .unwrap();
+    let options = FsOptions::new().max_path_depth(3).max_path_component_len(8);
+    let fs = FileSystem::new(file, options).await.unwrap();
+    let root_dir = fs.root_dir();
+    let dir = root_dir.create_dir_all("a/b/c").await.unwrap();
+    dir.create_file("file.txt").await.unwrap();
+    assert!(matches!(
+        root_dir.create_dir_all("a/b/c/d").await,
+        Err(Error::InvalidInput)
+    ));
+    assert!(matches!(
+        root_dir.open_file("a/b/c/file.txt").await,
+        Err(Error::InvalidInput)
+    ));
+    assert!(matches!(
+        root_dir.rename("a/b/c/file.txt", &root_dir, "x.txt").await,
+        Err(Error::InvalidInput)
+    ));
+    // the depth is counted from the directory the method is called on
+    let a = root_dir.open_dir("a").await.unwrap();
+    a.open_file("b/c/file.txt").await.unwrap();
+    // leading, trailing and repeated slashes are not components
+    root_dir.open_dir("/a//b/c/").await.unwrap();
+    assert!(matches!(
+        root_dir.create_file("long-name").await,
+        Err(Error::InvalidInput)
+    ));
+    assert!(matches!(
+        root_dir.open_meta("a/abcdefghi").await,
+        Err(Error::InvalidInput)
+    ));
+    root_dir.create_file("abcdefgh").await.unwrap();
+    drop((a, dir, root_dir));
+    fs.unmount().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_path_limits_fat12() {
+    call_with_tmp_img(test_path_limits, FAT12_IMG, 46).await
+}
+
+#[tokio::test]
+async fn test_path_limits_fat16() {
+    call_with_tmp_img(test_path_limits, FAT16_IMG, 46).await
+}
+
+#[tokio::test]
+async fn test_path_limits_fat32() {
+    call_with_tmp_img(test_path_limits, FAT32_IMG, 46).await
+}
+
+async fn test_dot_components(tmp_path: String) {
+    use embedded_fatfs::Error;
+    let fs = open_filesystem_rw(tmp_path).await;
+    let root_dir = fs.root_dir();
+    let inner = root_dir.create_dir_all("sub/inner").await.unwrap();
+    let mut file = root_dir.create_file("other.txt").await.unwrap();
+    file.write_all(TEST_STR.as_bytes()).await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+    root_dir.create_file("sub/inner/../f.txt").await.unwrap();
+
+    // down and back up again
+    let mut file = root_dir.open_file("sub/inner/../../other.txt").await.unwrap();
+    assert_eq!(read_to_end(&mut file).await.unwrap(), TEST_STR.as_bytes());
+    drop(file);
+    inner.open_file("../../other.txt").await.unwrap();
+    inner.open_file("./.././f.txt").await.unwrap();
+    let sub = root_dir.open_dir("sub/inner/..").await.unwrap();
+    let mut names = Vec::new();
+    let mut iter = sub.iter();
+    while let Some(r) = iter.next().await {
+        names.push(r.unwrap().file_name());
+    }
+    assert_eq!(names, [".", "..", "inner", "f.txt"]);
+    drop(iter);
+    let root = sub.open_dir("..").await.unwrap();
+    root.open_file("other.txt").await.unwrap();
+    // `..` does not leave the root directory
+    root_dir.open_file("../../other.txt").await.unwrap();
+    root_dir.open_file("./other.txt").await.unwrap();
+    root.open_dir("../sub/./inner").await.unwrap();
+    let dir = root_dir.create_dir("sub/..").await.unwrap();
+    dir.open_file("other.txt").await.unwrap();
+    drop(dir);
+
+    // dot entries cannot be created, removed or renamed
+    assert!(matches!(
+        root_dir.create_new_dir("sub/.").await,
+        Err(Error::AlreadyExists)
+    ));
+    assert!(matches!(root_dir.create_file("sub/..").await, Err(Error::InvalidInput)));
+    assert!(matches!(root_dir.create_file("..").await, Err(Error::InvalidInput)));
+    assert!(matches!(root_dir.remove("sub/inner/.").await, Err(Error::InvalidInput)));
+    assert!(matches!(
+        root_dir.rename("sub/f.txt", &root_dir, "sub/..").await,
+        Err(Error::InvalidInput)
+    ));
+    inner.open_dir(".").await.unwrap();
+    drop((root, sub, inner, root_dir));
+    assert_eq!(fs.check_consistency().await.unwrap(), vec![]);
+    fs.unmount().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_dot_components_fat12() {
+    call_with_tmp_img(test_dot_components, FAT12_IMG, 47).await
+}
+
+#[tokio::test]
+async fn test_dot_components_fat16() {
+    call_with_tmp_img(test_dot_components, FAT16_IMG, 47).await
+}
+
+#[tokio::test]
+async fn test_dot_components_fat32() {
+    call_with_tmp_img(test_dot_components, FAT32_IMG, 47).await
+}
+
+async fn test_lfn_outside_bmp(tmp_path: String) {
+    // the surrogate pair of the second emoji is split between two LFN entries
+    let names = ["\u{1F600}.txt", "abcdefghijkl\u{1F680}.txt"];
+    {
+        let fs = open_filesystem_rw(tmp_path.clone()).await;
+        let dir = fs.root_dir().create_dir("emoji").await.unwrap();
+        for name in names {
+            let mut file = dir.create_file(name).await.unwrap();
+            file.write_all(name.as_bytes()).await.unwrap();
+            file.flush().await.unwrap();
+        }
+        // 128 emoji take 256 UTF-16 code units which is over the limit
+        let too_long = "\u{1F600}".repeat(128);
+        assert!(matches!(
+            dir.create_file(&too_long).await,
+            Err(embedded_fatfs::Error::InvalidFileNameLength)
+        ));
+        dir.create_file(&too_long[4..]).await.unwrap();
+        drop(dir);
+        fs.unmount().await.unwrap();
+    }
+
+    let fs = open_filesystem_rw(tmp_path).await;
+    let dir = fs.root_dir().open_dir("emoji").await.unwrap();
+    let mut found = Vec::new();
+    let mut iter = dir.iter();
+    while let Some(r) = iter.next().await {
+        let e = r.unwrap();
+        if !e.is_dir() {
+            found.push(e.file_name());
+        }
+    }
+    assert_eq!(found, [names[0], names[1], &"\u{1F600}".repeat(127)]);
+    drop(iter);
+    for name in names {
+        let mut file = dir.open_file(&name.to_uppercase()).await.unwrap();
+        assert_eq!(read_to_end(&mut file).await.unwrap(), name.as_bytes());
+    }
+    drop(dir);
+    fs.unmount().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_lfn_outside_bmp_fat12() {
+    call_with_tmp_img(test_lfn_outside_bmp, FAT12_IMG, 48).await
+}
+
+#[tokio::test]
+async fn test_lfn_outside_bmp_fat16() {
+    call_with_tmp_img(test_lfn_outside_bmp, FAT16_IMG, 48).await
+}
+
+#[tokio::test]
+async fn test_lfn_outside_bmp_fat32() {
+    call_with_tmp_img(test_lfn_outside_bmp, FAT32_IMG, 48).await
+}
+
+async fn test_invalid_name_chars(tmp_path: String) {
+    use embedded_fatfs::Error;
+    {
+        let fs = open_filesystem_rw(tmp_path.clone()).await;
+        let root_dir = fs.root_dir();
+        root_dir.create_file("valid.txt").await.unwrap();
+        // '/' is a path separator so it can never be a part of a name
+        let invalid = ['\\', ':', '*', '?', '"', '<', '>', '|', '\u{7F}']
+            .into_iter()
+            .chain((0..0x20).map(char::from));
+        for c in invalid {
+            let name = format!("a{}b.txt", c);
+            assert!(
+                matches!(
+                    root_dir.create_file(&name).await,
+                    Err(Error::UnsupportedFileNameCharacter)
+                ),
+                "{:?}",
+                c
+            );
+            assert!(
+                matches!(
+                    root_dir.create_dir(&name).await,
+                    Err(Error::UnsupportedFileNameCharacter)
+                ),
+                "{:?}",
+                c
+            );
+            assert!(
+                matches!(
+                    root_dir.rename("valid.txt", &root_dir, &name).await,
+                    Err(Error::UnsupportedFileNameCharacter)
+                ),
+                "{:?}",
+                c
+            );
+        }
+        // trailing dots and spaces are stripped
+        let file = root_dir.create_file("trail. .").await.unwrap();
+        drop(file);
+        root_dir.open_file("trail").await.unwrap();
+        root_dir.create_dir("dir..").await.unwrap();
+        root_dir.open_dir("dir").await.unwrap();
+        root_dir.rename("valid.txt", &root_dir, "renamed.txt ").await.unwrap();
+        root_dir.open_file("renamed.txt").await.unwrap();
+        assert!(matches!(
+            root_dir.create_file("...").await,
+            Err(Error::InvalidFileNameLength)
+        ));
+        let names = root_dir
+            .iter()
+            .collect()
+            .await
+            .iter()
+            .map(|r| r.as_ref().unwrap().file_name())
+            .collect::<Vec<String>>();
+        assert!(names.iter().any(|n| n == "trail"));
+        assert!(!names
+            .iter()
+            .any(|n| n.contains(':') || n.ends_with('.') || n.ends_with(' ')));
+        drop((root_dir, names));
+        fs.unmount().await.unwrap();
+    }
+
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&tmp_path)
+        .await
+        .unwrap();
+    let options = FsOptions::new().replace_invalid_name_chars(true);
+    let fs = FileSystem::new(file, options).await.unwrap();
+    let root_dir = fs.root_dir();
+    let file = root_dir.create_file("a:b?.txt").await.unwrap();
+    drop(file);
+    root_dir.open_file("a_b_.txt").await.unwrap();
+    root_dir.create_dir("x|y\u{1}z").await.unwrap();
+    root_dir.open_dir("x_y_z").await.unwrap();
+    root_dir
+        .rename("a_b_.txt", &root_dir, "\u{17c}<\u{17c}>.txt")
+        .await
+        .unwrap();
+    root_dir.open_file("\u{17c}_\u{17c}_.txt").await.unwrap();
+    drop(root_dir);
+    fs.unmount().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_invalid_name_chars_fat12() {
+    call_with_tmp_img(test_invalid_name_chars, FAT12_IMG, 49).await
+}
+
+#[tokio::test]
+async fn test_invalid_name_chars_fat16() {
+    call_with_tmp_img(test_invalid_name_chars, FAT16_IMG, 49).await
+}
+
+#[tokio::test]
+async fn test_invalid_name_chars_fat32() {
+    call_with_tmp_img(test_invalid_name_chars, FAT32_IMG, 49).await
+}
+
+async fn test_raw_cluster_access(fs: FileSystem) {
+    use embedded_fatfs::{Error, FatType};
+    let cluster_size = fs.cluster_size() as usize;
+    match fs.fat_type() {
+        FatType::Fat32 => assert_eq!(fs.root_dir_cluster(), Some(2)),
+        _ => assert_eq!(fs.root_dir_cluster(), None),
+    }
+    let root_dir = fs.root_dir();
+    let mut file = root_dir.create_file("raw.bin").await.unwrap();
+    file.write_all(&vec![0x5A; cluster_size * 2]).await.unwrap();
+    file.flush().await.unwrap();
+    let clusters = fs.clusters_for_path("raw.bin").await.unwrap();
+    // unflushed file data is visible
+    file.seek(SeekFrom::Start(0)).await.unwrap();
+    file.write_all(b"buffered").await.unwrap();
+    let mut buf = vec![0; cluster_size];
+    fs.read_cluster(clusters[0], &mut buf).await.unwrap();
+    assert_eq!(&buf[..8], b"buffered");
+    assert!(buf[8..].iter().all(|&b| b == 0x5A));
+    file.flush().await.unwrap();
+    drop(file);
+    fs.write_cluster(clusters[0], &vec![0xA5; cluster_size]).await.unwrap();
+    fs.write_cluster(clusters[1], b"raw").await.unwrap();
+    let mut file = root_dir.open_file("raw.bin").await.unwrap();
+    let data = read_to_end(&mut file).await.unwrap();
+    assert!(data[..cluster_size].iter().all(|&b| b == 0xA5));
+    assert_eq!(&data[cluster_size..cluster_size + 3], b"raw");
+    assert!(data[cluster_size + 3..].iter().all(|&b| b == 0x5A));
+    file.flush().await.unwrap();
+    drop(file);
+    // only data clusters of the volume are accessible
+    let end = fs.total_clusters() + 2;
+    fs.read_cluster(end - 1, &mut buf).await.unwrap();
+    for cluster in [0, 1, end, u32::MAX] {
+        assert!(matches!(
+            fs.read_cluster(cluster, &mut buf).await,
+            Err(Error::InvalidInput)
+        ));
+        assert!(matches!(
+            fs.write_cluster(cluster, &buf).await,
+            Err(Error::InvalidInput)
+        ));
+    }
+    let mut long_buf = vec![0; cluster_size + 1];
+    assert!(matches!(
+        fs.read_cluster(clusters[0], &mut long_buf).await,
+        Err(Error::InvalidInput)
+    ));
+    assert!(matches!(
+        fs.write_cluster(clusters[0], &long_buf).await,
+        Err(Error::InvalidInput)
+    ));
+}
+
+#[tokio::test]
+async fn test_raw_cluster_access_fat12() {
+    call_with_fs(test_raw_cluster_access, FAT12_IMG, 50).await
+}
+
+#[tokio::test]
+async fn test_raw_cluster_access_fat16() {
+    call_with_fs(test_raw_cluster_access, FAT16_IMG, 50).await
+}
+
+#[tokio::test]
+async fn test_raw_cluster_access_fat32() {
+    call_with_fs(test_raw_cluster_access, FAT32_IMG, 50).await
+}
+
+async fn test_cluster_chunks(fs: FileSystem) {
+    let cluster_size = fs.cluster_size();
+    let root_dir = fs.root_dir();
+    let mut file = root_dir.create_file("chunks.bin").await.unwrap();
+    let mut chunks = file.cluster_chunks();
+    assert!(chunks.next().await.is_none());
+    let len = cluster_size * 2 + cluster_size / 2;
+    file.write_all(&vec![0x3C; len as usize]).await.unwrap();
+    file.flush().await.unwrap();
+    let clusters = fs.clusters_for_path("chunks.bin").await.unwrap();
+    let mut chunks = file.cluster_chunks();
+    let mut spans = Vec::new();
+    while let Some(r) = chunks.next().await {
+        let extent = r.unwrap();
+        spans.push((extent.offset, extent.size));
+    }
+    let expected = clusters
+        .iter()
+        .zip([cluster_size, cluster_size, cluster_size / 2])
+        .map(|(&n, size)| (fs.cluster_offset(n), size))
+        .collect::<Vec<_>>();
+    assert_eq!(spans, expected);
+    // the last span holds the tail of the file
+    let mut buf = vec![0; cluster_size as usize];
+    fs.read_cluster(clusters[2], &mut buf).await.unwrap();
+    assert!(buf[..(cluster_size / 2) as usize].iter().all(|&b| b == 0x3C));
+}
+
+#[tokio::test]
+async fn test_cluster_chunks_fat12() {
+    call_with_fs(test_cluster_chunks, FAT12_IMG, 51).await
+}
+
+#[tokio::test]
+async fn test_cluster_chunks_fat16() {
+    call_with_fs(test_cluster_chunks, FAT16_IMG, 51).await
+}
+
+#[tokio::test]
+async fn test_cluster_chunks_fat32() {
+    call_with_fs(test_cluster_chunks, FAT32_IMG, 51).await
+}
+
+async fn test_truncate_positions(fs: FileSystem) {
+    let cluster_size = fs.cluster_size();
+    let root_dir = fs.root_dir();
+    let mut file = root_dir.create_file("trunc.bin").await.unwrap();
+    let data = (0..cluster_size * 4).map(|i| i as u8).collect::<Vec<u8>>();
+    file.write_all(&data).await.unwrap();
+    file.flush().await.unwrap();
+    let full = fs.stats().await.unwrap().free_clusters();
+    let chain_len = || async { fs.clusters_for_path("trunc.bin").await.unwrap().len() as u32 };
+    assert_eq!(chain_len().await, 4);
+
+    // mid-cluster keeps the partially used cluster
+    let len = cluster_size * 2 + 10;
+    file.seek(SeekFrom::Start(len.into())).await.unwrap();
+    file.truncate().await.unwrap();
+    file.flush().await.unwrap();
+    assert_eq!(fs.stats().await.unwrap().free_clusters(), full + 1);
+    assert_eq!(chain_len().await, 3);
+    let mut reopened = root_dir.open_file("trunc.bin").await.unwrap();
+    assert_eq!(reopened.seek(SeekFrom::End(0)).await.unwrap(), u64::from(len));
+    drop(reopened);
+
+    // at a cluster boundary the cluster after the boundary is freed
+    file.seek(SeekFrom::Start((cluster_size * 2).into())).await.unwrap();
+    file.truncate().await.unwrap();
+    file.flush().await.unwrap();
+    assert_eq!(fs.stats().await.unwrap().free_clusters(), full + 2);
+    assert_eq!(chain_len().await, 2);
+    let mut reopened = root_dir.open_file("trunc.bin").await.unwrap();
+    assert_eq!(
+        read_to_end(&mut reopened).await.unwrap(),
+        &data[..(cluster_size * 2) as usize]
+    );
+    drop(reopened);
+
+    // the file can grow again from the end of the shortened chain
+    file.write_all(b"tail").await.unwrap();
+    file.flush().await.unwrap();
+    assert_eq!(chain_len().await, 3);
+    assert_eq!(fs.stats().await.unwrap().free_clusters(), full + 1);
+
+    // truncating to zero frees the whole chain and clears the first cluster
+    file.seek(SeekFrom::Start(0)).await.unwrap();
+    file.truncate().await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+    assert_eq!(fs.stats().await.unwrap().free_clusters(), full + 4);
+    assert_eq!(chain_len().await, 0);
+    let mut iter = root_dir.iter();
+    let entry = loop {
+        let e = iter.next().await.unwrap().unwrap();
+        if e.file_name() == "trunc.bin" {
+            break e;
+        }
+    };
+    assert_eq!(entry.len(), 0);
+    assert!(entry.clusters().next().await.is_none());
+    drop(iter);
+
+    assert_eq!(fs.check_consistency().await.unwrap(), vec![]);
+    // the cached free count matches the FAT
+    assert_eq!(fs.free_space().await.unwrap().free_clusters(), full + 4);
+}
+
+#[tokio::test]
+async fn test_truncate_positions_fat12() {
+    call_with_fs(test_truncate_positions, FAT12_IMG, 52).await
+}
+
+#[tokio::test]
+async fn test_truncate_positions_fat16() {
+    call_with_fs(test_truncate_positions, FAT16_IMG, 52).await
+}
+
+#[tokio::test]
+async fn test_truncate_positions_fat32() {
+    call_with_fs(test_truncate_positions, FAT32_IMG, 52).await
+}
+
+async fn test_zero_stale_data(tmp_path: String) {
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&tmp_path)
+        .await
+        .unwrap();
+    let fs = FileSystem::new(file, FsOptions::new().zero_stale_data(true))
+        .await
+        .unwrap();
+    let cluster_size = fs.cluster_size() as usize;
+    let root_dir = fs.root_dir();
+    // leave old data in free clusters
+    let mut old = root_dir.create_file("old.bin").await.unwrap();
+    old.write_all(&vec![0xBB; cluster_size * 4]).await.unwrap();
+    old.flush().await.unwrap();
+    drop(old);
+    root_dir.remove("old.bin").await.unwrap();
+
+    // the tail of the last kept cluster is zeroed on truncate
+    let mut trunc = root_dir.create_file("trunc.bin").await.unwrap();
+    trunc.write_all(&vec![0xAA; cluster_size * 2]).await.unwrap();
+    trunc.seek(SeekFrom::Start(cluster_size as u64 + 10)).await.unwrap();
+    trunc.truncate().await.unwrap();
+    trunc.flush().await.unwrap();
+    let clusters = fs.clusters_for_path("trunc.bin").await.unwrap();
+    let mut buf = vec![0; cluster_size];
+    fs.read_cluster(clusters[1], &mut buf).await.unwrap();
+    assert!(buf[..10].iter().all(|&b| b == 0xAA));
+    assert!(buf[10..].iter().all(|&b| b == 0));
+
+    // space added to a file reads as zeros
+    let mut grow = root_dir.create_file("grow.bin").await.unwrap();
+    grow.write_all(b"12345").await.unwrap();
+    grow.allocate((cluster_size * 3) as u32).await.unwrap();
+    grow.flush().await.unwrap();
+    let data = read_to_end(&mut root_dir.open_file("grow.bin").await.unwrap())
+        .await
+        .unwrap();
+    assert_eq!(data.len(), cluster_size * 3);
+    assert_eq!(&data[..5], b"12345");
+    assert!(data[5..].iter().all(|&b| b == 0));
+    let mut prealloc = root_dir.create_file("prealloc.bin").await.unwrap();
+    prealloc
+        .preallocate_contiguous((cluster_size * 2) as u32)
+        .await
+        .unwrap();
+    prealloc.flush().await.unwrap();
+    let data = read_to_end(&mut root_dir.open_file("prealloc.bin").await.unwrap())
+        .await
+        .unwrap();
+    assert_eq!(data, vec![0; cluster_size * 2]);
+    drop((trunc, grow, prealloc, root_dir));
+    fs.unmount().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_zero_stale_data_fat12() {
+    call_with_tmp_img(test_zero_stale_data, FAT12_IMG, 53).await
+}
+
+#[tokio::test]
+async fn test_zero_stale_data_fat16() {
+    call_with_tmp_img(test_zero_stale_data, FAT16_IMG, 53).await
+}
+
+#[tokio::test]
+async fn test_zero_stale_data_fat32() {
+    call_with_tmp_img(test_zero_stale_data, FAT32_IMG, 53).await
+}
+
+async fn test_metadata(fs: FileSystem) {
+    use embedded_fatfs::{Date, DateTime, Time};
+
+    let modified = DateTime::new(Date::new(2005, 6, 7), Time::new(8, 9, 10, 0));
+    let root_dir = fs.root_dir();
+    let root_meta = root_dir.metadata();
+    assert!(root_meta.is_dir());
+    assert_eq!(root_meta.len(), 0);
+    assert_eq!(root_meta.first_cluster(), fs.root_dir_cluster());
+
+    let mut file = root_dir.create_file("meta.txt").await.unwrap();
+    let meta = file.metadata();
+    assert!(meta.is_file());
+    assert!(meta.is_empty());
+    assert_eq!(meta.first_cluster(), None);
+    // the length follows writes before the entry is flushed
+    file.write_all(TEST_STR.as_bytes()).await.unwrap();
+    file.set_modified(modified).unwrap();
+    let meta = file.metadata();
+    assert_eq!(meta.len(), TEST_STR.len() as u64);
+    assert_eq!(meta.modified(), modified);
+    assert!(meta.first_cluster().is_some());
+    file.flush().await.unwrap();
+
+    let find_entry = || async {
+        root_dir
+            .iter()
+            .collect()
+            .await
+            .into_iter()
+            .map(Result::unwrap)
+            .find(|e| e.file_name() == "meta.txt")
+            .unwrap()
+    };
+    let entry = find_entry().await;
+    assert_eq!(entry.metadata(), file.metadata());
+    assert_eq!(entry.metadata().attributes(), entry.attributes());
+    assert_eq!(entry.metadata().created(), entry.created());
+
+    // the entry metadata is a snapshot while the file metadata is live
+    file.write_all(TEST_STR2.as_bytes()).await.unwrap();
+    assert_eq!(entry.metadata().len(), TEST_STR.len() as u64);
+    assert_eq!(file.metadata().len(), (TEST_STR.len() + TEST_STR2.len()) as u64);
+    file.seek(SeekFrom::Start(0)).await.unwrap();
+    file.truncate().await.unwrap();
+    let meta = file.metadata();
+    assert!(meta.is_empty());
+    assert_eq!(meta.first_cluster(), None);
+    file.flush().await.unwrap();
+    drop(file);
+    assert_eq!(find_entry().await.metadata().len(), 0);
+
+    let dir = root_dir.create_dir("meta-dir").await.unwrap();
+    let dir_meta = dir.metadata();
+    assert!(dir_meta.is_dir());
+    assert!(dir_meta.attributes().contains(FileAttributes::DIRECTORY));
+    assert_eq!(dir_meta.len(), 0);
+    assert!(dir_meta.first_cluster().is_some());
+    let dir_entry = root_dir
+        .iter()
+        .collect()
+        .await
+        .into_iter()
+        .map(Result::unwrap)
+        .find(|e| e.file_name() == "meta-dir")
+        .unwrap();
+    assert_eq!(dir_entry.metadata(), dir_meta);
+}
+
+#[tokio::test]
+async fn test_metadata_fat12() {
+    call_with_fs(test_metadata, FAT12_IMG, 54).await
+}
+
+#[tokio::test]
+async fn test_metadata_fat16() {
+    call_with_fs(test_metadata, FAT16_IMG, 54).await
+}
+
+#[tokio::test]
+async fn test_metadata_fat32() {
+    call_with_fs(test_metadata, FAT32_IMG, 54).await
+}
+
+async fn test_check_storage_size(tmp_path: String) {
+    let open = || async {
+        fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&tmp_path)
+            .await
+            .unwrap()
+    };
+    let len = fs::metadata(&tmp_path).await.unwrap().len();
+    fs::OpenOptions::new()
+        .write(true)
+        .open(&tmp_path)
+        .await
+        .unwrap()
+        .set_len(len - 512)
+        .await
+        .unwrap();
+
+    let result = FileSystem::new(open().await, FsOptions::new()).await;
+    assert!(matches!(result, Err(embedded_fatfs::Error::UnexpectedEof)));
+
+    // the check can be disabled, the root directory is still readable
+    let fs = FileSystem::new(open().await, FsOptions::new().check_storage_size(false))
+        .await
+        .unwrap();
+    let root_dir = fs.root_dir();
+    let entries = root_dir.iter().collect().await;
+    assert!(entries.iter().all(Result::is_ok));
+    drop(entries);
+    drop(root_dir);
+    fs.unmount().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_check_storage_size_fat12() {
+    call_with_tmp_img(test_check_storage_size, FAT12_IMG, 55).await
+}
+
+#[tokio::test]
+async fn test_check_storage_size_fat16() {
+    call_with_tmp_img(test_check_storage_size, FAT16_IMG, 55).await
+}
+
+#[tokio::test]
+async fn test_check_storage_size_fat32() {
+    call_with_tmp_img(test_check_storage_size, FAT32_IMG, 55).await
+}
+
+async fn test_next_free_cluster_hint(tmp_path: String) {
+    let fs = open_filesystem_rw(tmp_path.clone()).await;
+    let root_dir = fs.root_dir();
+    let cluster_size = fs.cluster_size() as usize;
+    let data = vec![0xAB_u8; cluster_size * 2];
+    for name in ["a.bin", "b.bin"] {
+        let mut file = root_dir.create_file(name).await.unwrap();
+        file.write_all(&data).await.unwrap();
+        file.flush().await.unwrap();
+    }
+    let first_a = fs.clusters_for_path("a.bin").await.unwrap()[0];
+    let last_b = *fs.clusters_for_path("b.bin").await.unwrap().last().unwrap();
+
+    // allocation continues after the last allocated cluster
+    let mut file = root_dir.create_file("c.bin").await.unwrap();
+    file.write_all(&data[..1]).await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+    assert_eq!(fs.clusters_for_path("c.bin").await.unwrap(), vec![last_b + 1]);
+
+    // freeing clusters before the cursor moves it back
+    root_dir.remove("a.bin").await.unwrap();
+    let mut file = root_dir.create_file("d.bin").await.unwrap();
+    file.write_all(&data[..1]).await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+    assert_eq!(fs.clusters_for_path("d.bin").await.unwrap(), vec![first_a]);
+    let fat_type = fs.fat_type();
+    drop(root_dir);
+    fs.unmount().await.unwrap();
+
+    // the cursor is stored in the FSInfo sector of FAT32 volumes
+    if fat_type == embedded_fatfs::FatType::Fat32 {
+        let image = fs::read(&tmp_path).await.unwrap();
+        let bytes_per_sector = u16::from_le_bytes([image[11], image[12]]) as usize;
+        let fs_info_sector = u16::from_le_bytes([image[48], image[49]]) as usize;
+        let pos = fs_info_sector * bytes_per_sector + 492;
+        let next_free = u32::from_le_bytes(image[pos..pos + 4].try_into().unwrap());
+        assert_eq!(next_free, first_a + 1);
+    }
+}
+
+#[tokio::test]
+async fn test_next_free_cluster_hint_fat12() {
+    call_with_tmp_img(test_next_free_cluster_hint, FAT12_IMG, 56).await
+}
+
+#[tokio::test]
+async fn test_next_free_cluster_hint_fat16() {
+    call_with_tmp_img(test_next_free_cluster_hint, FAT16_IMG, 56).await
+}
+
+#[tokio::test]
+async fn test_next_free_cluster_hint_fat32() {
+    call_with_tmp_img(test_next_free_cluster_hint, FAT32_IMG, 56).await
+}
+
+async fn test_file_cluster_position(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    let cluster_size = fs.cluster_size();
+    let mut file = root_dir.create_file("pos.bin").await.unwrap();
+    assert_eq!(file.current_cluster(), None);
+    assert_eq!(file.offset_in_cluster(), 0);
+
+    let data = vec![0x5A_u8; (cluster_size * 3) as usize];
+    file.write_all(&data).await.unwrap();
+    file.flush().await.unwrap();
+    let clusters = fs.clusters_for_path("pos.bin").await.unwrap();
+    assert_eq!(clusters.len(), 3);
+
+    // a position at a cluster boundary belongs to the previous cluster
+    assert_eq!(file.current_cluster(), Some(clusters[2]));
+    assert_eq!(file.offset_in_cluster(), cluster_size);
+    file.seek(SeekFrom::Start(0)).await.unwrap();
+    assert_eq!(file.current_cluster(), Some(clusters[0]));
+    assert_eq!(file.offset_in_cluster(), 0);
+    let pos = u64::from(cluster_size + 10);
+    file.seek(SeekFrom::Start(pos)).await.unwrap();
+    assert_eq!(file.current_cluster(), Some(clusters[1]));
+    assert_eq!(file.offset_in_cluster(), 10);
+
+    assert_eq!(file.seek_to_cluster_boundary().await.unwrap(), u64::from(cluster_size));
+    assert_eq!(file.current_cluster(), Some(clusters[0]));
+    assert_eq!(file.offset_in_cluster(), cluster_size);
+    file.seek(SeekFrom::Start(5)).await.unwrap();
+    assert_eq!(file.seek_to_cluster_boundary().await.unwrap(), 0);
+    assert_eq!(file.seek_to_cluster_boundary().await.unwrap(), 0);
+    assert_eq!(file.current_cluster(), Some(clusters[0]));
+}
+
+#[tokio::test]
+async fn test_file_cluster_position_fat12() {
+    call_with_fs(test_file_cluster_position, FAT12_IMG, 57).await
+}
+
+#[tokio::test]
+async fn test_file_cluster_position_fat16() {
+    call_with_fs(test_file_cluster_position, FAT16_IMG, 57).await
+}
+
+#[tokio::test]
+async fn test_file_cluster_position_fat32() {
+    call_with_fs(test_file_cluster_position, FAT32_IMG, 57).await
+}
+
+async fn test_defragment_file(tmp_path: String) {
+    let fs = open_filesystem_rw(tmp_path.clone()).await;
+    let root_dir = fs.root_dir();
+    let cluster_size = fs.cluster_size() as usize;
+    let data: Vec<u8> = (0..cluster_size * 3).map(|i| (i / 7) as u8).collect();
+    // a file growing while another one is created gets fragmented
+    let mut frag = root_dir.create_file("frag.bin").await.unwrap();
+    frag.write_all(&data[..cluster_size]).await.unwrap();
+    frag.flush().await.unwrap();
+    let mut spacer = root_dir.create_file("spacer.bin").await.unwrap();
+    spacer.write_all(&data[..cluster_size]).await.unwrap();
+    spacer.flush().await.unwrap();
+    drop(spacer);
+    frag.write_all(&data[cluster_size..]).await.unwrap();
+    frag.flush().await.unwrap();
+    drop(frag);
+    let old_clusters = fs.clusters_for_path("frag.bin").await.unwrap();
+    assert_eq!(old_clusters.len(), 3);
+    assert_ne!(old_clusters[1], old_clusters[0] + 1);
+    let free_before = fs.stats().await.unwrap().free_clusters();
+
+    // no run is large enough - nothing changes
+    let mut filler = root_dir.create_file("filler.bin").await.unwrap();
+    filler.allocate(free_before * cluster_size as u32).await.unwrap();
+    filler.flush().await.unwrap();
+    drop(filler);
+    assert!(matches!(
+        fs.defragment_file("frag.bin").await,
+        Err(embedded_fatfs::Error::NotEnoughSpace)
+    ));
+    assert_eq!(fs.clusters_for_path("frag.bin").await.unwrap(), old_clusters);
+    root_dir.remove("filler.bin").await.unwrap();
+    assert_eq!(fs.stats().await.unwrap().free_clusters(), free_before);
+
+    assert!(fs.defragment_file("frag.bin").await.unwrap());
+    let new_clusters = fs.clusters_for_path("frag.bin").await.unwrap();
+    assert_eq!(new_clusters, (new_clusters[0]..new_clusters[0] + 3).collect::<Vec<_>>());
+    assert_eq!(fs.stats().await.unwrap().free_clusters(), free_before);
+    // an already contiguous file and an empty file are left alone
+    assert!(!fs.defragment_file("frag.bin").await.unwrap());
+    drop(root_dir.create_file("empty.bin").await.unwrap());
+    assert!(!fs.defragment_file("empty.bin").await.unwrap());
+    root_dir.create_dir("dir").await.unwrap();
+    assert!(matches!(
+        fs.defragment_file("dir").await,
+        Err(embedded_fatfs::Error::InvalidInput)
+    ));
+    assert!(matches!(
+        fs.defragment_file("missing.bin").await,
+        Err(embedded_fatfs::Error::NotFound)
+    ));
+    drop(root_dir);
+    fs.unmount().await.unwrap();
+
+    let fs = open_filesystem_rw(tmp_path).await;
+    let mut file = fs.root_dir().open_file("frag.bin").await.unwrap();
+    assert_eq!(read_to_end(&mut file).await.unwrap(), data);
+    drop(file);
+    assert_eq!(fs.check_consistency().await.unwrap(), vec![]);
+    fs.unmount().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_defragment_file_fat12() {
+    call_with_tmp_img(test_defragment_file, FAT12_IMG, 58).await
+}
+
+#[tokio::test]
+async fn test_defragment_file_fat16() {
+    call_with_tmp_img(test_defragment_file, FAT16_IMG, 58).await
+}
+
+#[tokio::test]
+async fn test_defragment_file_fat32() {
+    call_with_tmp_img(test_defragment_file, FAT32_IMG, 58).await
+}
+
+#[tokio::test]
+async fn test_defragment_file_overlap() {
+    let _ = env_logger::builder().is_test(true).try_init();
+    // clusters are allocated one after another on a new volume
+    let image = new_mem_image(vec![0_u8; 1024 * 1024]);
+    let mut storage = MemStorage::new(image.clone());
+    let opts = embedded_fatfs::FormatVolumeOptions::new().bytes_per_cluster(2048);
+    embedded_fatfs::format_volume(&mut storage, opts).await.unwrap();
+    image.borrow_mut().set_position(0);
+    let fs = open_mem_filesystem(&image).await;
+    let root_dir = fs.root_dir();
+    let cluster_size = fs.cluster_size() as usize;
+    let data: Vec<u8> = (0..cluster_size * 3).map(|i| (i / 7) as u8).collect();
+    // clusters of a file in reverse order
+    let mut tail = root_dir.create_file("tail.bin").await.unwrap();
+    tail.write_all(&data[..cluster_size]).await.unwrap();
+    tail.flush().await.unwrap();
+    drop(tail);
+    let mut swapped = root_dir.create_file("swapped.bin").await.unwrap();
+    swapped.write_all(&data[..cluster_size]).await.unwrap();
+    swapped.flush().await.unwrap();
+    root_dir.remove("tail.bin").await.unwrap();
+    swapped.write_all(&data[cluster_size..cluster_size * 2]).await.unwrap();
+    swapped.flush().await.unwrap();
+    drop(swapped);
+    assert_eq!(fs.clusters_for_path("swapped.bin").await.unwrap(), vec![3, 2]);
+
+    // a free cluster left between the clusters of a file
+    let mut gap = root_dir.create_file("gap.bin").await.unwrap();
+    gap.write_all(&data[..cluster_size]).await.unwrap();
+    gap.flush().await.unwrap();
+    let mut spacer = root_dir.create_file("spacer.bin").await.unwrap();
+    spacer.write_all(&data[..cluster_size]).await.unwrap();
+    spacer.flush().await.unwrap();
+    drop(spacer);
+    gap.write_all(&data[cluster_size..]).await.unwrap();
+    gap.flush().await.unwrap();
+    drop(gap);
+    assert_eq!(fs.clusters_for_path("gap.bin").await.unwrap(), vec![4, 6, 7]);
+    root_dir.remove("spacer.bin").await.unwrap();
+    let free_before = fs.stats().await.unwrap().free_clusters();
+
+    // the file uses every cluster of the run and no free cluster is left to move one of them out of the way
+    let mut filler = root_dir.create_file("filler.bin").await.unwrap();
+    filler.allocate(free_before * cluster_size as u32).await.unwrap();
+    filler.flush().await.unwrap();
+    drop(filler);
+    assert!(matches!(
+        fs.defragment_file("swapped.bin").await,
+        Err(embedded_fatfs::Error::NotEnoughSpace)
+    ));
+    assert_eq!(fs.clusters_for_path("swapped.bin").await.unwrap(), vec![3, 2]);
+    root_dir.remove("filler.bin").await.unwrap();
+
+    assert!(fs.defragment_file("gap.bin").await.unwrap());
+    assert_eq!(fs.clusters_for_path("gap.bin").await.unwrap(), vec![4, 5, 6]);
+    assert!(fs.defragment_file("swapped.bin").await.unwrap());
+    assert_eq!(fs.clusters_for_path("swapped.bin").await.unwrap(), vec![2, 3]);
+    assert_eq!(fs.stats().await.unwrap().free_clusters(), free_before);
+
+    // every cluster of a longer file is in the place of another one
+    for name in ["a", "b", "c", "d"] {
+        let mut file = root_dir.create_file(name).await.unwrap();
+        file.write_all(&data[..cluster_size]).await.unwrap();
+        file.flush().await.unwrap();
+    }
+    let long_data: Vec<u8> = (0..cluster_size * 4)
+        .map(|i| (i / cluster_size * 50 + i % 7) as u8)
+        .collect();
+    let mut reversed = root_dir.create_file("reversed.bin").await.unwrap();
+    reversed.write_all(&long_data[..cluster_size]).await.unwrap();
+    reversed.flush().await.unwrap();
+    for (i, name) in ["d", "c", "b"].into_iter().enumerate() {
+        root_dir.remove(name).await.unwrap();
+        let range = cluster_size * (i + 1)..cluster_size * (i + 2);
+        reversed.write_all(&long_data[range]).await.unwrap();
+        reversed.flush().await.unwrap();
+    }
+    drop(reversed);
+    let clusters = fs.clusters_for_path("reversed.bin").await.unwrap();
+    let last = clusters[3];
+    assert_eq!(clusters, vec![last + 3, last + 2, last + 1, last]);
+    assert!(fs.defragment_file("reversed.bin").await.unwrap());
+    assert_eq!(
+        fs.clusters_for_path("reversed.bin").await.unwrap(),
+        vec![last, last + 1, last + 2, last + 3]
+    );
+    drop(root_dir);
+    fs.unmount().await.unwrap();
+
+    image.borrow_mut().set_position(0);
+    let fs = open_mem_filesystem(&image).await;
+    let root_dir = fs.root_dir();
+    let mut file = root_dir.open_file("gap.bin").await.unwrap();
+    assert_eq!(read_to_end(&mut file).await.unwrap(), data);
+    file.flush().await.unwrap();
+    drop(file);
+    let mut file = root_dir.open_file("swapped.bin").await.unwrap();
+    assert_eq!(read_to_end(&mut file).await.unwrap(), data[..cluster_size * 2]);
+    file.flush().await.unwrap();
+    drop(file);
+    let mut file = root_dir.open_file("reversed.bin").await.unwrap();
+    assert_eq!(read_to_end(&mut file).await.unwrap(), long_data);
+    file.flush().await.unwrap();
+    drop(file);
+    drop(root_dir);
+    assert_eq!(fs.check_consistency().await.unwrap(), vec![]);
+    fs.unmount().await.unwrap();
+}
+
+async fn test_flush_persists_size(tmp_path: String) {
+    let fs = open_filesystem_rw(tmp_path.clone()).await;
+    let root_dir = fs.root_dir();
+    let original = root_dir.open_meta("short.txt").await.unwrap().metadata();
+    let mut file = root_dir.open_file("short.txt").await.unwrap();
+    file.seek(SeekFrom::End(0)).await.unwrap();
+    file.write_all(&[b'x'; 1000]).await.unwrap();
+    let mut new_file = root_dir.create_file("new.txt").await.unwrap();
+    new_file.write_all(TEST_STR.as_bytes()).await.unwrap();
+    file.flush().await.unwrap();
+    new_file.flush().await.unwrap();
+    let modified = file.metadata().modified();
+    assert_ne!(modified, original.modified());
+    // simulate a crash - nothing is written after the flush
+    std::mem::forget(file);
+    std::mem::forget(new_file);
+    drop(root_dir);
+    drop(fs);
+
+    let fs = open_filesystem_rw(tmp_path).await;
+    assert!(fs.was_dirty_on_mount());
+    let root_dir = fs.root_dir();
+    let metadata = root_dir.open_meta("short.txt").await.unwrap().metadata();
+    assert_eq!(metadata.len(), original.len() + 1000);
+    assert_eq!(metadata.modified(), modified);
+    let mut file = root_dir.open_file("new.txt").await.unwrap();
+    assert_eq!(read_to_end(&mut file).await.unwrap(), TEST_STR.as_bytes());
+    drop(file);
+    drop(root_dir);
+    assert_eq!(fs.check_consistency().await.unwrap(), vec![]);
+    fs.unmount().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_flush_persists_size_fat12() {
+    call_with_tmp_img(test_flush_persists_size, FAT12_IMG, 59).await
+}
+
+#[tokio::test]
+async fn test_flush_persists_size_fat16() {
+    call_with_tmp_img(test_flush_persists_size, FAT16_IMG, 59).await
+}
+
+#[tokio::test]
+async fn test_flush_persists_size_fat32() {
+    call_with_tmp_img(test_flush_persists_size, FAT32_IMG, 59).await
+}
+
+async fn test_allocated_len(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    let cluster_size = u64::from(fs.cluster_size());
+    let mut file = root_dir.create_file("alloc.bin").await.unwrap();
+    assert_eq!(file.allocated_len().await.unwrap(), 0);
+    // the length is rounded up to whole clusters
+    file.write_all(&[0xA5; 1]).await.unwrap();
+    assert_eq!(file.allocated_len().await.unwrap(), cluster_size);
+    let data = vec![0xA5_u8; cluster_size as usize];
+    file.write_all(&data).await.unwrap();
+    assert_eq!(file.metadata().len(), cluster_size + 1);
+    assert_eq!(file.allocated_len().await.unwrap(), cluster_size * 2);
+    file.seek(SeekFrom::Start(cluster_size)).await.unwrap();
+    file.truncate().await.unwrap();
+    assert_eq!(file.allocated_len().await.unwrap(), cluster_size);
+    drop(file);
+
+    let mut file = root_dir.create_file("prealloc.bin").await.unwrap();
+    file.preallocate_contiguous(cluster_size as u32 * 2 + 10).await.unwrap();
+    assert_eq!(file.metadata().len(), cluster_size * 2 + 10);
+    assert_eq!(file.allocated_len().await.unwrap(), cluster_size * 3);
+    file.flush().await.unwrap();
+    assert_eq!(
+        file.allocated_len().await.unwrap(),
+        fs.clusters_for_path("prealloc.bin").await.unwrap().len() as u64 * cluster_size
+    );
+    drop(file);
+    drop(root_dir);
+    fs.unmount().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_allocated_len_fat12() {
+    call_with_fs(test_allocated_len, FAT12_IMG, 60).await
+}
+
+#[tokio::test]
+async fn test_allocated_len_fat16() {
+    call_with_fs(test_allocated_len, FAT16_IMG, 60).await
+}
+
+#[tokio::test]
+async fn test_allocated_len_fat32() {
+    call_with_fs(test_allocated_len, FAT32_IMG, 60).await
+}
+
+async fn test_size_mismatch_policy(tmp_path: String) {
+    use embedded_fatfs::SizeMismatchPolicy;
+    let open_fs = |policy: SizeMismatchPolicy, read_only: bool| {
+        let tmp_path = tmp_path.clone();
+        async move {
+            let file = fs::OpenOptions::new()
+                .read(true)
+                .write(!read_only)
+                .open(&tmp_path)
+                .await
+                .unwrap();
+            let options = FsOptions::new().size_mismatch_policy(policy).read_only(read_only);
+            FileSystem::new(file, options).await.unwrap()
+        }
+    };
+    let (cluster_size, clusters) = {
+        let fs = open_filesystem_rw(tmp_path.clone()).await;
+        let cluster_size = u64::from(fs.cluster_size());
+        let mut file = fs.root_dir().create_file("cut.bin").await.unwrap();
+        file.write_all(&vec![0x5A; cluster_size as usize * 2]).await.unwrap();
+        file.flush().await.unwrap();
+        drop(file);
+        let clusters = fs.clusters_for_path("cut.bin").await.unwrap();
+        fs.unmount().await.unwrap();
+        (cluster_size, clusters)
+    };
+    let end_of_chain = if tmp_path.ends_with(FAT12_IMG) {
+        0xFFF
+    } else if tmp_path.ends_with(FAT16_IMG) {
+        0xFFFF
+    } else {
+        0x0FFF_FFFF
+    };
+    // cut the chain after the first cluster like an interrupted write could
+    write_raw_fat_entry(&tmp_path, clusters[0], end_of_chain).await;
+
+    let fs = open_fs(SizeMismatchPolicy::Error, false).await;
+    let root_dir = fs.root_dir();
+    assert!(matches!(
+        root_dir.open_file("cut.bin").await,
+        Err(embedded_fatfs::Error::InvalidData)
+    ));
+    assert!(matches!(
+        root_dir.try_open_file("cut.bin").await,
+        Err(embedded_fatfs::Error::InvalidData)
+    ));
+    // every way of opening an existing file checks it
+    assert!(matches!(
+        root_dir.create_file("cut.bin").await,
+        Err(embedded_fatfs::Error::InvalidData)
+    ));
+    let options = embedded_fatfs::OpenOptions::new().read(true).write(true).create(true);
+    assert!(matches!(
+        root_dir.open_file_with("cut.bin", options).await,
+        Err(embedded_fatfs::Error::InvalidData)
+    ));
+    // other files are opened normally
+    root_dir.create_file("ok.txt").await.unwrap();
+    root_dir.open_file("ok.txt").await.unwrap();
+    drop(root_dir);
+    fs.unmount().await.unwrap();
+
+    let fs = open_fs(SizeMismatchPolicy::Ignore, true).await;
+    let file = fs.root_dir().open_file("cut.bin").await.unwrap();
+    assert_eq!(file.metadata().len(), cluster_size * 2);
+    drop(file);
+    drop(fs);
+
+    let fs = open_fs(SizeMismatchPolicy::Clamp, true).await;
+    let mut file = fs.root_dir().open_file("cut.bin").await.unwrap();
+    assert_eq!(file.metadata().len(), cluster_size);
+    assert_eq!(read_to_end(&mut file).await.unwrap(), vec![0x5A; cluster_size as usize]);
+    drop(file);
+    // the directory entry is not changed
+    assert_eq!(
+        fs.root_dir().open_meta("cut.bin").await.unwrap().len(),
+        cluster_size * 2
+    );
+    drop(fs);
+
+    let fs = open_fs(SizeMismatchPolicy::Fix, true).await;
+    assert!(matches!(
+        fs.root_dir().open_file("cut.bin").await,
+        Err(embedded_fatfs::Error::ReadOnly)
+    ));
+    drop(fs);
+
+    let fs = open_fs(SizeMismatchPolicy::Fix, false).await;
+    let file = fs.root_dir().open_file("cut.bin").await.unwrap();
+    assert_eq!(file.metadata().len(), cluster_size);
+    drop(file);
+    fs.unmount().await.unwrap();
+
+    let fs = open_fs(SizeMismatchPolicy::Error, false).await;
+    let file = fs.root_dir().open_file("cut.bin").await.unwrap();
+    assert_eq!(file.metadata().len(), cluster_size);
+    drop(file);
+    fs.unmount().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_size_mismatch_policy_fat12() {
+    call_with_tmp_img(test_size_mismatch_policy, FAT12_IMG, 61).await
+}
+
+#[tokio::test]
+async fn test_size_mismatch_policy_fat16() {
+    call_with_tmp_img(test_size_mismatch_policy, FAT16_IMG, 61).await
+}
+
+#[tokio::test]
+async fn test_size_mismatch_policy_fat32() {
+    call_with_tmp_img(test_size_mismatch_policy, FAT32_IMG, 61).await
+}
+
+async fn test_empty_file_clusters(tmp_path: String) {
+    use embedded_io_async::Read;
+    let free_clusters = {
+        let fs = open_filesystem_rw(tmp_path.clone()).await;
+        let free_clusters = fs.stats().await.unwrap().free_clusters();
+        let mut file = fs.root_dir().create_file("empty.bin").await.unwrap();
+        file.flush().await.unwrap();
+        drop(file);
+        assert_eq!(fs.stats().await.unwrap().free_clusters(), free_clusters);
+        fs.unmount().await.unwrap();
+        free_clusters
+    };
+
+    let fs = open_filesystem_rw(tmp_path.clone()).await;
+    let root_dir = fs.root_dir();
+    let entry = root_dir.open_meta("empty.bin").await.unwrap();
+    assert_eq!(entry.len(), 0);
+    // the entry has no first cluster
+    assert!(fs.clusters_for_path("empty.bin").await.unwrap().is_empty());
+    // reading returns no data
+    let mut file = root_dir.open_file("empty.bin").await.unwrap();
+    let mut buf = [0_u8; 16];
+    assert_eq!(file.read(&mut buf).await.unwrap(), 0);
+    assert_eq!(file.allocated_len().await.unwrap(), 0);
+    // the first write allocates the first cluster
+    file.write_all(b"x").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+    assert_eq!(fs.stats().await.unwrap().free_clusters(), free_clusters - 1);
+    let entry = root_dir.open_meta("empty.bin").await.unwrap();
+    assert_eq!(entry.len(), 1);
+    assert_eq!(fs.clusters_for_path("empty.bin").await.unwrap().len(), 1);
+    // removing an empty file frees nothing
+    root_dir.create_file("empty2.bin").await.unwrap();
+    root_dir.remove("empty2.bin").await.unwrap();
+    root_dir.remove("empty.bin").await.unwrap();
+    assert_eq!(fs.stats().await.unwrap().free_clusters(), free_clusters);
+    drop(root_dir);
+    assert_eq!(fs.check_consistency().await.unwrap(), vec![]);
+    fs.unmount().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_empty_file_clusters_fat12() {
+    call_with_tmp_img(test_empty_file_clusters, FAT12_IMG, 62).await
+}
+
+#[tokio::test]
+async fn test_empty_file_clusters_fat16() {
+    call_with_tmp_img(test_empty_file_clusters, FAT16_IMG, 62).await
+}
+
+#[tokio::test]
+async fn test_empty_file_clusters_fat32() {
+    call_with_tmp_img(test_empty_file_clusters, FAT32_IMG, 62).await
+}
+
+async fn test_truncate_on_open(fs: FileSystem) {
+    use embedded_fatfs::OpenOptions;
+    let root_dir = fs.root_dir();
+    let free_clusters = fs.stats().await.unwrap().free_clusters();
+    let mut file = root_dir.create_file("big.bin").await.unwrap();
+    file.write_all(&vec![0xC3; fs.cluster_size() as usize * 3])
+        .await
+        .unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+    assert_eq!(fs.stats().await.unwrap().free_clusters(), free_clusters - 3);
+
+    let options = OpenOptions::new().write(true).truncate(true);
+    let file = root_dir.open_file_with("big.bin", options).await.unwrap();
+    // all clusters are freed before the file is returned
+    assert_eq!(fs.stats().await.unwrap().free_clusters(), free_clusters);
+    assert!(fs.clusters_for_path("big.bin").await.unwrap().is_empty());
+    assert_eq!(file.metadata().len(), 0);
+    drop(file);
+    assert_eq!(root_dir.open_meta("big.bin").await.unwrap().len(), 0);
+
+    // an empty file is not changed
+    let file = root_dir.open_file_with("big.bin", options).await.unwrap();
+    assert_eq!(file.metadata().len(), 0);
+    drop(file);
+    assert_eq!(fs.stats().await.unwrap().free_clusters(), free_clusters);
+    drop(root_dir);
+    assert_eq!(fs.check_consistency().await.unwrap(), vec![]);
+    fs.unmount().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_truncate_on_open_fat12() {
+    call_with_fs(test_truncate_on_open, FAT12_IMG, 63).await
+}
+
+#[tokio::test]
+async fn test_truncate_on_open_fat16() {
+    call_with_fs(test_truncate_on_open, FAT16_IMG, 63).await
+}
+
+#[tokio::test]
+async fn test_truncate_on_open_fat32() {
+    call_with_fs(test_truncate_on_open, FAT32_IMG, 63).await
+}
+
+async fn test_unicode_short_names(tmp_path: String) {
+    let open_fs = || async {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&tmp_path)
+            .await
+            .unwrap();
+        let options = FsOptions::new().oem_cp_converter(embedded_fatfs::Cp850OemCpConverter::new());
+        embedded_fatfs::FileSystem::new(file, options).await.unwrap()
+    };
+    let names = [
+        ("caf\u{E9}.txt", "CAF\u{C9}.TXT"),
+        ("Caf\u{E9} cr\u{E8}me.txt", "CAF\u{C9}CR~1.TXT"),
+        ("stra\u{DF}e.txt", "STRA\u{DF}E.TXT"),
+        ("\u{65E5}\u{672C}\u{8A9E}.txt", "___~1.TXT"),
+        ("\u{3A9}mega.txt", "_MEGA~1.TXT"),
+        ("\u{3A9}mega2.txt", "_MEGA2~1.TXT"),
+        ("\u{3A9}mega\u{3A9}.txt", "_MEGA_~1.TXT"),
+    ];
+    {
+        let fs = open_fs().await;
+        let root_dir = fs.root_dir();
+        for (name, _) in names {
+            let mut file = root_dir.create_file(name).await.unwrap();
+            file.write_all(name.as_bytes()).await.unwrap();
+            file.flush().await.unwrap();
+        }
+        drop(root_dir);
+        fs.unmount().await.unwrap();
+    }
+
+    let fs = open_fs().await;
+    let root_dir = fs.root_dir();
+    for (name, short_name) in names {
+        let entry = root_dir.open_meta(name).await.unwrap();
+        assert_eq!(entry.file_name(), name);
+        assert_eq!(entry.short_file_name(), short_name);
+        // the file can be opened by its long and its short name
+        let mut file = root_dir.open_file(name).await.unwrap();
+        assert_eq!(read_to_end(&mut file).await.unwrap(), name.as_bytes());
+        drop(file);
+        let mut file = root_dir.open_file(short_name).await.unwrap();
+        assert_eq!(read_to_end(&mut file).await.unwrap(), name.as_bytes());
+    }
+    drop(root_dir);
+    fs.unmount().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_unicode_short_names_fat12() {
+    call_with_tmp_img(test_unicode_short_names, FAT12_IMG, 64).await
+}
+
+#[tokio::test]
+async fn test_unicode_short_names_fat16() {
+    call_with_tmp_img(test_unicode_short_names, FAT16_IMG, 64).await
+}
+
+#[tokio::test]
+async fn test_unicode_short_names_fat32() {
+    call_with_tmp_img(test_unicode_short_names, FAT32_IMG, 64).await
+}
+
+// Reads from the storage lent by `FileSystem::with_device`
+struct ReadDevice<'b> {
+    offset: u64,
+    buf: &'b mut [u8],
+}
+
+impl<IO: embedded_io_async::Read + Seek> embedded_fatfs::DeviceTask<IO> for ReadDevice<'_> {
+    type Output = ();
+
+    async fn run(self, device: &mut IO) {
+        device.seek(SeekFrom::Start(self.offset)).await.unwrap();
+        device.read_exact(self.buf).await.unwrap();
+    }
+}
+
+// Fills ranges of the storage lent by `FileSystem::with_device`, like a TRIM would
+struct FillDevice {
+    offsets: Vec<u64>,
+    len: usize,
+    byte: u8,
+}
+
+impl<IO: Write + Seek> embedded_fatfs::DeviceTask<IO> for FillDevice {
+    type Output = ();
+
+    async fn run(self, device: &mut IO) {
+        for offset in self.offsets {
+            device.seek(SeekFrom::Start(offset)).await.unwrap();
+            device.write_all(&vec![self.byte; self.len]).await.unwrap();
+        }
+        // the position is left anywhere
+        device.seek(SeekFrom::Start(0)).await.unwrap();
+    }
+}
+
+async fn test_with_device(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    let mut file = root_dir.create_file("dev.bin").await.unwrap();
+    file.write_all(&vec![0x3C; fs.cluster_size() as usize * 2])
+        .await
+        .unwrap();
+    file.flush().await.unwrap();
+    // buffered data is written to the storage before it is lent
+    file.seek(SeekFrom::Start(0)).await.unwrap();
+    file.write_all(b"unflushed").await.unwrap();
+    let clusters = fs.clusters_for_path("dev.bin").await.unwrap();
+    let mut buf = [0_u8; 9];
+    let task = ReadDevice {
+        offset: fs.cluster_offset(clusters[0]),
+        buf: &mut buf,
+    };
+    fs.with_device(task).await.unwrap();
+    assert_eq!(&buf, b"unflushed");
+    file.flush().await.unwrap();
+    drop(file);
+
+    // erase the clusters after the file is removed
+    root_dir.remove("dev.bin").await.unwrap();
+    let task = FillDevice {
+        offsets: clusters.iter().map(|&cluster| fs.cluster_offset(cluster)).collect(),
+        len: fs.cluster_size() as usize,
+        byte: 0xFF,
+    };
+    fs.with_device(task).await.unwrap();
+    let mut buf = vec![0_u8; fs.cluster_size() as usize];
+    fs.read_cluster(clusters[1], &mut buf).await.unwrap();
+    assert!(buf.iter().all(|&b| b == 0xFF));
+
+    // the filesystem keeps working
+    let mut file = root_dir.create_file("after.txt").await.unwrap();
+    file.write_all(TEST_STR.as_bytes()).await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+    let mut file = root_dir.open_file("after.txt").await.unwrap();
+    assert_eq!(read_to_end(&mut file).await.unwrap(), TEST_STR.as_bytes());
+    file.flush().await.unwrap();
+    drop(file);
+    drop(root_dir);
+    assert_eq!(fs.check_consistency().await.unwrap(), vec![]);
+    fs.unmount().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_with_device_fat12() {
+    call_with_fs(test_with_device, FAT12_IMG, 65).await
+}
+
+#[tokio::test]
+async fn test_with_device_fat16() {
+    call_with_fs(test_with_device, FAT16_IMG, 65).await
+}
+
+#[tokio::test]
+async fn test_with_device_fat32() {
+    call_with_fs(test_with_device, FAT32_IMG, 65).await
+}
+
+// Keeps the storage lent by `FileSystem::with_device` until `release` is set
+struct HoldDevice {
+    release: std::rc::Rc<std::cell::Cell<bool>>,
+}
+
+impl<IO> embedded_fatfs::DeviceTask<IO> for HoldDevice {
+    type Output = ();
+
+    async fn run(self, _device: &mut IO) {
+        std::future::poll_fn(|_| {
+            if self.release.get() {
+                std::task::Poll::Ready(())
+            } else {
+                std::task::Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+#[derive(Default)]
+struct CountingWaker(std::sync::atomic::AtomicUsize);
+
+impl CountingWaker {
+    fn count(&self) -> usize {
+        self.0.load(std::sync::atomic::Ordering::SeqCst)
+    }
+}
+
+impl std::task::Wake for CountingWaker {
+    fn wake(self: std::sync::Arc<Self>) {
+        self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+#[tokio::test]
+async fn test_dir_update_waiters() {
+    use std::sync::Arc;
+    use std::task::{Context, Waker};
+    let _ = env_logger::builder().is_test(true).try_init();
+    let image = new_mem_image(fs::read(format!("{}/{}", IMG_DIR, FAT16_IMG)).await.unwrap());
+    let fs = MemFileSystem::new(MemStorage::new(image), FsOptions::new())
+        .await
+        .unwrap();
+    let root_dir = fs.root_dir();
+    let counters = [(); 3].map(|_| Arc::new(CountingWaker::default()));
+    let wakers = counters.each_ref().map(|c| Waker::from(c.clone()));
+    let mut cx = wakers.each_ref().map(Context::from_waker);
+    let counts = |i: usize| counters[i].count();
+    {
+        let release = std::rc::Rc::new(std::cell::Cell::new(false));
+        let mut holder = std::pin::pin!(fs.with_device(HoldDevice {
+            release: release.clone()
+        }));
+        let mut first = std::pin::pin!(root_dir.create_file("first.txt"));
+        let mut second = std::pin::pin!(root_dir.create_file("second.txt"));
+        assert!(holder.as_mut().poll(&mut cx[0]).is_pending());
+        // tasks waiting for the lock do not wake each other
+        for _ in 0..3 {
+            assert!(first.as_mut().poll(&mut cx[1]).is_pending());
+            assert!(second.as_mut().poll(&mut cx[2]).is_pending());
+        }
+        assert_eq!((counts(1), counts(2)), (0, 0));
+        // the lock is handed over in the order the tasks started waiting
+        release.set(true);
+        assert!(holder.as_mut().poll(&mut cx[0]).is_ready());
+        assert_eq!((counts(1), counts(2)), (1, 0));
+        assert!(second.as_mut().poll(&mut cx[2]).is_pending());
+        let std::task::Poll::Ready(file) = first.as_mut().poll(&mut cx[1]) else {
+            panic!("the first waiter did not take the lock");
+        };
+        drop(file.unwrap());
+        assert_eq!(counts(2), 1);
+        let std::task::Poll::Ready(file) = second.as_mut().poll(&mut cx[2]) else {
+            panic!("the second waiter did not take the lock");
+        };
+        drop(file.unwrap());
+    }
+    drop(root_dir);
+    fs.unmount().await.unwrap();
+}
+
+async fn test_max_file_size_free_space(fs: FileSystem) {
+    let free_bytes = fs.stats().await.unwrap().free_bytes();
+    assert_eq!(fs.max_file_size().await.unwrap(), free_bytes);
+    let cluster_size = u64::from(fs.cluster_size());
+    let root_dir = fs.root_dir();
+    let mut file = root_dir.create_file("a.bin").await.unwrap();
+    file.write_all(&vec![0x55; cluster_size as usize * 2]).await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+    assert_eq!(fs.max_file_size().await.unwrap(), free_bytes - cluster_size * 2);
+    // a file of the returned size fills the volume
+    let mut file = root_dir.create_file("b.bin").await.unwrap();
+    file.allocate(fs.max_file_size().await.unwrap() as u32).await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+    assert_eq!(fs.max_file_size().await.unwrap(), 0);
+    root_dir.remove("a.bin").await.unwrap();
+    root_dir.remove("b.bin").await.unwrap();
+    assert_eq!(fs.max_file_size().await.unwrap(), free_bytes);
+    drop(root_dir);
+    fs.unmount().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_max_file_size_free_space_fat12() {
+    call_with_fs(test_max_file_size_free_space, FAT12_IMG, 67).await
+}
+
+#[tokio::test]
+async fn test_max_file_size_free_space_fat16() {
+    call_with_fs(test_max_file_size_free_space, FAT16_IMG, 67).await
+}
+
+#[tokio::test]
+async fn test_max_file_size_free_space_fat32() {
+    call_with_fs(test_max_file_size_free_space, FAT32_IMG, 67).await
+}
+
+async fn test_rename_in_same_dir(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    let dir = root_dir.create_dir("dir").await.unwrap();
+    for name in ["a.txt", "b.txt", "c.txt"] {
+        let mut file = dir.create_file(name).await.unwrap();
+        file.write_all(name.as_bytes()).await.unwrap();
+        file.flush().await.unwrap();
+    }
+    dir.remove("a.txt").await.unwrap();
+    let stats = fs.stats().await.unwrap();
+    // short -> long reusing the deleted entry before the old one keeps the position in the directory
+    dir.rename("b.txt", &dir, "long b.txt").await.unwrap();
+    let entries = dir.iter().collect().await;
+    let names = entries
+        .iter()
+        .map(|r| r.as_ref().unwrap().file_name())
+        .collect::<Vec<_>>();
+    assert_eq!(names, [".", "..", "long b.txt", "c.txt"]);
+    // short -> long without free entries before the old one moves the entry to the end
+    dir.rename("c.txt", &dir, "long c.txt").await.unwrap();
+    let entries = dir.iter().collect().await;
+    let names = entries
+        .iter()
+        .map(|r| r.as_ref().unwrap().file_name())
+        .collect::<Vec<_>>();
+    assert_eq!(names, [".", "..", "long b.txt", "long c.txt"]);
+    // long -> short is done in place
+    dir.rename("long b.txt", &dir, "d.txt").await.unwrap();
+    dir.rename("long c.txt", &dir, "e.txt").await.unwrap();
+    let entries = dir.iter().collect().await;
+    let names = entries
+        .iter()
+        .map(|r| r.as_ref().unwrap().file_name())
+        .collect::<Vec<_>>();
+    assert_eq!(names, [".", "..", "d.txt", "e.txt"]);
+    assert!(matches!(
+        dir.rename("d.txt", &dir, "e.txt").await,
+        Err(embedded_fatfs::Error::AlreadyExists)
+    ));
+    // the data is not touched
+    for (name, content) in [("d.txt", "b.txt"), ("e.txt", "c.txt")] {
+        let mut file = dir.open_file(name).await.unwrap();
+        let buf = read_to_end(&mut file).await.unwrap();
+        file.flush().await.unwrap();
+        assert_eq!(str::from_utf8(&buf).unwrap(), content);
+    }
+    // renaming back and forth reuses the slots and does not grow the directory
+    for _ in 0..64 {
+        dir.rename("d.txt", &dir, "a much longer name needing several entries.txt")
+            .await
+            .unwrap();
+        dir.rename("a much longer name needing several entries.txt", &dir, "d.txt")
+            .await
+            .unwrap();
+    }
+    assert_eq!(fs.stats().await.unwrap().free_clusters(), stats.free_clusters());
+    drop(dir);
+    drop(root_dir);
+    fs.unmount().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_rename_in_same_dir_fat12() {
+    call_with_fs(test_rename_in_same_dir, FAT12_IMG, 68).await
+}
+
+#[tokio::test]
+async fn test_rename_in_same_dir_fat16() {
+    call_with_fs(test_rename_in_same_dir, FAT16_IMG, 68).await
+}
+
+#[tokio::test]
+async fn test_rename_in_same_dir_fat32() {
+    call_with_fs(test_rename_in_same_dir, FAT32_IMG, 68).await
+}
+
+// A listener recording the runs of freed clusters, the test keeps a handle to the shared runs
+#[derive(Default)]
+struct FreedRecorder {
+    runs: std::sync::Arc<std::sync::Mutex<Vec<(u32, u32)>>>,
+}
+
+impl embedded_fatfs::FreedClustersListener for FreedRecorder {
+    fn clusters_freed(&mut self, first_cluster: u32, count: u32) {
+        self.runs.lock().unwrap().push((first_cluster, count));
+    }
+}
+
+// Coalesces clusters into runs of consecutive clusters
+fn cluster_runs(clusters: &[u32]) -> Vec<(u32, u32)> {
+    let mut runs: Vec<(u32, u32)> = Vec::new();
+    for &cluster in clusters {
+        match runs.last_mut() {
+            Some((first, count)) if *first + *count == cluster => *count += 1,
+            _ => runs.push((cluster, 1)),
+        }
+    }
+    runs
+}
+
+async fn test_freed_clusters_listener(fs: FileSystem) {
+    let runs = std::sync::Arc::default();
+    let previous = fs.set_freed_clusters_listener(Some(Box::new(FreedRecorder {
+        runs: std::sync::Arc::clone(&runs),
+    })));
+    assert!(previous.is_none());
+    let cluster_size = fs.cluster_size() as usize;
+    let root_dir = fs.root_dir();
+
+    let mut file = root_dir.create_file("a.bin").await.unwrap();
+    file.write_all(&vec![0x11; cluster_size * 3]).await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+    let clusters = fs.clusters_for_path("a.bin").await.unwrap();
+    root_dir.remove("a.bin").await.unwrap();
+    assert_eq!(std::mem::take(&mut *runs.lock().unwrap()), cluster_runs(&clusters));
+    // the reported clusters are free
+    let entries = fat_entries(&fs).await;
+    assert!(clusters
+        .iter()
+        .all(|&c| entries.iter().any(|&(n, v)| n == c && v == FatValue::Free)));
+
+    // a fragmented file is reported in several runs
+    let mut a = root_dir.create_file("a.bin").await.unwrap();
+    let mut b = root_dir.create_file("b.bin").await.unwrap();
+    for _ in 0..2 {
+        a.write_all(&vec![0x22; cluster_size]).await.unwrap();
+        a.flush().await.unwrap();
+        b.write_all(&vec![0x33; cluster_size]).await.unwrap();
+        b.flush().await.unwrap();
+    }
+    drop(a);
+    drop(b);
+    let clusters = fs.clusters_for_path("a.bin").await.unwrap();
+    root_dir.remove("a.bin").await.unwrap();
+    let fragments = std::mem::take(&mut *runs.lock().unwrap());
+    assert_eq!(fragments, cluster_runs(&clusters));
+    assert_eq!(fragments.len(), 2);
+
+    // clusters cut off by truncate are reported
+    let mut file = root_dir.create_file("c.bin").await.unwrap();
+    file.write_all(&vec![0x44; cluster_size * 3]).await.unwrap();
+    file.flush().await.unwrap();
+    let clusters = fs.clusters_for_path("c.bin").await.unwrap();
+    file.seek(SeekFrom::Start(cluster_size as u64)).await.unwrap();
+    file.truncate().await.unwrap();
+    drop(file);
+    assert_eq!(std::mem::take(&mut *runs.lock().unwrap()), cluster_runs(&clusters[1..]));
+
+    // nothing is reported without a listener
+    assert!(fs.set_freed_clusters_listener(None).is_some());
+    root_dir.remove("b.bin").await.unwrap();
+    root_dir.remove("c.bin").await.unwrap();
+    assert!(runs.lock().unwrap().is_empty());
+    drop(root_dir);
+    fs.unmount().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_freed_clusters_listener_fat12() {
+    call_with_fs(test_freed_clusters_listener, FAT12_IMG, 66).await
+}
+
+#[tokio::test]
+async fn test_freed_clusters_listener_fat16() {
+    call_with_fs(test_freed_clusters_listener, FAT16_IMG, 66).await
+}
+
+#[tokio::test]
+async fn test_freed_clusters_listener_fat32() {
+    call_with_fs(test_freed_clusters_listener, FAT32_IMG, 66).await
+}
+
+async fn test_freed_clusters_listener_ref(fs: FileSystem) {
+    let runs = std::sync::Arc::default();
+    let listener = Box::leak(Box::new(FreedRecorder {
+        runs: std::sync::Arc::clone(&runs),
+    }));
+    assert!(fs.set_freed_clusters_listener_ref(Some(listener)).is_none());
+    let root_dir = fs.root_dir();
+    let mut file = root_dir.create_file("a.bin").await.unwrap();
+    file.write_all(&vec![0x11; fs.cluster_size() as usize * 2])
+        .await
+        .unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+    let clusters = fs.clusters_for_path("a.bin").await.unwrap();
+    root_dir.remove("a.bin").await.unwrap();
+    assert_eq!(std::mem::take(&mut *runs.lock().unwrap()), cluster_runs(&clusters));
+
+    // an owned listener replaces the borrowed one which is not returned
+    assert!(fs
+        .set_freed_clusters_listener(Some(Box::new(FreedRecorder::default())))
+        .is_none());
+    assert!(fs.set_freed_clusters_listener_ref(None).is_none());
+    drop(root_dir);
+    fs.unmount().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_freed_clusters_listener_ref_fat16() {
+    call_with_fs(test_freed_clusters_listener_ref, FAT16_IMG, 69).await
+}
+
+#[tokio::test]
+async fn test_write_data_reads() {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let mut storage = MemStorage::new(new_mem_image(vec![0_u8; 1024 * 1024]));
+    let reads = storage.reads.clone();
+    let opts = embedded_fatfs::FormatVolumeOptions::new().bytes_per_cluster(2048);
+    embedded_fatfs::format_volume(&mut storage, opts).await.unwrap();
+    let fs = embedded_fatfs::FileSystem::new(storage, FsOptions::new())
+        .await
+        .unwrap();
+    // the FAT12 root directory is placed before the data region so only file data is counted
+    let data_begin = fs.cluster_offset(2);
+    let data_reads = || reads.borrow().iter().filter(|&&pos| pos >= data_begin).count();
+    let cluster_size = fs.cluster_size() as usize;
+    let data: Vec<u8> = (0..cluster_size * 3 + 100).map(|i| (i % 251) as u8).collect();
+    let root_dir = fs.root_dir();
+
+    // blocks placed after the end of the file are not read before small writes
+    let mut small = root_dir.create_file("small.bin").await.unwrap();
+    for chunk in data.chunks(100) {
+        small.write_all(chunk).await.unwrap();
+    }
+    small.flush().await.unwrap();
+    // whole clusters are written directly and the partial block at the end starts the buffer
+    let mut large = root_dir.create_file("large.bin").await.unwrap();
+    large.write_all(&data).await.unwrap();
+    large.flush().await.unwrap();
+    assert_eq!(data_reads(), 0);
+
+    // overwriting file data reads only the partial block at the end of the write
+    large.seek(SeekFrom::Start(50)).await.unwrap();
+    large.write_all(&data[..cluster_size * 2]).await.unwrap();
+    large.flush().await.unwrap();
+    assert_eq!(data_reads(), 1);
+
+    small.seek(SeekFrom::Start(0)).await.unwrap();
+    assert_eq!(read_to_end(&mut small).await.unwrap(), data);
+    let mut expected = data.clone();
+    expected[50..50 + cluster_size * 2].copy_from_slice(&data[..cluster_size * 2]);
+    large.seek(SeekFrom::Start(0)).await.unwrap();
+    assert_eq!(read_to_end(&mut large).await.unwrap(), expected);
+    drop(small);
+    drop(large);
+    drop(root_dir);
+    fs.unmount().await.unwrap();
+}
+
+// An in-memory storage sharing its image with the test. It records the position of every read and can yield once
+// before each access, so a future using the filesystem can be dropped between any two storage accesses, or fail a
+// single access with a timeout, which a caller may want to retry.
+struct MemStorage {
+    image: MemImage,
+    yielding: bool,
+    reads: std::rc::Rc<std::cell::RefCell<Vec<u64>>>,
+    // number of accesses left before the failing one
+    fail_in: std::rc::Rc<std::cell::Cell<Option<u32>>>,
+}
+
+impl MemStorage {
+    fn new(image: MemImage) -> Self {
+        Self {
+            image,
+            yielding: false,
+            reads: std::rc::Rc::default(),
+            fail_in: std::rc::Rc::default(),
+        }
+    }
+
+    async fn access(&self) -> std::io::Result<()> {
+        if self.yielding {
+            let mut yielded = false;
+            std::future::poll_fn(|cx| {
+                if yielded {
+                    std::task::Poll::Ready(())
+                } else {
+                    yielded = true;
+                    cx.waker().wake_by_ref();
+                    std::task::Poll::Pending
+                }
+            })
+            .await;
+        }
+        match self.fail_in.get() {
+            Some(0) => {
+                self.fail_in.set(None);
+                Err(std::io::ErrorKind::TimedOut.into())
+            }
+            Some(n) => {
+                self.fail_in.set(Some(n - 1));
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl embedded_io_async::ErrorType for MemStorage {
+    type Error = std::io::Error;
+}
+
+impl embedded_io_async::Read for MemStorage {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.access().await?;
+        let mut image = self.image.borrow_mut();
+        self.reads.borrow_mut().push(image.position());
+        std::io::Read::read(&mut *image, buf)
+    }
+}
+
+impl Write for MemStorage {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.access().await?;
+        std::io::Write::write(&mut *self.image.borrow_mut(), buf)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.access().await
+    }
+}
+
+impl Seek for MemStorage {
+    async fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+        self.access().await?;
+        let pos = match pos {
+            SeekFrom::Start(n) => std::io::SeekFrom::Start(n),
+            SeekFrom::End(n) => std::io::SeekFrom::End(n),
+            SeekFrom::Current(n) => std::io::SeekFrom::Current(n),
+        };
+        std::io::Seek::seek(&mut *self.image.borrow_mut(), pos)
+    }
+}
+
+type MemFileSystem = embedded_fatfs::FileSystem<MemStorage, embedded_fatfs::DefaultTimeProvider, LossyOemCpConverter>;
+
+type MemFile<'a> = embedded_fatfs::File<'a, MemStorage, embedded_fatfs::DefaultTimeProvider, LossyOemCpConverter>;
+
+type MemImage = std::rc::Rc<std::cell::RefCell<std::io::Cursor<Vec<u8>>>>;
+
+async fn open_mem_filesystem(image: &MemImage) -> MemFileSystem {
+    let storage = MemStorage {
+        yielding: true,
+        ..MemStorage::new(image.clone())
+    };
+    MemFileSystem::new(storage, FsOptions::new()).await.unwrap()
+}
+
+fn new_mem_image(data: Vec<u8>) -> MemImage {
+    std::rc::Rc::new(std::cell::RefCell::new(std::io::Cursor::new(data)))
+}
+
+#[test]
+fn test_std_io_adaptor() {
+    use std::io::{Read as _, Seek as _, Write as _};
+    let _ = env_logger::builder().is_test(true).try_init();
+    // the adaptor blocks the thread so it is used outside of the runtime
+    let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let image = new_mem_image(std::fs::read(format!("{}/{}", IMG_DIR, FAT16_IMG)).unwrap());
+    let fs = rt.block_on(open_mem_filesystem(&image));
+    let root_dir = fs.root_dir();
+    let mut file = rt.block_on(root_dir.create_file("std.txt")).unwrap().into_std();
+    file.write_all(b"Hello, std::io!").unwrap();
+    file.flush().unwrap();
+    assert_eq!(file.seek(std::io::SeekFrom::Start(7)).unwrap(), 7);
+    let mut text = String::new();
+    file.read_to_string(&mut text).unwrap();
+    assert_eq!(text, "std::io!");
+    assert_eq!(file.seek(std::io::SeekFrom::End(-3)).unwrap(), 12);
+    // errors keep their kind
+    let err = file.seek(std::io::SeekFrom::Current(-100)).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    assert_eq!(file.get_ref().metadata().len(), 15);
+    drop(file.into_inner());
+
+    let mut file = rt.block_on(root_dir.open_file("std.txt")).unwrap();
+    assert_eq!(rt.block_on(read_to_end(&mut file)).unwrap(), b"Hello, std::io!");
+    drop(file);
+    drop(root_dir);
+    rt.block_on(fs.unmount()).unwrap();
+}
+
+struct NoopWaker;
+
+impl std::task::Wake for NoopWaker {
+    fn wake(self: std::sync::Arc<Self>) {}
+}
+
+// Polls `fut` at most `polls` times and drops it, returning `true` if it completed
+fn poll_and_drop<F: Future>(fut: F, polls: u32) -> bool {
+    let waker = std::task::Waker::from(std::sync::Arc::new(NoopWaker));
+    let mut cx = std::task::Context::from_waker(&waker);
+    let mut fut = std::pin::pin!(fut);
+    (0..polls).any(|_| fut.as_mut().poll(&mut cx).is_ready())
+}
+
+#[derive(Clone, Copy, Debug)]
+enum CancelledOp {
+    Write,
+    Truncate,
+    Remove,
+    CreateDir,
+    PreallocateContiguous,
+}
+
+// The opened file is kept in `file`, so it can be flushed after the future is dropped
+async fn run_cancelled_op<'a>(fs: &'a MemFileSystem, op: CancelledOp, data: &[u8], file: &mut Option<MemFile<'a>>) {
+    let root_dir = fs.root_dir();
+    match op {
+        CancelledOp::Write => {
+            let file = file.insert(root_dir.create_file("new.bin").await.unwrap());
+            file.write_all(data).await.unwrap();
+            file.flush().await.unwrap();
+        }
+        CancelledOp::Truncate => {
+            let file = file.insert(root_dir.open_file("old.bin").await.unwrap());
+            file.seek(SeekFrom::Start(u64::from(fs.cluster_size()) + 1))
+                .await
+                .unwrap();
+            file.truncate().await.unwrap();
+            file.seek(SeekFrom::Start(0)).await.unwrap();
+            file.truncate().await.unwrap();
+            file.flush().await.unwrap();
+        }
+        CancelledOp::Remove => root_dir.remove("old.bin").await.unwrap(),
+        CancelledOp::CreateDir => {
+            let dir = root_dir.create_dir("newdir").await.unwrap();
+            let file = file.insert(dir.create_file("new.bin").await.unwrap());
+            file.write_all(data).await.unwrap();
+            file.flush().await.unwrap();
+        }
+        CancelledOp::PreallocateContiguous => {
+            let file = file.insert(root_dir.create_file("new.bin").await.unwrap());
+            file.preallocate_contiguous(data.len() as u32).await.unwrap();
+            file.flush().await.unwrap();
+        }
+    }
+}
+
+async fn assert_consistent(fs: &MemFileSystem) {
+    assert_eq!(fs.check().await.unwrap(), None);
+    // no FAT entry points to a free cluster
+    let mut entries = Vec::new();
+    let mut iter = fs.fat_entries();
+    while let Some(r) = iter.next().await {
+        entries.push(r.unwrap().1);
+    }
+    for value in &entries {
+        if let FatValue::Data(n) = *value {
+            assert_ne!(entries[n as usize - 2], FatValue::Free);
+        }
+    }
+    let free = entries.iter().filter(|v| **v == FatValue::Free).count() as u32;
+    assert_eq!(fs.stats().await.unwrap().free_clusters(), free);
+    // every file has enough clusters for its size
+    let mut dirs = vec![fs.root_dir()];
+    while let Some(dir) = dirs.pop() {
+        let mut iter = dir.iter();
+        while let Some(entry) = iter.next().await {
+            let entry = entry.unwrap();
+            if entry.is_dir() {
+                if entry.file_name() != "." && entry.file_name() != ".." {
+                    dirs.push(entry.to_dir());
+                }
+                continue;
+            }
+            let mut clusters = entry.clusters();
+            let mut num_clusters = 0_u64;
+            while let Some(r) = clusters.next().await {
+                r.unwrap();
+                num_clusters += 1;
+            }
+            assert!(num_clusters * u64::from(fs.cluster_size()) >= entry.len());
+        }
+    }
+}
+
+// Drops the tested futures after every `poll_step`-th poll, the larger images are sampled to keep the test fast
+async fn test_cancelled_operations(filename: &str, poll_step: usize) {
+    let _ = env_logger::builder().is_test(true).try_init();
+    // prepare a file spanning multiple clusters
+    let image = new_mem_image(fs::read(format!("{}/{}", IMG_DIR, filename)).await.unwrap());
+    let fs = open_mem_filesystem(&image).await;
+    let cluster_size = fs.cluster_size() as usize;
+    let data = vec![0x5A; cluster_size * 3 + 100];
+    let mut file = fs.root_dir().create_file("old.bin").await.unwrap();
+    file.write_all(&data).await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+    fs.unmount().await.unwrap();
+    let image = image.take().into_inner();
+
+    for op in [
+        CancelledOp::Write,
+        CancelledOp::Truncate,
+        CancelledOp::Remove,
+        CancelledOp::CreateDir,
+        CancelledOp::PreallocateContiguous,
+    ] {
+        for polls in (1..).step_by(poll_step) {
+            let fs = open_mem_filesystem(&new_mem_image(image.clone())).await;
+            let mut file = None;
+            let completed = poll_and_drop(run_cancelled_op(&fs, op, &data, &mut file), polls);
+            // the filesystem stays consistent after dropping the future
+            assert_consistent(&fs).await;
+            // and after flushing the file left open by the dropped future
+            if let Some(file) = file.as_mut() {
+                file.flush().await.unwrap();
+                assert_consistent(&fs).await;
+            }
+            drop(file);
+            fs.unmount().await.unwrap();
+            if completed {
+                break;
+            }
+        }
+    }
+
+    // a file kept open stays usable after dropping a write extending it
+    let new_data = vec![0xC3; cluster_size * 5];
+    for polls in (1..).step_by(poll_step) {
+        let fs = open_mem_filesystem(&new_mem_image(image.clone())).await;
+        let root_dir = fs.root_dir();
+        let mut file = root_dir.open_file("old.bin").await.unwrap();
+        let completed = poll_and_drop(file.write_all(&new_data), polls);
+        file.seek(SeekFrom::Start(0)).await.unwrap();
+        file.write_all(&new_data).await.unwrap();
+        file.flush().await.unwrap();
+        file.seek(SeekFrom::Start(0)).await.unwrap();
+        assert_eq!(read_to_end(&mut file).await.unwrap(), new_data);
+        drop(file);
+        assert_consistent(&fs).await;
+        drop(root_dir);
+        fs.unmount().await.unwrap();
+        if completed {
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_cancelled_operations_fat12() {
+    test_cancelled_operations(FAT12_IMG, 3).await
+}
+
+#[tokio::test]
+async fn test_cancelled_operations_fat16() {
+    test_cancelled_operations(FAT16_IMG, 17).await
+}
+
+#[tokio::test]
+async fn test_cancelled_operations_fat32() {
+    test_cancelled_operations(FAT32_IMG, 97).await
+}
+
+// Drops a rename moving a file to another directory after every `poll_step`-th poll and retries it if it did not start
+async fn test_cancelled_rename(filename: &str, poll_step: usize) {
+    use embedded_fatfs::CheckFinding;
+    let _ = env_logger::builder().is_test(true).try_init();
+    let image = new_mem_image(fs::read(format!("{}/{}", IMG_DIR, filename)).await.unwrap());
+    let fs = open_mem_filesystem(&image).await;
+    fs.root_dir().create_dir("sub").await.unwrap();
+    let mut file = fs.root_dir().create_file("old.txt").await.unwrap();
+    file.write_all(TEST_STR.as_bytes()).await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+    fs.unmount().await.unwrap();
+    let image = image.take().into_inner();
+
+    let mut cross_linked = false;
+    for polls in (1..).step_by(poll_step) {
+        let fs = open_mem_filesystem(&new_mem_image(image.clone())).await;
+        let root_dir = fs.root_dir();
+        let sub_dir = root_dir.open_dir("sub").await.unwrap();
+        let completed = poll_and_drop(root_dir.rename("old.txt", &sub_dir, "new name.txt"), polls);
+        let findings = fs.check_consistency().await.unwrap();
+        let old_exists = root_dir.try_open_file("old.txt").await.unwrap().is_some();
+        if old_exists && root_dir.try_open_file("sub/new name.txt").await.unwrap().is_some() {
+            // both entries share the cluster chain and retrying does not remove either of them
+            assert!(matches!(findings[..], [CheckFinding::CrossLinked { .. }, ..]));
+            assert!(matches!(
+                root_dir.rename("old.txt", &sub_dir, "new name.txt").await,
+                Err(embedded_fatfs::Error::AlreadyExists)
+            ));
+            assert!(root_dir.try_open_file("old.txt").await.unwrap().is_some());
+            cross_linked = true;
+        } else {
+            assert_eq!(findings, vec![]);
+            if old_exists {
+                root_dir.rename("old.txt", &sub_dir, "new name.txt").await.unwrap();
+            }
+            assert!(root_dir.try_open_file("old.txt").await.unwrap().is_none());
+            assert_eq!(fs.check_consistency().await.unwrap(), vec![]);
+        }
+        let mut file = root_dir.open_file("sub/new name.txt").await.unwrap();
+        assert_eq!(read_to_end(&mut file).await.unwrap(), TEST_STR.as_bytes());
+        drop(file);
+        drop(sub_dir);
+        drop(root_dir);
+        fs.unmount().await.unwrap();
+        if completed {
+            break;
+        }
+    }
+    assert!(cross_linked);
+}
+
+#[tokio::test]
+async fn test_cancelled_rename_fat12() {
+    test_cancelled_rename(FAT12_IMG, 1).await
+}
+
+#[tokio::test]
+async fn test_cancelled_rename_fat16() {
+    test_cancelled_rename(FAT16_IMG, 3).await
+}
+
+#[tokio::test]
+async fn test_cancelled_rename_fat32() {
+    test_cancelled_rename(FAT32_IMG, 17).await
+}
+
+// Drops positional reads and writes after every `poll_step`-th poll, the position of the file must not change
+async fn test_cancelled_positional_io(filename: &str, poll_step: usize) {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let image = new_mem_image(fs::read(format!("{}/{}", IMG_DIR, filename)).await.unwrap());
+    let fs = open_mem_filesystem(&image).await;
+    let cluster_size = fs.cluster_size() as usize;
+    let data = (0..cluster_size * 3 + 100)
+        .map(|i| (i % 251) as u8)
+        .collect::<Vec<u8>>();
+    let mut file = fs.root_dir().create_file("pos.bin").await.unwrap();
+    file.write_all(&data).await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+    fs.unmount().await.unwrap();
+    let image = image.take().into_inner();
+
+    let pos = cluster_size + 10;
+    let new_data = vec![0xC3; cluster_size + 200];
+    for polls in (1..).step_by(poll_step) {
+        let fs = open_mem_filesystem(&new_mem_image(image.clone())).await;
+        let root_dir = fs.root_dir();
+        let mut file = root_dir.open_file("pos.bin").await.unwrap();
+        file.seek(SeekFrom::Start(pos as u64)).await.unwrap();
+        let mut buf = vec![0; data.len()];
+        let read_completed = poll_and_drop(file.read_exact_at(0, &mut buf), polls);
+        // write over the end of the file so clusters are allocated
+        let write_completed = poll_and_drop(file.write_at((cluster_size * 3) as u64, &new_data), polls);
+        assert_eq!(file.seek(SeekFrom::Current(0)).await.unwrap(), pos as u64);
+        let mut buf = [0; 20];
+        embedded_io_async::Read::read_exact(&mut file, &mut buf).await.unwrap();
+        assert_eq!(buf[..], data[pos..pos + 20]);
+        file.flush().await.unwrap();
+        drop(file);
+        drop(root_dir);
+        fs.unmount().await.unwrap();
+        if read_completed && write_completed {
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_cancelled_positional_io_fat12() {
+    test_cancelled_positional_io(FAT12_IMG, 1).await
+}
+
+#[tokio::test]
+async fn test_cancelled_positional_io_fat16() {
+    test_cancelled_positional_io(FAT16_IMG, 3).await
+}
+
+#[tokio::test]
+async fn test_cancelled_positional_io_fat32() {
+    test_cancelled_positional_io(FAT32_IMG, 17).await
+}
+
+fn assert_timed_out<T>(r: Result<T, embedded_fatfs::Error<std::io::Error>>) {
+    match r {
+        Err(embedded_fatfs::Error::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::TimedOut),
+        Err(e) => panic!("unexpected error {:?}", e),
+        Ok(_) => panic!("unexpected success"),
+    }
+}
+
+async fn test_retry_after_storage_error(filename: &str) {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let image = new_mem_image(fs::read(format!("{}/{}", IMG_DIR, filename)).await.unwrap());
+    let storage = MemStorage::new(image);
+    let fail_in = storage.fail_in.clone();
+    let fs = embedded_fatfs::FileSystem::new(storage, FsOptions::new())
+        .await
+        .unwrap();
+    let root_dir = fs.root_dir();
+    let cluster_size = fs.cluster_size() as usize;
+    let data: Vec<u8> = (0..cluster_size * 3 + 100).map(|i| i as u8).collect();
+    let mut file = root_dir.create_file("retry.bin").await.unwrap();
+
+    // fail every storage access of a write in turn, the error is returned unchanged and the write can be retried
+    for n in 0.. {
+        file.seek(SeekFrom::Start(0)).await.unwrap();
+        fail_in.set(Some(n));
+        let r = file.write_all(&data).await;
+        let failed = fail_in.get().is_none();
+        fail_in.set(None);
+        if !failed {
+            r.unwrap();
+            break;
+        }
+        assert_timed_out(r);
+        file.seek(SeekFrom::Start(0)).await.unwrap();
+        file.write_all(&data).await.unwrap();
+    }
+    file.flush().await.unwrap();
+
+    // the same for reads
+    for n in 0.. {
+        file.seek(SeekFrom::Start(0)).await.unwrap();
+        fail_in.set(Some(n));
+        let r = read_to_end(&mut file).await;
+        let failed = fail_in.get().is_none();
+        fail_in.set(None);
+        if !failed {
+            assert_eq!(r.unwrap(), data);
+            break;
+        }
+        assert_timed_out(r);
+        file.seek(SeekFrom::Start(0)).await.unwrap();
+        assert_eq!(read_to_end(&mut file).await.unwrap(), data);
+    }
+    drop(file);
+    assert_eq!(fs.check().await.unwrap(), None);
+    drop(root_dir);
+    fs.unmount().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_retry_after_storage_error_fat12() {
+    test_retry_after_storage_error(FAT12_IMG).await
+}
+
+#[tokio::test]
+async fn test_retry_after_storage_error_fat16() {
+    test_retry_after_storage_error(FAT16_IMG).await
+}
+
+#[tokio::test]
+async fn test_retry_after_storage_error_fat32() {
+    test_retry_after_storage_error(FAT32_IMG).await
+}
+
+async fn test_dir_iter_storage_error(filename: &str) {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let image = new_mem_image(fs::read(format!("{}/{}", IMG_DIR, filename)).await.unwrap());
+    let storage = MemStorage::new(image);
+    let fail_in = storage.fail_in.clone();
+    let fs = embedded_fatfs::FileSystem::new(storage, FsOptions::new())
+        .await
+        .unwrap();
+    let root_dir = fs.root_dir();
+    let mut names = Vec::new();
+    let mut iter = root_dir.iter();
+    while let Some(r) = iter.next().await {
+        names.push(r.unwrap().file_name());
+    }
+    assert!(names.len() > 1);
+    drop(iter);
+
+    // the error is yielded once and ends the iteration
+    let mut failing = root_dir.iter();
+    assert_eq!(failing.next().await.unwrap().unwrap().file_name(), names[0]);
+    fail_in.set(Some(0));
+    assert_timed_out(failing.next().await.unwrap());
+    assert!(failing.next().await.is_none());
+    drop(failing);
+
+    // a new iterator reads the directory again
+    let mut again = root_dir.iter();
+    let mut names_after_error = Vec::new();
+    while let Some(r) = again.next().await {
+        names_after_error.push(r.unwrap().file_name());
+    }
+    assert_eq!(names_after_error, names);
+    drop(again);
+    drop(root_dir);
+    fs.unmount().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_dir_iter_storage_error_fat12() {
+    test_dir_iter_storage_error(FAT12_IMG).await
+}
+
+#[tokio::test]
+async fn test_dir_iter_storage_error_fat16() {
+    test_dir_iter_storage_error(FAT16_IMG).await
+}
+
+#[tokio::test]
+async fn test_dir_iter_storage_error_fat32() {
+    test_dir_iter_storage_error(FAT32_IMG).await
+}
+
+#[cfg(feature = "stream")]
+async fn test_dir_stream(filename: &str) {
+    use futures_core::Stream;
+    let _ = env_logger::builder().is_test(true).try_init();
+    let image = new_mem_image(fs::read(format!("{}/{}", IMG_DIR, filename)).await.unwrap());
+    let storage = MemStorage {
+        yielding: true,
+        ..MemStorage::new(image)
+    };
+    let fail_in = storage.fail_in.clone();
+    let fs = embedded_fatfs::FileSystem::new(storage, FsOptions::new())
+        .await
+        .unwrap();
+    let root_dir = fs.root_dir();
+    let mut names = Vec::new();
+    let mut iter = root_dir.iter();
+    while let Some(r) = iter.next().await {
+        names.push(r.unwrap().file_name());
+    }
+    drop(iter);
+
+    // the stream yields the same entries as the iterator
+    let mut stream = root_dir.iter().into_stream();
+    let mut stream_names = Vec::new();
+    while let Some(r) = std::future::poll_fn(|cx| std::pin::Pin::new(&mut stream).poll_next(cx)).await {
+        stream_names.push(r.unwrap().file_name());
+    }
+    assert_eq!(stream_names, names);
+    drop(stream);
+
+    // an error ends the stream
+    let mut stream = root_dir.iter().into_stream();
+    fail_in.set(Some(0));
+    assert_timed_out(
+        std::future::poll_fn(|cx| std::pin::Pin::new(&mut stream).poll_next(cx))
+            .await
+            .unwrap(),
+    );
+    assert!(std::future::poll_fn(|cx| std::pin::Pin::new(&mut stream).poll_next(cx))
+        .await
+        .is_none());
+    drop(stream);
+    drop(root_dir);
+    fs.unmount().await.unwrap();
+}
+
+#[cfg(feature = "stream")]
+#[tokio::test]
+async fn test_dir_stream_fat12() {
+    test_dir_stream(FAT12_IMG).await
+}
+
+#[cfg(feature = "stream")]
+#[tokio::test]
+async fn test_dir_stream_fat16() {
+    test_dir_stream(FAT16_IMG).await
+}
+
+#[cfg(feature = "stream")]
+#[tokio::test]
+async fn test_dir_stream_fat32() {
+    test_dir_stream(FAT32_IMG).await
+}
+
+async fn test_try_open(filename: &str) {
+    use embedded_fatfs::Error;
+    let _ = env_logger::builder().is_test(true).try_init();
+    let image = new_mem_image(fs::read(format!("{}/{}", IMG_DIR, filename)).await.unwrap());
+    let storage = MemStorage::new(image);
+    let fail_in = storage.fail_in.clone();
+    let fs = embedded_fatfs::FileSystem::new(storage, FsOptions::new())
+        .await
+        .unwrap();
+    let root_dir = fs.root_dir();
+    assert!(root_dir.try_open_file("short.txt").await.unwrap().is_some());
+    assert!(root_dir
+        .try_open_file("very/long/path/test.txt")
+        .await
+        .unwrap()
+        .is_some());
+    assert!(root_dir.try_open_file("missing.txt").await.unwrap().is_none());
+    assert!(root_dir.try_open_file("missing/test.txt").await.unwrap().is_none());
+    assert!(matches!(root_dir.try_open_file("very").await, Err(Error::InvalidInput)));
+    assert!(root_dir.try_open_dir("very/long").await.unwrap().is_some());
+    assert!(root_dir.try_open_dir("very/missing").await.unwrap().is_none());
+    assert!(matches!(
+        root_dir.try_open_dir("short.txt").await,
+        Err(Error::InvalidInput)
+    ));
+
+    // a storage error is not reported as a missing entry
+    fail_in.set(Some(0));
+    assert_timed_out(root_dir.try_open_file("missing.txt").await);
+    fail_in.set(Some(0));
+    assert_timed_out(root_dir.try_open_dir("very/missing").await);
+    assert!(root_dir.try_open_file("missing.txt").await.unwrap().is_none());
+    drop(root_dir);
+    fs.unmount().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_try_open_fat12() {
+    test_try_open(FAT12_IMG).await
+}
+
+#[tokio::test]
+async fn test_try_open_fat16() {
+    test_try_open(FAT16_IMG).await
+}
+
+#[tokio::test]
+async fn test_try_open_fat32() {
+    test_try_open(FAT32_IMG).await
+}
+
+async fn test_root_dir_capacity(filename: &str) {
+    use embedded_fatfs::Error;
+    let _ = env_logger::builder().is_test(true).try_init();
+    let image = new_mem_image(fs::read(format!("{}/{}", IMG_DIR, filename)).await.unwrap());
+    let fs = open_mem_filesystem(&image).await;
+    let root_dir = fs.root_dir();
+    let sub_dir = root_dir.create_dir("sub").await.unwrap();
+    assert_eq!(sub_dir.capacity(), None);
+    let Some(capacity) = root_dir.capacity() else {
+        assert_eq!(filename, FAT32_IMG);
+        return;
+    };
+    assert_eq!(capacity, 512);
+    let used = used_root_dir_slots(&image);
+
+    // upper case 8.3 names need no long name so every file takes a single short name entry
+    let mut created = 0;
+    let err = loop {
+        assert!(created <= capacity);
+        match root_dir.create_file(&format!("F{}.TXT", created)).await {
+            Ok(_) => created += 1,
+            Err(err) => break err,
+        }
+    };
+    assert!(matches!(err, Error::RootDirectoryFull));
+    assert_eq!(created, capacity - used);
+    let free_clusters = fs.stats().await.unwrap().free_clusters();
+    assert!(matches!(
+        root_dir.create_dir("DIR").await,
+        Err(Error::RootDirectoryFull)
+    ));
+    assert!(matches!(
+        root_dir.create_file("a long file name.txt").await,
+        Err(Error::RootDirectoryFull)
+    ));
+    // moving a file to the full root directory keeps it in the subdirectory
+    sub_dir.create_file("moved.txt").await.unwrap();
+    assert!(matches!(
+        sub_dir.rename("moved.txt", &root_dir, "moved.txt").await,
+        Err(Error::RootDirectoryFull)
+    ));
+    sub_dir.open_file("moved.txt").await.unwrap();
+    assert_eq!(fs.stats().await.unwrap().free_clusters(), free_clusters);
+
+    // directories stored in clusters still grow
+    for i in 0..fs.cluster_size() / 32 {
+        sub_dir.create_file(&format!("F{}.TXT", i)).await.unwrap();
+    }
+    drop(sub_dir);
+    // removing an entry makes room again
+    root_dir.remove("F0.TXT").await.unwrap();
+    root_dir.create_dir("DIR").await.unwrap();
+    drop(root_dir);
+    assert_eq!(fs.check_consistency().await.unwrap(), vec![]);
+    fs.unmount().await.unwrap();
+}
+
+// Counts the entries in use in the FAT12/FAT16 root directory of the image by reading the raw directory
+fn used_root_dir_slots(image: &MemImage) -> u32 {
+    let image = image.borrow();
+    let data = image.get_ref();
+    let u16_at = |pos: usize| u32::from(u16::from_le_bytes([data[pos], data[pos + 1]]));
+    let (bytes_per_sector, reserved_sectors, fats) = (u16_at(11), u16_at(14), u32::from(data[16]));
+    let (root_entries, sectors_per_fat) = (u16_at(17), u16_at(22));
+    let root_dir_start = ((reserved_sectors + fats * sectors_per_fat) * bytes_per_sector) as usize;
+    let mut used = 0;
+    for i in 0..root_entries as usize {
+        match data[root_dir_start + i * 32] {
+            0 => break,
+            0xE5 => {}
+            _ => used += 1,
+        }
+    }
+    used
+}
+
+#[tokio::test]
+async fn test_root_dir_capacity_fat12() {
+    test_root_dir_capacity(FAT12_IMG).await
+}
+
+#[tokio::test]
+async fn test_root_dir_capacity_fat16() {
+    test_root_dir_capacity(FAT16_IMG).await
+}
+
+#[tokio::test]
+async fn test_root_dir_capacity_fat32() {
+    test_root_dir_capacity(FAT32_IMG).await
 }
 
 async fn read_to_end<IO: embedded_io_async::Read>(io: &mut IO) -> Result<Vec<u8>, IO::Error> {